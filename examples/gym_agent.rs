@@ -0,0 +1,61 @@
+// Gym-style loop on top of the observation/action API: a random agent holding each action
+// for a few frames and observing a handful of RAM addresses
+//
+// cargo run --example gym_agent -- {nes_file_path}
+use std::env;
+
+use rust_nes_emulator::controller::ControllerState;
+use rust_nes_emulator::nes::{ActionNES, NES};
+
+const EPISODE_STEPS: usize = 100;
+const FRAMES_PER_STEP: usize = 4;
+// Addresses to observe, game specific (SMB stores player x position at $0086)
+const OBSERVED: [u16; 4] = [0x0086, 0x00CE, 0x0057, 0x075A];
+
+const ACTIONS: [ControllerState; 4] = [
+    ControllerState::empty(),
+    ControllerState::RIGHT,
+    ControllerState::A,
+    ControllerState::RIGHT.union(ControllerState::A),
+];
+
+/// xorshift so that runs are reproducible
+fn next_random(seed: &mut u32) -> u32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().collect();
+    let Some(path) = args.get(1) else {
+        println!("Pass .nes file path");
+        return Ok(());
+    };
+
+    let mut nes = ActionNES::new();
+    nes.load_from_path(path)?;
+    nes.reset()?;
+
+    let mut seed = 0x1234_5678;
+    let mut observation = nes.observe(&OBSERVED);
+    let mut total_reward = 0i64;
+    for step in 0..EPISODE_STEPS {
+        let action = ACTIONS[next_random(&mut seed) as usize % ACTIONS.len()];
+        nes.set_inputs(action);
+        nes.step_frames(FRAMES_PER_STEP)?;
+
+        let next_observation = nes.observe(&OBSERVED);
+        // Reward moving right
+        let reward = next_observation[0] as i64 - observation[0] as i64;
+        total_reward += reward;
+        println!(
+            "step {:3} action {:?} observation {:02x?} reward {}",
+            step, action, next_observation, reward
+        );
+        observation = next_observation;
+    }
+    println!("Total reward {}", total_reward);
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// Runs a ROM headless for 600 frames and saves the last frame as a PNG
+//
+// cargo run --example minimal_frontend -- {nes_file_path} {png_output_path}
+//
+// It also builds with the minimal feature, which has no PNG export and skips saving the frame.
+use std::{env, fs};
+
+use rust_nes_emulator::frontend::{run_frames, NullInput, VideoSink};
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::rom::ROM;
+use rust_nes_emulator::screen::frame::Frame;
+
+const FRAMES: usize = 600;
+
+/// Keeps a copy of the most recently presented frame
+struct LastFrameSink {
+    frame: Frame,
+    count: usize,
+}
+
+impl VideoSink for LastFrameSink {
+    fn present_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        self.frame.data = frame.data;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().collect();
+    let (Some(rom_path), Some(png_path)) = (args.get(1), args.get(2)) else {
+        println!("Pass .nes file path and .png output path");
+        return Ok(());
+    };
+
+    let mut nes = ActionNES::new();
+    let raw = fs::read(rom_path).map_err(|err| format!("Can't read {}: {}", rom_path, err))?;
+    nes.set_rom(ROM::from(raw)?)?;
+    nes.reset()?;
+
+    let mut video = LastFrameSink {
+        frame: Frame::new(),
+        count: 0,
+    };
+    run_frames(&mut nes, &mut video, &mut NullInput, FRAMES)?;
+
+    #[cfg(not(feature = "minimal"))]
+    {
+        video.frame.save_png(png_path)?;
+        println!(
+            "Ran {} frames, saved last frame to {}",
+            video.count, png_path
+        );
+    }
+    #[cfg(feature = "minimal")]
+    println!("Ran {} frames, not saving {}", video.count, png_path);
+    Ok(())
+}
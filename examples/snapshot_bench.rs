@@ -0,0 +1,47 @@
+// Measures snapshot capture and restore throughput, the target for per-frame rewind is
+// at least 1000 snapshots per second
+//
+// cargo run --release --example snapshot_bench -- [nes_file_path]
+use std::env;
+use std::time::Instant;
+
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::snapshot::{Snapshot, SnapshotBaseline};
+
+const WARMUP_FRAMES: usize = 60;
+const ITERATIONS: usize = 10_000;
+
+fn main() -> Result<(), String> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "test_roms/nestest.nes".to_string());
+    let mut nes = ActionNES::new();
+    nes.load_from_path(&path)?;
+    nes.reset()?;
+    nes.step_frames(WARMUP_FRAMES)?;
+    let baseline = SnapshotBaseline::power_on();
+
+    let start = Instant::now();
+    let mut snapshots = Vec::with_capacity(ITERATIONS);
+    for _ in 0..ITERATIONS {
+        snapshots.push(Snapshot::capture(&nes, &baseline));
+    }
+    let capture = start.elapsed();
+
+    let start = Instant::now();
+    for snapshot in &snapshots {
+        snapshot.restore(&mut nes, &baseline);
+    }
+    let restore = start.elapsed();
+
+    println!("Snapshot size: {} bytes", snapshots[0].size());
+    for (name, elapsed) in [("capture", capture), ("restore", restore)] {
+        println!(
+            "{}: {:.1} us each, {:.0} per second",
+            name,
+            elapsed.as_secs_f64() * 1e6 / ITERATIONS as f64,
+            ITERATIONS as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+// Accuracy toggles and the named presets bundling them
+//
+// The toggles live where they're used (CpuState, PpuState), this gathers them so frontends can
+// pick a preset. They can all be switched between instructions, and are kept in savestates so a
+// replay runs with the settings it was recorded with.
+use std::fmt;
+
+use crate::cpu::OpenBusModel;
+use crate::nes::ActionNES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accuracy {
+    pub open_bus: OpenBusModel,
+    // Reads from the wrong page when indexing crosses one, seen by $2002, $2007 and $4015-$4017
+    pub dummy_reads: bool,
+    // INC, DEC and the shifts on memory write the old value before the new one
+    pub rmw_double_writes: bool,
+    // 8 sprites per scanline
+    pub sprite_limit: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AccuracyPreset {
+    // Skips every optional behavior
+    Fast,
+    // What games rely on, without the sprite flicker
+    #[default]
+    Balanced,
+    Accurate,
+}
+
+impl AccuracyPreset {
+    pub const ALL: [AccuracyPreset; 3] = [
+        AccuracyPreset::Fast,
+        AccuracyPreset::Balanced,
+        AccuracyPreset::Accurate,
+    ];
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        AccuracyPreset::ALL
+            .into_iter()
+            .find(|preset| preset.to_string() == name.to_lowercase())
+            .ok_or_else(|| format!("Unknown accuracy preset {}", name))
+    }
+
+    pub fn settings(self) -> Accuracy {
+        match self {
+            AccuracyPreset::Fast => Accuracy {
+                open_bus: OpenBusModel::Zero,
+                dummy_reads: false,
+                rmw_double_writes: false,
+                sprite_limit: false,
+            },
+            AccuracyPreset::Balanced => Accuracy {
+                open_bus: OpenBusModel::LastBusValue,
+                dummy_reads: false,
+                rmw_double_writes: true,
+                sprite_limit: false,
+            },
+            AccuracyPreset::Accurate => Accuracy {
+                open_bus: OpenBusModel::LastBusValue,
+                dummy_reads: true,
+                rmw_double_writes: true,
+                sprite_limit: true,
+            },
+        }
+    }
+
+    /// The next preset, wrapping around, for cycling through them with a hotkey
+    pub fn next(self) -> Self {
+        let index = AccuracyPreset::ALL
+            .iter()
+            .position(|preset| *preset == self);
+        AccuracyPreset::ALL[(index.unwrap_or(0) + 1) % AccuracyPreset::ALL.len()]
+    }
+}
+
+impl fmt::Display for AccuracyPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccuracyPreset::Fast => write!(f, "fast"),
+            AccuracyPreset::Balanced => write!(f, "balanced"),
+            AccuracyPreset::Accurate => write!(f, "accurate"),
+        }
+    }
+}
+
+impl Accuracy {
+    /// Reads the settings `nes` is running with
+    pub fn of(nes: &ActionNES) -> Self {
+        Accuracy {
+            open_bus: nes.cpu_state.open_bus,
+            dummy_reads: nes.cpu_state.dummy_reads,
+            rmw_double_writes: nes.cpu_state.rmw_double_writes,
+            sprite_limit: nes.ppu_state.sprite_limit,
+        }
+    }
+
+    pub fn apply(&self, nes: &mut ActionNES) {
+        nes.cpu_state.open_bus = self.open_bus;
+        nes.cpu_state.dummy_reads = self.dummy_reads;
+        nes.cpu_state.rmw_double_writes = self.rmw_double_writes;
+        nes.ppu_state.sprite_limit = self.sprite_limit;
+    }
+
+    /// The preset these settings match, None for a custom mix
+    pub fn preset(&self) -> Option<AccuracyPreset> {
+        AccuracyPreset::ALL
+            .into_iter()
+            .find(|preset| preset.settings() == *self)
+    }
+
+    /// One bit per toggle, for savestates
+    pub fn to_bits(&self) -> u8 {
+        (self.open_bus == OpenBusModel::LastBusValue) as u8
+            | (self.dummy_reads as u8) << 1
+            | (self.rmw_double_writes as u8) << 2
+            | (self.sprite_limit as u8) << 3
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Accuracy {
+            open_bus: if bits & 1 != 0 {
+                OpenBusModel::LastBusValue
+            } else {
+                OpenBusModel::Zero
+            },
+            dummy_reads: bits & 0b10 != 0,
+            rmw_double_writes: bits & 0b100 != 0,
+            sprite_limit: bits & 0b1000 != 0,
+        }
+    }
+}
+
+impl Default for Accuracy {
+    fn default() -> Self {
+        AccuracyPreset::default().settings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for preset in AccuracyPreset::ALL {
+            let settings = preset.settings();
+            assert_eq!(Some(preset), settings.preset());
+            assert_eq!(settings, Accuracy::from_bits(settings.to_bits()));
+            assert_eq!(Ok(preset), AccuracyPreset::parse(&preset.to_string()));
+        }
+        assert_eq!(AccuracyPreset::Fast, AccuracyPreset::Accurate.next());
+        assert!(AccuracyPreset::parse("exact").is_err());
+
+        let mut nes = ActionNES::new();
+        AccuracyPreset::Accurate.settings().apply(&mut nes);
+        assert!(nes.ppu_state.sprite_limit);
+        assert_eq!(Some(AccuracyPreset::Accurate), Accuracy::of(&nes).preset());
+    }
+}
@@ -0,0 +1,410 @@
+use super::apu_state::{NOISE, PULSE_1, PULSE_2, TRIANGLE};
+use super::{ApuState, ApuStatus, Resampler};
+
+// Ref: https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// NTSC DMC rates in CPU cycles per output bit
+// Ref: https://www.nesdev.org/wiki/APU_DMC
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// Frame counter steps in CPU cycles after a $4017 write (NTSC)
+// Ref: https://www.nesdev.org/wiki/APU_Frame_Counter
+const QUARTER_FRAME_1: usize = 7457;
+const HALF_FRAME_1: usize = 14913;
+const QUARTER_FRAME_3: usize = 22371;
+const FOUR_STEP_LAST: usize = 29829;
+const FOUR_STEP_PERIOD: usize = 29830;
+const FIVE_STEP_LAST: usize = 37281;
+const FIVE_STEP_PERIOD: usize = 37282;
+
+pub struct ApuAction<'a> {
+    apu_state: &'a mut ApuState,
+    output: Option<&'a mut Resampler>,
+}
+
+impl<'a> ApuAction<'a> {
+    pub fn new(apu_state: &'a mut ApuState) -> Self {
+        ApuAction {
+            apu_state,
+            output: None,
+        }
+    }
+
+    /// Clocks the channel timers and sends their output to `output`. Without it only what's
+    /// visible through $4015 is emulated, nothing a game can read depends on the timers.
+    pub fn with_output(mut self, output: Option<&'a mut Resampler>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Write to $4000-$4013, $4015 or $4017
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.apu_state.pulse[0].write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.apu_state.pulse[1].write(addr - 0x4004, data),
+            0x4008..=0x400B => self.apu_state.triangle.write(addr - 0x4008, data),
+            0x400C..=0x400F => self.apu_state.noise.write(addr - 0x400C, data),
+            0x4011 => self.apu_state.dmc_output = data & 0b0111_1111,
+            _ => {}
+        }
+        match addr {
+            // Pulse and noise envelope registers hold the length counter halt flag
+            0x4000 => self.apu_state.length_halt[PULSE_1] = data & 0b0010_0000 != 0,
+            0x4004 => self.apu_state.length_halt[PULSE_2] = data & 0b0010_0000 != 0,
+            0x400C => self.apu_state.length_halt[NOISE] = data & 0b0010_0000 != 0,
+            0x4008 => self.apu_state.length_halt[TRIANGLE] = data & 0b1000_0000 != 0,
+            0x4003 => self.load_length_counter(PULSE_1, data),
+            0x4007 => self.load_length_counter(PULSE_2, data),
+            0x400B => self.load_length_counter(TRIANGLE, data),
+            0x400F => self.load_length_counter(NOISE, data),
+            0x4010 => {
+                self.apu_state.dmc_irq_enabled = data & 0b1000_0000 != 0;
+                self.apu_state.dmc_loop = data & 0b0100_0000 != 0;
+                self.apu_state.dmc_rate = DMC_RATE_TABLE[(data & 0b1111) as usize];
+                if !self.apu_state.dmc_irq_enabled {
+                    self.apu_state.dmc_irq = false;
+                }
+            }
+            0x4013 => self.apu_state.dmc_sample_length = ((data as u16) << 4) + 1,
+            0x4015 => self.write_status(data),
+            0x4017 => self.write_frame_counter(data),
+            // DMC samples aren't fetched, so their address isn't needed
+            _ => {}
+        }
+        if matches!(addr, 0x4010 | 0x4013 | 0x4015 | 0x4017) {
+            self.apu_state.timing_changed = true;
+        }
+    }
+
+    fn load_length_counter(&mut self, channel: usize, data: u8) {
+        if self.apu_state.enabled.bits() & (1 << channel) != 0 {
+            self.apu_state.length_counters[channel] = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    fn write_status(&mut self, data: u8) {
+        self.apu_state.enabled = ApuStatus::from_bits_truncate(data & 0b0001_1111);
+        // Disabling a channel silences it immediately
+        for channel in [PULSE_1, PULSE_2, TRIANGLE, NOISE] {
+            if data & (1 << channel) == 0 {
+                self.apu_state.length_counters[channel] = 0;
+            }
+        }
+        if !self.apu_state.enabled.contains(ApuStatus::DMC) {
+            self.apu_state.dmc_bytes_remaining = 0;
+        } else if self.apu_state.dmc_bytes_remaining == 0 {
+            self.restart_dmc();
+        }
+        self.apu_state.dmc_irq = false;
+    }
+
+    fn restart_dmc(&mut self) {
+        self.apu_state.dmc_bytes_remaining = self.apu_state.dmc_sample_length;
+        self.apu_state.dmc_timer = 0;
+    }
+
+    fn write_frame_counter(&mut self, data: u8) {
+        self.apu_state.five_step_mode = data & 0b1000_0000 != 0;
+        self.apu_state.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.apu_state.irq_inhibit {
+            self.apu_state.frame_irq = false;
+        }
+        self.apu_state.frame_cycle = 0;
+        // 5-step mode clocks a half frame right away
+        if self.apu_state.five_step_mode {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    /// Reads $4015, clearing the frame interrupt flag
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.apu_state.frame_irq = false;
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        self.apu_state.status().bits()
+    }
+
+    /// True while the frame counter or DMC is asserting the IRQ line
+    pub fn is_irq_pending(&self) -> bool {
+        self.apu_state.is_irq_pending()
+    }
+
+    /// Advances the frame counter and DMC by `cycles` CPU cycles
+    pub fn tick(&mut self, mut cycles: usize) {
+        // Jumps from event to event, the frame counter steps before the DMC on the same cycle
+        loop {
+            let frame_step = self.cycles_until_frame_step();
+            let dmc_byte = self.cycles_until_dmc_byte();
+            let next = frame_step.min(dmc_byte.unwrap_or(usize::MAX));
+            if next > cycles {
+                self.advance(cycles);
+                return;
+            }
+            self.advance(next);
+            cycles -= next;
+            if next == frame_step {
+                self.frame_step();
+            }
+            if Some(next) == dmc_byte {
+                self.dmc_byte();
+            }
+        }
+    }
+
+    /// CPU cycles until the frame counter next clocks the envelopes or length counters, raises
+    /// its IRQ or wraps around
+    pub fn cycles_until_frame_step(&self) -> usize {
+        let last = if self.apu_state.five_step_mode {
+            [FIVE_STEP_LAST, FIVE_STEP_PERIOD]
+        } else {
+            [FOUR_STEP_LAST, FOUR_STEP_PERIOD]
+        };
+        let steps = [
+            QUARTER_FRAME_1,
+            HALF_FRAME_1,
+            QUARTER_FRAME_3,
+            last[0],
+            last[1],
+        ];
+        let frame_cycle = self.apu_state.frame_cycle;
+        steps
+            .into_iter()
+            .find(|step| *step > frame_cycle)
+            .map_or(usize::MAX, |step| step - frame_cycle)
+    }
+
+    /// CPU cycles until the DMC finishes its current sample byte, None while it's silent
+    pub fn cycles_until_dmc_byte(&self) -> Option<usize> {
+        if self.apu_state.dmc_bytes_remaining == 0 {
+            return None;
+        }
+        let byte_cycles = 8 * self.apu_state.dmc_rate.max(1) as usize;
+        Some(byte_cycles.saturating_sub(self.apu_state.dmc_timer).max(1))
+    }
+
+    /// Moves the counters on by `cycles` CPU cycles without running any steps, callers make sure
+    /// no event is skipped
+    pub fn advance(&mut self, cycles: usize) {
+        self.apu_state.frame_cycle += cycles;
+        if self.apu_state.dmc_bytes_remaining > 0 {
+            self.apu_state.dmc_timer += cycles;
+        }
+        let Some(output) = self.output.as_deref_mut() else {
+            return;
+        };
+        let apu = &mut *self.apu_state;
+        for _ in 0..cycles {
+            apu.triangle.clock_timer(apu.length_counters[TRIANGLE]);
+            if apu.is_odd_cycle {
+                apu.pulse[0].clock_timer();
+                apu.pulse[1].clock_timer();
+                apu.noise.clock_timer();
+            }
+            apu.is_odd_cycle = !apu.is_odd_cycle;
+            output.push(apu.channel_outputs());
+        }
+    }
+
+    /// Runs the frame counter step due at the current cycle, if any
+    pub fn frame_step(&mut self) {
+        match (self.apu_state.five_step_mode, self.apu_state.frame_cycle) {
+            (_, QUARTER_FRAME_1) | (_, QUARTER_FRAME_3) => self.clock_quarter_frame(),
+            (_, HALF_FRAME_1) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (false, FOUR_STEP_LAST) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.apu_state.irq_inhibit {
+                    self.apu_state.frame_irq = true;
+                }
+            }
+            (false, FOUR_STEP_PERIOD) | (true, FIVE_STEP_PERIOD) => {
+                self.apu_state.frame_cycle = 0;
+            }
+            (true, FIVE_STEP_LAST) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            _ => {}
+        }
+    }
+
+    // Envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        let apu = &mut *self.apu_state;
+        apu.pulse[0].envelope.clock(apu.length_halt[PULSE_1]);
+        apu.pulse[1].envelope.clock(apu.length_halt[PULSE_2]);
+        apu.noise.envelope.clock(apu.length_halt[NOISE]);
+        apu.triangle.clock_linear_counter(apu.length_halt[TRIANGLE]);
+    }
+
+    // Length counters and sweeps
+    fn clock_half_frame(&mut self) {
+        self.apu_state.pulse[0].clock_sweep();
+        self.apu_state.pulse[1].clock_sweep();
+        for channel in [PULSE_1, PULSE_2, TRIANGLE, NOISE] {
+            let counter = &mut self.apu_state.length_counters[channel];
+            if !self.apu_state.length_halt[channel] && *counter > 0 {
+                *counter -= 1;
+            }
+        }
+    }
+
+    /// Finishes the current DMC sample byte. Bytes are counted down without being fetched,
+    /// 8 output bits per byte.
+    pub fn dmc_byte(&mut self) {
+        if self.apu_state.dmc_bytes_remaining == 0 {
+            return;
+        }
+        self.apu_state.dmc_timer = 0;
+        self.apu_state.dmc_bytes_remaining -= 1;
+        if self.apu_state.dmc_bytes_remaining == 0 {
+            if self.apu_state.dmc_loop {
+                self.restart_dmc();
+            } else if self.apu_state.dmc_irq_enabled {
+                self.apu_state.dmc_irq = true;
+            }
+        }
+    }
+}
+
+// Modeled after blargg's apu_test ROMs
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::MixerControls;
+
+    fn create_apu() -> ApuState {
+        let mut apu_state = ApuState::new();
+        // Start at the beginning of a 4-step sequence with IRQs off, like the test ROMs
+        ApuAction::new(&mut apu_state).write_register(0x4017, 0b0100_0000);
+        apu_state
+    }
+
+    #[test]
+    fn test_len_ctr() {
+        let mut apu_state = create_apu();
+        let mut apu = ApuAction::new(&mut apu_state);
+        // Length counter isn't loaded while the channel is disabled
+        apu.write_register(0x4003, 0x18);
+        assert_eq!(0, apu.read_status() & 0b1);
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0x18);
+        assert_eq!(1, apu.read_status() & 0b1);
+        // Length 2 runs out after two half frames
+        apu.tick(HALF_FRAME_1);
+        assert_eq!(1, apu.read_status() & 0b1);
+        apu.tick(FOUR_STEP_LAST - HALF_FRAME_1);
+        assert_eq!(0, apu.read_status() & 0b1);
+        // Disabling clears the counter
+        apu.write_register(0x4003, 0xF8);
+        apu.write_register(0x4015, 0);
+        assert_eq!(0, apu.read_status() & 0b1);
+    }
+
+    #[test]
+    fn test_len_halt() {
+        let mut apu_state = create_apu();
+        let mut apu = ApuAction::new(&mut apu_state);
+        apu.write_register(0x4015, 0b0000_0100);
+        apu.write_register(0x4008, 0b1000_0000);
+        apu.write_register(0x400B, 0x18);
+        apu.tick(FOUR_STEP_PERIOD * 2);
+        assert_eq!(0b100, apu.read_status() & 0b100);
+        assert_eq!(2, apu_state.length_counters[TRIANGLE]);
+    }
+
+    #[test]
+    fn test_len_table() {
+        let mut apu_state = create_apu();
+        let mut apu = ApuAction::new(&mut apu_state);
+        apu.write_register(0x4015, 0b0000_1000);
+        for (index, length) in LENGTH_TABLE.iter().enumerate() {
+            apu.write_register(0x400F, (index as u8) << 3);
+            assert_eq!(*length, apu.apu_state.length_counters[NOISE]);
+        }
+    }
+
+    #[test]
+    fn test_five_step_clocks_immediately() {
+        let mut apu_state = create_apu();
+        let mut apu = ApuAction::new(&mut apu_state);
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0x18);
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(1, apu.apu_state.length_counters[PULSE_1]);
+    }
+
+    #[test]
+    fn test_irq_flag() {
+        let mut apu_state = ApuState::new();
+        let mut apu = ApuAction::new(&mut apu_state);
+        apu.write_register(0x4017, 0);
+        apu.tick(FOUR_STEP_LAST - 1);
+        assert_eq!(0, apu.peek_status() & 0x40);
+        apu.tick(1);
+        assert!(apu.is_irq_pending());
+        // Reading clears the flag
+        assert_eq!(0x40, apu.read_status() & 0x40);
+        assert_eq!(0, apu.read_status() & 0x40);
+        // Setting the inhibit flag clears it too
+        apu.tick(FOUR_STEP_PERIOD);
+        assert_eq!(0x40, apu.peek_status() & 0x40);
+        apu.write_register(0x4017, 0b0100_0000);
+        assert_eq!(0, apu.peek_status() & 0x40);
+        // No IRQ in 5-step mode
+        apu.write_register(0x4017, 0b1000_0000);
+        apu.tick(FIVE_STEP_PERIOD * 2);
+        assert!(!apu.is_irq_pending());
+    }
+
+    #[test]
+    fn test_output() {
+        let mut apu_state = create_apu();
+        let mut resampler = Resampler::new(44100, MixerControls::new());
+        let mut apu = ApuAction::new(&mut apu_state).with_output(Some(&mut resampler));
+        // Pulse 1 at about 440Hz, constant volume 15
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x08);
+        apu.tick(FOUR_STEP_PERIOD);
+        let samples = resampler.take();
+        // A frame of audio (29830 cycles), not silent
+        assert!(samples.len().abs_diff(735) <= 1);
+        assert!(samples.iter().any(|sample| *sample > 1000));
+        assert!(samples.iter().any(|sample| *sample < -1000));
+    }
+
+    #[test]
+    fn test_dmc_status() {
+        let mut apu_state = create_apu();
+        let mut apu = ApuAction::new(&mut apu_state);
+        // Fastest rate with IRQ, 17 byte sample
+        apu.write_register(0x4010, 0b1000_1111);
+        apu.write_register(0x4013, 1);
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(0x10, apu.read_status() & 0x10);
+        apu.tick(17 * 8 * 54 - 1);
+        assert_eq!(0x10, apu.read_status() & 0x10);
+        apu.tick(1);
+        assert_eq!(0x80, apu.read_status() & 0x90);
+        // Writing $4015 acknowledges the DMC IRQ and restarts the sample
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(0x10, apu.read_status() & 0x90);
+        apu.write_register(0x4015, 0);
+        assert_eq!(0, apu.read_status() & 0x10);
+    }
+}
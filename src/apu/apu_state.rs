@@ -0,0 +1,142 @@
+use bitflags::bitflags;
+
+use super::channels::{Noise, Pulse, Triangle};
+use super::CHANNELS;
+
+// Channels with a length counter, in $4015 bit order
+pub const PULSE_1: usize = 0;
+pub const PULSE_2: usize = 1;
+pub const TRIANGLE: usize = 2;
+pub const NOISE: usize = 3;
+
+// What games can observe through $4015, and the tone generators behind the sound. Snapshot
+// bytes only keep the former, the sound is back in step after the next note.
+#[derive(Debug, Clone, Copy)]
+pub struct ApuState {
+    pub length_counters: [u8; 4],
+    pub length_halt: [bool; 4],
+    // Channel enables written to $4015
+    pub enabled: ApuStatus,
+
+    // DMC
+    pub dmc_irq_enabled: bool,
+    pub dmc_loop: bool,
+    pub dmc_rate: u16,
+    pub dmc_sample_length: u16,
+    pub dmc_bytes_remaining: u16,
+    pub dmc_timer: usize,
+
+    // Frame counter ($4017)
+    pub five_step_mode: bool,
+    pub irq_inhibit: bool,
+    // CPU cycles since the frame counter was reset
+    pub frame_cycle: usize,
+
+    // Interrupt flags read through $4015
+    pub frame_irq: bool,
+    pub dmc_irq: bool,
+
+    pub pulse: [Pulse; 2],
+    pub triangle: Triangle,
+    pub noise: Noise,
+    // Written directly through $4011, samples aren't played
+    pub dmc_output: u8,
+    // Pulse and noise timers are clocked every other CPU cycle
+    pub is_odd_cycle: bool,
+    // Set by writes that move the frame counter or DMC events, cleared once they're rescheduled
+    pub timing_changed: bool,
+}
+
+impl Default for ApuState {
+    fn default() -> Self {
+        ApuState {
+            length_counters: [0; 4],
+            length_halt: [false; 4],
+            enabled: ApuStatus::empty(),
+            dmc_irq_enabled: false,
+            dmc_loop: false,
+            dmc_rate: 0,
+            dmc_sample_length: 0,
+            dmc_bytes_remaining: 0,
+            dmc_timer: 0,
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_cycle: 0,
+            frame_irq: false,
+            dmc_irq: false,
+            pulse: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc_output: 0,
+            is_odd_cycle: false,
+            timing_changed: false,
+        }
+    }
+}
+
+impl ApuState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Output of each channel from 0.0 to 1.0, in mixer order
+    pub fn channel_outputs(&self) -> [f32; CHANNELS] {
+        [
+            self.pulse[0].output(self.length_counters[PULSE_1]) as f32 / 15.0,
+            self.pulse[1].output(self.length_counters[PULSE_2]) as f32 / 15.0,
+            self.triangle.output() as f32 / 15.0,
+            self.noise.output(self.length_counters[NOISE]) as f32 / 15.0,
+            self.dmc_output as f32 / 127.0,
+        ]
+    }
+
+    /// True while the frame counter or DMC is asserting the IRQ line
+    pub fn is_irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc_irq
+    }
+
+    // Value read from $4015, with no side effects
+    pub fn status(&self) -> ApuStatus {
+        let mut status = ApuStatus::empty();
+        for (channel, flag) in [
+            ApuStatus::PULSE_1,
+            ApuStatus::PULSE_2,
+            ApuStatus::TRIANGLE,
+            ApuStatus::NOISE,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            status.set(flag, self.length_counters[channel] > 0);
+        }
+        status.set(ApuStatus::DMC, self.dmc_bytes_remaining > 0);
+        status.set(ApuStatus::FRAME_IRQ, self.frame_irq);
+        status.set(ApuStatus::DMC_IRQ, self.dmc_irq);
+        status
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // IF-D NT21
+    // |||| ||||
+    // |||| |||+- Pulse 1 length counter > 0 / enable
+    // |||| ||+-- Pulse 2 length counter > 0 / enable
+    // |||| |+--- Triangle length counter > 0 / enable
+    // |||| +---- Noise length counter > 0 / enable
+    // |||+------ DMC bytes remaining > 0 / enable
+    // ||+------- Open bus
+    // |+-------- Frame interrupt
+    // +--------- DMC interrupt
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct ApuStatus: u8 {
+        const PULSE_1 =     0b0000_0001;
+        const PULSE_2 =     0b0000_0010;
+        const TRIANGLE =    0b0000_0100;
+        const NOISE =       0b0000_1000;
+        const DMC =         0b0001_0000;
+        const FRAME_IRQ =   0b0100_0000;
+        const DMC_IRQ =     0b1000_0000;
+    }
+}
@@ -0,0 +1,366 @@
+// Tone generators of the pulse, triangle and noise channels
+//
+// Length counters live in ApuState with the rest of what $4015 reports, these only shape the
+// sound. Timers are clocked by ApuAction::advance while audio output is enabled.
+
+// Ref: https://www.nesdev.org/wiki/APU_Pulse
+const DUTY_TABLE: [u8; 4] = [0b0000_0010, 0b0000_0110, 0b0001_1110, 0b1111_1001];
+// Ref: https://www.nesdev.org/wiki/APU_Triangle
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+// NTSC noise periods in APU cycles
+// Ref: https://www.nesdev.org/wiki/APU_Noise
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// Ref: https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Envelope {
+    pub start: bool,
+    pub constant_volume: bool,
+    // Constant volume, or the decay period
+    pub volume: u8,
+    pub divider: u8,
+    pub decay: u8,
+}
+
+impl Envelope {
+    // Bits 0-4 of $4000, $4004 and $400C
+    fn write(&mut self, data: u8) {
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b1111;
+    }
+
+    /// Quarter frame clock, `is_looping` is the length counter halt flag
+    pub fn clock(&mut self, is_looping: bool) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if is_looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pulse {
+    pub duty: u8,
+    pub duty_step: u8,
+    pub timer_period: u16,
+    pub timer: u16,
+    pub envelope: Envelope,
+    // Ref: https://www.nesdev.org/wiki/APU_Sweep
+    pub sweep_enabled: bool,
+    pub sweep_period: u8,
+    pub sweep_negate: bool,
+    pub sweep_shift: u8,
+    pub sweep_reload: bool,
+    pub sweep_divider: u8,
+    // Pulse 1 negates in one's complement
+    pub is_pulse_1: bool,
+}
+
+impl Pulse {
+    pub fn new(is_pulse_1: bool) -> Self {
+        Pulse {
+            is_pulse_1,
+            ..Default::default()
+        }
+    }
+
+    /// Writes $4000-$4003 (or $4004-$4007), by register index
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0b1000_0000 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0b1000 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.duty_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// APU cycle (every other CPU cycle) clock
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_step = (self.duty_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.is_pulse_1 {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    // The sweep unit mutes the channel even when it's disabled
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    /// Half frame clock
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Volume from 0 to 15
+    pub fn output(&self, length_counter: u8) -> u8 {
+        let is_high = DUTY_TABLE[self.duty as usize] & (0x80 >> self.duty_step) != 0;
+        if length_counter == 0 || self.is_muted() || !is_high {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Triangle {
+    pub timer_period: u16,
+    pub timer: u16,
+    pub step: u8,
+    pub linear_counter: u8,
+    pub linear_reload_value: u8,
+    pub linear_reload: bool,
+}
+
+impl Triangle {
+    /// Writes $4008-$400B, by register index
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.linear_reload_value = data & 0b0111_1111,
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// CPU cycle clock, the sequencer only moves while both counters are non-zero
+    pub fn clock_timer(&mut self, length_counter: u8) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            // Periods under 2 are ultrasonic and would only alias, they hold the output instead
+            if self.linear_counter > 0 && length_counter > 0 && self.timer_period >= 2 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Quarter frame clock, `control` is the length counter halt flag
+    pub fn clock_linear_counter(&mut self, control: bool) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Volume from 0 to 15. Silencing the channel stops the sequencer rather than muting it.
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    pub timer_period: u16,
+    pub timer: u16,
+    pub short_mode: bool,
+    pub shift_register: u16,
+    pub envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            timer_period: NOISE_PERIODS[0],
+            timer: 0,
+            short_mode: false,
+            // Loaded with 1 on power-up
+            shift_register: 1,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Noise {
+    /// Writes $400C-$400F, by register index
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.envelope.write(data),
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = NOISE_PERIODS[(data & 0b1111) as usize];
+            }
+            3 => self.envelope.start = true,
+            _ => {}
+        }
+    }
+
+    /// APU cycle clock
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Volume from 0 to 15
+    pub fn output(&self, length_counter: u8) -> u8 {
+        if length_counter == 0 || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut pulse = Pulse::new(true);
+        // 50% duty, constant volume 9, period 8 (9 APU cycles per step)
+        pulse.write(0, 0b1001_1001);
+        pulse.write(2, 8);
+        pulse.write(3, 0);
+        let mut highs = 0;
+        for _ in 0..9 * 8 {
+            pulse.clock_timer();
+            if pulse.output(1) == 9 {
+                highs += 1;
+            }
+        }
+        assert_eq!(9 * 4, highs);
+        assert_eq!(0, pulse.output(0));
+        // Periods under 8 are muted
+        pulse.write(2, 7);
+        assert!(pulse.is_muted());
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse_1 = Pulse::new(true);
+        let mut pulse_2 = Pulse::new(false);
+        for pulse in [&mut pulse_1, &mut pulse_2] {
+            pulse.write(2, 0x40);
+            // Enabled, period 0, negate, shift 1
+            pulse.write(1, 0b1000_1001);
+            pulse.clock_sweep();
+        }
+        assert_eq!(0x40 - 0x20 - 1, pulse_1.timer_period);
+        assert_eq!(0x40 - 0x20, pulse_2.timer_period);
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::default();
+        envelope.write(0);
+        envelope.start = true;
+        envelope.clock(false);
+        assert_eq!(15, envelope.output());
+        for _ in 0..15 {
+            envelope.clock(false);
+        }
+        assert_eq!(0, envelope.output());
+        envelope.clock(true);
+        assert_eq!(15, envelope.output());
+    }
+
+    #[test]
+    fn test_triangle_linear_counter() {
+        let mut triangle = Triangle::default();
+        triangle.write(0, 2);
+        triangle.write(2, 2);
+        triangle.write(3, 0);
+        triangle.clock_linear_counter(false);
+        for _ in 0..3 {
+            triangle.clock_timer(1);
+        }
+        assert_eq!(14, triangle.output());
+        // Runs out after two more quarter frames, freezing the sequencer
+        triangle.clock_linear_counter(false);
+        triangle.clock_linear_counter(false);
+        for _ in 0..30 {
+            triangle.clock_timer(1);
+        }
+        assert_eq!(14, triangle.output());
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut noise = Noise::default();
+        noise.clock_timer();
+        // Bits 0 and 1 of 1 differ, so a 1 is shifted into bit 14
+        assert_eq!(0x4000, noise.shift_register);
+        noise.write(2, 0b1000_0000);
+        let mut states = vec![noise.shift_register];
+        for _ in 0..4 + 93 * 4 {
+            noise.clock_timer();
+            states.push(noise.shift_register);
+        }
+        // Short mode repeats every 93 (or 31) steps
+        assert_eq!(states[4], states[4 + 93 * 4]);
+    }
+}
@@ -0,0 +1,192 @@
+// Volume controls shared between the UI thread and the audio thread
+//
+// Gains are percentages in atomics, so the UI can change them while the audio callback is
+// mixing without locking. Clones of MixerControls share the same gains.
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+pub const DMC: usize = 4;
+pub const CHANNELS: usize = 5;
+pub const CHANNEL_NAMES: [&str; CHANNELS] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+const MAX_GAIN: u8 = 100;
+
+struct Gains {
+    master: AtomicU8,
+    channels: [AtomicU8; CHANNELS],
+}
+
+#[derive(Clone)]
+pub struct MixerControls(Arc<Gains>);
+
+impl Default for MixerControls {
+    fn default() -> Self {
+        MixerControls(Arc::new(Gains {
+            master: AtomicU8::new(MAX_GAIN),
+            channels: [(); CHANNELS].map(|_| AtomicU8::new(MAX_GAIN)),
+        }))
+    }
+}
+
+impl fmt::Debug for MixerControls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MixerControls")
+            .field("master", &self.master())
+            .field(
+                "channels",
+                &(0..CHANNELS)
+                    .map(|channel| self.gain(channel))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl MixerControls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn master(&self) -> u8 {
+        self.0.master.load(Ordering::Relaxed)
+    }
+
+    /// Sets the master volume in percent, clamped to 100
+    pub fn set_master(&self, percent: u8) {
+        self.0
+            .master
+            .store(percent.min(MAX_GAIN), Ordering::Relaxed);
+    }
+
+    /// Changes the master volume by `delta` percent, returns the new volume
+    pub fn adjust_master(&self, delta: i16) -> u8 {
+        let percent = (self.master() as i16 + delta).clamp(0, MAX_GAIN as i16) as u8;
+        self.set_master(percent);
+        percent
+    }
+
+    pub fn gain(&self, channel: usize) -> u8 {
+        self.0.channels[channel].load(Ordering::Relaxed)
+    }
+
+    pub fn set_gain(&self, channel: usize, percent: u8) {
+        self.0.channels[channel].store(percent.min(MAX_GAIN), Ordering::Relaxed);
+    }
+
+    /// Mutes a channel, or unmutes it back to full volume, returns true if it's now muted
+    pub fn toggle_mute(&self, channel: usize) -> bool {
+        let is_muted = self.gain(channel) != 0;
+        self.set_gain(channel, if is_muted { 0 } else { MAX_GAIN });
+        is_muted
+    }
+
+    /// Mixes channel outputs (0.0 to 1.0 each) into one sample
+    pub fn mix(&self, channels: [f32; CHANNELS]) -> f32 {
+        let mixed: f32 = channels
+            .iter()
+            .enumerate()
+            .map(|(channel, output)| output * self.gain(channel) as f32 / 100.0)
+            .sum();
+        mixed / CHANNELS as f32 * self.master() as f32 / 100.0
+    }
+
+    /// Scales already mixed samples by the master volume
+    pub fn apply_master(&self, samples: &mut [i16]) {
+        let master = self.master() as i32;
+        for sample in samples {
+            *sample = (*sample as i32 * master / 100) as i16;
+        }
+    }
+
+    /// Ramps linearly from `from`, the last sample played, down to silence over `samples`, so
+    /// stopping the sound doesn't pop
+    pub fn fade_out(samples: &mut [i16], from: i16) {
+        let len = samples.len() as i32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = (from as i32 * (len - 1 - i as i32) / len) as i16;
+        }
+    }
+
+    /// "key=percent" lines, e.g. "master=80"
+    pub fn to_config(&self) -> String {
+        let mut config = format!("master={}\n", self.master());
+        for (channel, name) in CHANNEL_NAMES.iter().enumerate() {
+            config.push_str(&format!("{}={}\n", name, self.gain(channel)));
+        }
+        config
+    }
+
+    /// Loads gains written by to_config, unknown keys are an error
+    pub fn load_config(&self, config: &str) -> Result<(), String> {
+        for line in config.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid mixer setting {}", line))?;
+            let percent: u8 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid volume {}", value))?;
+            match key.trim() {
+                "master" => self.set_master(percent),
+                key => match CHANNEL_NAMES.iter().position(|name| *name == key) {
+                    Some(channel) => self.set_gain(channel, percent),
+                    None => return Err(format!("Unknown mixer channel {}", key)),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{PULSE_1, TRIANGLE};
+
+    #[test]
+    fn test_controls_are_shared() {
+        let mixer = MixerControls::new();
+        let audio_thread = mixer.clone();
+        mixer.set_master(50);
+        assert!(mixer.toggle_mute(TRIANGLE));
+        assert_eq!(50, audio_thread.master());
+        assert_eq!(0, audio_thread.gain(TRIANGLE));
+        assert_eq!(0, mixer.adjust_master(-80));
+        assert_eq!(100, mixer.adjust_master(150));
+
+        let mut samples = [1000, -1000];
+        mixer.set_master(25);
+        mixer.apply_master(&mut samples);
+        assert_eq!([250, -250], samples);
+    }
+
+    #[test]
+    fn test_fade_out() {
+        let mut samples = [1234; 4];
+        MixerControls::fade_out(&mut samples, -1000);
+        assert_eq!([-750, -500, -250, 0], samples);
+        let mut empty: [i16; 0] = [];
+        MixerControls::fade_out(&mut empty, 1000);
+    }
+
+    #[test]
+    fn test_mix() {
+        let mixer = MixerControls::new();
+        mixer.set_gain(PULSE_1, 50);
+        let mixed = mixer.mix([1.0, 1.0, 0.0, 0.0, 0.0]);
+        assert!((mixed - 1.5 / 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mixer = MixerControls::new();
+        mixer.set_master(70);
+        mixer.set_gain(DMC, 20);
+        let loaded = MixerControls::new();
+        loaded.load_config(&mixer.to_config()).unwrap();
+        assert_eq!(70, loaded.master());
+        assert_eq!(20, loaded.gain(DMC));
+        assert!(loaded.load_config("bass=10").is_err());
+    }
+}
@@ -0,0 +1,11 @@
+mod apu_action;
+mod apu_state;
+mod channels;
+mod mixer;
+mod resampler;
+
+pub use apu_action::ApuAction;
+pub use apu_state::{ApuState, ApuStatus, NOISE, PULSE_1, PULSE_2, TRIANGLE};
+pub use channels::{Envelope, Noise, Pulse, Triangle};
+pub use mixer::{MixerControls, CHANNELS, CHANNEL_NAMES, DMC};
+pub use resampler::Resampler;
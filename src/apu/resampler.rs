@@ -0,0 +1,166 @@
+// Turns channel outputs at the CPU clock into samples at the audio device's rate
+//
+// Each sample is the average of the CPU cycles it covers, which filters out most of what would
+// alias, then a high-pass filter takes away the DC offset like the console's output stage does.
+// The fraction of a cycle left over from each sample carries over to the next, so the sample
+// count never drifts from the CPU clock.
+use std::mem;
+
+use super::{MixerControls, CHANNELS};
+use crate::frontend::CPU_FREQUENCY;
+
+// Furthest adjust_for_queue moves the rate, half a percent isn't audible as a pitch change
+const MAX_RATE_ADJUST: f64 = 0.005;
+// About 20Hz at 44.1kHz
+const HIGH_PASS_FACTOR: f32 = 0.997;
+
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    cycles_per_sample: f64,
+    // cycles_per_sample nudged by adjust_for_queue
+    period: f64,
+    // Cycles into the current sample, with the fraction carried over from the last one
+    phase: f64,
+    sums: [f32; CHANNELS],
+    count: u32,
+    mixer: MixerControls,
+    // Last input and output of the high-pass filter
+    last_mixed: f32,
+    last_filtered: f32,
+    samples: Vec<i16>,
+    // Last sample handed out by fill
+    last_played: i16,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32, mixer: MixerControls) -> Self {
+        let cycles_per_sample = CPU_FREQUENCY / sample_rate as f64;
+        Resampler {
+            cycles_per_sample,
+            period: cycles_per_sample,
+            phase: 0.0,
+            sums: [0.0; CHANNELS],
+            count: 0,
+            mixer,
+            last_mixed: 0.0,
+            last_filtered: 0.0,
+            samples: Vec::new(),
+            last_played: 0,
+        }
+    }
+
+    /// Adds one CPU cycle of channel outputs (0.0 to 1.0 each)
+    pub fn push(&mut self, outputs: [f32; CHANNELS]) {
+        for (sum, output) in self.sums.iter_mut().zip(outputs) {
+            *sum += output;
+        }
+        self.count += 1;
+        self.phase += 1.0;
+        if self.phase < self.period {
+            return;
+        }
+        let count = self.count as f32;
+        let mixed = self.mixer.mix(self.sums.map(|sum| sum / count));
+        let filtered = mixed - self.last_mixed + HIGH_PASS_FACTOR * self.last_filtered;
+        self.last_mixed = mixed;
+        self.last_filtered = filtered;
+        self.samples
+            .push((filtered.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+        self.sums = [0.0; CHANNELS];
+        self.count = 0;
+        self.phase -= self.period;
+    }
+
+    /// Samples waiting to be played
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<i16> {
+        mem::take(&mut self.samples)
+    }
+
+    /// Fills `out` with the oldest samples, keeping the rest for the next call. Short buffers are
+    /// padded by repeating the last sample, which doesn't pop like silence would.
+    pub fn fill(&mut self, out: &mut [i16]) {
+        let len = out.len().min(self.samples.len());
+        out[..len].copy_from_slice(&self.samples[..len]);
+        self.last_played = out[..len].last().copied().unwrap_or(self.last_played);
+        out[len..].fill(self.last_played);
+        self.samples.drain(..len);
+    }
+
+    /// Speeds the sample rate up or down slightly to keep `queued` samples near `target`, for
+    /// when emulation is paced by the display and the audio device's clock runs at its own rate
+    pub fn adjust_for_queue(&mut self, queued: usize, target: usize) {
+        let error = (target as f64 - queued as f64) / target.max(1) as f64;
+        let adjust = (error * MAX_RATE_ADJUST).clamp(-MAX_RATE_ADJUST, MAX_RATE_ADJUST);
+        // A nearly empty queue needs more samples, so fewer cycles per sample
+        self.period = self.cycles_per_sample / (1.0 + adjust);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_count() {
+        let mut resampler = Resampler::new(44100, MixerControls::new());
+        for _ in 0..CPU_FREQUENCY as usize {
+            resampler.push([0.5; CHANNELS]);
+        }
+        assert!(resampler.len().abs_diff(44100) <= 1);
+        // Constant output is filtered down to silence
+        assert_eq!(0, *resampler.take().last().unwrap());
+        assert!(resampler.is_empty());
+    }
+
+    #[test]
+    fn test_square_wave() {
+        let mut resampler = Resampler::new(44100, MixerControls::new());
+        // 440Hz, full volume on pulse 1
+        let half_period = (CPU_FREQUENCY / 880.0) as usize;
+        for cycle in 0..half_period * 20 {
+            let level = if (cycle / half_period).is_multiple_of(2) {
+                1.0
+            } else {
+                0.0
+            };
+            resampler.push([level, 0.0, 0.0, 0.0, 0.0]);
+        }
+        let samples = resampler.take();
+        let max = *samples.iter().max().unwrap();
+        let min = *samples.iter().min().unwrap();
+        // Pulse 1 is a fifth of the mix, centered on zero by the filter
+        assert!(max > i16::MAX / 16 && min < -i16::MAX / 16);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut resampler = Resampler::new(44100, MixerControls::new());
+        resampler.samples = vec![1, 2, 3];
+        let mut out = [0; 2];
+        resampler.fill(&mut out);
+        assert_eq!([1, 2], out);
+        let mut out = [0; 3];
+        resampler.fill(&mut out);
+        assert_eq!([3, 3, 3], out);
+    }
+
+    #[test]
+    fn test_adjust_for_queue() {
+        let mut resampler = Resampler::new(44100, MixerControls::new());
+        resampler.adjust_for_queue(0, 2048);
+        assert!(resampler.period < resampler.cycles_per_sample);
+        resampler.adjust_for_queue(1_000_000, 2048);
+        let slowest = resampler.cycles_per_sample / (1.0 - MAX_RATE_ADJUST);
+        assert!((resampler.period - slowest).abs() < 1e-9);
+        resampler.adjust_for_queue(2048, 2048);
+        assert_eq!(resampler.cycles_per_sample, resampler.period);
+    }
+}
@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::nes::{ActionNES, NES};
+
+const DEFAULT_YIELD_INTERVAL: usize = 1000;
+
+/// Wrapper for running the emulator inside an async application (e.g. tokio) without
+/// dedicating an OS thread, yields back to the scheduler every `yield_interval` instructions
+#[derive(Debug, Clone)]
+pub struct AsyncNes {
+    nes: ActionNES,
+    yield_interval: usize,
+}
+
+impl Default for AsyncNes {
+    fn default() -> Self {
+        Self::new(ActionNES::new())
+    }
+}
+
+impl AsyncNes {
+    pub fn new(nes: ActionNES) -> Self {
+        AsyncNes {
+            nes,
+            yield_interval: DEFAULT_YIELD_INTERVAL,
+        }
+    }
+
+    pub fn with_yield_interval(mut self, yield_interval: usize) -> Self {
+        self.yield_interval = yield_interval.max(1);
+        self
+    }
+
+    pub fn nes(&self) -> &ActionNES {
+        &self.nes
+    }
+
+    pub fn nes_mut(&mut self) -> &mut ActionNES {
+        &mut self.nes
+    }
+
+    pub fn into_inner(self) -> ActionNES {
+        self.nes
+    }
+
+    /// Runs until the next frame, same as NES::next_ppu_frame but yielding periodically
+    pub async fn run_frame(&mut self) -> Result<(), String> {
+        let mut count = 0;
+        loop {
+            let prev_scanline = self.nes.ppu_state.cur_scanline;
+            self.nes.next_cpu_instruction()?;
+            // Scanline wraps back to 0 at the end of a frame
+            if self.nes.ppu_state.cur_scanline < prev_scanline {
+                return Ok(());
+            }
+            count += 1;
+            if count % self.yield_interval == 0 {
+                YieldNow::default().await;
+            }
+        }
+    }
+}
+
+/// Future that returns Pending once, giving other tasks a chance to run
+#[derive(Default)]
+struct YieldNow {
+    is_yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_yielded {
+            return Poll::Ready(());
+        }
+        self.is_yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls `future` on this thread until it's done, for driving AsyncNes without an async runtime
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Polls the future to completion, returning the output and how many times it yielded
+    fn block_on_counting<F: Future>(future: F) -> (F::Output, usize) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        let mut yields = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    fn create_nes() -> ActionNES {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+
+    #[test]
+    fn test_run_frame_matches_next_ppu_frame() {
+        let mut expected = create_nes();
+        expected.next_ppu_frame().unwrap();
+        expected.next_ppu_frame().unwrap();
+
+        let mut nes = AsyncNes::new(create_nes()).with_yield_interval(100);
+        let (result, yields) = block_on_counting(async {
+            nes.run_frame().await?;
+            nes.run_frame().await
+        });
+        result.unwrap();
+        assert!(yields > 0);
+        assert_eq!(
+            expected.cpu_state.cycle_counter,
+            nes.nes().cpu_state.cycle_counter
+        );
+        assert_eq!(
+            expected.cpu_state.program_counter,
+            nes.nes().cpu_state.program_counter
+        );
+    }
+}
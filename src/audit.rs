@@ -0,0 +1,142 @@
+// Determinism audit, records everything a run touched that could make a replay diverge
+//
+// The core never reads the wall clock or a random number generator, so what's left is
+// state the emulator fills in with a fixed value where real hardware doesn't (power-on
+// RAM, open bus) and code outside the core that changes the console (vblank hooks).
+use std::collections::BTreeMap;
+use std::fmt;
+
+const RAM_SIZE: usize = 0x800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Nondeterminism {
+    // RAM read before anything was written to it, random at power on
+    UninitializedRam(u16),
+    // Read of a write-only register, real hardware returns stale bus contents
+    OpenBus(u16),
+    // A vblank hook ran and may have changed the controller
+    VblankHook,
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Nondeterminism::UninitializedRam(addr) => write!(f, "uninitialized RAM ${:04X}", addr),
+            Nondeterminism::OpenBus(addr) => write!(f, "open bus ${:04X}", addr),
+            Nondeterminism::VblankHook => write!(f, "vblank hook"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuditRecord {
+    pub count: usize,
+    // CPU cycle of the first occurrence
+    pub first_cycle: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeterminismAudit {
+    ram_written: Vec<bool>,
+    records: BTreeMap<Nondeterminism, AuditRecord>,
+}
+
+impl Default for DeterminismAudit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeterminismAudit {
+    pub fn new() -> Self {
+        DeterminismAudit {
+            ram_written: vec![false; RAM_SIZE],
+            records: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, source: Nondeterminism, cycle: usize) {
+        self.records
+            .entry(source)
+            .and_modify(|record| record.count += 1)
+            .or_insert(AuditRecord {
+                count: 1,
+                first_cycle: cycle,
+            });
+    }
+
+    /// Tracks a write to internal RAM, `index` is the unmirrored offset
+    pub fn ram_write(&mut self, index: usize) {
+        self.ram_written[index] = true;
+    }
+
+    /// Tracks a read of internal RAM, recording it if the byte was never written
+    pub fn ram_read(&mut self, index: usize, cycle: usize) {
+        if !self.ram_written[index] {
+            self.record(Nondeterminism::UninitializedRam(index as u16), cycle);
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = (&Nondeterminism, &AuditRecord)> {
+        self.records.iter()
+    }
+
+    pub fn report(&self) -> String {
+        if self.is_clean() {
+            return "Determinism audit: no nondeterministic inputs\n".to_string();
+        }
+        let mut report = format!(
+            "Determinism audit: {} nondeterministic inputs\n",
+            self.records.len()
+        );
+        for (source, record) in &self.records {
+            report.push_str(&format!(
+                "  {:24} x{:<8} first at CYC:{}\n",
+                source.to_string(),
+                record.count,
+                record.first_cycle
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uninitialized_ram() {
+        let mut audit = DeterminismAudit::new();
+        audit.ram_read(0x10, 7);
+        audit.ram_read(0x10, 9);
+        audit.ram_write(0x11);
+        audit.ram_read(0x11, 12);
+        let records: Vec<_> = audit.records().collect();
+        assert_eq!(
+            vec![(
+                &Nondeterminism::UninitializedRam(0x10),
+                &AuditRecord {
+                    count: 2,
+                    first_cycle: 7
+                }
+            )],
+            records
+        );
+        assert!(audit.report().contains("uninitialized RAM $0010"));
+    }
+
+    #[test]
+    fn test_clean_report() {
+        let audit = DeterminismAudit::new();
+        assert!(audit.is_clean());
+        assert_eq!(
+            "Determinism audit: no nondeterministic inputs\n",
+            audit.report()
+        );
+    }
+}
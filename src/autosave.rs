@@ -0,0 +1,120 @@
+// Savestates written automatically when the frontend exits or crashes, restored with --resume
+//
+// Autosaves are kept next to the ROM (`game.nes` -> `game.autosave`) in the savestate.rs
+// container, with a byte saying whether it was taken on exit or on a crash followed by the
+// snapshot bytes.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::battery::write_atomic;
+use crate::nes::ActionNES;
+use crate::rom::ROM;
+use crate::savestate;
+use crate::snapshot::{Snapshot, SnapshotBaseline};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutosaveKind {
+    Exit,
+    // Taken from the last good state before a panic
+    Crash,
+}
+
+impl AutosaveKind {
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(AutosaveKind::Exit),
+            1 => Ok(AutosaveKind::Crash),
+            _ => Err(format!("Unknown autosave kind {}", tag)),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            AutosaveKind::Exit => 0,
+            AutosaveKind::Crash => 1,
+        }
+    }
+}
+
+impl fmt::Display for AutosaveKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutosaveKind::Exit => write!(f, "exit"),
+            AutosaveKind::Crash => write!(f, "crash"),
+        }
+    }
+}
+
+pub fn autosave_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("autosave")
+}
+
+/// Writes `snapshot`, which must be taken against SnapshotBaseline::power_on
+pub fn write(
+    path: &Path,
+    rom: &ROM,
+    snapshot: &Snapshot,
+    kind: AutosaveKind,
+) -> Result<(), String> {
+    let mut state = vec![kind.tag()];
+    state.extend(snapshot.to_bytes());
+    write_atomic(
+        path,
+        &savestate::encode(rom, &snapshot.board_state(), &state),
+    )
+}
+
+/// Loads the autosave at `path` into `nes`, which should have the ROM it was taken with
+pub fn restore(path: &Path, nes: &mut ActionNES) -> Result<AutosaveKind, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let (mapper_data, state) = savestate::decode(&bytes, &nes.rom)?;
+    let (tag, snapshot) = state
+        .split_first()
+        .ok_or_else(|| "Autosave is empty".to_string())?;
+    let kind = AutosaveKind::from_tag(*tag)?;
+    let mut snapshot = Snapshot::from_bytes(snapshot, nes)?;
+    snapshot.load_board_state(&mapper_data)?;
+    snapshot.restore(nes, &SnapshotBaseline::power_on());
+    Ok(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::NES;
+
+    #[test]
+    fn test_autosave_round_trip() {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        nes.step_frames(3).unwrap();
+        let path = std::env::temp_dir().join("nes_autosave_round_trip.autosave");
+        let snapshot = Snapshot::capture(&nes, &SnapshotBaseline::power_on());
+        write(&path, &nes.rom, &snapshot, AutosaveKind::Crash).unwrap();
+
+        let mut resumed = ActionNES::new();
+        resumed.load_from_path("test_roms/nestest.nes").unwrap();
+        resumed.reset().unwrap();
+        assert_eq!(AutosaveKind::Crash, restore(&path, &mut resumed).unwrap());
+        assert_eq!(nes.cpu_state.ram, resumed.cpu_state.ram);
+        assert_eq!(
+            nes.cpu_state.program_counter,
+            resumed.cpu_state.program_counter
+        );
+
+        // Refused for a different game
+        let mut other = ActionNES::new();
+        other.rom.prg_rom = vec![0xEA; 0x4000];
+        assert!(restore(&path, &mut other).is_err());
+    }
+
+    #[test]
+    fn test_autosave_path() {
+        assert_eq!(
+            PathBuf::from("roms/mario.autosave"),
+            autosave_path("roms/mario.nes")
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// Battery save files (.sav), written back to disk at most once per interval
+//
+// Games write battery RAM constantly during play, so saves are only marked dirty when the data
+// changes and written once it has been dirty for the flush interval, or on flush. Files are
+// written to a temporary file and renamed over the old save, so a crash mid-write never leaves a
+// truncated save behind.
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `game.nes` -> `game.sav`
+pub fn sav_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("sav")
+}
+
+/// Writes `data` to `path` through a temporary file in the same directory
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let mut file = File::create(&temp_path).map_err(|e| e.to_string())?;
+    file.write_all(data).map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, path).map_err(|e| e.to_string())
+}
+
+#[derive(Debug)]
+pub struct BatterySave {
+    path: PathBuf,
+    flush_interval: Duration,
+    // What's on disk, to tell if the game changed anything
+    saved: Vec<u8>,
+    // When the data first differed from what's on disk
+    dirty_since: Option<Instant>,
+}
+
+impl BatterySave {
+    pub fn new(path: impl Into<PathBuf>, flush_interval: Duration) -> Self {
+        BatterySave {
+            path: path.into(),
+            flush_interval,
+            saved: Vec::new(),
+            dirty_since: None,
+        }
+    }
+
+    /// Reads the save file, None if there isn't one yet
+    pub fn load(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.saved = data.clone();
+                Ok(Some(data))
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("Failed to read {}: {}", self.path.display(), err)),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_since.is_some()
+    }
+
+    /// Call with the current battery RAM, e.g. once per frame. Writes the file once the data
+    /// has been dirty for the flush interval, returns true if it did.
+    pub fn update(&mut self, data: &[u8], now: Instant) -> Result<bool, String> {
+        if data == self.saved {
+            self.dirty_since = None;
+            return Ok(false);
+        }
+        let dirty_since = *self.dirty_since.get_or_insert(now);
+        if now.duration_since(dirty_since) < self.flush_interval {
+            return Ok(false);
+        }
+        self.flush(data)?;
+        Ok(true)
+    }
+
+    /// Writes `data` now if it differs from the file, e.g. on exit
+    pub fn flush(&mut self, data: &[u8]) -> Result<(), String> {
+        if data != self.saved {
+            write_atomic(&self.path, data)
+                .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+            self.saved = data.to_vec();
+        }
+        self.dirty_since = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sav_path() {
+        assert_eq!(PathBuf::from("roms/zelda.sav"), sav_path("roms/zelda.nes"));
+    }
+
+    #[test]
+    fn test_throttled_writes() {
+        let path = std::env::temp_dir().join("nes_battery_throttle.sav");
+        fs::remove_file(&path).ok();
+        let mut save = BatterySave::new(&path, Duration::from_secs(5));
+        assert_eq!(None, save.load().unwrap());
+
+        let start = Instant::now();
+        assert!(!save.update(&[1, 2], start).unwrap());
+        assert!(save.is_dirty());
+        // Still changing, but the first change is what starts the interval
+        assert!(!save
+            .update(&[1, 3], start + Duration::from_secs(4))
+            .unwrap());
+        assert!(!path.exists());
+        assert!(save
+            .update(&[1, 3], start + Duration::from_secs(5))
+            .unwrap());
+        assert!(!save.is_dirty());
+        assert_eq!(vec![1, 3], fs::read(&path).unwrap());
+
+        // Unchanged data is never rewritten
+        assert!(!save
+            .update(&[1, 3], start + Duration::from_secs(60))
+            .unwrap());
+        save.update(&[4, 4], start + Duration::from_secs(61))
+            .unwrap();
+        save.flush(&[4, 4]).unwrap();
+        assert_eq!(vec![4, 4], fs::read(&path).unwrap());
+
+        let mut reloaded = BatterySave::new(&path, DEFAULT_FLUSH_INTERVAL);
+        assert_eq!(Some(vec![4, 4]), reloaded.load().unwrap());
+        assert!(!reloaded.update(&[4, 4], start).unwrap());
+    }
+}
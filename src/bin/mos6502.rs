@@ -0,0 +1,79 @@
+// Runs a raw 6502 program on the CPU core with 64KB of plain RAM, stopping at the first BRK
+//
+//     mos6502 program.bin [--load-addr 0x0600] [--start 0x0600] [--trace] [--max 1000000]
+use std::env;
+use std::fs;
+
+use rust_nes_emulator::cpu::FlatCpu;
+use rust_nes_emulator::history::HistoryEntry;
+
+const USAGE: &str =
+    "Usage: mos6502 program.bin [--load-addr 0x0600] [--start addr] [--trace] [--max count]";
+const DEFAULT_LOAD_ADDR: u16 = 0x0600;
+const DEFAULT_MAX_INSTRUCTIONS: usize = 1_000_000;
+
+fn parse_addr(text: Option<&String>) -> Result<u16, String> {
+    let text = text.ok_or_else(|| USAGE.to_string())?;
+    let parsed = match text.strip_prefix("0x").or(text.strip_prefix('$')) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid address {}", text))
+}
+
+fn main() {
+    if let Err(err) = run(env::args().skip(1).collect()) {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut path = None;
+    let mut load_addr = DEFAULT_LOAD_ADDR;
+    let mut start = None;
+    let mut trace = false;
+    let mut max_instructions = DEFAULT_MAX_INSTRUCTIONS;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load-addr" => load_addr = parse_addr(args.next())?,
+            "--start" => start = Some(parse_addr(args.next())?),
+            "--trace" => trace = true,
+            "--max" => {
+                max_instructions = args
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or_else(|| USAGE.to_string())?
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or_else(|| USAGE.to_string())?;
+    let program = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let mut cpu = FlatCpu::new();
+    cpu.load(&program, load_addr)?;
+    if let Some(start) = start {
+        cpu.cpu_state.program_counter = start;
+    }
+    let mut count = 0;
+    while !cpu.is_at_brk() {
+        if count == max_instructions {
+            return Err(format!("No BRK after {} instructions", max_instructions));
+        }
+        let mut entry = HistoryEntry::new(&cpu.cpu_state);
+        let instruction = cpu.step()?;
+        if trace {
+            entry.raw_opcode = Some(instruction.meta.raw_opcode);
+            println!("{}  {:?}", entry, instruction.opcode);
+        }
+        count += 1;
+    }
+    println!(
+        "BRK at {:04X} after {} instructions",
+        cpu.cpu_state.program_counter, count
+    );
+    println!("{}", HistoryEntry::new(&cpu.cpu_state));
+    Ok(())
+}
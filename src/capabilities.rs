@@ -0,0 +1,75 @@
+// What this build of the emulator can run, for launchers to filter game lists with
+//
+// Everything here is fixed at compile time, capabilities() can be called without loading a ROM.
+use crate::region::Region;
+use crate::rom::ROM;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    // iNES mapper numbers of the boards in rom::mapper
+    pub mappers: &'static [u8],
+    // Devices for the controller ports, named like in the game database
+    pub peripherals: &'static [&'static str],
+    // Regions that are detected, they all run with NTSC timing for now
+    pub regions: &'static [Region],
+    // Snapshot::to_bytes and savestate::encode
+    pub save_states: bool,
+    pub rewind: bool,
+    // Pulse, triangle and noise output, DMC samples aren't played yet
+    pub audio: bool,
+    // Compiled in features
+    pub sdl_frontend: bool,
+    pub libretro: bool,
+}
+
+/// Capabilities of this build
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        mappers: &[0, 1, 2, 4, 16, 88, 206],
+        peripherals: &["joypad", "paddle", "mouse"],
+        regions: &[Region::Ntsc, Region::Pal, Region::Dendy],
+        save_states: true,
+        rewind: true,
+        audio: true,
+        sdl_frontend: cfg!(all(feature = "sdl", not(feature = "minimal"))),
+        libretro: cfg!(feature = "libretro"),
+    }
+}
+
+impl Capabilities {
+    pub fn supports_mapper(&self, mapper: u8) -> bool {
+        self.mappers.contains(&mapper)
+    }
+
+    /// Err with the reason `rom` won't run, before trying to load it
+    pub fn check_rom(&self, rom: &ROM) -> Result<(), String> {
+        if !self.supports_mapper(rom.mapper) {
+            return Err(format!("Mapper {} isn't supported", rom.mapper));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_check_rom() {
+        let caps = capabilities();
+        let mut rom = ROM::new();
+        rom.chr_rom = vec![0; 0x2000];
+        assert_eq!(Ok(()), caps.check_rom(&rom));
+        rom.mapper = 5;
+        assert_eq!(
+            Err("Mapper 5 isn't supported".to_string()),
+            caps.check_rom(&rom)
+        );
+        rom.mapper = 1;
+        rom.chr_ram = true;
+        assert_eq!(Ok(()), caps.check_rom(&rom));
+        rom.mirroring = Mirroring::FourScreen;
+        assert_eq!(Ok(()), caps.check_rom(&rom));
+    }
+}
@@ -0,0 +1,121 @@
+// Address space interface shared by the CPU and PPU buses, so tools like hexdump work on either
+
+/// A 16-bit address space, multi-byte values are little endian
+pub trait Memory {
+    /// Reads a byte, may have side effects (e.g. clearing vblank on a $2002 read)
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Reads a byte with no side effects
+    fn peek(&self, addr: u16) -> u8;
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lsb = self.read(addr) as u16;
+        let msb = self.read(addr.wrapping_add(1)) as u16;
+        (msb << 8) | lsb
+    }
+
+    fn peek_u16(&self, addr: u16) -> u16 {
+        let lsb = self.peek(addr) as u16;
+        let msb = self.peek(addr.wrapping_add(1)) as u16;
+        (msb << 8) | lsb
+    }
+
+    /// Peeks `length` bytes starting at `start`, wrapping around at $FFFF
+    fn peek_range(&self, start: u16, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|offset| self.peek(start.wrapping_add(offset as u16)))
+            .collect()
+    }
+}
+
+/// Plain 64KB of RAM with no mirroring or registers, for running bare 6502 code and tests
+#[derive(Debug, Clone)]
+pub struct FlatMemory {
+    pub data: Vec<u8>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            data: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Memory for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.data[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+/// CRC-32 (IEEE), as used by zip and PNG
+pub fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Formats `length` bytes from `start` as rows of 16, e.g. "0010: 00 01 ..."
+pub fn hexdump(memory: &impl Memory, start: u16, length: usize) -> String {
+    let bytes = memory.peek_range(start, length);
+    let mut dump = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        let addr = start.wrapping_add(16 * row as u16);
+        dump.push_str(&format!("{:04X}: {}\n", addr, hex.join(" ")));
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u16_little_endian() {
+        let mut memory = FlatMemory::new();
+        memory.write(0x10, 0x34);
+        memory.write(0x11, 0x12);
+        assert_eq!(0x1234, memory.read_u16(0x10));
+        assert_eq!(0x1234, memory.peek_u16(0x10));
+        // Wraps around the end of the address space
+        memory.write(0xFFFF, 0xCD);
+        memory.write(0x0000, 0xAB);
+        assert_eq!(0xABCD, memory.peek_u16(0xFFFF));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let mut memory = FlatMemory::new();
+        for addr in 0x20..0x32 {
+            memory.write(addr, addr as u8);
+        }
+        let dump = hexdump(&memory, 0x20, 18);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("0020: 20 21 22"));
+        assert_eq!("0030: 30 31", lines[1]);
+    }
+}
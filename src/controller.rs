@@ -2,7 +2,7 @@ use bitflags::bitflags;
 
 bitflags! {
     // https://www.nesdev.org/wiki/Standard_controller
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct ControllerState: u8 {
         const A        = 0b00000001;
         const B        = 0b00000010;
@@ -17,8 +17,9 @@ bitflags! {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Controller {
-    strobe: bool,
-    cur_flag: u8,
+    pub(crate) strobe: bool,
+    // Button the next read returns, 0 once all 8 have been read
+    pub(crate) cur_flag: u8,
     pub controller_state: ControllerState,
 }
 
@@ -1,64 +1,118 @@
-use crate::{controller::Controller, ppu::PpuState, rom::ROM};
+use crate::{
+    apu::ApuState,
+    audit::DeterminismAudit,
+    common::FlatMemory,
+    controller::Controller,
+    peripheral::PortDevice,
+    ppu::{PpuAction, PpuState},
+    profiler::MemoryProfile,
+    rom::ROM,
+};
 
 use super::instructions::decode_opcode;
 use super::{
-    instructions::{AddressingMode, InstructionMetaData, Opcode, Param},
-    interrupt::{Interrupt, NMI_INTERRUPT},
+    instructions::{AddressingMode, InstructionMetaData, Opcode, OpcodeInfo, Param},
+    interrupt::{Interrupt, BRK_INTERRUPT, IRQ_INTERRUPT, NMI_INTERRUPT},
     CpuBus, CpuState, CpuStatus, Instruction,
 };
 
+// ORed into A by the unstable XAA and LAX #i, it varies between chips and with temperature
+// Ref: https://www.nesdev.org/wiki/Visual6502wiki/6502_Opcode_8B_(XAA,_ANE)
+const UNSTABLE_CONSTANT: u8 = 0xEE;
+
 pub struct CpuAction<'a, 'b, 'c, 'd> {
     cpu_state: &'a mut CpuState,
     ppu_state: &'b mut PpuState,
+    apu_state: &'b mut ApuState,
     controller: &'c mut Controller,
-    rom: &'d ROM,
+    port_2: &'c mut PortDevice,
+    rom: &'d mut ROM,
+    audit: Option<&'c mut DeterminismAudit>,
+    profile: Option<&'c mut MemoryProfile>,
+    flat_memory: Option<&'c mut FlatMemory>,
 }
 
 impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
     pub fn new(
         cpu_state: &'a mut CpuState,
         ppu_state: &'b mut PpuState,
+        apu_state: &'b mut ApuState,
         controller: &'c mut Controller,
-        rom: &'d ROM,
+        port_2: &'c mut PortDevice,
+        rom: &'d mut ROM,
     ) -> Self {
         CpuAction {
             cpu_state,
             ppu_state,
+            apu_state,
             controller,
+            port_2,
             rom,
+            audit: None,
+            profile: None,
+            flat_memory: None,
         }
     }
 
+    /// Audits every bus access made by the instructions, see CpuBus::with_audit
+    pub fn with_audit(mut self, audit: Option<&'c mut DeterminismAudit>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Profiles every bus access made by the instructions, see CpuBus::with_profile
+    pub fn with_profile(mut self, profile: Option<&'c mut MemoryProfile>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Runs against plain RAM instead of the NES memory map, see CpuBus::with_flat_memory
+    pub fn with_flat_memory(mut self, flat_memory: Option<&'c mut FlatMemory>) -> Self {
+        self.flat_memory = flat_memory;
+        self
+    }
+
     pub fn next_cpu_instruction(&mut self) -> Result<Instruction, String> {
         // ! TODO: eventually, I want this to follow a pipelining pattern (fetch, decode, execute, mem, wb) or something similar
-        // 1. Check for interrupt
-        if let Some(()) = self.ppu_state.nmi_interrupt_poll.take() {
+        // 1. Check for interrupts, skipping an NMI that was already taken over by a BRK
+        let nmi_hijacked = std::mem::take(&mut self.cpu_state.nmi_hijacked);
+        let irq_polled = self.cpu_state.irq_interrupt_poll.take().is_some();
+        let nmi = self.ppu_state.nmi_interrupt_poll.take();
+        if nmi.is_some() && !nmi_hijacked {
+            self.ppu_state.timing.nmi_scanline = Some(self.ppu_state.cur_scanline);
             self.execute_interrupt(NMI_INTERRUPT);
+        } else if irq_polled && self.is_irq_asserted() {
+            // Level triggered, an IRQ that was acknowledged since the poll isn't taken
+            self.execute_interrupt(IRQ_INTERRUPT);
         }
+        let status = self.cpu_state.status;
 
-        // 2. Read opcode and decode it to an instruction, always takes 1 cycle
-        let start_pc = self.cpu_state.program_counter;
-        let raw_opcode = self.as_bus().read_byte_from_pc();
-        let (opcode, mode, base_cycles) = decode_opcode(raw_opcode)?;
-
-        // 3. Read some number of bytes depending on what the addressing mode is and decode the instruction parameter, may take many cycles
-        // Ref: http://www.6502.org/tutorials/6502opcodes.html
-        let param = self.read_arg(&mode);
-        let end_pc = self.cpu_state.program_counter;
-        let length = end_pc - start_pc;
+        // 2-3. Decode the instruction and its parameter
+        let (raw_opcode, info, param) = self.parse_instruction()?;
+        let (opcode, mode) = (info.opcode, info.mode);
 
         // 4. Execute the instruction
         self.execute_instruction(&opcode, param)?;
 
+        // The IRQ line is polled before the last cycle, so CLI, SEI and PLP change the I flag
+        // too late for it and an IRQ lands an instruction after CLI. RTI restores it earlier,
+        // and BRK's interrupt sequence doesn't poll.
+        let status = match opcode {
+            Opcode::RTI | Opcode::BRK => self.cpu_state.status,
+            _ => status,
+        };
+        self.cpu_state.irq_interrupt_poll =
+            (!status.contains(CpuStatus::INT_DISABLE)).then_some(());
+
         // 5. Update cycles
-        let cycles = base_cycles + self.compute_extra_cycles(&opcode, &mode);
+        let cycles = info.cycles + self.compute_extra_cycles(info);
         self.increment_cycle_counters(cycles);
 
         let meta = InstructionMetaData {
             cycles,
             mode,
             raw_opcode,
-            length,
+            length: info.size as u16,
         };
         let instruction = Instruction {
             opcode,
@@ -67,22 +121,52 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         };
         Ok(instruction)
     }
+
+    /// Reads the instruction at PC without executing it, leaving PC after its last byte.
+    /// Returns the raw opcode with what it decodes to.
+    pub fn parse_instruction(&mut self) -> Result<(u8, &'static OpcodeInfo, Param), String> {
+        // 2. Read opcode and decode it to an instruction, always takes 1 cycle
+        self.ppu_state.chr_writes.pc = self.cpu_state.program_counter;
+        let raw_opcode = self.as_bus().read_byte_from_pc();
+        let info = decode_opcode(raw_opcode)?;
+
+        // 3. Read some number of bytes depending on what the addressing mode is and decode the instruction parameter, may take many cycles
+        // Ref: http://www.6502.org/tutorials/6502opcodes.html
+        let param = self.read_arg(&info.mode);
+        Ok((raw_opcode, info, param))
+    }
 }
 
 impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
-    fn as_bus(&mut self) -> CpuBus {
+    fn as_bus(&mut self) -> CpuBus<'_, '_, '_, '_> {
         let Self {
             cpu_state,
             ppu_state,
+            apu_state,
             controller,
+            port_2,
             rom,
+            audit,
+            profile,
+            flat_memory,
         } = self;
-        CpuBus::new(cpu_state, ppu_state, controller, rom)
+        CpuBus::new(cpu_state, ppu_state, apu_state, controller, port_2, rom)
+            .with_audit(audit.as_deref_mut())
+            .with_profile(profile.as_deref_mut())
+            .with_flat_memory(flat_memory.as_deref_mut())
     }
 
     fn increment_cycle_counters(&mut self, cycles: u8) {
         self.cpu_state.cycle_counter += cycles as usize;
         self.ppu_state.cycle_counter += 3 * cycles as usize;
+        let scanline = self.ppu_state.cur_scanline;
+        self.ppu_state.timing.add_cpu_cycles(scanline, cycles);
+        self.rom.board.tick_cpu_cycles(cycles as usize);
+    }
+
+    // The cartridge and the APU hold the IRQ line low until the game acknowledges them
+    fn is_irq_asserted(&self) -> bool {
+        self.rom.board.is_irq_pending() || self.apu_state.is_irq_pending()
     }
 
     fn push_to_stack(&mut self, value: u8) {
@@ -100,6 +184,25 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         self.as_bus().read_byte(stack_addr)
     }
 
+    // Read-modify-write instructions write the unmodified value back before the result, which
+    // registers like $2007 see as two writes
+    fn write_modified(&mut self, address: u16, original: u8, result: u8) {
+        let rmw_double_writes = self.cpu_state.rmw_double_writes;
+        let mut bus = self.as_bus();
+        if rmw_double_writes {
+            bus.write_byte(address, original);
+        }
+        bus.write_byte(address, result);
+    }
+
+    // Indexing that crosses a page first reads from the address before the high byte is fixed
+    fn dummy_read_on_page_cross(&mut self, orig_addr: u16, mem_addr: u16) {
+        if self.cpu_state.dummy_reads && self.cpu_state.page_cross_flag {
+            let dummy_addr = (orig_addr & 0xFF00) | (mem_addr & 0x00FF);
+            self.as_bus().dummy_read(dummy_addr);
+        }
+    }
+
     fn set_zero_flag(&mut self, result: u8) {
         if result == 0 {
             self.cpu_state.status.insert(CpuStatus::ZERO);
@@ -136,44 +239,19 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         self.push_to_stack(lsb);
         self.push_to_stack(status.bits());
 
-        // Set INT_DISABLE flag depending on interrupt type
-        self.cpu_state
-            .status
-            .set(CpuStatus::INT_DISABLE, interrupt.is_hardware_interrupt);
+        // Every interrupt sequence sets the INT_DISABLE flag
+        self.cpu_state.status.insert(CpuStatus::INT_DISABLE);
         self.cpu_state.program_counter = self.as_bus().read_two_bytes(interrupt.vector);
     }
 
-    fn compute_extra_cycles(&self, opcode: &Opcode, addressing_mode: &AddressingMode) -> u8 {
-        match (opcode, addressing_mode) {
-            (
-                Opcode::ADC
-                | Opcode::AND
-                | Opcode::CMP
-                | Opcode::EOR
-                | Opcode::LDA
-                | Opcode::LDX
-                | Opcode::LDY
-                | Opcode::ORA
-                | Opcode::SBC,
-                AddressingMode::AbsoluteIndexX
-                | AddressingMode::AbsoluteIndexY
-                | AddressingMode::IndirectY,
-            ) => self.cpu_state.page_cross_flag as u8,
-            (
-                Opcode::BPL
-                | Opcode::BMI
-                | Opcode::BVC
-                | Opcode::BVS
-                | Opcode::BCC
-                | Opcode::BCS
-                | Opcode::BNE
-                | Opcode::BEQ,
-                _,
-            ) => {
+    fn compute_extra_cycles(&self, info: &OpcodeInfo) -> u8 {
+        match info.mode {
+            // Branches take one more cycle when taken, and another when that crosses a page
+            AddressingMode::Relative => {
                 (self.cpu_state.branch_flag as u8)
                     + ((self.cpu_state.branch_flag & self.cpu_state.page_cross_flag) as u8)
             }
-            _ => 0,
+            _ => (info.page_cross_penalty && self.cpu_state.page_cross_flag) as u8,
         }
     }
 }
@@ -185,8 +263,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
     /// ! Has side effects from page cross and maybe reading using the bus?
     // TODO: want to return (Param, &[u8]) at some point
     fn read_arg(&mut self, mode: &AddressingMode) -> Param {
-        // TODO?: I had to create bus in a couple weird places to get this to work, revisit to see if there's a better way to do this
-        let mut bus = CpuBus::new(self.cpu_state, self.ppu_state, self.controller, self.rom);
+        let mut bus = self.as_bus();
         match mode {
             AddressingMode::Implicit => Param::None,
             AddressingMode::Accumulator => Param::Value(self.cpu_state.reg_a),
@@ -247,6 +324,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 let mem_addr = orig_addr.wrapping_add(self.cpu_state.reg_x as u16);
                 let msb = (mem_addr >> 8) as u8;
                 self.cpu_state.page_cross_flag = orig_msb != msb;
+                self.dummy_read_on_page_cross(orig_addr, mem_addr);
                 Param::Address(mem_addr)
             }
             AddressingMode::AbsoluteIndexY => {
@@ -256,14 +334,14 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 let mem_addr = orig_addr.wrapping_add(self.cpu_state.reg_y as u16);
                 let msb = (mem_addr >> 8) as u8;
                 self.cpu_state.page_cross_flag = orig_msb != msb;
+                self.dummy_read_on_page_cross(orig_addr, mem_addr);
                 Param::Address(mem_addr)
             }
             AddressingMode::IndirectX => {
                 // Form <instruction (<addr>, X), where <addr> is u8
                 let base = bus.read_byte_from_pc();
                 let zero_page_addr = (base.wrapping_add(self.cpu_state.reg_x)) as u16;
-                let mut bus =
-                    CpuBus::new(self.cpu_state, self.ppu_state, self.controller, self.rom);
+                let mut bus = self.as_bus();
                 // TODO: may need to re-evaluate how this is done when there's a page cross
                 let mem_addr = bus.read_two_page_bytes(zero_page_addr);
                 Param::Address(mem_addr)
@@ -276,6 +354,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 let mem_addr = orig_addr.wrapping_add(self.cpu_state.reg_y as u16);
                 let msb = (mem_addr >> 8) as u8;
                 self.cpu_state.page_cross_flag = orig_msb != msb;
+                self.dummy_read_on_page_cross(orig_addr, mem_addr);
                 Param::Address(mem_addr)
             }
         }
@@ -302,7 +381,9 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 self.and(byte)
             }
             (Opcode::ASL, Param::Value(val)) => self.asl_acc(val),
-            (Opcode::ASL, Param::Address(mem_addr)) => self.asl(mem_addr),
+            (Opcode::ASL, Param::Address(mem_addr)) => {
+                self.asl(mem_addr);
+            }
             (Opcode::BIT, Param::Value(val)) => self.bit(val),
             (Opcode::BIT, Param::Address(mem_addr)) => {
                 let byte = self.as_bus().read_byte(mem_addr);
@@ -317,9 +398,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
             (Opcode::BCS, Param::Value(val)) => self.bcs(val),
             (Opcode::BNE, Param::Value(val)) => self.bne(val),
             (Opcode::BEQ, Param::Value(val)) => self.beq(val),
-            (Opcode::BRK, Param::None) => {
-                self.brk() // TODO: remove this, should be an interrupt type
-            }
+            (Opcode::BRK, Param::None) => self.brk(),
             // COMPARISON
             (Opcode::CMP, Param::Value(val)) => self.cmp(val),
             (Opcode::CMP, Param::Address(mem_addr)) => {
@@ -336,7 +415,9 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 let byte = self.as_bus().read_byte(mem_addr);
                 self.cpy(byte)
             }
-            (Opcode::DEC, Param::Address(mem_addr)) => self.dec(mem_addr),
+            (Opcode::DEC, Param::Address(mem_addr)) => {
+                self.dec(mem_addr);
+            }
             (Opcode::EOR, Param::Value(val)) => self.eor(val),
             (Opcode::EOR, Param::Address(mem_addr)) => {
                 let byte = self.as_bus().read_byte(mem_addr);
@@ -349,7 +430,9 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
             (Opcode::CLV, Param::None) => self.clv(),
             (Opcode::CLD, Param::None) => self.cld(),
             (Opcode::SED, Param::None) => self.sed(),
-            (Opcode::INC, Param::Address(mem_addr)) => self.inc(mem_addr),
+            (Opcode::INC, Param::Address(mem_addr)) => {
+                self.inc(mem_addr);
+            }
             (Opcode::JMP, Param::Address(mem_addr)) => self.jmp(mem_addr),
             (Opcode::JSR, Param::Address(mem_addr)) => self.jsr(mem_addr),
             (Opcode::LDA, Param::Value(val)) => self.lda(val),
@@ -368,9 +451,13 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
                 self.ldy(byte)
             }
             (Opcode::LSR, Param::Value(val)) => self.lsr_acc(val),
-            (Opcode::LSR, Param::Address(mem_addr)) => self.lsr(mem_addr),
-            (Opcode::NOP, Param::None) => {
-                // TODO: implement this?
+            (Opcode::LSR, Param::Address(mem_addr)) => {
+                self.lsr(mem_addr);
+            }
+            (Opcode::NOP, Param::None | Param::Value(_)) => {}
+            (Opcode::NOP, Param::Address(mem_addr)) => {
+                // Unofficial NOPs still read their operand, which registers like $2002 notice
+                self.as_bus().read_byte(mem_addr);
             }
             (Opcode::ORA, Param::Value(val)) => self.ora(val),
             (Opcode::ORA, Param::Address(mem_addr)) => {
@@ -387,9 +474,13 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
             (Opcode::DEY, Param::None) => self.dey(),
             (Opcode::INY, Param::None) => self.iny(),
             (Opcode::ROL, Param::Value(val)) => self.rol_acc(val),
-            (Opcode::ROL, Param::Address(mem_addr)) => self.rol(mem_addr),
+            (Opcode::ROL, Param::Address(mem_addr)) => {
+                self.rol(mem_addr);
+            }
             (Opcode::ROR, Param::Value(val)) => self.ror_acc(val),
-            (Opcode::ROR, Param::Address(mem_addr)) => self.ror(mem_addr),
+            (Opcode::ROR, Param::Address(mem_addr)) => {
+                self.ror(mem_addr);
+            }
             (Opcode::RTI, Param::None) => self.rti(),
             (Opcode::RTS, Param::None) => self.rts(),
             (Opcode::SBC, Param::Value(val)) => self.sbc(val),
@@ -407,6 +498,50 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
             (Opcode::STA, Param::Address(mem_addr)) => self.sta(mem_addr),
             (Opcode::STX, Param::Address(mem_addr)) => self.stx(mem_addr),
             (Opcode::STY, Param::Address(mem_addr)) => self.sty(mem_addr),
+            // UNOFFICIAL INSTRUCTIONS
+            (Opcode::LAX, Param::Value(val)) => self.lxa(val),
+            (Opcode::LAX, Param::Address(mem_addr)) => {
+                let byte = self.as_bus().read_byte(mem_addr);
+                self.lax(byte)
+            }
+            (Opcode::SAX, Param::Address(mem_addr)) => self.sax(mem_addr),
+            (Opcode::DCP, Param::Address(mem_addr)) => {
+                let byte = self.dec(mem_addr);
+                self.cmp(byte)
+            }
+            (Opcode::ISB, Param::Address(mem_addr)) => {
+                let byte = self.inc(mem_addr);
+                self.sbc(byte)
+            }
+            (Opcode::SLO, Param::Address(mem_addr)) => {
+                let byte = self.asl(mem_addr);
+                self.ora(byte)
+            }
+            (Opcode::RLA, Param::Address(mem_addr)) => {
+                let byte = self.rol(mem_addr);
+                self.and(byte)
+            }
+            (Opcode::SRE, Param::Address(mem_addr)) => {
+                let byte = self.lsr(mem_addr);
+                self.eor(byte)
+            }
+            (Opcode::RRA, Param::Address(mem_addr)) => {
+                let byte = self.ror(mem_addr);
+                self.adc(byte)
+            }
+            (Opcode::ANC, Param::Value(val)) => self.anc(val),
+            (Opcode::ALR, Param::Value(val)) => self.alr(val),
+            (Opcode::ARR, Param::Value(val)) => self.arr(val),
+            (Opcode::AXS, Param::Value(val)) => self.axs(val),
+            (Opcode::XAA, Param::Value(val)) => self.xaa(val),
+            (Opcode::LAS, Param::Address(mem_addr)) => {
+                let byte = self.as_bus().read_byte(mem_addr);
+                self.las(byte)
+            }
+            (Opcode::AHX, Param::Address(mem_addr)) => self.ahx(mem_addr),
+            (Opcode::SHX, Param::Address(mem_addr)) => self.shx(mem_addr),
+            (Opcode::SHY, Param::Address(mem_addr)) => self.shy(mem_addr),
+            (Opcode::TAS, Param::Address(mem_addr)) => self.tas(mem_addr),
             _ => return Err(String::from("Invalid")),
         };
         Ok(())
@@ -463,15 +598,16 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         self.set_carry_flag(result);
     }
 
-    fn asl(&mut self, address: u16) {
+    fn asl(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z C
         let parameter = self.as_bus().read_byte(address);
         let result = (parameter as u16) << 1;
-        self.as_bus().write_byte(address, result as u8);
+        self.write_modified(address, parameter, result as u8);
 
         self.set_negative_flag(result as u8);
         self.set_zero_flag(result as u8);
         self.set_carry_flag(result);
+        result as u8
     }
 
     fn bit(&mut self, parameter: u8) {
@@ -591,9 +727,20 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
     }
 
     fn brk(&mut self) {
-        // BRK causes a non-maskable interrupt and increments the program counter by one TODO figure out what this means
-        // Affects Flags: B
-        self.cpu_state.status.insert(CpuStatus::BRK);
+        // Affects Flags: I
+        // BRK skips the padding byte after the opcode, so the return address is PC + 2
+        self.cpu_state.program_counter = self.cpu_state.program_counter.wrapping_add(1);
+
+        // Interrupt hijacking: if an NMI is asserted before the vector fetch (first 4 cycles of BRK),
+        // the NMI vector is used instead, but the pushed status still has B set
+        // https://www.nesdev.org/wiki/CPU_interrupts#Interrupt_hijacking
+        // An IRQ hijacking BRK is indistinguishable since both use the same vector
+        let mut interrupt = BRK_INTERRUPT;
+        if PpuAction::new(self.ppu_state, self.rom).is_nmi_within(4) {
+            interrupt.vector = NMI_INTERRUPT.vector;
+            self.cpu_state.nmi_hijacked = true;
+        }
+        self.execute_interrupt(interrupt);
     }
 
     fn cmp(&mut self, parameter: u8) {
@@ -638,13 +785,15 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         }
     }
 
-    fn dec(&mut self, address: u16) {
+    fn dec(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z
-        let result = self.as_bus().read_byte(address).wrapping_sub(1);
-        self.as_bus().write_byte(address, result);
+        let parameter = self.as_bus().read_byte(address);
+        let result = parameter.wrapping_sub(1);
+        self.write_modified(address, parameter, result);
 
         self.set_negative_flag(result);
         self.set_zero_flag(result);
+        result
     }
 
     fn eor(&mut self, parameter: u8) {
@@ -691,13 +840,15 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         self.cpu_state.status.insert(CpuStatus::DECIMAL);
     }
 
-    fn inc(&mut self, address: u16) {
+    fn inc(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z
-        let result = self.as_bus().read_byte(address).wrapping_add(1);
-        self.as_bus().write_byte(address, result);
+        let parameter = self.as_bus().read_byte(address);
+        let result = parameter.wrapping_add(1);
+        self.write_modified(address, parameter, result);
 
         self.set_negative_flag(result);
         self.set_zero_flag(result);
+        result
     }
 
     fn jmp(&mut self, address: u16) {
@@ -756,12 +907,12 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         }
     }
 
-    fn lsr(&mut self, address: u16) {
+    fn lsr(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z C
         // I think this writes to reg_a? Not sure
         let parameter = self.as_bus().read_byte(address);
         let result = parameter >> 1;
-        self.as_bus().write_byte(address, result);
+        self.write_modified(address, parameter, result);
 
         self.set_negative_flag(result);
         self.set_zero_flag(result);
@@ -771,6 +922,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         } else {
             self.cpu_state.status.remove(CpuStatus::CARRY);
         }
+        result
     }
 
     fn ora(&mut self, parameter: u8) {
@@ -858,18 +1010,19 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         self.set_carry_flag(result);
     }
 
-    fn rol(&mut self, address: u16) {
+    fn rol(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z C
         let parameter = self.as_bus().read_byte(address);
         let mut result = (parameter as u16) << 1;
         if self.cpu_state.status.contains(CpuStatus::CARRY) {
             result += 1; // this should be safe from overflow
         }
-        self.as_bus().write_byte(address, result as u8);
+        self.write_modified(address, parameter, result as u8);
 
         self.set_negative_flag(result as u8);
         self.set_zero_flag(result as u8);
         self.set_carry_flag(result);
+        result as u8
     }
 
     fn ror_acc(&mut self, parameter: u8) {
@@ -890,14 +1043,14 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         }
     }
 
-    fn ror(&mut self, address: u16) {
+    fn ror(&mut self, address: u16) -> u8 {
         // Affects Flags: N Z C
         let parameter = self.as_bus().read_byte(address);
         let mut result = parameter >> 1;
         if self.cpu_state.status.contains(CpuStatus::CARRY) {
             result += 0b1000_0000;
         }
-        self.as_bus().write_byte(address, result);
+        self.write_modified(address, parameter, result);
 
         self.set_negative_flag(result);
         self.set_zero_flag(result);
@@ -907,6 +1060,7 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         } else {
             self.cpu_state.status.remove(CpuStatus::CARRY);
         }
+        result
     }
 
     fn rti(&mut self) {
@@ -991,4 +1145,115 @@ impl<'a, 'b, 'c, 'd> CpuAction<'a, 'b, 'c, 'd> {
         let value = self.cpu_state.reg_y;
         self.as_bus().write_byte(address, value);
     }
+
+    // Unofficial instructions
+    // Ref: https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+    fn lax(&mut self, parameter: u8) {
+        // Affects Flags: N Z
+        self.lda(parameter);
+        self.cpu_state.reg_x = parameter;
+    }
+
+    fn lxa(&mut self, parameter: u8) {
+        // Affects Flags: N Z
+        // LAX #i, unstable
+        self.lax((self.cpu_state.reg_a | UNSTABLE_CONSTANT) & parameter);
+    }
+
+    fn sax(&mut self, address: u16) {
+        // Affected Flags: None
+        let value = self.cpu_state.reg_a & self.cpu_state.reg_x;
+        self.as_bus().write_byte(address, value);
+    }
+
+    fn anc(&mut self, parameter: u8) {
+        // Affects Flags: N Z C
+        self.and(parameter);
+        // Carry is bit 7 of the result, like after an ASL
+        let is_negative = self.cpu_state.status.contains(CpuStatus::NEGATIVE);
+        self.cpu_state.status.set(CpuStatus::CARRY, is_negative);
+    }
+
+    fn alr(&mut self, parameter: u8) {
+        // Affects Flags: N Z C
+        self.and(parameter);
+        self.lsr_acc(self.cpu_state.reg_a);
+    }
+
+    fn arr(&mut self, parameter: u8) {
+        // Affects Flags: N V Z C
+        self.and(parameter);
+        self.ror_acc(self.cpu_state.reg_a);
+        // Carry and overflow come from bits 6 and 5 of the result, as if it went through the adder
+        let result = self.cpu_state.reg_a;
+        let bit_6 = result & 0b0100_0000 != 0;
+        let bit_5 = result & 0b0010_0000 != 0;
+        self.cpu_state.status.set(CpuStatus::CARRY, bit_6);
+        self.cpu_state
+            .status
+            .set(CpuStatus::OVERFLOW, bit_6 ^ bit_5);
+    }
+
+    fn axs(&mut self, parameter: u8) {
+        // Affects Flags: N Z C
+        // Compares A & X against the parameter like CMP, keeping the difference in X
+        let value = self.cpu_state.reg_a & self.cpu_state.reg_x;
+        self.cpu_state.reg_x = value.wrapping_sub(parameter);
+
+        self.set_negative_flag(self.cpu_state.reg_x);
+        self.set_zero_flag(self.cpu_state.reg_x);
+        self.cpu_state
+            .status
+            .set(CpuStatus::CARRY, value >= parameter);
+    }
+
+    fn xaa(&mut self, parameter: u8) {
+        // Affects Flags: N Z
+        // Unstable
+        let value = (self.cpu_state.reg_a | UNSTABLE_CONSTANT) & self.cpu_state.reg_x & parameter;
+        self.lda(value);
+    }
+
+    fn las(&mut self, parameter: u8) {
+        // Affects Flags: N Z
+        let value = parameter & self.cpu_state.stack_pointer;
+        self.cpu_state.stack_pointer = value;
+        self.lax(value);
+    }
+
+    // AHX, SHX, SHY and TAS store a value ANDed with the high byte of the address before indexing,
+    // plus one. When indexing crosses a page the high byte of the address is replaced by the
+    // stored value too.
+    fn write_and_high_byte(&mut self, address: u16, index: u8, value: u8) {
+        let base_address = address.wrapping_sub(index as u16);
+        let result = value & ((base_address >> 8) as u8).wrapping_add(1);
+        let address = if self.cpu_state.page_cross_flag {
+            ((result as u16) << 8) | (address & 0x00FF)
+        } else {
+            address
+        };
+        self.as_bus().write_byte(address, result);
+    }
+
+    fn ahx(&mut self, address: u16) {
+        // Affected Flags: None
+        let value = self.cpu_state.reg_a & self.cpu_state.reg_x;
+        self.write_and_high_byte(address, self.cpu_state.reg_y, value);
+    }
+
+    fn shx(&mut self, address: u16) {
+        // Affected Flags: None
+        self.write_and_high_byte(address, self.cpu_state.reg_y, self.cpu_state.reg_x);
+    }
+
+    fn shy(&mut self, address: u16) {
+        // Affected Flags: None
+        self.write_and_high_byte(address, self.cpu_state.reg_x, self.cpu_state.reg_y);
+    }
+
+    fn tas(&mut self, address: u16) {
+        // Affected Flags: None
+        self.cpu_state.stack_pointer = self.cpu_state.reg_a & self.cpu_state.reg_x;
+        self.write_and_high_byte(address, self.cpu_state.reg_y, self.cpu_state.stack_pointer);
+    }
 }
@@ -1,11 +1,18 @@
 use crate::{
+    apu::{ApuAction, ApuState},
+    audit::{DeterminismAudit, Nondeterminism},
+    common::{FlatMemory, Memory},
     controller::Controller,
+    peripheral::{Peripheral, PortDevice},
     ppu::{PpuAction, PpuState},
+    profiler::MemoryProfile,
     rom::ROM,
 };
 
-use super::CpuState;
+use super::{CpuState, OpenBusModel, PRG_RAM_SIZE, PRG_RAM_START};
 
+// The 2KB of internal RAM at $0000-$07FF is mirrored three times up to $1FFF, so every
+// access path (read, write, peek and dumps) treats $0000, $0800, $1000 and $1800 as the same byte
 const RAM_START: u16 = 0x0000;
 const RAM_END: u16 = 0x1FFF;
 const PPU_REG_START: u16 = 0x2000;
@@ -15,8 +22,10 @@ const APUIO_END: u16 = 0x401F;
 const CART_START: u16 = 0x4020;
 const CART_END: u16 = 0xFFFF;
 
-const PRG_ROM_START: u16 = 0x8000;
-const PRG_ROM_END: u16 = 0xFFFF;
+const PRG_RAM_END: u16 = 0x7FFF;
+
+// Bits of $4016/$4017 driven by the controller port devices
+const PORT_DATA_MASK: u8 = 0x1F;
 
 const RAM_MASK: u16 = (0b1 << 11) - 1;
 const PPU_MASK: u16 = (0b1 << 3) - 1;
@@ -24,10 +33,13 @@ const PPU_MASK: u16 = (0b1 << 3) - 1;
 pub struct CpuBus<'a, 'b, 'c, 'd> {
     cpu_state: &'a mut CpuState,
     ppu_state: &'b mut PpuState,
+    apu_state: &'b mut ApuState,
     controller: &'c mut Controller,
-    rom: &'d ROM,
-    // TODO: I think this needs to be moved somewhere else?
-    apuio_reg: [u8; 0x20],
+    port_2: &'c mut PortDevice,
+    rom: &'d mut ROM,
+    audit: Option<&'c mut DeterminismAudit>,
+    profile: Option<&'c mut MemoryProfile>,
+    flat_memory: Option<&'c mut FlatMemory>,
 }
 
 // impl From<CpuAction> for CpuBus {
@@ -41,19 +53,42 @@ impl<'a, 'b, 'c, 'd> CpuBus<'a, 'b, 'c, 'd> {
     pub fn new(
         cpu_state: &'a mut CpuState,
         ppu_state: &'b mut PpuState,
+        apu_state: &'b mut ApuState,
         controller: &'c mut Controller,
-        rom: &'d ROM,
+        port_2: &'c mut PortDevice,
+        rom: &'d mut ROM,
     ) -> Self {
-        // TODO: apuio_reg should also be a slice probably
         CpuBus {
             cpu_state,
             ppu_state,
+            apu_state,
             controller,
+            port_2,
             rom,
-            apuio_reg: [0; 0x20],
+            audit: None,
+            profile: None,
+            flat_memory: None,
         }
     }
 
+    /// Replaces the whole NES memory map with plain RAM, for running bare 6502 programs
+    pub fn with_flat_memory(mut self, flat_memory: Option<&'c mut FlatMemory>) -> Self {
+        self.flat_memory = flat_memory;
+        self
+    }
+
+    /// Reports reads of uninitialized RAM and open bus to `audit`
+    pub fn with_audit(mut self, audit: Option<&'c mut DeterminismAudit>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Counts every read and write in `profile`
+    pub fn with_profile(mut self, profile: Option<&'c mut MemoryProfile>) -> Self {
+        self.profile = profile;
+        self
+    }
+
     /// Read a byte from the program counter, incrementing it
     pub fn read_byte_from_pc(&mut self) -> u8 {
         let read_addr = self.cpu_state.program_counter;
@@ -86,8 +121,20 @@ impl<'a, 'b, 'c, 'd> CpuBus<'a, 'b, 'c, 'd> {
 
     /// Writes a byte to a location
     pub fn write_byte(&mut self, index: u16, value: u8) {
+        if let Some(profile) = &mut self.profile {
+            profile.write(index);
+        }
+        if let Some(memory) = &mut self.flat_memory {
+            return memory.write(index, value);
+        }
         match index {
-            RAM_START..=RAM_END => self.cpu_state.ram[(index & RAM_MASK) as usize] = value,
+            RAM_START..=RAM_END => {
+                let ram_index = (index & RAM_MASK) as usize;
+                if let Some(audit) = &mut self.audit {
+                    audit.ram_write(ram_index);
+                }
+                self.cpu_state.ram[ram_index] = value;
+            }
             PPU_REG_START..=PPU_REG_END => {
                 let masked_index = index & PPU_MASK;
                 let mut ppu_action = PpuAction::new(self.ppu_state, self.rom);
@@ -114,22 +161,45 @@ impl<'a, 'b, 'c, 'd> CpuBus<'a, 'b, 'c, 'd> {
                 ppu_action.write_oamdma(&buffer);
             }
             0x4016 => {
+                // Strobe is shared by both ports
+                self.cpu_state.output_latch.write(value);
                 self.controller.write(value);
+                self.port_2.write(value);
             }
-            APUIO_START..=APUIO_END => {
-                let index = index - APUIO_START;
-                self.apuio_reg[index as usize] = value;
-            }
-            CART_START..=CART_END => {
-                panic!("Attempted write to read only memory, address {:x}", index);
+            APUIO_START..=APUIO_END => ApuAction::new(self.apu_state).write_register(index, value),
+            PRG_RAM_START..=PRG_RAM_END if self.rom.prg_ram_size > 0 => {
+                self.cpu_state.prg_ram[self.prg_ram_index(index)] = value
             }
+            // Bank switching registers
+            CART_START..=CART_END => self.rom.board.write_register(index, value),
         }
     }
 
+    // Fills the bits of a controller port read that the device doesn't drive
+    fn with_open_bus(&self, index: u16, data: u8) -> u8 {
+        let open_bus = match self.cpu_state.open_bus {
+            OpenBusModel::Zero => 0,
+            OpenBusModel::LastBusValue => (index >> 8) as u8,
+        };
+        (data & PORT_DATA_MASK) | (open_bus & !PORT_DATA_MASK)
+    }
+
     /// Reads a byte from a location, may have side effects from triggering PPU behavior
     pub fn read_byte(&mut self, index: u16) -> u8 {
+        if let Some(profile) = &mut self.profile {
+            profile.read(index);
+        }
+        if let Some(memory) = &mut self.flat_memory {
+            return memory.read(index);
+        }
         match index {
-            RAM_START..=RAM_END => self.cpu_state.ram[(index & RAM_MASK) as usize],
+            RAM_START..=RAM_END => {
+                let ram_index = (index & RAM_MASK) as usize;
+                if let Some(audit) = &mut self.audit {
+                    audit.ram_read(ram_index, self.cpu_state.cycle_counter);
+                }
+                self.cpu_state.ram[ram_index]
+            }
             PPU_REG_START..=PPU_REG_END => {
                 let masked_index = index & PPU_MASK;
                 let mut ppu_action = PpuAction::new(self.ppu_state, self.rom);
@@ -145,45 +215,92 @@ impl<'a, 'b, 'c, 'd> CpuBus<'a, 'b, 'c, 'd> {
                     _ => panic!("Invalid PPU_REG index"),
                 }
             }
-            0x4016 => self.controller.read(),
+            0x4015 => ApuAction::new(self.apu_state).read_status(),
+            0x4016 => {
+                let data = self.controller.read();
+                self.with_open_bus(index, data)
+            }
+            0x4017 => {
+                let data = self.port_2.read();
+                self.with_open_bus(index, data)
+            }
+            // The other APU registers are write-only
             APUIO_START..=APUIO_END => {
-                let index = index - APUIO_START;
-                self.apuio_reg[index as usize]
+                if let Some(audit) = &mut self.audit {
+                    audit.record(Nondeterminism::OpenBus(index), self.cpu_state.cycle_counter);
+                }
+                0
             }
-            PRG_ROM_START..=PRG_ROM_END => {
-                let mut index = index - PRG_ROM_START;
-                if self.rom.prg_rom.len() == 0x4000 && index >= 0x4000 {
-                    //mirror if needed
-                    index %= 0x4000;
+            PRG_RAM_START..=PRG_RAM_END if self.rom.prg_ram_size > 0 => {
+                self.cpu_state.prg_ram[self.prg_ram_index(index)]
+            }
+            CART_START..=CART_END => match self.rom.board.read_register(index) {
+                Some(data) => data,
+                None => self.read_prg(index),
+            },
+        }
+    }
+
+    // PRG ROM through the board's banks, addresses it leaves unmapped are open bus
+    // PRG RAM smaller than the 8KB at $6000-$7FFF is mirrored across it. Without any, the
+    // accesses go to the board.
+    fn prg_ram_index(&self, index: u16) -> usize {
+        let size = self.rom.prg_ram_size.clamp(1, PRG_RAM_SIZE);
+        (index - PRG_RAM_START) as usize % size
+    }
+
+    fn read_prg(&mut self, index: u16) -> u8 {
+        match self.rom.map_prg(index) {
+            Some(offset) => self.rom.prg_rom[offset],
+            None => {
+                if let Some(audit) = &mut self.audit {
+                    audit.record(Nondeterminism::OpenBus(index), self.cpu_state.cycle_counter);
                 }
-                self.rom.prg_rom[index as usize]
+                0
             }
-            _ => panic!("Cannot read from {:x}", index),
+        }
+    }
+
+    /// A read the CPU throws away, e.g. from the wrong page while indexing. Only registers with
+    /// read side effects are actually read, the write-only ones would panic.
+    pub fn dummy_read(&mut self, index: u16) {
+        let has_side_effects = match index {
+            PPU_REG_START..=PPU_REG_END => matches!(index & PPU_MASK, 2 | 7),
+            0x4015..=0x4017 => true,
+            _ => false,
+        };
+        if has_side_effects {
+            self.read_byte(index);
         }
     }
 
     /// Reads a byte from a location with no side effects!
     pub fn peek_byte(&self, index: u16) -> u8 {
+        if let Some(memory) = &self.flat_memory {
+            return memory.peek(index);
+        }
         match index {
             RAM_START..=RAM_END => self.cpu_state.ram[(index & RAM_MASK) as usize],
-            PPU_REG_START..=PPU_REG_END => {
-                let _masked_index = index & PPU_MASK;
-                panic!("Invalid PPU_REG index")
-            }
-            0x4016 => self.controller.peek(),
-            APUIO_START..=APUIO_END => {
-                let index = index - APUIO_START;
-                self.apuio_reg[index as usize]
+            // What a read would return, without clearing vblank or moving the VRAM address.
+            // Write-only registers peek as 0.
+            PPU_REG_START..=PPU_REG_END => match index & PPU_MASK {
+                2 => self.ppu_state.ppustatus.bits(),
+                4 => self.ppu_state.oam_data[self.ppu_state.oamaddr.read() as usize],
+                7 => self.ppu_state.ppudata,
+                _ => 0,
+            },
+            0x4015 => self.apu_state.status().bits(),
+            0x4016 => self.with_open_bus(index, self.controller.peek()),
+            0x4017 => self.with_open_bus(index, self.port_2.peek()),
+            APUIO_START..=APUIO_END => 0,
+            PRG_RAM_START..=PRG_RAM_END if self.rom.prg_ram_size > 0 => {
+                self.cpu_state.prg_ram[self.prg_ram_index(index)]
             }
-            PRG_ROM_START..=PRG_ROM_END => {
-                let mut index = index - PRG_ROM_START;
-                if self.rom.prg_rom.len() == 0x4000 && index >= 0x4000 {
-                    //mirror if needed
-                    index %= 0x4000;
-                }
-                self.rom.prg_rom[index as usize]
-            }
-            _ => panic!("Cannot read from {:x}", index),
+            // Board registers peek as the PRG under them, reading them can have side effects
+            CART_START..=CART_END => self
+                .rom
+                .map_prg(index)
+                .map_or(0, |offset| self.rom.prg_rom[offset]),
         }
     }
 
@@ -194,3 +311,132 @@ impl<'a, 'b, 'c, 'd> CpuBus<'a, 'b, 'c, 'd> {
         (msb << 8) + lsb
     }
 }
+
+impl Memory for CpuBus<'_, '_, '_, '_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.write_byte(addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.peek_byte(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::ControllerState;
+
+    const MIRRORS: [u16; 4] = [0x0000, 0x0800, 0x1000, 0x1800];
+
+    struct TestBus {
+        cpu_state: CpuState,
+        ppu_state: PpuState,
+        apu_state: ApuState,
+        controller: Controller,
+        port_2: PortDevice,
+        rom: ROM,
+    }
+
+    impl TestBus {
+        fn new() -> Self {
+            TestBus {
+                cpu_state: CpuState::new(),
+                ppu_state: PpuState::new(),
+                apu_state: ApuState::new(),
+                controller: Controller::new(),
+                port_2: PortDevice::Disconnected,
+                rom: ROM::new(),
+            }
+        }
+
+        fn bus(&mut self) -> CpuBus<'_, '_, '_, '_> {
+            CpuBus::new(
+                &mut self.cpu_state,
+                &mut self.ppu_state,
+                &mut self.apu_state,
+                &mut self.controller,
+                &mut self.port_2,
+                &mut self.rom,
+            )
+        }
+    }
+
+    #[test]
+    fn test_ram_mirroring_write() {
+        for write_base in MIRRORS {
+            let mut test_bus = TestBus::new();
+            let mut bus = test_bus.bus();
+            bus.write_byte(write_base + 0x42, 0xAB);
+            for read_base in MIRRORS {
+                assert_eq!(0xAB, bus.read_byte(read_base + 0x42));
+                assert_eq!(0xAB, bus.peek_byte(read_base + 0x42));
+            }
+            assert_eq!(0xAB, test_bus.cpu_state.ram[0x42]);
+        }
+    }
+
+    #[test]
+    fn test_ram_mirroring_two_bytes() {
+        let mut test_bus = TestBus::new();
+        let mut bus = test_bus.bus();
+        bus.write_byte(0x1810, 0x34);
+        bus.write_byte(0x0011, 0x12);
+        for base in MIRRORS {
+            assert_eq!(0x1234, bus.read_two_bytes(base + 0x10));
+            assert_eq!(0x1234, bus.peek_two_bytes(base + 0x10));
+        }
+        // Reading across the end of a mirror wraps to the start of RAM
+        bus.write_byte(0x07FF, 0xCD);
+        bus.write_byte(0x0000, 0xAB);
+        assert_eq!(0xABCD, bus.read_two_bytes(0x07FF));
+        assert_eq!(0xABCD, bus.peek_two_bytes(0x0FFF));
+    }
+
+    #[test]
+    fn test_ram_mirroring_peek_range() {
+        let mut test_bus = TestBus::new();
+        for i in 0..0x800 {
+            test_bus.cpu_state.ram[i] = i as u8;
+        }
+        let bus = test_bus.bus();
+        let ram = bus.peek_range(0x0000, 0x800);
+        for base in MIRRORS {
+            assert_eq!(ram, bus.peek_range(base, 0x800));
+        }
+        assert_eq!(vec![0xFE, 0xFF, 0x00, 0x01], bus.peek_range(0x17FE, 4));
+    }
+
+    #[test]
+    fn test_output_latch() {
+        let mut test_bus = TestBus::new();
+        let mut bus = test_bus.bus();
+        // Only OUT0-OUT2 are latched
+        bus.write_byte(0x4016, 0b1111_0101);
+        assert_eq!(0b101, test_bus.cpu_state.output_latch.bits());
+        assert!(test_bus.cpu_state.output_latch.is_set(2));
+        assert!(!test_bus.cpu_state.output_latch.is_set(1));
+    }
+
+    #[test]
+    fn test_controller_port_open_bus() {
+        let mut test_bus = TestBus::new();
+        test_bus.controller.set_controller_state(ControllerState::A);
+        let mut bus = test_bus.bus();
+        bus.write_byte(0x4016, 1);
+        bus.write_byte(0x4016, 0);
+        assert_eq!(0x41, bus.peek_byte(0x4016));
+        assert_eq!(0x41, bus.read_byte(0x4016));
+        // Nothing is connected to port 2
+        assert_eq!(0x40, bus.read_byte(0x4017));
+
+        test_bus.cpu_state.open_bus = OpenBusModel::Zero;
+        let mut bus = test_bus.bus();
+        assert_eq!(0x00, bus.read_byte(0x4016));
+        assert_eq!(0x00, bus.read_byte(0x4017));
+    }
+}
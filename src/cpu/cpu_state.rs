@@ -1,13 +1,30 @@
 use bitflags::bitflags;
 
+use crate::peripheral::OutputLatch;
+
 const STACK_POINTER_INIT: u8 = 0xFD;
 const PROGRAM_COUNTER_INIT: u16 = 0x600;
+pub const PRG_RAM_START: u16 = 0x6000;
+pub const PRG_RAM_SIZE: usize = 0x2000;
+
+/// What the controller port reads ($4016/$4017) return in the bits no device drives (D5-D7)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OpenBusModel {
+    /// Undriven bits read as 0
+    Zero,
+    /// Undriven bits keep the last value on the data bus, the high byte of the address ($40)
+    /// for the usual LDA $4016, so a disconnected port reads $40
+    #[default]
+    LastBusValue,
+}
 
 // ! This struct should never create a Bus or an Action
 #[derive(Debug, Clone, Copy)]
 pub struct CpuState {
     // 2KB RAM
     pub ram: [u8; 0x800],
+    // 8KB cartridge RAM at $6000-$7FFF, kept here until the mappers own their memory
+    pub prg_ram: [u8; PRG_RAM_SIZE],
     // General purpose registers
     pub reg_a: u8,
     pub reg_x: u8,
@@ -22,9 +39,21 @@ pub struct CpuState {
     pub branch_flag: bool,
 
     // Interrupts
+    // Set when the last instruction polled the IRQ line with interrupts enabled, an IRQ asserted
+    // by the next instruction is taken before it
     pub irq_interrupt_poll: Option<()>,
+    // Set when a BRK took over an NMI, so that the NMI is not serviced a second time
+    pub nmi_hijacked: bool,
 
     pub cycle_counter: usize,
+
+    // OUT pins, set by $4016 writes
+    pub output_latch: OutputLatch,
+
+    // Console configuration, not reset, see accuracy
+    pub open_bus: OpenBusModel,
+    pub dummy_reads: bool,
+    pub rmw_double_writes: bool,
 }
 
 impl Default for CpuState {
@@ -37,34 +66,43 @@ impl CpuState {
     pub fn new() -> Self {
         CpuState {
             ram: [0; 0x800],
+            prg_ram: [0; PRG_RAM_SIZE],
             reg_a: 0,
             reg_x: 0,
             reg_y: 0,
             // status: CpuStatus::ALWAYS | CpuStatus::BRK,
             status: CpuStatus::ALWAYS | CpuStatus::INT_DISABLE,
-            stack_pointer: STACK_POINTER_INIT, // probably needs to initialize to something else
+            // As after power on and reset, for bare programs (FlatCpu)
+            stack_pointer: STACK_POINTER_INIT,
             program_counter: PROGRAM_COUNTER_INIT, // same here
             page_cross_flag: false,
             branch_flag: false,
             irq_interrupt_poll: None,
+            nmi_hijacked: false,
             cycle_counter: 0,
+            open_bus: OpenBusModel::default(),
+            // The balanced accuracy preset
+            dummy_reads: false,
+            rmw_double_writes: true,
+            output_latch: OutputLatch::default(),
         }
     }
 
-    // TODO: should this reset the rest of the state as well?
-    pub fn reset(&mut self) {
+    /// Registers when the console is switched on, before the reset sequence. RAM is left alone.
+    pub fn power_on(&mut self) {
         self.reg_a = 0;
         self.reg_x = 0;
         self.reg_y = 0;
-        self.stack_pointer = STACK_POINTER_INIT;
-        // self.status = CpuStatus::ALWAYS | CpuStatus::BRK;
+        // The reset sequence takes it to $FD
+        self.stack_pointer = 0;
         self.status = CpuStatus::ALWAYS | CpuStatus::INT_DISABLE;
+    }
 
-        // self.ram = [0; 0x800];
-        // self.program_counter = PROGRAM_COUNTER_INIT;
-        // self.page_cross_flag = false;
-        // self.branch_flag = false;
-        // self.cycle_counter = 0;
+    /// Reset sequence, an interrupt with its 3 pushes turned into reads so only SP moves. The
+    /// caller loads the PC from $FFFC. A, X, Y and the other flags keep their values.
+    pub fn reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status.insert(CpuStatus::INT_DISABLE);
     }
 }
 
@@ -91,4 +129,25 @@ mod tests {
         let cpu_state = CpuState::new();
         assert_eq!(0, cpu_state.reg_a)
     }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu_state = CpuState::new();
+        cpu_state.power_on();
+        cpu_state.reset();
+        assert_eq!(0xFD, cpu_state.stack_pointer);
+        assert_eq!(0x24, cpu_state.status.bits());
+
+        // A warm reset keeps the registers and flags other than I
+        cpu_state.reg_a = 0x42;
+        cpu_state.status = CpuStatus::ALWAYS | CpuStatus::CARRY;
+        cpu_state.reset();
+        assert_eq!(0xFA, cpu_state.stack_pointer);
+        assert_eq!(0x42, cpu_state.reg_a);
+        assert_eq!(0x25, cpu_state.status.bits());
+        // SP wraps
+        cpu_state.stack_pointer = 0x01;
+        cpu_state.reset();
+        assert_eq!(0xFE, cpu_state.stack_pointer);
+    }
 }
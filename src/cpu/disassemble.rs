@@ -0,0 +1,190 @@
+// Code listings from raw bytes, without a CPU or bus to run them on
+//
+// Operands are written like the tracer's, without the values it reads through the bus, or with
+// names for the addresses they refer to (see disasm.rs for ca65 listings). Bytes that don't start
+// an instruction, the JAM opcodes and instructions cut off at the end, come out as .byte lines so
+// the listing keeps going.
+use std::fmt;
+
+use super::{decode_opcode, AddressingMode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledLine {
+    pub address: u16,
+    // The opcode and its operand bytes
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    // Empty for implied instructions
+    pub operand: String,
+    // Address a branch, jump or absolute operand refers to
+    pub target: Option<u16>,
+}
+
+impl DisassembledLine {
+    /// A byte that isn't an instruction
+    pub fn is_data(&self) -> bool {
+        self.mnemonic == DATA_MNEMONIC
+    }
+}
+
+impl fmt::Display for DisassembledLine {
+    // C000  A9 01     LDA #$01
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let instruction = format!("{} {}", self.mnemonic, self.operand);
+        write!(
+            f,
+            "{:04X}  {:<8}  {}",
+            self.address,
+            bytes.join(" "),
+            instruction.trim_end()
+        )
+    }
+}
+
+const DATA_MNEMONIC: &str = ".byte";
+
+fn target_address(address: u16, mode: AddressingMode, bytes: &[u8]) -> Option<u16> {
+    match mode {
+        AddressingMode::Relative => {
+            Some(address.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16))
+        }
+        AddressingMode::Absolute
+        | AddressingMode::AbsoluteJump
+        | AddressingMode::AbsoluteIndexX
+        | AddressingMode::AbsoluteIndexY
+        | AddressingMode::IndirectJump => Some(u16::from_le_bytes([bytes[1], bytes[2]])),
+        _ => None,
+    }
+}
+
+fn format_operand<F>(address: u16, mode: AddressingMode, bytes: &[u8], symbol: &F) -> String
+where
+    F: Fn(u16, AddressingMode) -> Option<String>,
+{
+    let zero_page =
+        || symbol(bytes[1] as u16, mode).unwrap_or_else(|| format!("${:02X}", bytes[1]));
+    let word = || {
+        let value = target_address(address, mode, bytes).unwrap();
+        symbol(value, mode).unwrap_or_else(|| format!("${:04X}", value))
+    };
+    match mode {
+        AddressingMode::Implicit => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", bytes[1]),
+        AddressingMode::ZeroPage => zero_page(),
+        AddressingMode::ZeroPageIndexX => format!("{},X", zero_page()),
+        AddressingMode::ZeroPageIndexY => format!("{},Y", zero_page()),
+        AddressingMode::IndirectX => format!("({},X)", zero_page()),
+        AddressingMode::IndirectY => format!("({}),Y", zero_page()),
+        AddressingMode::Relative | AddressingMode::Absolute | AddressingMode::AbsoluteJump => {
+            word()
+        }
+        AddressingMode::AbsoluteIndexX => format!("{},X", word()),
+        AddressingMode::AbsoluteIndexY => format!("{},Y", word()),
+        AddressingMode::IndirectJump => format!("({})", word()),
+    }
+}
+
+/// Decodes `bytes` as instructions one after the other, the first at `origin`. Addresses wrap
+/// around past $FFFF.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<DisassembledLine> {
+    disassemble_with(bytes, origin, |_, _| None)
+}
+
+/// Like disassemble, with `symbol` naming the addresses operands refer to. It's given the
+/// address (a zero page one, or a branch target) and the addressing mode, None writes it in hex.
+pub fn disassemble_with<F>(bytes: &[u8], origin: u16, symbol: F) -> Vec<DisassembledLine>
+where
+    F: Fn(u16, AddressingMode) -> Option<String>,
+{
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        let decoded = decode_opcode(bytes[offset])
+            .ok()
+            .filter(|info| offset + info.size as usize <= bytes.len());
+        let line = match decoded {
+            Some(info) => {
+                let raw = &bytes[offset..offset + info.size as usize];
+                DisassembledLine {
+                    address,
+                    bytes: raw.to_vec(),
+                    mnemonic: format!("{:?}", info.opcode),
+                    operand: format_operand(address, info.mode, raw, &symbol),
+                    target: target_address(address, info.mode, raw),
+                }
+            }
+            None => DisassembledLine {
+                address,
+                bytes: vec![bytes[offset]],
+                mnemonic: DATA_MNEMONIC.to_string(),
+                operand: format!("${:02X}", bytes[offset]),
+                target: None,
+            },
+        };
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        // LDA #$01, loop: BNE loop, JMP ($0200), STA $10,X, JAM, then half of an LDA abs
+        let lines = disassemble(
+            &[
+                0xA9, 0x01, 0xD0, 0xFE, 0x6C, 0x00, 0x02, 0x95, 0x10, 0x02, 0xAD, 0x00,
+            ],
+            0xC000,
+        );
+        let listing: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            vec![
+                "C000  A9 01     LDA #$01",
+                "C002  D0 FE     BNE $C002",
+                "C004  6C 00 02  JMP ($0200)",
+                "C007  95 10     STA $10,X",
+                "C009  02        .byte $02",
+                "C00A  AD        .byte $AD",
+                "C00B  00        BRK",
+            ],
+            listing
+        );
+        assert_eq!(vec![0x6C, 0x00, 0x02], lines[2].bytes);
+        assert_eq!(Some(0x0200), lines[2].target);
+        assert!(lines[4].is_data() && !lines[6].is_data());
+        assert_eq!(
+            ("JMP", "($0200)"),
+            (&*lines[2].mnemonic, &*lines[2].operand)
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        // LDA $10, BNE $C000, STA $2000,X
+        let lines = disassemble_with(
+            &[0xA5, 0x10, 0xD0, 0xFC, 0x9D, 0x00, 0x20],
+            0xC000,
+            |address, mode| match (address, mode) {
+                (0x10, AddressingMode::ZeroPage) => Some("counter".to_string()),
+                (0xC000, _) => Some("loop".to_string()),
+                _ => None,
+            },
+        );
+        let operands: Vec<&str> = lines.iter().map(|line| &*line.operand).collect();
+        assert_eq!(vec!["counter", "loop", "$2000,X"], operands);
+    }
+
+    #[test]
+    fn test_disassemble_wraps_around() {
+        let lines = disassemble(&[0xEA, 0x0A], 0xFFFF);
+        assert_eq!((0xFFFF, "NOP"), (lines[0].address, &*lines[0].mnemonic));
+        assert_eq!((0x0000, "A"), (lines[1].address, &*lines[1].operand));
+    }
+}
@@ -0,0 +1,135 @@
+// The CPU core on its own, wired to 64KB of plain RAM instead of the NES memory map
+use crate::{
+    apu::ApuState, common::FlatMemory, controller::Controller, peripheral::PortDevice,
+    ppu::PpuState, rom::ROM,
+};
+
+use super::{CpuAction, CpuState, Instruction, OpcodeInfo, Param};
+
+const BRK_OPCODE: u8 = 0x00;
+const RUN_PROGRAM_LIMIT: usize = 1_000_000;
+
+/// Runs `program` loaded at `start_addr` on a FlatCpu until its first BRK, returning the CPU
+/// registers at the BRK. Zero page and the rest of memory start cleared.
+///
+/// ```
+/// use rust_nes_emulator::cpu::run_program;
+///
+/// // LDX #$05, loop: INY, DEX, BNE loop, BRK
+/// let state = run_program(&[0xA2, 0x05, 0xC8, 0xCA, 0xD0, 0xFC, 0x00], 0x0600);
+/// assert_eq!(5, state.reg_y);
+/// assert_eq!(0x0606, state.program_counter);
+/// ```
+///
+/// Panics if the program fails or doesn't reach a BRK within a million instructions, use
+/// FlatCpu directly to handle those or to look at memory afterwards.
+pub fn run_program(program: &[u8], start_addr: u16) -> CpuState {
+    let mut cpu = FlatCpu::new();
+    cpu.load(program, start_addr)
+        .and_then(|_| cpu.run_until_brk(RUN_PROGRAM_LIMIT))
+        .unwrap_or_else(|err| panic!("Program at {:04X} failed: {}", start_addr, err));
+    cpu.cpu_state
+}
+
+/// A bare 6502 for running raw programs, e.g. test snippets or teaching examples
+#[derive(Debug, Clone, Default)]
+pub struct FlatCpu {
+    pub cpu_state: CpuState,
+    pub memory: FlatMemory,
+    // Unused, but the CPU core expects the rest of the console to be there
+    ppu_state: PpuState,
+    apu_state: ApuState,
+    controller: Controller,
+    port_2: PortDevice,
+    rom: ROM,
+}
+
+impl FlatCpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies `program` to `addr` and points the PC at it
+    pub fn load(&mut self, program: &[u8], addr: u16) -> Result<(), String> {
+        let start = addr as usize;
+        let end = start + program.len();
+        if end > self.memory.data.len() {
+            return Err(format!(
+                "Program of {} bytes doesn't fit at {:04X}",
+                program.len(),
+                addr
+            ));
+        }
+        self.memory.data[start..end].copy_from_slice(program);
+        self.cpu_state.program_counter = addr;
+        Ok(())
+    }
+
+    /// True if the next instruction is a BRK, which bare programs use to stop
+    pub fn is_at_brk(&self) -> bool {
+        self.memory.data[self.cpu_state.program_counter as usize] == BRK_OPCODE
+    }
+
+    fn as_cpu_action(&mut self) -> CpuAction<'_, '_, '_, '_> {
+        CpuAction::new(
+            &mut self.cpu_state,
+            &mut self.ppu_state,
+            &mut self.apu_state,
+            &mut self.controller,
+            &mut self.port_2,
+            &mut self.rom,
+        )
+        .with_flat_memory(Some(&mut self.memory))
+    }
+
+    pub fn step(&mut self) -> Result<Instruction, String> {
+        self.as_cpu_action().next_cpu_instruction()
+    }
+
+    /// Decodes the instruction at PC without running it, see CpuAction::parse_instruction
+    pub fn parse(&mut self) -> Result<(u8, &'static OpcodeInfo, Param), String> {
+        self.as_cpu_action().parse_instruction()
+    }
+
+    /// Runs until a BRK, returns the number of instructions executed. Fails if the program
+    /// runs for more than `max_instructions`.
+    pub fn run_until_brk(&mut self, max_instructions: usize) -> Result<usize, String> {
+        for count in 0..max_instructions {
+            if self.is_at_brk() {
+                return Ok(count);
+            }
+            self.step()?;
+        }
+        Err(format!(
+            "No BRK after {} instructions, PC:{:04X}",
+            max_instructions, self.cpu_state.program_counter
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_memory() {
+        // LDA #$42, STA $2000, LDX $2000, BRK
+        let program = [0xA9, 0x42, 0x8D, 0x00, 0x20, 0xAE, 0x00, 0x20, 0x00];
+        let mut cpu = FlatCpu::new();
+        cpu.load(&program, 0x0600).unwrap();
+        assert_eq!(3, cpu.run_until_brk(100).unwrap());
+        // $2000 is plain RAM here, not a PPU register
+        assert_eq!(0x42, cpu.memory.data[0x2000]);
+        assert_eq!(0x42, cpu.cpu_state.reg_x);
+        assert_eq!(0x0608, cpu.cpu_state.program_counter);
+    }
+
+    #[test]
+    fn test_runaway_program() {
+        // JMP $0600
+        let mut cpu = FlatCpu::new();
+        cpu.load(&[0x4C, 0x00, 0x06], 0x0600).unwrap();
+        assert!(cpu.run_until_brk(10).is_err());
+        assert!(cpu.load(&[0xEA; 4], 0xFFFE).is_err());
+    }
+}
@@ -1,317 +1,510 @@
-// * THIS IS COMPLETE
+// Opcode table, the one place that knows each instruction's addressing mode, size and cycles.
+// Decoding, cycle counting, the tracer and the disassembler all read it.
 use super::{AddressingMode, CpuCycleUnit, Opcode};
+use AddressingMode::*;
+use Opcode::*;
 
-/// Decodes a raw byte to
-///     1. An Opcode corresponding to the instruction type
-///     2. An AddressingMode describing how the instruction Param will be used
-///     3. The base number of cycles the instruction will use
-pub fn decode_opcode(opcode: u8) -> Result<(Opcode, AddressingMode, CpuCycleUnit), String> {
-    // Used this reference for decoding opcodes to Opcode addressing mode pairs
-    // Ref: http://www.6502.org/tutorials/6502opcodes.html#LDA
-    let result = match opcode {
-        // Immediate     ADC #$44      $69  2   2
-        // Zero Page     ADC $44       $65  2   3
-        // Zero Page,X   ADC $44,X     $75  2   4
-        // Absolute      ADC $4400     $6D  3   4
-        // Absolute,X    ADC $4400,X   $7D  3   4+
-        // Absolute,Y    ADC $4400,Y   $79  3   4+
-        // Indirect,X    ADC ($44,X)   $61  2   6
-        // Indirect,Y    ADC ($44),Y   $71  2   5+
-        0x69 => (Opcode::ADC, AddressingMode::Immediate, 2),
-        0x65 => (Opcode::ADC, AddressingMode::ZeroPage, 3),
-        0x75 => (Opcode::ADC, AddressingMode::ZeroPageIndexX, 4),
-        0x6D => (Opcode::ADC, AddressingMode::Absolute, 4),
-        0x7D => (Opcode::ADC, AddressingMode::AbsoluteIndexX, 4),
-        0x79 => (Opcode::ADC, AddressingMode::AbsoluteIndexY, 4),
-        0x61 => (Opcode::ADC, AddressingMode::IndirectX, 6),
-        0x71 => (Opcode::ADC, AddressingMode::IndirectY, 5),
-        // Immediate     AND #$44      $29  2   2
-        // Zero Page     AND $44       $25  2   3
-        // Zero Page,X   AND $44,X     $35  2   4
-        // Absolute      AND $4400     $2D  3   4
-        // Absolute,X    AND $4400,X   $3D  3   4+
-        // Absolute,Y    AND $4400,Y   $39  3   4+
-        // Indirect,X    AND ($44,X)   $21  2   6
-        // Indirect,Y    AND ($44),Y   $31  2   5+
-        0x29 => (Opcode::AND, AddressingMode::Immediate, 2),
-        0x25 => (Opcode::AND, AddressingMode::ZeroPage, 3),
-        0x35 => (Opcode::AND, AddressingMode::ZeroPageIndexX, 4),
-        0x2D => (Opcode::AND, AddressingMode::Absolute, 4),
-        0x3D => (Opcode::AND, AddressingMode::AbsoluteIndexX, 4),
-        0x39 => (Opcode::AND, AddressingMode::AbsoluteIndexY, 4),
-        0x21 => (Opcode::AND, AddressingMode::IndirectX, 6),
-        0x31 => (Opcode::AND, AddressingMode::IndirectY, 5),
-        // Accumulator   ASL A         $0A  1   2
-        // Zero Page     ASL $44       $06  2   5
-        // Zero Page,X   ASL $44,X     $16  2   6
-        // Absolute      ASL $4400     $0E  3   6
-        // Absolute,X    ASL $4400,X   $1E  3   7
-        0x0A => (Opcode::ASL, AddressingMode::Accumulator, 2),
-        0x06 => (Opcode::ASL, AddressingMode::ZeroPage, 5),
-        0x16 => (Opcode::ASL, AddressingMode::ZeroPageIndexX, 6),
-        0x0E => (Opcode::ASL, AddressingMode::Absolute, 6),
-        0x1E => (Opcode::ASL, AddressingMode::AbsoluteIndexX, 7),
-        // BPL (Branch on PLus)           $10
-        // BMI (Branch on MInus)          $30
-        // BVC (Branch on oVerflow Clear) $50
-        // BVS (Branch on oVerflow Set)   $70
-        // BCC (Branch on Carry Clear)    $90
-        // BCS (Branch on Carry Set)      $B0
-        // BNE (Branch on Not Equal)      $D0
-        // BEQ (Branch on EQual)          $F0
-        0x10 => (Opcode::BPL, AddressingMode::Relative, 2),
-        0x30 => (Opcode::BMI, AddressingMode::Relative, 2),
-        0x50 => (Opcode::BVC, AddressingMode::Relative, 2),
-        0x70 => (Opcode::BVS, AddressingMode::Relative, 2),
-        0x90 => (Opcode::BCC, AddressingMode::Relative, 2),
-        0xB0 => (Opcode::BCS, AddressingMode::Relative, 2),
-        0xD0 => (Opcode::BNE, AddressingMode::Relative, 2),
-        0xF0 => (Opcode::BEQ, AddressingMode::Relative, 2),
-        // Zero Page     BIT $44       $24  2   3
-        // Absolute      BIT $4400     $2C  3   4
-        0x24 => (Opcode::BIT, AddressingMode::ZeroPage, 3),
-        0x2C => (Opcode::BIT, AddressingMode::Absolute, 4),
-        // Implied       BRK           $00  1   7
-        0x00 => (Opcode::BRK, AddressingMode::Implicit, 7),
-        // Immediate     CMP #$44      $C9  2   2
-        // Zero Page     CMP $44       $C5  2   3
-        // Zero Page,X   CMP $44,X     $D5  2   4
-        // Absolute      CMP $4400     $CD  3   4
-        // Absolute,X    CMP $4400,X   $DD  3   4+
-        // Absolute,Y    CMP $4400,Y   $D9  3   4+
-        // Indirect,X    CMP ($44,X)   $C1  2   6
-        // Indirect,Y    CMP ($44),Y   $D1  2   5+
-        0xC9 => (Opcode::CMP, AddressingMode::Immediate, 2),
-        0xC5 => (Opcode::CMP, AddressingMode::ZeroPage, 3),
-        0xD5 => (Opcode::CMP, AddressingMode::ZeroPageIndexX, 4),
-        0xCD => (Opcode::CMP, AddressingMode::Absolute, 4),
-        0xDD => (Opcode::CMP, AddressingMode::AbsoluteIndexX, 4),
-        0xD9 => (Opcode::CMP, AddressingMode::AbsoluteIndexY, 4),
-        0xC1 => (Opcode::CMP, AddressingMode::IndirectX, 6),
-        0xD1 => (Opcode::CMP, AddressingMode::IndirectY, 5),
-        // Immediate     CPX #$44      $E0  2   2
-        // Zero Page     CPX $44       $E4  2   3
-        // Absolute      CPX $4400     $EC  3   4
-        0xE0 => (Opcode::CPX, AddressingMode::Immediate, 2),
-        0xE4 => (Opcode::CPX, AddressingMode::ZeroPage, 3),
-        0xEC => (Opcode::CPX, AddressingMode::Absolute, 4),
-        // Immediate     CPY #$44      $C0  2   2
-        // Zero Page     CPY $44       $C4  2   3
-        // Absolute      CPY $4400     $CC  3   4
-        0xC0 => (Opcode::CPY, AddressingMode::Immediate, 2),
-        0xC4 => (Opcode::CPY, AddressingMode::ZeroPage, 3),
-        0xCC => (Opcode::CPY, AddressingMode::Absolute, 4),
-        // Zero Page     DEC $44       $C6  2   5
-        // Zero Page,X   DEC $44,X     $D6  2   6
-        // Absolute      DEC $4400     $CE  3   6
-        // Absolute,X    DEC $4400,X   $DE  3   7
-        0xC6 => (Opcode::DEC, AddressingMode::ZeroPage, 5),
-        0xD6 => (Opcode::DEC, AddressingMode::ZeroPageIndexX, 6),
-        0xCE => (Opcode::DEC, AddressingMode::Absolute, 6),
-        0xDE => (Opcode::DEC, AddressingMode::AbsoluteIndexX, 7),
-        // Immediate     EOR #$44      $49  2   2
-        // Zero Page     EOR $44       $45  2   3
-        // Zero Page,X   EOR $44,X     $55  2   4
-        // Absolute      EOR $4400     $4D  3   4
-        // Absolute,X    EOR $4400,X   $5D  3   4+
-        // Absolute,Y    EOR $4400,Y   $59  3   4+
-        // Indirect,X    EOR ($44,X)   $41  2   6
-        // Indirect,Y    EOR ($44),Y   $51  2   5+
-        0x49 => (Opcode::EOR, AddressingMode::Immediate, 2),
-        0x45 => (Opcode::EOR, AddressingMode::ZeroPage, 3),
-        0x55 => (Opcode::EOR, AddressingMode::ZeroPageIndexX, 4),
-        0x4D => (Opcode::EOR, AddressingMode::Absolute, 4),
-        0x5D => (Opcode::EOR, AddressingMode::AbsoluteIndexX, 4),
-        0x59 => (Opcode::EOR, AddressingMode::AbsoluteIndexY, 4),
-        0x41 => (Opcode::EOR, AddressingMode::IndirectX, 6),
-        0x51 => (Opcode::EOR, AddressingMode::IndirectY, 5),
-        // CLC (CLear Carry)              $18
-        // SEC (SEt Carry)                $38
-        // CLI (CLear Interrupt)          $58
-        // SEI (SEt Interrupt)            $78
-        // CLV (CLear oVerflow)           $B8
-        // CLD (CLear Decimal)            $D8
-        // SED (SEt Decimal)              $F8
-        0x18 => (Opcode::CLC, AddressingMode::Implicit, 2),
-        0x38 => (Opcode::SEC, AddressingMode::Implicit, 2),
-        0x58 => (Opcode::CLI, AddressingMode::Implicit, 2),
-        0x78 => (Opcode::SEI, AddressingMode::Implicit, 2),
-        0xB8 => (Opcode::CLV, AddressingMode::Implicit, 2),
-        0xD8 => (Opcode::CLD, AddressingMode::Implicit, 2),
-        0xF8 => (Opcode::SED, AddressingMode::Implicit, 2),
-        // Zero Page     INC $44       $E6  2   5
-        // Zero Page,X   INC $44,X     $F6  2   6
-        // Absolute      INC $4400     $EE  3   6
-        // Absolute,X    INC $4400,X   $FE  3   7
-        0xE6 => (Opcode::INC, AddressingMode::ZeroPage, 5),
-        0xF6 => (Opcode::INC, AddressingMode::ZeroPageIndexX, 6),
-        0xEE => (Opcode::INC, AddressingMode::Absolute, 6),
-        0xFE => (Opcode::INC, AddressingMode::AbsoluteIndexX, 7),
-        // Absolute      JMP $5597     $4C  3   3
-        // Indirect      JMP ($5597)   $6C  3   5
-        0x4C => (Opcode::JMP, AddressingMode::AbsoluteJump, 3),
-        0x6C => (Opcode::JMP, AddressingMode::IndirectJump, 5),
-        // Absolute      JSR $5597     $20  3   6
-        0x20 => (Opcode::JSR, AddressingMode::AbsoluteJump, 6),
-        // Immediate     LDA #$44      $A9  2   2
-        // Zero Page     LDA $44       $A5  2   3
-        // Zero Page,X   LDA $44,X     $B5  2   4
-        // Absolute      LDA $4400     $AD  3   4
-        // Absolute,X    LDA $4400,X   $BD  3   4+
-        // Absolute,Y    LDA $4400,Y   $B9  3   4+
-        // Indirect,X    LDA ($44,X)   $A1  2   6
-        // Indirect,Y    LDA ($44),Y   $B1  2   5+
-        0xA9 => (Opcode::LDA, AddressingMode::Immediate, 2),
-        0xA5 => (Opcode::LDA, AddressingMode::ZeroPage, 3),
-        0xB5 => (Opcode::LDA, AddressingMode::ZeroPageIndexX, 4),
-        0xAD => (Opcode::LDA, AddressingMode::Absolute, 4),
-        0xBD => (Opcode::LDA, AddressingMode::AbsoluteIndexX, 4),
-        0xB9 => (Opcode::LDA, AddressingMode::AbsoluteIndexY, 4),
-        0xA1 => (Opcode::LDA, AddressingMode::IndirectX, 6),
-        0xB1 => (Opcode::LDA, AddressingMode::IndirectY, 5),
-        // Immediate     LDX #$44      $A2  2   2
-        // Zero Page     LDX $44       $A6  2   3
-        // Zero Page,Y   LDX $44,Y     $B6  2   4
-        // Absolute      LDX $4400     $AE  3   4
-        // Absolute,Y    LDX $4400,Y   $BE  3   4+
-        0xA2 => (Opcode::LDX, AddressingMode::Immediate, 2),
-        0xA6 => (Opcode::LDX, AddressingMode::ZeroPage, 3),
-        0xB6 => (Opcode::LDX, AddressingMode::ZeroPageIndexY, 4),
-        0xAE => (Opcode::LDX, AddressingMode::Absolute, 4),
-        0xBE => (Opcode::LDX, AddressingMode::AbsoluteIndexY, 4),
-        // Immediate     LDY #$44      $A0  2   2
-        // Zero Page     LDY $44       $A4  2   3
-        // Zero Page,X   LDY $44,X     $B4  2   4
-        // Absolute      LDY $4400     $AC  3   4
-        // Absolute,X    LDY $4400,X   $BC  3   4+
-        0xA0 => (Opcode::LDY, AddressingMode::Immediate, 2),
-        0xA4 => (Opcode::LDY, AddressingMode::ZeroPage, 3),
-        0xB4 => (Opcode::LDY, AddressingMode::ZeroPageIndexX, 4),
-        0xAC => (Opcode::LDY, AddressingMode::Absolute, 4),
-        0xBC => (Opcode::LDY, AddressingMode::AbsoluteIndexX, 4),
-        // Accumulator   LSR A         $4A  1   2
-        // Zero Page     LSR $44       $46  2   5
-        // Zero Page,X   LSR $44,X     $56  2   6
-        // Absolute      LSR $4400     $4E  3   6
-        // Absolute,X    LSR $4400,X   $5E  3   7
-        0x4A => (Opcode::LSR, AddressingMode::Accumulator, 2),
-        0x46 => (Opcode::LSR, AddressingMode::ZeroPage, 5),
-        0x56 => (Opcode::LSR, AddressingMode::ZeroPageIndexX, 6),
-        0x4E => (Opcode::LSR, AddressingMode::Absolute, 6),
-        0x5E => (Opcode::LSR, AddressingMode::AbsoluteIndexX, 7),
-        // Implied       NOP           $EA  1   2
-        0xEA => (Opcode::NOP, AddressingMode::Implicit, 2),
-        // Immediate     ORA #$44      $09  2   2
-        // Zero Page     ORA $44       $05  2   3
-        // Zero Page,X   ORA $44,X     $15  2   4
-        // Absolute      ORA $4400     $0D  3   4
-        // Absolute,X    ORA $4400,X   $1D  3   4+
-        // Absolute,Y    ORA $4400,Y   $19  3   4+
-        // Indirect,X    ORA ($44,X)   $01  2   6
-        // Indirect,Y    ORA ($44),Y   $11  2   5+
-        0x09 => (Opcode::ORA, AddressingMode::Immediate, 2),
-        0x05 => (Opcode::ORA, AddressingMode::ZeroPage, 3),
-        0x15 => (Opcode::ORA, AddressingMode::ZeroPageIndexX, 4),
-        0x0D => (Opcode::ORA, AddressingMode::Absolute, 4),
-        0x1D => (Opcode::ORA, AddressingMode::AbsoluteIndexX, 4),
-        0x19 => (Opcode::ORA, AddressingMode::AbsoluteIndexY, 4),
-        0x01 => (Opcode::ORA, AddressingMode::IndirectX, 6),
-        0x11 => (Opcode::ORA, AddressingMode::IndirectY, 5),
-        // TAX (Transfer A to X)    $AA
-        // TXA (Transfer X to A)    $8A
-        // DEX (DEcrement X)        $CA
-        // INX (INcrement X)        $E8
-        // TAY (Transfer A to Y)    $A8
-        // TYA (Transfer Y to A)    $98
-        // DEY (DEcrement Y)        $88
-        // INY (INcrement Y)        $C8
-        0xAA => (Opcode::TAX, AddressingMode::Implicit, 2),
-        0x8A => (Opcode::TXA, AddressingMode::Implicit, 2),
-        0xCA => (Opcode::DEX, AddressingMode::Implicit, 2),
-        0xE8 => (Opcode::INX, AddressingMode::Implicit, 2),
-        0xA8 => (Opcode::TAY, AddressingMode::Implicit, 2),
-        0x98 => (Opcode::TYA, AddressingMode::Implicit, 2),
-        0x88 => (Opcode::DEY, AddressingMode::Implicit, 2),
-        0xC8 => (Opcode::INY, AddressingMode::Implicit, 2),
-        // Accumulator   ROL A         $2A  1   2
-        // Zero Page     ROL $44       $26  2   5
-        // Zero Page,X   ROL $44,X     $36  2   6
-        // Absolute      ROL $4400     $2E  3   6
-        // Absolute,X    ROL $4400,X   $3E  3   7
-        0x2A => (Opcode::ROL, AddressingMode::Accumulator, 2),
-        0x26 => (Opcode::ROL, AddressingMode::ZeroPage, 5),
-        0x36 => (Opcode::ROL, AddressingMode::ZeroPageIndexX, 6),
-        0x2E => (Opcode::ROL, AddressingMode::Absolute, 6),
-        0x3E => (Opcode::ROL, AddressingMode::AbsoluteIndexX, 7),
-        // Accumulator   ROR A         $6A  1   2
-        // Zero Page     ROR $44       $66  2   5
-        // Zero Page,X   ROR $44,X     $76  2   6
-        // Absolute      ROR $4400     $6E  3   6
-        // Absolute,X    ROR $4400,X   $7E  3   7
-        0x6A => (Opcode::ROR, AddressingMode::Accumulator, 2),
-        0x66 => (Opcode::ROR, AddressingMode::ZeroPage, 5),
-        0x76 => (Opcode::ROR, AddressingMode::ZeroPageIndexX, 6),
-        0x6E => (Opcode::ROR, AddressingMode::Absolute, 6),
-        0x7E => (Opcode::ROR, AddressingMode::AbsoluteIndexX, 7),
-        // Implied       RTI           $40  1   6
-        0x40 => (Opcode::RTI, AddressingMode::Implicit, 6),
-        // Implied       RTS           $60  1   6
-        0x60 => (Opcode::RTS, AddressingMode::Implicit, 6),
-        // Immediate     SBC #$44      $E9  2   2
-        // Zero Page     SBC $44       $E5  2   3
-        // Zero Page,X   SBC $44,X     $F5  2   4
-        // Absolute      SBC $4400     $ED  3   4
-        // Absolute,X    SBC $4400,X   $FD  3   4+
-        // Absolute,Y    SBC $4400,Y   $F9  3   4+
-        // Indirect,X    SBC ($44,X)   $E1  2   6
-        // Indirect,Y    SBC ($44),Y   $F1  2   5+
-        0xE9 => (Opcode::SBC, AddressingMode::Immediate, 2),
-        0xE5 => (Opcode::SBC, AddressingMode::ZeroPage, 3),
-        0xF5 => (Opcode::SBC, AddressingMode::ZeroPageIndexX, 4),
-        0xED => (Opcode::SBC, AddressingMode::Absolute, 4),
-        0xFD => (Opcode::SBC, AddressingMode::AbsoluteIndexX, 4),
-        0xF9 => (Opcode::SBC, AddressingMode::AbsoluteIndexY, 4),
-        0xE1 => (Opcode::SBC, AddressingMode::IndirectX, 6),
-        0xF1 => (Opcode::SBC, AddressingMode::IndirectY, 5),
-        // Zero Page     STA $44       $85  2   3
-        // Zero Page,X   STA $44,X     $95  2   4
-        // Absolute      STA $4400     $8D  3   4
-        // Absolute,X    STA $4400,X   $9D  3   5
-        // Absolute,Y    STA $4400,Y   $99  3   5
-        // Indirect,X    STA ($44,X)   $81  2   6
-        // Indirect,Y    STA ($44),Y   $91  2   6
-        0x85 => (Opcode::STA, AddressingMode::ZeroPage, 3),
-        0x95 => (Opcode::STA, AddressingMode::ZeroPageIndexX, 4),
-        0x8D => (Opcode::STA, AddressingMode::Absolute, 4),
-        0x9D => (Opcode::STA, AddressingMode::AbsoluteIndexX, 5),
-        0x99 => (Opcode::STA, AddressingMode::AbsoluteIndexY, 5),
-        0x81 => (Opcode::STA, AddressingMode::IndirectX, 6),
-        0x91 => (Opcode::STA, AddressingMode::IndirectY, 6),
-        // TXS (Transfer X to Stack ptr)   $9A  2
-        // TSX (Transfer Stack ptr to X)   $BA  2
-        // PHA (PusH Accumulator)          $48  3
-        // PLA (PuLl Accumulator)          $68  4
-        // PHP (PusH Processor status)     $08  3
-        // PLP (PuLl Processor status)     $28  4
-        0x9A => (Opcode::TXS, AddressingMode::Implicit, 2),
-        0xBA => (Opcode::TSX, AddressingMode::Implicit, 2),
-        0x48 => (Opcode::PHA, AddressingMode::Implicit, 3),
-        0x68 => (Opcode::PLA, AddressingMode::Implicit, 4),
-        0x08 => (Opcode::PHP, AddressingMode::Implicit, 3),
-        0x28 => (Opcode::PLP, AddressingMode::Implicit, 4),
-        // Zero Page     STX $44       $86  2   3
-        // Zero Page,Y   STX $44,Y     $96  2   4
-        // Absolute      STX $4400     $8E  3   4
-        0x86 => (Opcode::STX, AddressingMode::ZeroPage, 3),
-        0x96 => (Opcode::STX, AddressingMode::ZeroPageIndexY, 4),
-        0x8E => (Opcode::STX, AddressingMode::Absolute, 4),
-        // Zero Page     STY $44       $84  2   3
-        // Zero Page,X   STY $44,X     $94  2   4
-        // Absolute      STY $4400     $8C  3   4
-        0x84 => (Opcode::STY, AddressingMode::ZeroPage, 3),
-        0x94 => (Opcode::STY, AddressingMode::ZeroPageIndexX, 4),
-        0x8C => (Opcode::STY, AddressingMode::Absolute, 4),
-        _ => return Err(format!("Opcode not implemented {:02x}", opcode)),
-    };
-    Ok(result)
+/// Everything known about an opcode before running it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    pub mode: AddressingMode,
+    // Bytes including the opcode
+    pub size: u8,
+    // Cycles without any penalty
+    pub cycles: CpuCycleUnit,
+    // One more cycle when indexing crosses a page. Branches have their own penalties, see
+    // CpuAction::compute_extra_cycles.
+    pub page_cross_penalty: bool,
+    // Documented by MOS
+    pub official: bool,
+}
+
+// A row of OPCODES, the raw opcode and what it decodes to
+struct Entry(u8, OpcodeInfo);
+
+impl Entry {
+    // The "+" cycle counts of the reference tables
+    const fn page_cross(mut self) -> Self {
+        self.1.page_cross_penalty = true;
+        self
+    }
+
+    const fn unofficial(mut self) -> Self {
+        self.1.official = false;
+        self
+    }
+}
+
+const fn op(raw_opcode: u8, opcode: Opcode, mode: AddressingMode, cycles: CpuCycleUnit) -> Entry {
+    Entry(
+        raw_opcode,
+        OpcodeInfo {
+            opcode,
+            mode,
+            size: mode.size(),
+            cycles,
+            page_cross_penalty: false,
+            official: true,
+        },
+    )
+}
+
+// Used this reference for decoding opcodes to Opcode addressing mode pairs
+// Ref: http://www.6502.org/tutorials/6502opcodes.html#LDA
+const OPCODES: &[Entry] = &[
+    // Immediate     ADC #$44      $69  2   2
+    // Zero Page     ADC $44       $65  2   3
+    // Zero Page,X   ADC $44,X     $75  2   4
+    // Absolute      ADC $4400     $6D  3   4
+    // Absolute,X    ADC $4400,X   $7D  3   4+
+    // Absolute,Y    ADC $4400,Y   $79  3   4+
+    // Indirect,X    ADC ($44,X)   $61  2   6
+    // Indirect,Y    ADC ($44),Y   $71  2   5+
+    op(0x69, ADC, Immediate, 2),
+    op(0x65, ADC, ZeroPage, 3),
+    op(0x75, ADC, ZeroPageIndexX, 4),
+    op(0x6D, ADC, Absolute, 4),
+    op(0x7D, ADC, AbsoluteIndexX, 4).page_cross(),
+    op(0x79, ADC, AbsoluteIndexY, 4).page_cross(),
+    op(0x61, ADC, IndirectX, 6),
+    op(0x71, ADC, IndirectY, 5).page_cross(),
+    // Immediate     AND #$44      $29  2   2
+    // Zero Page     AND $44       $25  2   3
+    // Zero Page,X   AND $44,X     $35  2   4
+    // Absolute      AND $4400     $2D  3   4
+    // Absolute,X    AND $4400,X   $3D  3   4+
+    // Absolute,Y    AND $4400,Y   $39  3   4+
+    // Indirect,X    AND ($44,X)   $21  2   6
+    // Indirect,Y    AND ($44),Y   $31  2   5+
+    op(0x29, AND, Immediate, 2),
+    op(0x25, AND, ZeroPage, 3),
+    op(0x35, AND, ZeroPageIndexX, 4),
+    op(0x2D, AND, Absolute, 4),
+    op(0x3D, AND, AbsoluteIndexX, 4).page_cross(),
+    op(0x39, AND, AbsoluteIndexY, 4).page_cross(),
+    op(0x21, AND, IndirectX, 6),
+    op(0x31, AND, IndirectY, 5).page_cross(),
+    // Accumulator   ASL A         $0A  1   2
+    // Zero Page     ASL $44       $06  2   5
+    // Zero Page,X   ASL $44,X     $16  2   6
+    // Absolute      ASL $4400     $0E  3   6
+    // Absolute,X    ASL $4400,X   $1E  3   7
+    op(0x0A, ASL, Accumulator, 2),
+    op(0x06, ASL, ZeroPage, 5),
+    op(0x16, ASL, ZeroPageIndexX, 6),
+    op(0x0E, ASL, Absolute, 6),
+    op(0x1E, ASL, AbsoluteIndexX, 7),
+    // BPL (Branch on PLus)           $10
+    // BMI (Branch on MInus)          $30
+    // BVC (Branch on oVerflow Clear) $50
+    // BVS (Branch on oVerflow Set)   $70
+    // BCC (Branch on Carry Clear)    $90
+    // BCS (Branch on Carry Set)      $B0
+    // BNE (Branch on Not Equal)      $D0
+    // BEQ (Branch on EQual)          $F0
+    op(0x10, BPL, Relative, 2),
+    op(0x30, BMI, Relative, 2),
+    op(0x50, BVC, Relative, 2),
+    op(0x70, BVS, Relative, 2),
+    op(0x90, BCC, Relative, 2),
+    op(0xB0, BCS, Relative, 2),
+    op(0xD0, BNE, Relative, 2),
+    op(0xF0, BEQ, Relative, 2),
+    // Zero Page     BIT $44       $24  2   3
+    // Absolute      BIT $4400     $2C  3   4
+    op(0x24, BIT, ZeroPage, 3),
+    op(0x2C, BIT, Absolute, 4),
+    // Implied       BRK           $00  1   7
+    op(0x00, BRK, Implicit, 7),
+    // Immediate     CMP #$44      $C9  2   2
+    // Zero Page     CMP $44       $C5  2   3
+    // Zero Page,X   CMP $44,X     $D5  2   4
+    // Absolute      CMP $4400     $CD  3   4
+    // Absolute,X    CMP $4400,X   $DD  3   4+
+    // Absolute,Y    CMP $4400,Y   $D9  3   4+
+    // Indirect,X    CMP ($44,X)   $C1  2   6
+    // Indirect,Y    CMP ($44),Y   $D1  2   5+
+    op(0xC9, CMP, Immediate, 2),
+    op(0xC5, CMP, ZeroPage, 3),
+    op(0xD5, CMP, ZeroPageIndexX, 4),
+    op(0xCD, CMP, Absolute, 4),
+    op(0xDD, CMP, AbsoluteIndexX, 4).page_cross(),
+    op(0xD9, CMP, AbsoluteIndexY, 4).page_cross(),
+    op(0xC1, CMP, IndirectX, 6),
+    op(0xD1, CMP, IndirectY, 5).page_cross(),
+    // Immediate     CPX #$44      $E0  2   2
+    // Zero Page     CPX $44       $E4  2   3
+    // Absolute      CPX $4400     $EC  3   4
+    op(0xE0, CPX, Immediate, 2),
+    op(0xE4, CPX, ZeroPage, 3),
+    op(0xEC, CPX, Absolute, 4),
+    // Immediate     CPY #$44      $C0  2   2
+    // Zero Page     CPY $44       $C4  2   3
+    // Absolute      CPY $4400     $CC  3   4
+    op(0xC0, CPY, Immediate, 2),
+    op(0xC4, CPY, ZeroPage, 3),
+    op(0xCC, CPY, Absolute, 4),
+    // Zero Page     DEC $44       $C6  2   5
+    // Zero Page,X   DEC $44,X     $D6  2   6
+    // Absolute      DEC $4400     $CE  3   6
+    // Absolute,X    DEC $4400,X   $DE  3   7
+    op(0xC6, DEC, ZeroPage, 5),
+    op(0xD6, DEC, ZeroPageIndexX, 6),
+    op(0xCE, DEC, Absolute, 6),
+    op(0xDE, DEC, AbsoluteIndexX, 7),
+    // Immediate     EOR #$44      $49  2   2
+    // Zero Page     EOR $44       $45  2   3
+    // Zero Page,X   EOR $44,X     $55  2   4
+    // Absolute      EOR $4400     $4D  3   4
+    // Absolute,X    EOR $4400,X   $5D  3   4+
+    // Absolute,Y    EOR $4400,Y   $59  3   4+
+    // Indirect,X    EOR ($44,X)   $41  2   6
+    // Indirect,Y    EOR ($44),Y   $51  2   5+
+    op(0x49, EOR, Immediate, 2),
+    op(0x45, EOR, ZeroPage, 3),
+    op(0x55, EOR, ZeroPageIndexX, 4),
+    op(0x4D, EOR, Absolute, 4),
+    op(0x5D, EOR, AbsoluteIndexX, 4).page_cross(),
+    op(0x59, EOR, AbsoluteIndexY, 4).page_cross(),
+    op(0x41, EOR, IndirectX, 6),
+    op(0x51, EOR, IndirectY, 5).page_cross(),
+    // CLC (CLear Carry)              $18
+    // SEC (SEt Carry)                $38
+    // CLI (CLear Interrupt)          $58
+    // SEI (SEt Interrupt)            $78
+    // CLV (CLear oVerflow)           $B8
+    // CLD (CLear Decimal)            $D8
+    // SED (SEt Decimal)              $F8
+    op(0x18, CLC, Implicit, 2),
+    op(0x38, SEC, Implicit, 2),
+    op(0x58, CLI, Implicit, 2),
+    op(0x78, SEI, Implicit, 2),
+    op(0xB8, CLV, Implicit, 2),
+    op(0xD8, CLD, Implicit, 2),
+    op(0xF8, SED, Implicit, 2),
+    // Zero Page     INC $44       $E6  2   5
+    // Zero Page,X   INC $44,X     $F6  2   6
+    // Absolute      INC $4400     $EE  3   6
+    // Absolute,X    INC $4400,X   $FE  3   7
+    op(0xE6, INC, ZeroPage, 5),
+    op(0xF6, INC, ZeroPageIndexX, 6),
+    op(0xEE, INC, Absolute, 6),
+    op(0xFE, INC, AbsoluteIndexX, 7),
+    // Absolute      JMP $5597     $4C  3   3
+    // Indirect      JMP ($5597)   $6C  3   5
+    op(0x4C, JMP, AbsoluteJump, 3),
+    op(0x6C, JMP, IndirectJump, 5),
+    // Absolute      JSR $5597     $20  3   6
+    op(0x20, JSR, AbsoluteJump, 6),
+    // Immediate     LDA #$44      $A9  2   2
+    // Zero Page     LDA $44       $A5  2   3
+    // Zero Page,X   LDA $44,X     $B5  2   4
+    // Absolute      LDA $4400     $AD  3   4
+    // Absolute,X    LDA $4400,X   $BD  3   4+
+    // Absolute,Y    LDA $4400,Y   $B9  3   4+
+    // Indirect,X    LDA ($44,X)   $A1  2   6
+    // Indirect,Y    LDA ($44),Y   $B1  2   5+
+    op(0xA9, LDA, Immediate, 2),
+    op(0xA5, LDA, ZeroPage, 3),
+    op(0xB5, LDA, ZeroPageIndexX, 4),
+    op(0xAD, LDA, Absolute, 4),
+    op(0xBD, LDA, AbsoluteIndexX, 4).page_cross(),
+    op(0xB9, LDA, AbsoluteIndexY, 4).page_cross(),
+    op(0xA1, LDA, IndirectX, 6),
+    op(0xB1, LDA, IndirectY, 5).page_cross(),
+    // Immediate     LDX #$44      $A2  2   2
+    // Zero Page     LDX $44       $A6  2   3
+    // Zero Page,Y   LDX $44,Y     $B6  2   4
+    // Absolute      LDX $4400     $AE  3   4
+    // Absolute,Y    LDX $4400,Y   $BE  3   4+
+    op(0xA2, LDX, Immediate, 2),
+    op(0xA6, LDX, ZeroPage, 3),
+    op(0xB6, LDX, ZeroPageIndexY, 4),
+    op(0xAE, LDX, Absolute, 4),
+    op(0xBE, LDX, AbsoluteIndexY, 4).page_cross(),
+    // Immediate     LDY #$44      $A0  2   2
+    // Zero Page     LDY $44       $A4  2   3
+    // Zero Page,X   LDY $44,X     $B4  2   4
+    // Absolute      LDY $4400     $AC  3   4
+    // Absolute,X    LDY $4400,X   $BC  3   4+
+    op(0xA0, LDY, Immediate, 2),
+    op(0xA4, LDY, ZeroPage, 3),
+    op(0xB4, LDY, ZeroPageIndexX, 4),
+    op(0xAC, LDY, Absolute, 4),
+    op(0xBC, LDY, AbsoluteIndexX, 4).page_cross(),
+    // Accumulator   LSR A         $4A  1   2
+    // Zero Page     LSR $44       $46  2   5
+    // Zero Page,X   LSR $44,X     $56  2   6
+    // Absolute      LSR $4400     $4E  3   6
+    // Absolute,X    LSR $4400,X   $5E  3   7
+    op(0x4A, LSR, Accumulator, 2),
+    op(0x46, LSR, ZeroPage, 5),
+    op(0x56, LSR, ZeroPageIndexX, 6),
+    op(0x4E, LSR, Absolute, 6),
+    op(0x5E, LSR, AbsoluteIndexX, 7),
+    // Implied       NOP           $EA  1   2
+    op(0xEA, NOP, Implicit, 2),
+    // Immediate     ORA #$44      $09  2   2
+    // Zero Page     ORA $44       $05  2   3
+    // Zero Page,X   ORA $44,X     $15  2   4
+    // Absolute      ORA $4400     $0D  3   4
+    // Absolute,X    ORA $4400,X   $1D  3   4+
+    // Absolute,Y    ORA $4400,Y   $19  3   4+
+    // Indirect,X    ORA ($44,X)   $01  2   6
+    // Indirect,Y    ORA ($44),Y   $11  2   5+
+    op(0x09, ORA, Immediate, 2),
+    op(0x05, ORA, ZeroPage, 3),
+    op(0x15, ORA, ZeroPageIndexX, 4),
+    op(0x0D, ORA, Absolute, 4),
+    op(0x1D, ORA, AbsoluteIndexX, 4).page_cross(),
+    op(0x19, ORA, AbsoluteIndexY, 4).page_cross(),
+    op(0x01, ORA, IndirectX, 6),
+    op(0x11, ORA, IndirectY, 5).page_cross(),
+    // TAX (Transfer A to X)    $AA
+    // TXA (Transfer X to A)    $8A
+    // DEX (DEcrement X)        $CA
+    // INX (INcrement X)        $E8
+    // TAY (Transfer A to Y)    $A8
+    // TYA (Transfer Y to A)    $98
+    // DEY (DEcrement Y)        $88
+    // INY (INcrement Y)        $C8
+    op(0xAA, TAX, Implicit, 2),
+    op(0x8A, TXA, Implicit, 2),
+    op(0xCA, DEX, Implicit, 2),
+    op(0xE8, INX, Implicit, 2),
+    op(0xA8, TAY, Implicit, 2),
+    op(0x98, TYA, Implicit, 2),
+    op(0x88, DEY, Implicit, 2),
+    op(0xC8, INY, Implicit, 2),
+    // Accumulator   ROL A         $2A  1   2
+    // Zero Page     ROL $44       $26  2   5
+    // Zero Page,X   ROL $44,X     $36  2   6
+    // Absolute      ROL $4400     $2E  3   6
+    // Absolute,X    ROL $4400,X   $3E  3   7
+    op(0x2A, ROL, Accumulator, 2),
+    op(0x26, ROL, ZeroPage, 5),
+    op(0x36, ROL, ZeroPageIndexX, 6),
+    op(0x2E, ROL, Absolute, 6),
+    op(0x3E, ROL, AbsoluteIndexX, 7),
+    // Accumulator   ROR A         $6A  1   2
+    // Zero Page     ROR $44       $66  2   5
+    // Zero Page,X   ROR $44,X     $76  2   6
+    // Absolute      ROR $4400     $6E  3   6
+    // Absolute,X    ROR $4400,X   $7E  3   7
+    op(0x6A, ROR, Accumulator, 2),
+    op(0x66, ROR, ZeroPage, 5),
+    op(0x76, ROR, ZeroPageIndexX, 6),
+    op(0x6E, ROR, Absolute, 6),
+    op(0x7E, ROR, AbsoluteIndexX, 7),
+    // Implied       RTI           $40  1   6
+    op(0x40, RTI, Implicit, 6),
+    // Implied       RTS           $60  1   6
+    op(0x60, RTS, Implicit, 6),
+    // Immediate     SBC #$44      $E9  2   2
+    // Zero Page     SBC $44       $E5  2   3
+    // Zero Page,X   SBC $44,X     $F5  2   4
+    // Absolute      SBC $4400     $ED  3   4
+    // Absolute,X    SBC $4400,X   $FD  3   4+
+    // Absolute,Y    SBC $4400,Y   $F9  3   4+
+    // Indirect,X    SBC ($44,X)   $E1  2   6
+    // Indirect,Y    SBC ($44),Y   $F1  2   5+
+    op(0xE9, SBC, Immediate, 2),
+    op(0xE5, SBC, ZeroPage, 3),
+    op(0xF5, SBC, ZeroPageIndexX, 4),
+    op(0xED, SBC, Absolute, 4),
+    op(0xFD, SBC, AbsoluteIndexX, 4).page_cross(),
+    op(0xF9, SBC, AbsoluteIndexY, 4).page_cross(),
+    op(0xE1, SBC, IndirectX, 6),
+    op(0xF1, SBC, IndirectY, 5).page_cross(),
+    // Zero Page     STA $44       $85  2   3
+    // Zero Page,X   STA $44,X     $95  2   4
+    // Absolute      STA $4400     $8D  3   4
+    // Absolute,X    STA $4400,X   $9D  3   5
+    // Absolute,Y    STA $4400,Y   $99  3   5
+    // Indirect,X    STA ($44,X)   $81  2   6
+    // Indirect,Y    STA ($44),Y   $91  2   6
+    op(0x85, STA, ZeroPage, 3),
+    op(0x95, STA, ZeroPageIndexX, 4),
+    op(0x8D, STA, Absolute, 4),
+    op(0x9D, STA, AbsoluteIndexX, 5),
+    op(0x99, STA, AbsoluteIndexY, 5),
+    op(0x81, STA, IndirectX, 6),
+    op(0x91, STA, IndirectY, 6),
+    // TXS (Transfer X to Stack ptr)   $9A  2
+    // TSX (Transfer Stack ptr to X)   $BA  2
+    // PHA (PusH Accumulator)          $48  3
+    // PLA (PuLl Accumulator)          $68  4
+    // PHP (PusH Processor status)     $08  3
+    // PLP (PuLl Processor status)     $28  4
+    op(0x9A, TXS, Implicit, 2),
+    op(0xBA, TSX, Implicit, 2),
+    op(0x48, PHA, Implicit, 3),
+    op(0x68, PLA, Implicit, 4),
+    op(0x08, PHP, Implicit, 3),
+    op(0x28, PLP, Implicit, 4),
+    // Zero Page     STX $44       $86  2   3
+    // Zero Page,Y   STX $44,Y     $96  2   4
+    // Absolute      STX $4400     $8E  3   4
+    op(0x86, STX, ZeroPage, 3),
+    op(0x96, STX, ZeroPageIndexY, 4),
+    op(0x8E, STX, Absolute, 4),
+    // Zero Page     STY $44       $84  2   3
+    // Zero Page,X   STY $44,X     $94  2   4
+    // Absolute      STY $4400     $8C  3   4
+    op(0x84, STY, ZeroPage, 3),
+    op(0x94, STY, ZeroPageIndexX, 4),
+    op(0x8C, STY, Absolute, 4),
+    // Unofficial opcodes, most combine two official instructions sharing an addressing mode
+    // Ref: https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    // Ref: https://www.nesdev.org/undocumented_opcodes.txt
+    // Read-modify-write then ALU: SLO (ASL+ORA), RLA (ROL+AND), SRE (LSR+EOR), RRA (ROR+ADC),
+    // DCP (DEC+CMP), ISB (INC+SBC). No page cross penalty, like the official RMW instructions.
+    op(0x07, SLO, ZeroPage, 5).unofficial(),
+    op(0x17, SLO, ZeroPageIndexX, 6).unofficial(),
+    op(0x0F, SLO, Absolute, 6).unofficial(),
+    op(0x1F, SLO, AbsoluteIndexX, 7).unofficial(),
+    op(0x1B, SLO, AbsoluteIndexY, 7).unofficial(),
+    op(0x03, SLO, IndirectX, 8).unofficial(),
+    op(0x13, SLO, IndirectY, 8).unofficial(),
+    op(0x27, RLA, ZeroPage, 5).unofficial(),
+    op(0x37, RLA, ZeroPageIndexX, 6).unofficial(),
+    op(0x2F, RLA, Absolute, 6).unofficial(),
+    op(0x3F, RLA, AbsoluteIndexX, 7).unofficial(),
+    op(0x3B, RLA, AbsoluteIndexY, 7).unofficial(),
+    op(0x23, RLA, IndirectX, 8).unofficial(),
+    op(0x33, RLA, IndirectY, 8).unofficial(),
+    op(0x47, SRE, ZeroPage, 5).unofficial(),
+    op(0x57, SRE, ZeroPageIndexX, 6).unofficial(),
+    op(0x4F, SRE, Absolute, 6).unofficial(),
+    op(0x5F, SRE, AbsoluteIndexX, 7).unofficial(),
+    op(0x5B, SRE, AbsoluteIndexY, 7).unofficial(),
+    op(0x43, SRE, IndirectX, 8).unofficial(),
+    op(0x53, SRE, IndirectY, 8).unofficial(),
+    op(0x67, RRA, ZeroPage, 5).unofficial(),
+    op(0x77, RRA, ZeroPageIndexX, 6).unofficial(),
+    op(0x6F, RRA, Absolute, 6).unofficial(),
+    op(0x7F, RRA, AbsoluteIndexX, 7).unofficial(),
+    op(0x7B, RRA, AbsoluteIndexY, 7).unofficial(),
+    op(0x63, RRA, IndirectX, 8).unofficial(),
+    op(0x73, RRA, IndirectY, 8).unofficial(),
+    op(0xC7, DCP, ZeroPage, 5).unofficial(),
+    op(0xD7, DCP, ZeroPageIndexX, 6).unofficial(),
+    op(0xCF, DCP, Absolute, 6).unofficial(),
+    op(0xDF, DCP, AbsoluteIndexX, 7).unofficial(),
+    op(0xDB, DCP, AbsoluteIndexY, 7).unofficial(),
+    op(0xC3, DCP, IndirectX, 8).unofficial(),
+    op(0xD3, DCP, IndirectY, 8).unofficial(),
+    op(0xE7, ISB, ZeroPage, 5).unofficial(),
+    op(0xF7, ISB, ZeroPageIndexX, 6).unofficial(),
+    op(0xEF, ISB, Absolute, 6).unofficial(),
+    op(0xFF, ISB, AbsoluteIndexX, 7).unofficial(),
+    op(0xFB, ISB, AbsoluteIndexY, 7).unofficial(),
+    op(0xE3, ISB, IndirectX, 8).unofficial(),
+    op(0xF3, ISB, IndirectY, 8).unofficial(),
+    // SAX stores A & X, LAX loads A and X. LAX #i is unstable on real hardware.
+    op(0x87, SAX, ZeroPage, 3).unofficial(),
+    op(0x97, SAX, ZeroPageIndexY, 4).unofficial(),
+    op(0x8F, SAX, Absolute, 4).unofficial(),
+    op(0x83, SAX, IndirectX, 6).unofficial(),
+    op(0xAB, LAX, Immediate, 2).unofficial(),
+    op(0xA7, LAX, ZeroPage, 3).unofficial(),
+    op(0xB7, LAX, ZeroPageIndexY, 4).unofficial(),
+    op(0xAF, LAX, Absolute, 4).unofficial(),
+    op(0xBF, LAX, AbsoluteIndexY, 4).page_cross().unofficial(),
+    op(0xA3, LAX, IndirectX, 6).unofficial(),
+    op(0xB3, LAX, IndirectY, 5).page_cross().unofficial(),
+    // Immediate ALU combinations, and a copy of SBC #i
+    op(0x0B, ANC, Immediate, 2).unofficial(),
+    op(0x2B, ANC, Immediate, 2).unofficial(),
+    op(0x4B, ALR, Immediate, 2).unofficial(),
+    op(0x6B, ARR, Immediate, 2).unofficial(),
+    op(0xCB, AXS, Immediate, 2).unofficial(),
+    op(0x8B, XAA, Immediate, 2).unofficial(),
+    op(0xEB, SBC, Immediate, 2).unofficial(),
+    op(0xBB, LAS, AbsoluteIndexY, 4).page_cross().unofficial(),
+    // Stores ANDed with the high byte of the address plus one
+    op(0x93, AHX, IndirectY, 6).unofficial(),
+    op(0x9F, AHX, AbsoluteIndexY, 5).unofficial(),
+    op(0x9E, SHX, AbsoluteIndexY, 5).unofficial(),
+    op(0x9C, SHY, AbsoluteIndexX, 5).unofficial(),
+    op(0x9B, TAS, AbsoluteIndexY, 5).unofficial(),
+    // NOPs, the ones with operands still read them
+    op(0x1A, NOP, Implicit, 2).unofficial(),
+    op(0x3A, NOP, Implicit, 2).unofficial(),
+    op(0x5A, NOP, Implicit, 2).unofficial(),
+    op(0x7A, NOP, Implicit, 2).unofficial(),
+    op(0xDA, NOP, Implicit, 2).unofficial(),
+    op(0xFA, NOP, Implicit, 2).unofficial(),
+    op(0x80, NOP, Immediate, 2).unofficial(),
+    op(0x82, NOP, Immediate, 2).unofficial(),
+    op(0x89, NOP, Immediate, 2).unofficial(),
+    op(0xC2, NOP, Immediate, 2).unofficial(),
+    op(0xE2, NOP, Immediate, 2).unofficial(),
+    op(0x04, NOP, ZeroPage, 3).unofficial(),
+    op(0x44, NOP, ZeroPage, 3).unofficial(),
+    op(0x64, NOP, ZeroPage, 3).unofficial(),
+    op(0x14, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0x34, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0x54, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0x74, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0xD4, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0xF4, NOP, ZeroPageIndexX, 4).unofficial(),
+    op(0x0C, NOP, Absolute, 4).unofficial(),
+    op(0x1C, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+    op(0x3C, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+    op(0x5C, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+    op(0x7C, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+    op(0xDC, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+    op(0xFC, NOP, AbsoluteIndexX, 4).page_cross().unofficial(),
+];
+
+/// Every opcode indexed by its raw byte, None for the JAM opcodes that halt the CPU until reset
+pub static OPCODE_TABLE: [Option<OpcodeInfo>; 256] = {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < OPCODES.len() {
+        let Entry(raw_opcode, info) = &OPCODES[i];
+        assert!(table[*raw_opcode as usize].is_none(), "Opcode listed twice");
+        table[*raw_opcode as usize] = Some(*info);
+        i += 1;
+    }
+    table
+};
+
+/// Decodes a raw byte to its Opcode, the AddressingMode describing how the instruction Param
+/// will be used, and its size and base cycles
+pub fn decode_opcode(opcode: u8) -> Result<&'static OpcodeInfo, String> {
+    OPCODE_TABLE[opcode as usize]
+        .as_ref()
+        .ok_or_else(|| format!("Opcode {:02x} jams the CPU", opcode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_opcode() {
+        let lda = decode_opcode(0xBD).unwrap();
+        assert_eq!(
+            (LDA, AbsoluteIndexX, 3, 4),
+            (lda.opcode, lda.mode, lda.size, lda.cycles)
+        );
+        assert!(lda.page_cross_penalty);
+        // Stores always take the extra cycle, it's in the base count
+        let sta = decode_opcode(0x9D).unwrap();
+        assert_eq!(5, sta.cycles);
+        assert!(!sta.page_cross_penalty);
+        assert_eq!(1, decode_opcode(0x0A).unwrap().size);
+        let lax = decode_opcode(0xB3).unwrap();
+        assert_eq!((LAX, IndirectY, 5), (lax.opcode, lax.mode, lax.cycles));
+        assert!(lax.page_cross_penalty && !lax.official);
+        let jams = [
+            0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+        ];
+        for opcode in 0..=0xFF {
+            assert_eq!(jams.contains(&opcode), decode_opcode(opcode).is_err());
+        }
+    }
 }
@@ -1,6 +1,6 @@
 mod decode;
 
-pub use decode::decode_opcode;
+pub use decode::{decode_opcode, OpcodeInfo, OPCODE_TABLE};
 
 // pub use parse::parse_instruction;
 
@@ -85,6 +85,26 @@ pub enum Opcode {
     STA,
     STX,
     STY,
+    // Unofficial instructions, named like nestest.log names them
+    // Ref: https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    LAX,
+    SAX,
+    DCP,
+    ISB,
+    SLO,
+    RLA,
+    SRE,
+    RRA,
+    ANC,
+    ALR,
+    ARR,
+    AXS,
+    LAS,
+    XAA,
+    AHX,
+    SHX,
+    SHY,
+    TAS,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -112,3 +132,24 @@ pub enum AddressingMode {
     IndirectX,      // val = peek(peek((arg + X) % 256) + PEEK((arg + X + 1) % 256) * 256)
     IndirectY,
 }
+
+impl AddressingMode {
+    /// Bytes taken by an instruction with this mode, including the opcode
+    pub const fn size(self) -> u8 {
+        match self {
+            AddressingMode::Implicit | AddressingMode::Accumulator => 1,
+            AddressingMode::Immediate
+            | AddressingMode::Relative
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageIndexX
+            | AddressingMode::ZeroPageIndexY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY => 2,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteJump
+            | AddressingMode::AbsoluteIndexX
+            | AddressingMode::AbsoluteIndexY
+            | AddressingMode::IndirectJump => 3,
+        }
+    }
+}
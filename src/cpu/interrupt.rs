@@ -1,4 +1,4 @@
-/**
+/*
  * https://www.nesdev.org/wiki/CPU_interrupts
  * https://www.nesdev.org/wiki/Status_flags
  *
@@ -14,9 +14,13 @@ pub enum InterruptKind {
 
 // TODO: some of these fields might be unnecessary
 pub struct Interrupt {
+    // Not read yet, the vector tells the interrupts apart
+    #[allow(dead_code)]
     pub kind: InterruptKind,
     pub vector: u16,
     pub is_set_b_flag: bool,
+    // Not read yet, BRK is the only software interrupt and sets the B flag
+    #[allow(dead_code)]
     pub is_hardware_interrupt: bool,
 }
 
@@ -27,10 +31,17 @@ pub const NMI_INTERRUPT: Interrupt = Interrupt {
     is_hardware_interrupt: true,
 };
 
-#[allow(dead_code)]
 pub const IRQ_INTERRUPT: Interrupt = Interrupt {
     kind: InterruptKind::IRQ,
     vector: 0xFFFE,
     is_set_b_flag: false,
     is_hardware_interrupt: true,
 };
+
+// BRK is a software interrupt sharing the IRQ vector, but pushes the status with B set
+pub const BRK_INTERRUPT: Interrupt = Interrupt {
+    kind: InterruptKind::BRK,
+    vector: 0xFFFE,
+    is_set_b_flag: true,
+    is_hardware_interrupt: false,
+};
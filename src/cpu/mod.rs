@@ -1,11 +1,18 @@
 mod cpu_action;
 mod cpu_bus;
 mod cpu_state;
+mod disassemble;
+mod flat_cpu;
 mod instructions;
 mod interrupt;
 
 pub use cpu_action::CpuAction;
 pub use cpu_bus::CpuBus;
-pub use cpu_state::{CpuState, CpuStatus};
+pub use cpu_state::{CpuState, CpuStatus, OpenBusModel, PRG_RAM_SIZE, PRG_RAM_START};
+pub use disassemble::{disassemble, disassemble_with, DisassembledLine};
+pub use flat_cpu::{run_program, FlatCpu};
 
-pub use self::instructions::{AddressingMode, Instruction, InstructionMetaData, Opcode, Param};
+pub use self::instructions::{
+    decode_opcode, AddressingMode, Instruction, InstructionMetaData, Opcode, OpcodeInfo, Param,
+    OPCODE_TABLE,
+};
@@ -0,0 +1,245 @@
+// Breakpoints, stepping and memory pokes, with a small command language for debug consoles
+//
+//     peek 0x0300 16    hexdump 16 bytes of CPU memory (length defaults to 1)
+//     poke 0x00FF 3     write a byte of RAM or PRG RAM
+//     undo              roll back the last poke
+//     break 0x8123      toggle a breakpoint, with no address lists them
+//     step              run one instruction
+//     frame             run until the next frame or breakpoint
+//     stalls            toggle warnings for games stuck polling $2002, see stall
+use std::collections::BTreeSet;
+
+use crate::common::hexdump;
+use crate::cpu::Instruction;
+use crate::history::HistoryEntry;
+use crate::nes::{format_emulated_time, ActionNES, NES};
+use crate::stall::DEFAULT_STALL_FRAMES;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    FrameDone,
+    // Stopped before running the instruction at this address
+    Breakpoint(u16),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+}
+
+/// Parses `0x1F`, `$1F` or decimal numbers
+fn parse_number(text: &str) -> Result<u16, String> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number {}", text))
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or removes a breakpoint, returns true if it was added
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.remove(&addr) {
+            false
+        } else {
+            self.breakpoints.insert(addr)
+        }
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    pub fn step(&self, nes: &mut ActionNES) -> Result<Instruction, String> {
+        nes.next_cpu_instruction()
+    }
+
+    /// Runs to the start of the next frame, stopping early at breakpoints. The instruction at
+    /// the current PC always runs, so calling this again continues past a breakpoint.
+    pub fn run_frame(&self, nes: &mut ActionNES) -> Result<StopReason, String> {
+        let mut is_first = true;
+        loop {
+            let program_counter = nes.cpu_state.program_counter;
+            if !is_first && self.breakpoints.contains(&program_counter) {
+                return Ok(StopReason::Breakpoint(program_counter));
+            }
+            is_first = false;
+            let scanline = nes.ppu_state.cur_scanline;
+            nes.next_cpu_instruction()?;
+            if nes.ppu_state.cur_scanline < scanline {
+                return Ok(StopReason::FrameDone);
+            }
+        }
+    }
+
+    /// Runs a console command, returning the text to show
+    pub fn execute(&mut self, nes: &mut ActionNES, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["peek", addr] | ["peek", addr, _] => {
+                let addr = parse_number(addr)?;
+                let length = match words.get(2) {
+                    Some(length) => parse_number(length)? as usize,
+                    None => 1,
+                };
+                Ok(hexdump(&nes.as_cpu_bus(), addr, length))
+            }
+            ["poke", addr, value] => {
+                let addr = parse_number(addr)?;
+                let value = u8::try_from(parse_number(value)?)
+                    .map_err(|_| format!("{} doesn't fit in a byte", value))?;
+                nes.with_memory_edit(|mem| mem.write(addr, value))?;
+                Ok(format!("{:04X} = {:02X}", addr, value))
+            }
+            ["undo"] => match nes.undo_memory_edit() {
+                Some(addrs) => {
+                    let values: Vec<String> = addrs
+                        .iter()
+                        .map(|&addr| format!("{:04X} = {:02X}", addr, nes.observe(&[addr])[0]))
+                        .collect();
+                    Ok(format!("Restored {}", values.join(", ")))
+                }
+                None => Err("Nothing to undo".to_string()),
+            },
+            ["break"] => {
+                let addrs: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .map(|a| format!("{:04X}", a))
+                    .collect();
+                Ok(format!("Breakpoints: {}", addrs.join(" ")))
+            }
+            ["break", addr] => {
+                let addr = parse_number(addr)?;
+                match self.toggle_breakpoint(addr) {
+                    true => Ok(format!("Breakpoint set at {:04X}", addr)),
+                    false => Ok(format!("Breakpoint removed at {:04X}", addr)),
+                }
+            }
+            ["step"] => {
+                let mut entry = HistoryEntry::new(&nes.cpu_state);
+                let instruction = self.step(nes)?;
+                entry.raw_opcode = Some(instruction.meta.raw_opcode);
+                Ok(format!("{}  {:?}", entry, instruction.opcode))
+            }
+            ["frame"] => match self.run_frame(nes)? {
+                StopReason::FrameDone => Ok(format!(
+                    "Frame {} done at {}, PC:{:04X}",
+                    nes.frame_count(),
+                    format_emulated_time(nes.emulated_duration()),
+                    nes.cpu_state.program_counter
+                )),
+                StopReason::Breakpoint(addr) => Ok(format!("Break at {:04X}", addr)),
+            },
+            ["stalls"] => match nes.stall_detector() {
+                Some(_) => {
+                    nes.disable_stall_detector();
+                    Ok("Stall detection off".to_string())
+                }
+                None => {
+                    nes.enable_stall_detector(DEFAULT_STALL_FRAMES);
+                    Ok("Stall detection on".to_string())
+                }
+            },
+            [] => Ok(String::new()),
+            _ => Err(format!("Unknown command {}", line.trim())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_kit::program_nes;
+
+    // LDA #$01, STA $10, JMP $8000
+    fn create_nes() -> ActionNES {
+        program_nes(&[0xA9, 0x01, 0x85, 0x10, 0x4C, 0x00, 0x80])
+    }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut nes = create_nes();
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            "0300 = 2A",
+            debugger.execute(&mut nes, "poke 0x0300 42").unwrap()
+        );
+        assert_eq!(
+            "0300: 2A 00\n",
+            debugger.execute(&mut nes, "peek $300 2").unwrap()
+        );
+        assert!(debugger.execute(&mut nes, "poke 0x0300 256").is_err());
+        assert!(debugger.execute(&mut nes, "poke 0x2000 1").is_err());
+        assert_eq!(
+            "Restored 0300 = 00",
+            debugger.execute(&mut nes, "undo").unwrap()
+        );
+        assert!(debugger.execute(&mut nes, "undo").is_err());
+        assert!(debugger.execute(&mut nes, "peek zz").is_err());
+        assert!(debugger.execute(&mut nes, "jump").is_err());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut nes = create_nes();
+        let mut debugger = Debugger::new();
+        let output = debugger.execute(&mut nes, "step").unwrap();
+        assert!(output.starts_with("8000  A9  A:00"));
+        assert!(output.ends_with("LDA"));
+        assert_eq!(0x01, nes.cpu_state.reg_a);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut nes = create_nes();
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut nes, "break 0x8004").unwrap();
+        assert_eq!(
+            "Break at 8004",
+            debugger.execute(&mut nes, "frame").unwrap()
+        );
+        assert_eq!(0x01, nes.cpu_state.ram[0x10]);
+        // Continues past the breakpoint, hitting it again after the jump
+        assert_eq!(
+            StopReason::Breakpoint(0x8004),
+            debugger.run_frame(&mut nes).unwrap()
+        );
+        assert_eq!(
+            "Breakpoint removed at 8004",
+            debugger.execute(&mut nes, "break $8004").unwrap()
+        );
+        assert_eq!(StopReason::FrameDone, debugger.run_frame(&mut nes).unwrap());
+    }
+
+    #[test]
+    fn test_stalls() {
+        let mut nes = create_nes();
+        // BIT $2002, BVC $8000: waits for a sprite 0 hit that never comes with rendering off
+        nes.rom.prg_rom[..5].copy_from_slice(&[0x2C, 0x02, 0x20, 0x50, 0xFB]);
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            "Stall detection on",
+            debugger.execute(&mut nes, "stalls").unwrap()
+        );
+        for _ in 0..=DEFAULT_STALL_FRAMES {
+            nes.next_ppu_frame().unwrap();
+        }
+        let stall = nes.stall_detector().unwrap().stall().unwrap();
+        assert_eq!(0x8000, stall.program_counter);
+        assert_eq!(
+            "Stall detection off",
+            debugger.execute(&mut nes, "stalls").unwrap()
+        );
+        assert!(nes.stall_detector().is_none());
+    }
+}
@@ -0,0 +1,389 @@
+// Exports the PRG ROM as a ca65 compatible .asm file
+// CDL format: https://fceux.com/web/help/CodeDataLogger.html
+//
+// Instructions come from cpu::disassemble_with, with labels and symbols as the names of the
+// addresses they refer to. Each bank is decoded twice, once to find the addresses that need
+// labels and again to write them in.
+use std::collections::{HashMap, HashSet};
+use std::fs::{read, read_to_string, write};
+
+use crate::cpu::{disassemble_with, AddressingMode, DisassembledLine};
+use crate::rom::ROM;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+// NMI, RESET and IRQ vectors at the end of the fixed bank
+const VECTORS_OFFSET: usize = 0x3FFA;
+const VECTOR_NAMES: [&str; 3] = ["nmi", "reset", "irq"];
+const BYTES_PER_LINE: usize = 8;
+
+// CDL flags for each PRG byte
+const CDL_CODE: u8 = 0b0000_0001;
+const CDL_DATA: u8 = 0b0000_0010;
+
+#[derive(Debug, Default, Clone)]
+pub struct DisasmOptions {
+    // Code/data log, one byte per PRG byte, used to mark data regions
+    pub cdl: Option<Vec<u8>>,
+    // Names for addresses, e.g. from an ld65 label file
+    pub symbols: HashMap<u16, String>,
+}
+
+enum Line {
+    Instruction(DisassembledLine),
+    Data {
+        address: u16,
+        bytes: Vec<u8>,
+    },
+    Vector {
+        address: u16,
+        name: &'static str,
+        target: u16,
+    },
+}
+
+impl Line {
+    fn address(&self) -> u16 {
+        match self {
+            Line::Instruction(line) => line.address,
+            Line::Data { address, .. } | Line::Vector { address, .. } => *address,
+        }
+    }
+}
+
+struct Bank {
+    index: usize,
+    origin: u16,
+    lines: Vec<Line>,
+    labels: HashMap<u16, String>,
+}
+
+// Adds a byte to the previous .byte line if there's room
+fn push_data(lines: &mut Vec<Line>, address: u16, byte: u8) {
+    match lines.last_mut() {
+        Some(Line::Data { bytes, .. }) if bytes.len() < BYTES_PER_LINE => bytes.push(byte),
+        _ => lines.push(Line::Data {
+            address,
+            bytes: vec![byte],
+        }),
+    }
+}
+
+// Instructions and .byte lines for `prg`, code is split where the CDL marks data so no
+// instruction runs into it
+fn decode_lines<F>(prg: &[u8], origin: u16, is_data: impl Fn(usize) -> bool, symbol: F) -> Vec<Line>
+where
+    F: Fn(u16, AddressingMode) -> Option<String>,
+{
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < prg.len() {
+        let data = is_data(start);
+        let end = (start..prg.len())
+            .find(|&offset| is_data(offset) != data)
+            .unwrap_or(prg.len());
+        let address = origin + start as u16;
+        if data {
+            for (i, &byte) in prg[start..end].iter().enumerate() {
+                push_data(&mut lines, address + i as u16, byte);
+            }
+        } else {
+            for line in disassemble_with(&prg[start..end], address, &symbol) {
+                if line.is_data() {
+                    push_data(&mut lines, line.address, line.bytes[0]);
+                } else {
+                    lines.push(Line::Instruction(line));
+                }
+            }
+        }
+        start = end;
+    }
+    lines
+}
+
+impl Bank {
+    fn new(rom: &ROM, index: usize, options: &DisasmOptions) -> Self {
+        let bank_count = rom.prg_rom.len().div_ceil(PRG_BANK_SIZE);
+        let is_fixed = index + 1 == bank_count;
+        // The last bank is fixed at $C000, others are switched into $8000 (NROM-128 is mirrored)
+        let origin = if is_fixed { 0xC000 } else { 0x8000 };
+        let start = index * PRG_BANK_SIZE;
+        let prg = &rom.prg_rom[start..(start + PRG_BANK_SIZE).min(rom.prg_rom.len())];
+        let code_end = if is_fixed && prg.len() == PRG_BANK_SIZE {
+            VECTORS_OFFSET
+        } else {
+            prg.len()
+        };
+
+        let is_data = |offset: usize| match &options.cdl {
+            Some(cdl) => cdl
+                .get(start + offset)
+                .is_some_and(|flags| flags & CDL_DATA != 0 && flags & CDL_CODE == 0),
+            None => false,
+        };
+
+        let code = &prg[..code_end];
+        let vectors = || {
+            let mut lines = Vec::new();
+            if code_end == VECTORS_OFFSET {
+                for (i, name) in VECTOR_NAMES.iter().enumerate() {
+                    let offset = VECTORS_OFFSET + 2 * i;
+                    lines.push(Line::Vector {
+                        address: origin + offset as u16,
+                        name,
+                        target: u16::from_le_bytes([prg[offset], prg[offset + 1]]),
+                    });
+                }
+            }
+            lines
+        };
+
+        let mut bank = Bank {
+            index,
+            origin,
+            lines: decode_lines(code, origin, is_data, |_, _| None),
+            labels: HashMap::new(),
+        };
+        bank.lines.extend(vectors());
+        bank.create_labels(is_fixed, options);
+        let mut lines = decode_lines(code, origin, is_data, |value, mode| {
+            bank.operand_name(value, mode, options)
+        });
+        lines.extend(vectors());
+        bank.lines = lines;
+        bank
+    }
+
+    // Labels every referenced address that starts a line
+    fn create_labels(&mut self, is_fixed: bool, options: &DisasmOptions) {
+        let starts: HashSet<u16> = self.lines.iter().map(Line::address).collect();
+        let mut targets = HashSet::new();
+        for line in &self.lines {
+            match line {
+                Line::Instruction(line) => targets.extend(line.target),
+                Line::Vector { target, .. } => {
+                    targets.insert(*target);
+                }
+                Line::Data { .. } => {}
+            }
+        }
+        for address in starts {
+            // Symbols have no bank information, so only use them in the fixed bank
+            let name = match options.symbols.get(&address) {
+                Some(name) if is_fixed => name.clone(),
+                _ if !targets.contains(&address) => continue,
+                _ if is_fixed => format!("L{:04X}", address),
+                _ => format!("B{}_{:04X}", self.index, address),
+            };
+            self.labels.insert(address, name);
+        }
+    }
+
+    fn name_for(&self, address: u16, options: &DisasmOptions) -> Option<String> {
+        if let Some(label) = self.labels.get(&address) {
+            return Some(label.clone());
+        }
+        if address >= 0x8000 {
+            return None;
+        }
+        options.symbols.get(&address).cloned()
+    }
+
+    // Absolute operands in the zero page are written with a: so ca65 doesn't shrink the
+    // instruction
+    fn operand_name(
+        &self,
+        value: u16,
+        mode: AddressingMode,
+        options: &DisasmOptions,
+    ) -> Option<String> {
+        let absolute = matches!(
+            mode,
+            AddressingMode::Absolute
+                | AddressingMode::AbsoluteJump
+                | AddressingMode::AbsoluteIndexX
+                | AddressingMode::AbsoluteIndexY
+                | AddressingMode::IndirectJump
+        );
+        match self.name_for(value, options) {
+            None if absolute && value < 0x100 => Some(format!("a:${:04X}", value)),
+            name => name,
+        }
+    }
+
+    fn format_line(&self, line: &Line, options: &DisasmOptions) -> String {
+        match line {
+            Line::Instruction(line) => {
+                let mnemonic = line.mnemonic.to_lowercase();
+                format!("{} {}", mnemonic, line.operand)
+                    .trim_end()
+                    .to_string()
+            }
+            Line::Data { bytes, .. } => {
+                let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                format!(".byte {}", values.join(", "))
+            }
+            Line::Vector { target, .. } => {
+                let value = self
+                    .name_for(*target, options)
+                    .unwrap_or_else(|| format!("${:04X}", target));
+                format!(".word {}", value)
+            }
+        }
+    }
+
+    fn write_asm(&self, out: &mut String, options: &DisasmOptions) {
+        out.push_str(&format!(
+            "\n.segment \"PRG{}\"\n.org ${:04X}\n\n",
+            self.index, self.origin
+        ));
+        for line in &self.lines {
+            let address = line.address();
+            if let Some(label) = self.labels.get(&address) {
+                out.push_str(&format!("{}:\n", label));
+            }
+            // Listing comment with the address and raw bytes
+            let comment = match line {
+                Line::Instruction(DisassembledLine { bytes, .. }) | Line::Data { bytes, .. } => {
+                    bytes
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                }
+                Line::Vector { name, .. } => name.to_string(),
+            };
+            out.push_str(&format!(
+                "    {:<32}; {:04X}  {}\n",
+                self.format_line(line, options),
+                address,
+                comment
+            ));
+        }
+    }
+}
+
+/// Disassembles every PRG bank, marking undecodable bytes and CDL data as .byte directives
+pub fn disassemble_rom(rom: &ROM, options: &DisasmOptions) -> String {
+    let mut out = String::from(".setcpu \"6502\"\n");
+    let mut symbols: Vec<(&u16, &String)> = options
+        .symbols
+        .iter()
+        .filter(|(address, _)| **address < 0x8000)
+        .collect();
+    symbols.sort();
+    if !symbols.is_empty() {
+        out.push('\n');
+    }
+    for (address, name) in symbols {
+        out.push_str(&format!("{} = ${:04X}\n", name, address));
+    }
+    let bank_count = rom.prg_rom.len().div_ceil(PRG_BANK_SIZE);
+    for index in 0..bank_count {
+        Bank::new(rom, index, options).write_asm(&mut out, options);
+    }
+    out
+}
+
+/// Parses an ld65 label file (`al 00C004 .reset`)
+pub fn parse_symbols(text: &str) -> Result<HashMap<u16, String>, String> {
+    let mut symbols = HashMap::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["al", address, name] => {
+                let address = u32::from_str_radix(address, 16)
+                    .map_err(|_| format!("Invalid symbol address in line: {}", line))?;
+                symbols.insert(address as u16, name.trim_start_matches('.').to_string());
+            }
+            _ => return Err(format!("Invalid symbol line: {}", line)),
+        }
+    }
+    Ok(symbols)
+}
+
+/// Writes the disassembly of the .nes file at `rom_path` to `out_path`
+pub fn export_asm(
+    rom_path: &str,
+    out_path: &str,
+    cdl_path: Option<&str>,
+    symbols_path: Option<&str>,
+) -> Result<(), String> {
+    let rom = ROM::create_from_nes(rom_path)?;
+    let mut options = DisasmOptions::default();
+    if let Some(path) = cdl_path {
+        options.cdl = Some(read(path).map_err(|err| err.to_string())?);
+    }
+    if let Some(path) = symbols_path {
+        options.symbols = parse_symbols(&read_to_string(path).map_err(|err| err.to_string())?)?;
+    }
+    write(out_path, disassemble_rom(&rom, &options)).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA #$01, loop: BNE loop, JMP $C000, then a table
+    fn create_rom() -> ROM {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0xEA; PRG_BANK_SIZE];
+        rom.prg_rom[..10]
+            .copy_from_slice(&[0xA9, 0x01, 0xD0, 0xFE, 0x4C, 0x00, 0xC0, 0x02, 0x12, 0x34]);
+        rom.prg_rom[VECTORS_OFFSET..].copy_from_slice(&[0x00, 0xC0, 0x00, 0xC0, 0x02, 0xC0]);
+        rom
+    }
+
+    #[test]
+    fn test_disassemble_rom() {
+        let asm = disassemble_rom(&create_rom(), &DisasmOptions::default());
+        assert!(asm.contains(".org $C000"));
+        assert!(asm.contains("LC000:\n    lda #$01"));
+        assert!(asm.contains("LC002:\n    bne LC002"));
+        assert!(asm.contains("jmp LC000"));
+        // $02 isn't an opcode so it becomes data, along with the bytes after it
+        assert!(asm.contains(".byte $02"));
+        assert!(asm.contains(".word LC000"));
+        assert!(asm.contains(".word LC002"));
+    }
+
+    #[test]
+    fn test_cdl_marks_data() {
+        let mut cdl = vec![CDL_CODE; PRG_BANK_SIZE];
+        cdl[0] = CDL_DATA;
+        cdl[1] = CDL_DATA;
+        let options = DisasmOptions {
+            cdl: Some(cdl),
+            ..Default::default()
+        };
+        let asm = disassemble_rom(&create_rom(), &options);
+        assert!(asm.contains(".byte $A9, $01"));
+        assert!(!asm.contains("lda #$01"));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = parse_symbols("al 00C000 .reset\nal 002000 .PPUCTRL\n").unwrap();
+        let mut rom = create_rom();
+        rom.prg_rom[0x10..0x13].copy_from_slice(&[0x8D, 0x00, 0x20]);
+        rom.prg_rom[0x13..0x16].copy_from_slice(&[0xAD, 0x10, 0x00]);
+        let options = DisasmOptions {
+            symbols,
+            ..Default::default()
+        };
+        let asm = disassemble_rom(&rom, &options);
+        assert!(asm.contains("PPUCTRL = $2000"));
+        assert!(asm.contains("reset:\n    lda #$01"));
+        assert!(asm.contains("jmp reset"));
+        assert!(asm.contains("sta PPUCTRL"));
+        assert!(asm.contains("lda a:$0010"));
+    }
+
+    #[test]
+    fn test_multiple_banks() {
+        let mut rom = create_rom();
+        rom.prg_rom = [vec![0x60; PRG_BANK_SIZE], rom.prg_rom].concat();
+        let asm = disassemble_rom(&rom, &DisasmOptions::default());
+        assert!(asm.contains(".segment \"PRG0\"\n.org $8000"));
+        assert!(asm.contains(".segment \"PRG1\"\n.org $C000"));
+    }
+}
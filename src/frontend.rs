@@ -0,0 +1,333 @@
+// Traits for embedding the emulator in a custom frontend without SDL
+#[cfg(not(feature = "minimal"))]
+use std::fs::{File, OpenOptions};
+#[cfg(not(feature = "minimal"))]
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+
+use crate::{controller::ControllerState, nes::NES, peripheral::OutputLatch, screen::frame::Frame};
+
+/// Receives every rendered frame, e.g. a window, an encoder or a file writer
+pub trait VideoSink {
+    fn present_frame(&mut self, frame: &Frame) -> Result<(), String>;
+}
+
+/// Provides the controller state to use for the next frame
+pub trait InputPort {
+    fn poll_input(&mut self) -> ControllerState;
+}
+
+/// Receives the console's output pins after every frame, e.g. to drive gamepad rumble
+pub trait OutputPort {
+    fn update_outputs(&mut self, latch: OutputLatch);
+}
+
+/// Input port with no buttons held, useful for headless runs
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullInput;
+
+impl InputPort for NullInput {
+    fn poll_input(&mut self) -> ControllerState {
+        ControllerState::empty()
+    }
+}
+
+/// Buttons from key events, which can arrive any number of times per frame. A press is kept until
+/// the next poll even if the button was released before it, so quick taps between frames (or
+/// within one slow frame) still reach the game.
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonLatch {
+    held: ControllerState,
+    // Pressed since the last poll
+    pressed: ControllerState,
+}
+
+impl Default for ButtonLatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ButtonLatch {
+    pub fn new() -> Self {
+        ButtonLatch {
+            held: ControllerState::empty(),
+            pressed: ControllerState::empty(),
+        }
+    }
+
+    pub fn press(&mut self, buttons: ControllerState) {
+        self.held.insert(buttons);
+        self.pressed.insert(buttons);
+    }
+
+    pub fn release(&mut self, buttons: ControllerState) {
+        self.held.remove(buttons);
+    }
+
+    /// Replaces the held buttons, dropping pending taps, for input that comes once per frame
+    pub fn set(&mut self, buttons: ControllerState) {
+        self.held = buttons;
+        self.pressed = ControllerState::empty();
+    }
+}
+
+impl InputPort for ButtonLatch {
+    fn poll_input(&mut self) -> ControllerState {
+        let state = self.held | self.pressed;
+        self.pressed = ControllerState::empty();
+        state
+    }
+}
+
+/// When buttons from the frontend become visible to the game
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputLatch {
+    // Once per frame at the start of vblank, so every read within a frame agrees even for games
+    // that read the controller several times to work around DMC corruption
+    #[default]
+    Vblank,
+    // As soon as the frontend has them, at the start of the frame
+    Immediate,
+}
+
+impl InputLatch {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "vblank" => Ok(InputLatch::Vblank),
+            "immediate" => Ok(InputLatch::Immediate),
+            _ => Err(format!("Unknown input latch {}", name)),
+        }
+    }
+}
+
+/// Input driven by an external program over a line based stream (stdin or a named pipe)
+///
+/// Before every frame the frame number is written as a line, then one line is read with
+/// the controller bitmask for that frame (decimal, `0x` hex or `0b` binary, bit 0 = A ...
+/// bit 7 = Right). Empty lines, bad lines and end of input keep the previous buttons held.
+#[cfg(not(feature = "minimal"))]
+pub struct StreamInput<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+    frame: u64,
+    state: ControllerState,
+    is_closed: bool,
+}
+
+#[cfg(not(feature = "minimal"))]
+impl<R: BufRead, W: Write> StreamInput<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        StreamInput {
+            reader,
+            writer,
+            frame: 0,
+            state: ControllerState::empty(),
+            is_closed: false,
+        }
+    }
+}
+
+/// Parses a controller bitmask in decimal, `0x` hex or `0b` binary, as sent to StreamInput
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn parse_buttons(line: &str) -> Option<ControllerState> {
+    let line = line.trim();
+    let bits = if let Some(hex) = line.strip_prefix("0x") {
+        u8::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = line.strip_prefix("0b") {
+        u8::from_str_radix(binary, 2).ok()?
+    } else {
+        line.parse().ok()?
+    };
+    Some(ControllerState::from_bits_retain(bits))
+}
+
+#[cfg(not(feature = "minimal"))]
+impl StreamInput<BufReader<Stdin>, Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+#[cfg(not(feature = "minimal"))]
+impl StreamInput<BufReader<File>, File> {
+    /// Opens named pipes (e.g. made with mkfifo) for reading input and writing frame numbers
+    pub fn open_fifo(input_path: &str, output_path: &str) -> Result<Self, String> {
+        let input = File::open(input_path).map_err(|err| err.to_string())?;
+        let output = OpenOptions::new()
+            .write(true)
+            .open(output_path)
+            .map_err(|err| err.to_string())?;
+        Ok(Self::new(BufReader::new(input), output))
+    }
+}
+
+#[cfg(not(feature = "minimal"))]
+impl<R: BufRead, W: Write> InputPort for StreamInput<R, W> {
+    fn poll_input(&mut self) -> ControllerState {
+        if self.is_closed {
+            return self.state;
+        }
+        if writeln!(self.writer, "{}", self.frame)
+            .and_then(|_| self.writer.flush())
+            .is_err()
+        {
+            log::warn!("Input driver stopped reading frame numbers");
+            self.is_closed = true;
+        }
+        self.frame += 1;
+
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => self.is_closed = true,
+            Ok(_) if line.trim().is_empty() => {}
+            Ok(_) => match parse_buttons(&line) {
+                Some(state) => self.state = state,
+                None => log::warn!("Invalid input line {:?}", line.trim()),
+            },
+        }
+        self.state
+    }
+}
+
+// NTSC CPU clock in Hz
+pub const CPU_CLOCK: u64 = 1_789_773;
+// For audio, which is floating point anyway
+pub const CPU_FREQUENCY: f64 = CPU_CLOCK as f64;
+
+/// Converts audio buffer sizes into CPU cycles, for frontends where emulation is paced by the
+/// audio device. Fractional cycles and the overshoot of the last instruction carry over to the
+/// next buffer so the emulated time never drifts from the audio clock. The math is in integers,
+/// so the same buffers run the same cycles on every platform.
+#[derive(Debug, Clone)]
+pub struct CycleBudget {
+    sample_rate: i64,
+    // Cycles owed to (positive) or run ahead of (negative) the audio clock, in 1/sample_rate
+    // cycles
+    balance: i64,
+}
+
+impl CycleBudget {
+    pub fn new(sample_rate: u32) -> Self {
+        CycleBudget {
+            sample_rate: sample_rate.max(1) as i64,
+            balance: 0,
+        }
+    }
+
+    /// Runs the cycles covered by `samples` audio samples, returns the cycles actually run
+    pub fn run_for_samples(&mut self, nes: &mut impl NES, samples: usize) -> Result<usize, String> {
+        self.balance += samples as i64 * CPU_CLOCK as i64;
+        let owed = self.balance / self.sample_rate;
+        if owed < 1 {
+            return Ok(0);
+        }
+        let start = nes.peek_cpu_state().cycle_counter;
+        nes.next_cpu_cycles(owed as usize)?;
+        let cycles = nes.peek_cpu_state().cycle_counter - start;
+        self.balance -= cycles as i64 * self.sample_rate;
+        Ok(cycles)
+    }
+}
+
+/// Runs `frames` frames, sampling input before and presenting video after every frame
+pub fn run_frames(
+    nes: &mut (impl NES + ?Sized),
+    video: &mut impl VideoSink,
+    input: &mut impl InputPort,
+    frames: usize,
+) -> Result<(), String> {
+    let mut frame = Frame::new();
+    for _ in 0..frames {
+        let state = input.poll_input();
+        nes.update_controller(0, ControllerState::all(), false);
+        nes.update_controller(0, state, true);
+
+        nes.next_ppu_frame()?;
+        nes.render_frame(&mut frame);
+        video.present_frame(&frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ActionNES;
+
+    struct CountingSink {
+        count: usize,
+    }
+
+    impl VideoSink for CountingSink {
+        fn present_frame(&mut self, _frame: &Frame) -> Result<(), String> {
+            self.count += 1;
+            Ok(())
+        }
+    }
+
+    struct HoldStart;
+
+    impl InputPort for HoldStart {
+        fn poll_input(&mut self) -> ControllerState {
+            ControllerState::START
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_stream_input() {
+        let commands = "1\n0x90\n\nnot a number\n0b1000\n";
+        let mut output = Vec::new();
+        let mut input = StreamInput::new(commands.as_bytes(), &mut output);
+        let states: Vec<u8> = (0..7).map(|_| input.poll_input().bits()).collect();
+        // Empty, invalid lines and end of input keep the previous state
+        assert_eq!(vec![0x01, 0x90, 0x90, 0x90, 0x08, 0x08, 0x08], states);
+        // Frame numbers stop being written once the input is closed
+        assert_eq!("0\n1\n2\n3\n4\n5\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_button_latch() {
+        let mut latch = ButtonLatch::new();
+        // Tapped between two polls
+        latch.press(ControllerState::A);
+        latch.release(ControllerState::A);
+        latch.press(ControllerState::RIGHT);
+        assert_eq!(0x81, latch.poll_input().bits());
+        assert_eq!(0x80, latch.poll_input().bits());
+        latch.release(ControllerState::RIGHT);
+        assert!(latch.poll_input().is_empty());
+
+        latch.press(ControllerState::START);
+        latch.set(ControllerState::B);
+        assert_eq!(0x02, latch.poll_input().bits());
+    }
+
+    #[test]
+    fn test_cycle_budget() {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        let mut budget = CycleBudget::new(44100);
+        let total: usize = (0..100)
+            .map(|_| budget.run_for_samples(&mut nes, 441).unwrap())
+            .sum();
+        // One second of audio, off by at most the overshoot of one instruction
+        let expected = CPU_CLOCK as usize;
+        assert!(total >= expected - 1 && total <= expected + 7);
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        let mut video = CountingSink { count: 0 };
+        run_frames(&mut nes, &mut video, &mut HoldStart, 3).unwrap();
+        assert_eq!(3, video.count);
+        assert!(nes
+            .controller
+            .controller_state
+            .contains(ControllerState::START));
+    }
+}
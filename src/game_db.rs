@@ -0,0 +1,277 @@
+// Per-game settings looked up when a ROM is loaded: the device plugged into port 2, the region
+// and when input is latched
+//
+// Database format, one setting per line, the first matching entry for each setting wins:
+//     crc32:158B0388 paddle     # matches the CRC32 of the headerless ROM
+//     name:arkanoid paddle      # matches part of the file name, ignoring case
+//     crc32:3FE272FB pal        # regions are ntsc, pal or dendy
+//     name:smb3 latch:immediate # input latch is vblank or immediate
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::controller::Controller;
+use crate::frontend::InputLatch;
+use crate::peripheral::{ArkanoidPaddle, PortDevice, SnesMouse};
+use crate::region::Region;
+use crate::rom::ROM;
+
+const BUILTIN_DATABASE: &str = "\
+name:arkanoid paddle
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Disconnected,
+    Joypad,
+    Paddle,
+    Mouse,
+}
+
+impl DeviceKind {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "none" => Ok(DeviceKind::Disconnected),
+            "joypad" => Ok(DeviceKind::Joypad),
+            "paddle" => Ok(DeviceKind::Paddle),
+            "mouse" => Ok(DeviceKind::Mouse),
+            _ => Err(format!("Unknown device {}", name)),
+        }
+    }
+
+    pub fn create(&self) -> PortDevice {
+        match self {
+            DeviceKind::Disconnected => PortDevice::Disconnected,
+            DeviceKind::Joypad => PortDevice::Joypad(Controller::new()),
+            DeviceKind::Paddle => PortDevice::Paddle(ArkanoidPaddle::new()),
+            DeviceKind::Mouse => PortDevice::Mouse(SnesMouse::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum GamePattern {
+    Crc32(u32),
+    // Lowercase part of the file name
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameEntry {
+    pattern: GamePattern,
+    // Exactly one of these is set
+    pub port_2: Option<DeviceKind>,
+    pub region: Option<Region>,
+    pub input_latch: Option<InputLatch>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GameDatabase {
+    entries: Vec<GameEntry>,
+}
+
+impl GameDatabase {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_DATABASE).expect("Built-in game database is invalid")
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        Self::parse(&read_to_string(path).map_err(|err| err.to_string())?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid game database line: {}", line);
+            let (pattern, setting) = line.rsplit_once(char::is_whitespace).ok_or_else(invalid)?;
+            let pattern = match pattern.trim().split_once(':') {
+                Some(("crc32", crc)) => {
+                    GamePattern::Crc32(u32::from_str_radix(crc, 16).map_err(|_| invalid())?)
+                }
+                Some(("name", name)) => GamePattern::Name(name.to_lowercase()),
+                _ => return Err(invalid()),
+            };
+            let mut entry = GameEntry {
+                pattern,
+                port_2: None,
+                region: None,
+                input_latch: None,
+            };
+            if let Some(latch) = setting.strip_prefix("latch:") {
+                entry.input_latch = Some(InputLatch::parse(latch)?);
+            } else if let Ok(region) = Region::parse(setting) {
+                entry.region = Some(region);
+            } else {
+                entry.port_2 = Some(DeviceKind::parse(setting)?);
+            }
+            entries.push(entry);
+        }
+        Ok(GameDatabase { entries })
+    }
+
+    /// Adds the entries of `other` in front, so they take priority
+    pub fn prepend(&mut self, other: GameDatabase) {
+        let mut entries = other.entries;
+        entries.append(&mut self.entries);
+        self.entries = entries;
+    }
+
+    /// Entries matching the ROM, highest priority first
+    pub fn lookup<'a>(&'a self, rom: &ROM, path: &str) -> impl Iterator<Item = &'a GameEntry> {
+        let crc = rom.crc32();
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.entries
+            .iter()
+            .filter(move |entry| match &entry.pattern {
+                GamePattern::Crc32(entry_crc) => *entry_crc == crc,
+                GamePattern::Name(name) => file_name.contains(name.as_str()),
+            })
+    }
+
+    pub fn port_2(&self, rom: &ROM, path: &str) -> Option<DeviceKind> {
+        self.lookup(rom, path).find_map(|entry| entry.port_2)
+    }
+
+    pub fn region(&self, rom: &ROM, path: &str) -> Option<Region> {
+        self.lookup(rom, path).find_map(|entry| entry.region)
+    }
+
+    pub fn input_latch(&self, rom: &ROM, path: &str) -> Option<InputLatch> {
+        self.lookup(rom, path).find_map(|entry| entry.input_latch)
+    }
+}
+
+// Built-in entries with the user's in front
+fn load_database(user_database: Option<&str>) -> GameDatabase {
+    let mut database = GameDatabase::builtin();
+    if let Some(user_database) = user_database {
+        match GameDatabase::load(user_database) {
+            Ok(user_database) => database.prepend(user_database),
+            Err(err) => log::warn!("Failed to load game database {}: {}", user_database, err),
+        }
+    }
+    database
+}
+
+/// Picks the port 2 device for a ROM from the built-in database and an optional user database
+pub fn detect_port_2(rom: &ROM, path: &str, user_database: Option<&str>) -> PortDevice {
+    match load_database(user_database).port_2(rom, path) {
+        Some(device) => {
+            log::info!("Detected {:?} in port 2 for {}", device, path);
+            device.create()
+        }
+        // A second controller, like the console comes with
+        None => DeviceKind::Joypad.create(),
+    }
+}
+
+/// Picks the region for a ROM from the game database, then the header, then the file name tags,
+/// NTSC if none of them say
+pub fn detect_region(rom: &ROM, path: &str, user_database: Option<&str>) -> Region {
+    let detected = [
+        (
+            "game database",
+            load_database(user_database).region(rom, path),
+        ),
+        ("header", rom.region),
+        ("file name", Region::from_file_name(path)),
+    ]
+    .into_iter()
+    .find_map(|(source, region)| region.map(|region| (source, region)));
+    match detected {
+        Some((source, region)) => {
+            log::info!(
+                "Detected {} region from the {} for {}",
+                region,
+                source,
+                path
+            );
+            region
+        }
+        None => Region::default(),
+    }
+}
+
+/// Picks when input is latched for a ROM from the game database, at vblank if it isn't listed
+pub fn detect_input_latch(rom: &ROM, path: &str, user_database: Option<&str>) -> InputLatch {
+    let latch = load_database(user_database).input_latch(rom, path);
+    if let Some(latch) = latch {
+        log::info!("Detected {:?} input latch for {}", latch, path);
+    }
+    latch.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_name_match() {
+        let database = GameDatabase::builtin();
+        let rom = ROM::new();
+        let device = database.port_2(&rom, "roms/Arkanoid (USA).nes");
+        assert_eq!(Some(DeviceKind::Paddle), device);
+        assert!(database.port_2(&rom, "roms/arkanoid/smb.nes").is_none());
+    }
+
+    #[test]
+    fn test_user_entries_take_priority() {
+        let rom = ROM::create_from_nes("test_roms/nestest.nes").unwrap();
+        let mut database = GameDatabase::builtin();
+        let user =
+            GameDatabase::parse("# overrides\ncrc32:158B0388 mouse\nname:arkanoid none\n").unwrap();
+        database.prepend(user);
+        let device = database.port_2(&rom, "nestest.nes");
+        assert_eq!(Some(DeviceKind::Mouse), device);
+        let device = database.port_2(&ROM::new(), "arkanoid.nes");
+        assert_eq!(Some(DeviceKind::Disconnected), device);
+    }
+
+    #[test]
+    fn test_detect_region() {
+        let mut rom = ROM::create_from_nes("test_roms/nestest.nes").unwrap();
+        assert_eq!(Region::Ntsc, detect_region(&rom, "nestest.nes", None));
+        assert_eq!(Region::Pal, detect_region(&rom, "nestest (E).nes", None));
+        rom.region = Some(Region::Dendy);
+        assert_eq!(Region::Dendy, detect_region(&rom, "nestest (E).nes", None));
+
+        // Database entries beat the header, and don't hide other settings for the same game
+        let database = GameDatabase::parse("crc32:158B0388 pal\ncrc32:158B0388 mouse").unwrap();
+        assert_eq!(Some(Region::Pal), database.region(&rom, "nestest.nes"));
+        assert_eq!(
+            Some(DeviceKind::Mouse),
+            database.port_2(&rom, "nestest.nes")
+        );
+        let path = std::env::temp_dir().join("nes_region_game_db.txt");
+        std::fs::write(&path, "name:nestest pal\n").unwrap();
+        let region = detect_region(&rom, "nestest.nes", path.to_str());
+        assert_eq!(Region::Pal, region);
+    }
+
+    #[test]
+    fn test_input_latch() {
+        let rom = ROM::new();
+        let database = GameDatabase::parse("name:smb3 latch:immediate\nname:smb3 mouse").unwrap();
+        let latch = database.input_latch(&rom, "smb3.nes");
+        assert_eq!(Some(InputLatch::Immediate), latch);
+        assert_eq!(Some(DeviceKind::Mouse), database.port_2(&rom, "smb3.nes"));
+        assert_eq!(
+            InputLatch::Vblank,
+            detect_input_latch(&rom, "smb3.nes", None)
+        );
+        assert!(GameDatabase::parse("name:smb3 latch:never").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(GameDatabase::parse("crc32:xyz paddle").is_err());
+        assert!(GameDatabase::parse("name:duck zapper").is_err());
+        assert!(GameDatabase::parse("paddle").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::CpuState;
+
+/// CPU registers right before an instruction was executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    pub program_counter: u16,
+    // None if the instruction failed before it was decoded
+    pub raw_opcode: Option<u8>,
+    pub reg_a: u8,
+    pub reg_x: u8,
+    pub reg_y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+    pub cycle_counter: usize,
+}
+
+impl HistoryEntry {
+    pub fn new(cpu_state: &CpuState) -> Self {
+        HistoryEntry {
+            program_counter: cpu_state.program_counter,
+            raw_opcode: None,
+            reg_a: cpu_state.reg_a,
+            reg_x: cpu_state.reg_x,
+            reg_y: cpu_state.reg_y,
+            status: cpu_state.status.bits(),
+            stack_pointer: cpu_state.stack_pointer,
+            cycle_counter: cpu_state.cycle_counter,
+        }
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opcode = match self.raw_opcode {
+            Some(raw_opcode) => format!("{:02X}", raw_opcode),
+            None => "??".to_string(),
+        };
+        write!(
+            f,
+            "{:04X}  {}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.program_counter,
+            opcode,
+            self.reg_a,
+            self.reg_x,
+            self.reg_y,
+            self.status,
+            self.stack_pointer,
+            self.cycle_counter
+        )
+    }
+}
+
+/// Fixed size ring buffer of the last executed instructions, much cheaper than TraceNes
+#[derive(Debug, Clone)]
+pub struct ExecutionHistory {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl ExecutionHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        ExecutionHistory {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Entries from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.entries.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Formats the history for a crash report, newest instruction last
+    pub fn dump(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_last_entries() {
+        let mut history = ExecutionHistory::new(3);
+        let mut cpu_state = CpuState::new();
+        for pc in 0..5 {
+            cpu_state.program_counter = pc;
+            history.push(HistoryEntry::new(&cpu_state));
+        }
+        let pcs: Vec<u16> = history.iter().map(|entry| entry.program_counter).collect();
+        assert_eq!(vec![2, 3, 4], pcs);
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut history = ExecutionHistory::new(2);
+        let mut entry = HistoryEntry::new(&CpuState::new());
+        entry.raw_opcode = Some(0xEA);
+        history.push(entry);
+        history.push(HistoryEntry::new(&CpuState::new()));
+        assert_eq!(
+            "0600  EA  A:00 X:00 Y:00 P:24 SP:FD CYC:0\n0600  ??  A:00 X:00 Y:00 P:24 SP:FD CYC:0",
+            history.dump()
+        );
+    }
+}
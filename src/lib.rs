@@ -1,9 +1,44 @@
 #![allow(clippy::upper_case_acronyms)]
 
+pub mod accuracy;
+pub mod apu;
+pub mod async_nes;
+pub mod audit;
+#[cfg(not(feature = "minimal"))]
+pub mod autosave;
+#[cfg(not(feature = "minimal"))]
+pub mod battery;
+pub mod capabilities;
+pub mod common;
 pub mod controller;
 pub mod cpu;
+pub mod debugger;
+#[cfg(not(feature = "minimal"))]
+pub mod disasm;
+pub mod frontend;
+#[cfg(not(feature = "minimal"))]
+pub mod game_db;
+pub mod history;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod memory_edit;
+pub mod movie;
 pub mod nes;
+pub mod peripheral;
 pub mod ppu;
+pub mod profiler;
+pub mod region;
+pub mod rewind;
 pub mod rom;
+pub mod savestate;
+pub mod scheduler;
 pub mod screen;
+#[cfg(not(feature = "minimal"))]
+pub mod server;
+pub mod snapshot;
+pub mod stall;
+#[cfg(test)]
+mod test_kit;
 pub mod tracer;
+#[cfg(not(feature = "minimal"))]
+pub mod wav;
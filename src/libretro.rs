@@ -0,0 +1,478 @@
+// libretro core so the emulator can run inside RetroArch and other libretro frontends, build with
+//     cargo rustc --release --lib --features libretro --crate-type cdylib
+// Ref: https://github.com/libretro/libretro-common/blob/master/include/libretro.h
+use std::ffi::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use crate::controller::ControllerState;
+use crate::nes::{ActionNES, NES};
+use crate::rom::ROM;
+use crate::screen::frame::{Frame, HEIGHT, WIDTH};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const FPS: f64 = 60.0988;
+const SAMPLE_RATE: f64 = 44100.0;
+
+// Joypad button ids in the RetroPad layout, mapped to the NES controller
+const BUTTON_MAP: [(c_uint, ControllerState); 8] = [
+    (0, ControllerState::B),
+    (2, ControllerState::SELECT),
+    (3, ControllerState::START),
+    (4, ControllerState::UP),
+    (5, ControllerState::DOWN),
+    (6, ControllerState::LEFT),
+    (7, ControllerState::RIGHT),
+    (8, ControllerState::A),
+];
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    nes: ActionNES,
+    frame: Frame,
+    // Frame converted to XRGB8888
+    video: Vec<u32>,
+    // The APU's mono samples doubled into left and right
+    audio: Vec<i16>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().expect("libretro callbacks poisoned")
+}
+
+fn poll_input(callbacks: &Callbacks) -> ControllerState {
+    let mut state = ControllerState::empty();
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+    if let Some(input_state) = callbacks.input_state {
+        for (id, button) in BUTTON_MAP {
+            let pressed = unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0;
+            state.set(button, pressed);
+        }
+    }
+    state
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` must point to a valid retro_system_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"rust-nes-emulator".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"nes".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a valid retro_system_av_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: RetroSystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        if let Err(err) = core.nes.reset() {
+            log::error!("Failed to reset: {}", err);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+    let input = poll_input(&callbacks);
+    core.nes.set_inputs(input);
+    if let Err(err) = core.nes.next_ppu_frame() {
+        log::error!("Failed to run frame: {}", err);
+    }
+
+    core.nes.render_frame(&mut core.frame);
+    for (pixel, (r, g, b)) in core.video.iter_mut().zip(core.frame.data.iter()) {
+        *pixel = ((*r as u32) << 16) | ((*g as u32) << 8) | *b as u32;
+    }
+    if let Some(video_refresh) = callbacks.video_refresh {
+        unsafe {
+            video_refresh(
+                core.video.as_ptr() as *const c_void,
+                WIDTH as c_uint,
+                HEIGHT as c_uint,
+                WIDTH * 4,
+            )
+        };
+    }
+    let samples = core
+        .nes
+        .audio()
+        .map(|audio| audio.take())
+        .unwrap_or_default();
+    core.audio.clear();
+    core.audio
+        .extend(samples.iter().flat_map(|&sample| [sample, sample]));
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        unsafe { audio_sample_batch(core.audio.as_ptr(), samples.len()) };
+    }
+}
+
+// Savestates are NES::save_state zero padded to max_state_len, frontends size their rewind,
+// runahead and netplay buffers once from it
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match CORE.lock().unwrap().as_ref() {
+        Some(core) => core.nes.max_state_len(),
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = CORE.lock().unwrap();
+    let Some(core) = core.as_ref() else {
+        return false;
+    };
+    let state = core.nes.save_state();
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+    ptr::write_bytes((data as *mut u8).add(state.len()), 0, size - state.len());
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return false;
+    };
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size);
+    match core.nes.load_state(state) {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!("Failed to load state: {}", err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a valid retro_game_info with `size` bytes of data
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    if let Some(environment) = callbacks().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        ) {
+            log::error!("Frontend doesn't support XRGB8888");
+            return false;
+        }
+    }
+    let data = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    let rom = match ROM::from(data.to_vec()) {
+        Ok(rom) => rom,
+        Err(err) => {
+            log::error!("Failed to load game: {}", err);
+            return false;
+        }
+    };
+    let mut nes = ActionNES::new();
+    if nes.set_rom(rom).and_then(|_| nes.reset()).is_err() {
+        return false;
+    }
+    nes.enable_audio(SAMPLE_RATE as u32);
+    *CORE.lock().unwrap() = Some(Core {
+        nes,
+        frame: Frame::new(),
+        video: vec![0; WIDTH * HEIGHT],
+        audio: Vec::new(),
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match (id, CORE.lock().unwrap().as_mut()) {
+        // The core lives in a static, so the pointer stays valid until the game is unloaded
+        (RETRO_MEMORY_SYSTEM_RAM, Some(core)) => core.nes.cpu_state.ram.as_mut_ptr() as *mut c_void,
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match (id, CORE.lock().unwrap().as_ref()) {
+        (RETRO_MEMORY_SYSTEM_RAM, Some(core)) => core.nes.cpu_state.ram.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static AUDIO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn environment(_cmd: c_uint, _data: *mut c_void) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn video_refresh(
+        _data: *const c_void,
+        width: c_uint,
+        height: c_uint,
+        pitch: usize,
+    ) {
+        assert_eq!((256, 240, 1024), (width, height, pitch));
+        FRAMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        AUDIO_FRAMES.fetch_add(frames, Ordering::SeqCst);
+        frames
+    }
+
+    unsafe extern "C" fn input_state(
+        _port: c_uint,
+        _device: c_uint,
+        _index: c_uint,
+        id: c_uint,
+    ) -> i16 {
+        // Hold START
+        (id == 3) as i16
+    }
+
+    #[test]
+    fn test_load_and_run() {
+        let data = read("test_roms/nestest.nes").unwrap();
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: data.as_ptr() as *const c_void,
+            size: data.len(),
+            meta: ptr::null(),
+        };
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_state(input_state);
+        retro_init();
+        assert!(unsafe { retro_load_game(&game) });
+        retro_run();
+        retro_run();
+        assert_eq!(2, FRAMES.load(Ordering::SeqCst));
+        // About 735 samples a frame at 44.1kHz
+        let audio_frames = AUDIO_FRAMES.load(Ordering::SeqCst);
+        assert!(
+            (2 * 730..=2 * 740).contains(&audio_frames),
+            "{}",
+            audio_frames
+        );
+        assert_eq!(0x800, retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM));
+        let controller = CORE.lock().unwrap().as_ref().unwrap().nes.controller;
+        assert_eq!(
+            ControllerState::START.bits(),
+            controller.controller_state.bits()
+        );
+
+        // A state saved now brings back the frame count after running on
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_run();
+        assert_eq!(state.len(), retro_serialize_size());
+        assert_eq!(3, CORE.lock().unwrap().as_ref().unwrap().nes.frame_count());
+        // The size stays put however much of RAM the game has touched
+        let ram = retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM) as *mut u8;
+        for index in 0..0x800 {
+            unsafe { *ram.add(index) = index as u8 ^ 0xA5 };
+        }
+        assert_eq!(state.len(), retro_serialize_size());
+        let mut touched = vec![0xFFu8; state.len()];
+        assert!(unsafe { retro_serialize(touched.as_mut_ptr() as *mut c_void, touched.len()) });
+        assert_eq!(0, *touched.last().unwrap());
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert_eq!(2, CORE.lock().unwrap().as_ref().unwrap().nes.frame_count());
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 10) });
+        assert!(!unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, 10) });
+
+        retro_unload_game();
+        assert_eq!(0, retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM));
+        assert_eq!(0, retro_serialize_size());
+        retro_deinit();
+    }
+}
@@ -1,12 +1,453 @@
-use std::env;
+#[cfg(not(feature = "minimal"))]
+use std::{env, io};
 
-use rust_nes_emulator::screen::run;
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::accuracy::AccuracyPreset;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::async_nes::AsyncNes;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::capabilities::capabilities;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::disasm::export_asm;
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::frontend::InputLatch;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::frontend::{run_frames, NullInput, VideoSink};
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::game_db::detect_region;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::movie::Movie;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::nes::{ActionNES, NES};
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::peripheral::{ArkanoidPaddle, PortDevice, SnesMouse};
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::region::Region;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::rom::ROM;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::screen::chr_sheet::export_chr_sheet;
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::screen::display::{Overscan, Rotation, SyncMode};
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::screen::frame::Frame;
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::screen::frame_diff::{diff_image, frame_diff};
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::screen::nametable_map::NametableMap;
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+use rust_nes_emulator::screen::{run, InputSource, RunOptions};
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::server::{self, FrameFormat};
+#[cfg(not(feature = "minimal"))]
+use rust_nes_emulator::tracer::TraceNes;
 
+#[cfg(feature = "minimal")]
 fn main() {
+    println!("Built with the minimal feature, there's no frontend")
+}
+
+// The library reports through `log`, warnings and errors go to stderr
+#[cfg(not(feature = "minimal"))]
+struct StderrLogger;
+
+#[cfg(not(feature = "minimal"))]
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(not(feature = "minimal"))]
+static LOGGER: StderrLogger = StderrLogger;
+
+#[cfg(not(feature = "minimal"))]
+fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
     let args: Vec<String> = env::args().collect();
-    if let Some(path) = args.get(1) {
-        run(path);
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => return disasm(&args[2..]),
+        Some("framediff") => return framediff(&args[2..]),
+        Some("rominfo") => return rominfo(&args[2..]),
+        Some("nametables") => return nametables(&args[2..]),
+        Some("chr") => return chr(&args[2..]),
+        Some("headless") => return headless(&args[2..]),
+        Some("serve") => return serve(&args[2..]),
+        _ => {}
+    }
+    window(&args[1..]);
+}
+
+#[cfg(all(feature = "sdl", not(feature = "minimal")))]
+fn window(args: &[String]) {
+    let mut path = None;
+    let mut options = RunOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--paddle" => options.port_2 = Some(PortDevice::Paddle(ArkanoidPaddle::new())),
+            "--mouse" => options.port_2 = Some(PortDevice::Mouse(SnesMouse::new())),
+            "--no-port-2" => options.port_2 = Some(PortDevice::Disconnected),
+            "--game-db" => options.game_db = args.next().cloned(),
+            "--region" => match args.next().map(|name| Region::parse(name)) {
+                Some(Ok(region)) => options.region = Some(region),
+                Some(Err(err)) => {
+                    println!("{}", err);
+                    return;
+                }
+                None => {
+                    println!("--region needs ntsc, pal or dendy");
+                    return;
+                }
+            },
+            "--crop-overscan" => options.display.overscan = Overscan::Crop,
+            "--pal-border" => options.display.pal_border = true,
+            "--rotate" => options.display.rotation = Rotation::Clockwise,
+            "--rotate-ccw" => options.display.rotation = Rotation::CounterClockwise,
+            "--adaptive-vsync" => options.display.sync = SyncMode::Adaptive,
+            "--no-vsync" => options.display.sync = SyncMode::Sleep,
+            "--smooth-frames" => options.display.smoothing = true,
+            "--audit" => options.audit = true,
+            "--audio-sync" => options.audio_sync = true,
+            "--record-audio" => options.record_audio = args.next().cloned(),
+            "--record-movie" => options.record_movie = args.next().cloned(),
+            "--frame-stats" => options.frame_stats = args.next().cloned(),
+            "--resume" => options.resume = true,
+            "--pause-on-focus-loss" => options.pause_on_focus_loss = true,
+            "--debug-window" => options.debug_window = true,
+            "--detect-stalls" => options.detect_stalls = true,
+            "--profile-memory" => options.profile_memory = true,
+            "--rumble" => match args.next().and_then(|line| line.parse().ok()) {
+                Some(line @ 1..=2) => options.rumble_line = Some(line),
+                _ => {
+                    println!("--rumble needs an output line, 1 or 2");
+                    return;
+                }
+            },
+            "--accuracy" => match args.next().map(|name| AccuracyPreset::parse(name)) {
+                Some(Ok(preset)) => options.accuracy = Some(preset),
+                Some(Err(err)) => {
+                    println!("{}", err);
+                    return;
+                }
+                None => {
+                    println!("--accuracy needs fast, balanced or accurate");
+                    return;
+                }
+            },
+            "--input-latch" => match args.next().map(|name| InputLatch::parse(name)) {
+                Some(Ok(latch)) => options.input_latch = Some(latch),
+                Some(Err(err)) => {
+                    println!("{}", err);
+                    return;
+                }
+                None => {
+                    println!("--input-latch needs vblank or immediate");
+                    return;
+                }
+            },
+            "--input-stdin" => options.input = InputSource::Stdin,
+            "--input-fifo" => match (args.next(), args.next()) {
+                (Some(input), Some(output)) => {
+                    options.input = InputSource::Fifo {
+                        input: input.clone(),
+                        output: output.clone(),
+                    }
+                }
+                _ => {
+                    println!("--input-fifo needs an input and an output pipe path");
+                    return;
+                }
+            },
+            _ => path = Some(arg),
+        }
+    }
+    if let Some(path) = path {
+        run(path, options);
     } else {
         println!("Pass .nes file path to run")
     }
 }
+
+#[cfg(all(not(feature = "sdl"), not(feature = "minimal")))]
+fn window(_args: &[String]) {
+    println!("Built without the sdl feature, there's no window, only the subcommands")
+}
+
+// disasm <rom> -o out.asm [--cdl file.cdl] [--symbols labels.txt]
+#[cfg(not(feature = "minimal"))]
+fn disasm(args: &[String]) {
+    let mut rom_path = None;
+    let mut out_path = None;
+    let mut cdl_path = None;
+    let mut symbols_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = args.next(),
+            "--cdl" => cdl_path = args.next(),
+            "--symbols" => symbols_path = args.next(),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let (Some(rom_path), Some(out_path)) = (rom_path, out_path) else {
+        println!("Usage: disasm <rom> -o out.asm [--cdl file.cdl] [--symbols labels.txt]");
+        return;
+    };
+    if let Err(err) = export_asm(
+        rom_path,
+        out_path,
+        cdl_path.map(String::as_str),
+        symbols_path.map(String::as_str),
+    ) {
+        println!("Failed to disassemble {}: {}", rom_path, err);
+    }
+}
+
+// rominfo <rom>
+#[cfg(not(feature = "minimal"))]
+fn rominfo(args: &[String]) {
+    let [rom_path] = args else {
+        println!("Usage: rominfo <rom>");
+        return;
+    };
+    match std::fs::read(rom_path)
+        .map_err(|e| e.to_string())
+        .and_then(ROM::from)
+    {
+        Ok(rom) => {
+            println!("{}", rom.info());
+            println!("Region: {}", detect_region(&rom, rom_path, None));
+            if let Err(reason) = capabilities().check_rom(&rom) {
+                println!("Won't run: {}", reason);
+            }
+        }
+        Err(err) => println!("Failed to load {}: {}", rom_path, err),
+    }
+}
+
+// nametables <rom> -o map.png [--frames 120]
+#[cfg(not(feature = "minimal"))]
+fn nametables(args: &[String]) {
+    let mut rom_path = None;
+    let mut out_path = None;
+    let mut frames = Some(120);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = args.next(),
+            "--frames" => frames = args.next().and_then(|frames| frames.parse().ok()),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let (Some(rom_path), Some(out_path), Some(frames)) = (rom_path, out_path, frames) else {
+        println!("Usage: nametables <rom> -o map.png [--frames 120]");
+        return;
+    };
+    let mut nes = ActionNES::new();
+    let map = nes
+        .load_from_path(rom_path)
+        .and_then(|_| nes.reset())
+        .and_then(|_| nes.step_frames(frames))
+        .map(|_| NametableMap::render(&nes.ppu_state, &nes.rom));
+    if let Err(err) = map.and_then(|map| map.save_png(out_path)) {
+        println!("Failed to export nametables of {}: {}", rom_path, err);
+    }
+}
+
+// Cores that can be picked with --core, the frontend only sees the NES trait
+#[cfg(not(feature = "minimal"))]
+fn create_core(name: &str) -> Result<Box<dyn NES>, String> {
+    match name {
+        "action" => Ok(Box::new(ActionNES::new())),
+        // Streams the trace to stdout
+        "trace" => Ok(Box::new(TraceNes::new().log_to(Box::new(io::stdout())))),
+        _ => Err(format!("Unknown core {}, pick action or trace", name)),
+    }
+}
+
+// Saves the last of `frames_left` frames to `path`
+#[cfg(not(feature = "minimal"))]
+struct SaveLastFrame<'a> {
+    path: Option<&'a String>,
+    frames_left: usize,
+}
+
+#[cfg(not(feature = "minimal"))]
+impl VideoSink for SaveLastFrame<'_> {
+    fn present_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        self.frames_left -= 1;
+        match self.path {
+            Some(path) if self.frames_left == 0 => frame.save_png(path),
+            _ => Ok(()),
+        }
+    }
+}
+
+// headless <rom> [--core action] [--frames 60] [--movie run.fm2] [-o last_frame.png]
+#[cfg(not(feature = "minimal"))]
+fn headless(args: &[String]) {
+    let mut rom_path = None;
+    let mut out_path = None;
+    let mut movie_path = None;
+    let mut core = "action";
+    let mut frames = Some(60);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = args.next(),
+            "--core" => core = args.next().map_or("", String::as_str),
+            "--frames" => frames = args.next().and_then(|frames| frames.parse().ok()),
+            "--movie" => movie_path = args.next(),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let (Some(rom_path), Some(frames)) = (rom_path, frames) else {
+        println!("Usage: headless <rom> [--core action|trace] [--frames 60] [--movie run.fm2] [-o last_frame.png]");
+        return;
+    };
+    if let Some(movie_path) = movie_path {
+        return play_movie(rom_path, movie_path, out_path);
+    }
+    let mut video = SaveLastFrame {
+        path: out_path,
+        frames_left: frames,
+    };
+    let result = create_core(core).and_then(|mut nes| {
+        nes.load_from_path(rom_path)?;
+        nes.reset()?;
+        run_frames(nes.as_mut(), &mut video, &mut NullInput, frames)
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to run {}: {}", rom_path, err);
+    }
+}
+
+// Plays a whole movie on the action core, the state hash at the end tells runs apart
+#[cfg(not(feature = "minimal"))]
+fn play_movie(rom_path: &str, movie_path: &str, out_path: Option<&String>) {
+    let mut nes = ActionNES::new();
+    let result = Movie::load(movie_path).and_then(|movie| {
+        nes.load_from_path(rom_path)?;
+        nes.reset()?;
+        movie.play(&mut nes)
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to play {}: {}", movie_path, err);
+        return;
+    }
+    println!(
+        "Played {} frames, state hash {:08X}",
+        nes.frame_count(),
+        nes.state_hash()
+    );
+    if let Some(out_path) = out_path {
+        let mut frame = Frame::new();
+        nes.render_frame(&mut frame);
+        if let Err(err) = frame.save_png(out_path) {
+            println!("Failed to save {}: {}", out_path, err);
+        }
+    }
+}
+
+// serve <rom> [--host 127.0.0.1] [--port 8080] [--format png|raw]
+#[cfg(not(feature = "minimal"))]
+fn serve(args: &[String]) {
+    let mut rom_path = None;
+    let mut host = Some(server::DEFAULT_HOST);
+    let mut port = Some(8080);
+    let mut format = Ok(FrameFormat::Png);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => host = args.next().map(String::as_str),
+            "--port" => port = args.next().and_then(|port| port.parse().ok()),
+            "--format" => format = FrameFormat::parse(args.next().map_or("", String::as_str)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let (Some(rom_path), Some(host), Some(port), Ok(format)) = (rom_path, host, port, format)
+    else {
+        println!("Usage: serve <rom> [--host 127.0.0.1] [--port 8080] [--format png|raw]");
+        return;
+    };
+    let mut nes = ActionNES::new();
+    let result = nes
+        .load_from_path(rom_path)
+        .and_then(|_| nes.reset())
+        .and_then(|_| server::serve(AsyncNes::new(nes), host, port, format));
+    if let Err(err) = result {
+        eprintln!("Failed to serve {}: {}", rom_path, err);
+    }
+}
+
+// chr <rom> -o tiles.png [--palette 0]
+#[cfg(not(feature = "minimal"))]
+fn chr(args: &[String]) {
+    let mut rom_path = None;
+    let mut out_path = None;
+    let mut palette = Some(0);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = args.next(),
+            "--palette" => palette = args.next().and_then(|palette| palette.parse().ok()),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let (Some(rom_path), Some(out_path), Some(palette)) = (rom_path, out_path, palette) else {
+        println!("Usage: chr <rom> -o tiles.png [--palette 0]");
+        return;
+    };
+    let written =
+        ROM::create_from_nes(rom_path).and_then(|rom| export_chr_sheet(&rom, palette, out_path));
+    match written {
+        Ok(written) => println!("Wrote {}", written.join(", ")),
+        Err(err) => println!("Failed to export CHR of {}: {}", rom_path, err),
+    }
+}
+
+// framediff <before.png> <after.png> [-o diff.png]
+#[cfg(not(feature = "minimal"))]
+fn framediff(args: &[String]) {
+    let mut paths = Vec::new();
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out_path = args.next(),
+            _ => paths.push(arg),
+        }
+    }
+    let [before_path, after_path] = paths[..] else {
+        println!("Usage: framediff <before.png> <after.png> [-o diff.png]");
+        return;
+    };
+    let frames = Frame::load_png(before_path).and_then(|before| {
+        let after = Frame::load_png(after_path)?;
+        Ok((before, after))
+    });
+    let (before, after) = match frames {
+        Ok(frames) => frames,
+        Err(err) => {
+            println!("Failed to load frames: {}", err);
+            return;
+        }
+    };
+    println!("{}", frame_diff(&before, &after));
+    if let Some(out_path) = out_path {
+        if let Err(err) = diff_image(&before, &after).save_png(out_path) {
+            println!("Failed to save {}: {}", out_path, err);
+        }
+    }
+}
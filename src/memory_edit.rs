@@ -0,0 +1,146 @@
+// Live memory edits that can be rolled back, for debugger pokes
+//
+// Only RAM ($0000-$1FFF) and PRG RAM ($6000-$7FFF, when the cartridge has some) can be edited:
+// writes to registers have side effects there's no undoing. Edits go straight to memory, past the audit and profiler layers.
+use crate::cpu::{CpuState, PRG_RAM_SIZE, PRG_RAM_START};
+
+const RAM_END: u16 = 0x1FFF;
+const RAM_MASK: u16 = 0x07FF;
+const PRG_RAM_END: u16 = 0x7FFF;
+
+/// Undo history of with_memory_edit, one entry per call with the (address, old value) of every
+/// byte written
+#[derive(Debug, Default, Clone)]
+pub struct EditJournal {
+    edits: Vec<Vec<(u16, u8)>>,
+}
+
+impl EditJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.edits.clear();
+    }
+
+    pub(crate) fn push(&mut self, edit: Vec<(u16, u8)>) {
+        // Edits that failed before writing anything have nothing to undo
+        if !edit.is_empty() {
+            self.edits.push(edit);
+        }
+    }
+
+    /// Restores the memory written by the last edit, returning the addresses restored
+    pub(crate) fn undo(
+        &mut self,
+        cpu_state: &mut CpuState,
+        prg_ram_size: usize,
+    ) -> Option<Vec<u16>> {
+        let edit = self.edits.pop()?;
+        let mut editor = MemoryEditor::new(cpu_state, prg_ram_size);
+        // Backwards, so a byte written twice ends up with its value from before the first write
+        for &(addr, old) in edit.iter().rev() {
+            editor
+                .write(addr, old)
+                .expect("journaled address is editable");
+        }
+        Some(edit.into_iter().map(|(addr, _)| addr).collect())
+    }
+}
+
+/// Memory handed to the closure of ActionNES::with_memory_edit
+pub struct MemoryEditor<'a> {
+    cpu_state: &'a mut CpuState,
+    // ROM::prg_ram_size, mirrored like the bus does
+    prg_ram_size: usize,
+    journal: Vec<(u16, u8)>,
+}
+
+impl<'a> MemoryEditor<'a> {
+    pub(crate) fn new(cpu_state: &'a mut CpuState, prg_ram_size: usize) -> Self {
+        MemoryEditor {
+            cpu_state,
+            prg_ram_size: prg_ram_size.min(PRG_RAM_SIZE),
+            journal: Vec::new(),
+        }
+    }
+
+    pub(crate) fn into_journal(self) -> Vec<(u16, u8)> {
+        self.journal
+    }
+
+    fn slot(&mut self, addr: u16) -> Result<&mut u8, String> {
+        match addr {
+            0..=RAM_END => Ok(&mut self.cpu_state.ram[(addr & RAM_MASK) as usize]),
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_size > 0 => {
+                let index = (addr - PRG_RAM_START) as usize % self.prg_ram_size;
+                Ok(&mut self.cpu_state.prg_ram[index])
+            }
+            _ => Err(format!(
+                "{:04X} can't be edited, only RAM and PRG RAM can",
+                addr
+            )),
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> Result<u8, String> {
+        self.slot(addr).map(|value| *value)
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) -> Result<(), String> {
+        let slot = self.slot(addr)?;
+        let old = std::mem::replace(slot, value);
+        self.journal.push((addr, old));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo() {
+        let mut cpu_state = CpuState::new();
+        cpu_state.ram[0x10] = 7;
+        let mut journal = EditJournal::new();
+        let mut editor = MemoryEditor::new(&mut cpu_state, PRG_RAM_SIZE);
+        editor.write(0x10, 1).unwrap();
+        // Mirror of $0010
+        editor.write(0x0810, 2).unwrap();
+        editor.write(0x6000, 3).unwrap();
+        assert!(editor.write(0x2000, 4).is_err());
+        assert!(editor.write(0x8000, 4).is_err());
+        journal.push(editor.into_journal());
+        assert_eq!(2, cpu_state.ram[0x10]);
+        assert_eq!(3, cpu_state.prg_ram[0]);
+
+        assert_eq!(
+            Some(vec![0x10, 0x0810, 0x6000]),
+            journal.undo(&mut cpu_state, PRG_RAM_SIZE)
+        );
+        assert_eq!(7, cpu_state.ram[0x10]);
+        assert_eq!(0, cpu_state.prg_ram[0]);
+        assert_eq!(None, journal.undo(&mut cpu_state, PRG_RAM_SIZE));
+    }
+
+    #[test]
+    fn test_prg_ram_size() {
+        let mut cpu_state = CpuState::new();
+        // 2KB mirrored over $6000-$7FFF
+        let mut editor = MemoryEditor::new(&mut cpu_state, 0x800);
+        editor.write(0x7801, 5).unwrap();
+        assert_eq!(5, editor.read(0x6001).unwrap());
+        let mut editor = MemoryEditor::new(&mut cpu_state, 0);
+        assert!(editor.write(0x6000, 1).is_err());
+    }
+}
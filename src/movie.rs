@@ -0,0 +1,560 @@
+// Per-frame input for replays
+//
+// A frame is the controller 1 buttons followed by chunks for the other inputs, each tagged with
+// its kind and layout version:
+//
+//     buttons, chunk count, (tag, version, length, payload)...
+//
+// Readers skip chunks with tags they don't know, so a Zapper chunk can be added without breaking
+// older movies, and refuse chunks newer than they can read. Inputs are captured as the device's
+// state at the start of the frame, so applying them to the same state replays the same reads.
+//
+// Movie files are text laid out like FCEUX's FM2 (though not readable by it): `key value` header
+// lines, then a line for every frame since power on:
+//
+//     |0|R.D....A|........|
+//
+// The commands field (resets in FM2) is always 0. Joypads are written RLDUTSBA with a . for the
+// buttons released, the paddle as its position and F for fire, the mouse as its motion and LR.
+// An empty port 2 field is an empty port.
+#[cfg(not(feature = "minimal"))]
+use std::fs;
+
+use crate::controller::ControllerState;
+use crate::nes::{ActionNES, NES};
+use crate::peripheral::{ArkanoidPaddle, PortDevice, SnesMouse};
+use crate::rom::ROM;
+
+const PADDLE_TAG: u8 = 1;
+const MOUSE_TAG: u8 = 2;
+const JOYPAD_2_TAG: u8 = 3;
+// Latest layout of each chunk
+const PADDLE_VERSION: u8 = 1;
+const MOUSE_VERSION: u8 = 1;
+const JOYPAD_2_VERSION: u8 = 1;
+// Latest movie file layout
+const MOVIE_VERSION: u32 = 1;
+// Joypad buttons from bit 7 to bit 0, the FM2 order
+const JOYPAD_FLAGS: &str = "RLDUTSBA";
+const FIRE_FLAGS: &str = "F";
+const MOUSE_FLAGS: &str = "LR";
+
+/// State of the device in port 2 for one frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortInput {
+    Joypad(ControllerState),
+    Paddle {
+        position: u8,
+        fire: bool,
+    },
+    // Motion not yet latched by the game
+    Mouse {
+        delta_x: i16,
+        delta_y: i16,
+        left: bool,
+        right: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInput {
+    pub joypad: ControllerState,
+    // None when port 2 is empty
+    pub port_2: Option<PortInput>,
+}
+
+impl FrameInput {
+    pub fn capture(nes: &ActionNES) -> Self {
+        let port_2 = match &nes.port_2 {
+            PortDevice::Disconnected => None,
+            PortDevice::Joypad(controller) => Some(PortInput::Joypad(controller.controller_state)),
+            PortDevice::Paddle(paddle) => Some(PortInput::Paddle {
+                position: paddle.position,
+                fire: paddle.fire,
+            }),
+            PortDevice::Mouse(mouse) => {
+                let (delta_x, delta_y) = mouse.motion();
+                // Latching clamps to 127 anyway
+                let clamp = |delta: i32| delta.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                Some(PortInput::Mouse {
+                    delta_x: clamp(delta_x),
+                    delta_y: clamp(delta_y),
+                    left: mouse.left,
+                    right: mouse.right,
+                })
+            }
+        };
+        FrameInput {
+            joypad: nes.controller.controller_state,
+            port_2,
+        }
+    }
+
+    /// Sets the controllers to this frame's input, plugging in the recorded port 2 device if a
+    /// different one is connected
+    pub fn apply(&self, nes: &mut ActionNES) {
+        nes.controller.set_controller_state(self.joypad);
+        match (self.port_2, &mut nes.port_2) {
+            (None, port_2) => *port_2 = PortDevice::Disconnected,
+            (Some(PortInput::Joypad(state)), PortDevice::Joypad(controller)) => {
+                controller.set_controller_state(state)
+            }
+            (Some(PortInput::Paddle { position, fire }), PortDevice::Paddle(paddle)) => {
+                paddle.position = position;
+                paddle.fire = fire;
+            }
+            (
+                Some(PortInput::Mouse {
+                    delta_x,
+                    delta_y,
+                    left,
+                    right,
+                }),
+                PortDevice::Mouse(mouse),
+            ) => {
+                mouse.set_motion(delta_x as i32, delta_y as i32);
+                mouse.left = left;
+                mouse.right = right;
+            }
+            (Some(input), port_2) => {
+                *port_2 = match input {
+                    PortInput::Joypad(_) => PortDevice::Joypad(Default::default()),
+                    PortInput::Paddle { .. } => PortDevice::Paddle(ArkanoidPaddle::new()),
+                    PortInput::Mouse { .. } => PortDevice::Mouse(SnesMouse::new()),
+                };
+                self.apply(nes);
+            }
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.joypad.bits());
+        let (tag, version, payload) = match self.port_2 {
+            None => {
+                out.push(0);
+                return;
+            }
+            Some(PortInput::Joypad(state)) => (JOYPAD_2_TAG, JOYPAD_2_VERSION, vec![state.bits()]),
+            Some(PortInput::Paddle { position, fire }) => {
+                (PADDLE_TAG, PADDLE_VERSION, vec![position, fire as u8])
+            }
+            Some(PortInput::Mouse {
+                delta_x,
+                delta_y,
+                left,
+                right,
+            }) => {
+                let mut payload = Vec::with_capacity(5);
+                payload.extend_from_slice(&delta_x.to_le_bytes());
+                payload.extend_from_slice(&delta_y.to_le_bytes());
+                payload.push(left as u8 | (right as u8) << 1);
+                (MOUSE_TAG, MOUSE_VERSION, payload)
+            }
+        };
+        out.extend_from_slice(&[1, tag, version, payload.len() as u8]);
+        out.extend_from_slice(&payload);
+    }
+
+    /// Reads a frame from the start of `bytes`, returning it with the number of bytes used
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), String> {
+        let truncated = || "Truncated input frame".to_string();
+        let [buttons, chunks, ..] = *bytes else {
+            return Err(truncated());
+        };
+        let mut frame = FrameInput {
+            joypad: ControllerState::from_bits_retain(buttons),
+            port_2: None,
+        };
+        let mut pos = 2;
+        for _ in 0..chunks {
+            let header = bytes.get(pos..pos + 3).ok_or_else(truncated)?;
+            let (tag, version, len) = (header[0], header[1], header[2]);
+            pos += 3;
+            let payload = bytes.get(pos..pos + len as usize).ok_or_else(truncated)?;
+            pos += len as usize;
+            let latest = match tag {
+                PADDLE_TAG => PADDLE_VERSION,
+                MOUSE_TAG => MOUSE_VERSION,
+                JOYPAD_2_TAG => JOYPAD_2_VERSION,
+                // From a newer version, e.g. a device this one doesn't emulate
+                _ => continue,
+            };
+            if version > latest {
+                return Err(format!(
+                    "Input chunk {} has version {}, only {} is supported",
+                    tag, version, latest
+                ));
+            }
+            let too_short = || format!("Input chunk {} is too short", tag);
+            frame.port_2 = Some(match tag {
+                PADDLE_TAG => match *payload {
+                    [position, fire, ..] => PortInput::Paddle {
+                        position,
+                        fire: fire != 0,
+                    },
+                    _ => return Err(too_short()),
+                },
+                MOUSE_TAG => match *payload {
+                    [x_lo, x_hi, y_lo, y_hi, buttons, ..] => PortInput::Mouse {
+                        delta_x: i16::from_le_bytes([x_lo, x_hi]),
+                        delta_y: i16::from_le_bytes([y_lo, y_hi]),
+                        left: buttons & 1 != 0,
+                        right: buttons & 2 != 0,
+                    },
+                    _ => return Err(too_short()),
+                },
+                _ => match *payload {
+                    [state, ..] => PortInput::Joypad(ControllerState::from_bits_retain(state)),
+                    _ => return Err(too_short()),
+                },
+            });
+        }
+        Ok((frame, pos))
+    }
+}
+
+// Bits from the highest to the lowest as their letter, or . when clear
+fn write_flags(bits: u8, names: &str) -> String {
+    let len = names.len();
+    names
+        .chars()
+        .enumerate()
+        .map(|(i, name)| match bits & 1 << (len - 1 - i) {
+            0 => '.',
+            _ => name,
+        })
+        .collect()
+}
+
+// Anything but . counts as set, like FM2
+fn parse_flags(field: &str, names: &str) -> Result<u8, String> {
+    if field.len() != names.len() {
+        return Err(format!("Expected {} flags, got {:?}", names, field));
+    }
+    Ok(field
+        .bytes()
+        .fold(0, |bits, flag| bits << 1 | (flag != b'.') as u8))
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str) -> Result<T, String> {
+    field.parse().map_err(|_| format!("Bad number {:?}", field))
+}
+
+fn write_port_2(port_2: Option<PortInput>) -> String {
+    match port_2 {
+        None => String::new(),
+        Some(PortInput::Joypad(state)) => write_flags(state.bits(), JOYPAD_FLAGS),
+        Some(PortInput::Paddle { position, fire }) => {
+            format!("{} {}", position, write_flags(fire as u8, FIRE_FLAGS))
+        }
+        Some(PortInput::Mouse {
+            delta_x,
+            delta_y,
+            left,
+            right,
+        }) => {
+            let buttons = (left as u8) << 1 | right as u8;
+            format!(
+                "{} {} {}",
+                delta_x,
+                delta_y,
+                write_flags(buttons, MOUSE_FLAGS)
+            )
+        }
+    }
+}
+
+// The device is told apart by the number of values
+fn parse_port_2(field: &str) -> Result<Option<PortInput>, String> {
+    let values: Vec<&str> = field.split_whitespace().collect();
+    Ok(Some(match values[..] {
+        [] => return Ok(None),
+        [buttons] => PortInput::Joypad(ControllerState::from_bits_retain(parse_flags(
+            buttons,
+            JOYPAD_FLAGS,
+        )?)),
+        [position, fire] => PortInput::Paddle {
+            position: parse_number(position)?,
+            fire: parse_flags(fire, FIRE_FLAGS)? != 0,
+        },
+        [delta_x, delta_y, buttons] => {
+            let buttons = parse_flags(buttons, MOUSE_FLAGS)?;
+            PortInput::Mouse {
+                delta_x: parse_number(delta_x)?,
+                delta_y: parse_number(delta_y)?,
+                left: buttons & 2 != 0,
+                right: buttons & 1 != 0,
+            }
+        }
+        _ => return Err(format!("Unknown port 2 input {:?}", field)),
+    }))
+}
+
+fn parse_frame(line: &str) -> Result<FrameInput, String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    let ["", commands, joypad, port_2, ""] = fields[..] else {
+        return Err("Expected |commands|port 1|port 2|".to_string());
+    };
+    if commands != "0" {
+        return Err(format!("Unsupported commands {:?}", commands));
+    }
+    Ok(FrameInput {
+        joypad: ControllerState::from_bits_retain(parse_flags(joypad, JOYPAD_FLAGS)?),
+        port_2: parse_port_2(port_2)?,
+    })
+}
+
+/// Input for every frame since power on, and the ROM it was recorded with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    // ROM::crc32 of the game
+    pub rom_crc: u32,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Movie {
+    pub fn new(rom: &ROM) -> Self {
+        Movie {
+            rom_crc: rom.crc32(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Records the input of the frame about to run. Recording a frame that was already recorded
+    /// (after loading a state or rewinding) drops the frames after it, like rerecording a TAS.
+    /// Frames past the end, e.g. after resuming a later session, aren't recorded.
+    pub fn record(&mut self, nes: &ActionNES) {
+        let frame = nes.frame_count();
+        if frame <= self.frames.len() {
+            self.frames.truncate(frame);
+            self.frames.push(FrameInput::capture(nes));
+        }
+    }
+
+    /// Input of the frame about to run, None once the movie is over
+    pub fn input(&self, nes: &ActionNES) -> Option<&FrameInput> {
+        self.frames.get(nes.frame_count())
+    }
+
+    pub fn check_rom(&self, rom: &ROM) -> Result<(), String> {
+        match rom.crc32() {
+            crc if crc == self.rom_crc => Ok(()),
+            crc => Err(format!(
+                "Movie was recorded with ROM {:08X}, this is {:08X}",
+                self.rom_crc, crc
+            )),
+        }
+    }
+
+    /// Plays the rest of the movie from the frame `nes` is at. The whole movie plays on a console
+    /// that was just loaded and reset.
+    pub fn play(&self, nes: &mut ActionNES) -> Result<(), String> {
+        self.check_rom(&nes.rom)?;
+        while let Some(input) = self.input(nes) {
+            input.apply(nes);
+            nes.next_ppu_frame()?;
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "version {}\nromChecksum {:08X}\n",
+            MOVIE_VERSION, self.rom_crc
+        );
+        for frame in &self.frames {
+            text += &format!(
+                "|0|{}|{}|\n",
+                write_flags(frame.joypad.bits(), JOYPAD_FLAGS),
+                write_port_2(frame.port_2)
+            );
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut version = None;
+        let mut rom_crc = None;
+        let mut frames = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            let in_line = |err| format!("Line {}: {}", number + 1, err);
+            if line.starts_with('|') {
+                frames.push(parse_frame(line).map_err(in_line)?);
+                continue;
+            }
+            match line.split_once(' ') {
+                Some(("version", value)) => {
+                    version = Some(parse_number::<u32>(value).map_err(in_line)?)
+                }
+                Some(("romChecksum", value)) => {
+                    let crc = u32::from_str_radix(value, 16)
+                        .map_err(|_| in_line(format!("Bad checksum {:?}", value)))?;
+                    rom_crc = Some(crc);
+                }
+                // Comments, and keys from later versions
+                _ => {}
+            }
+        }
+        match version {
+            None => return Err("Not a movie, there's no version".to_string()),
+            Some(version) if version > MOVIE_VERSION => {
+                return Err(format!(
+                    "Movie has version {}, only {} is supported",
+                    version, MOVIE_VERSION
+                ))
+            }
+            Some(_) => {}
+        }
+        Ok(Movie {
+            rom_crc: rom_crc.ok_or("Movie has no romChecksum")?,
+            frames,
+        })
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        Self::parse(&text)
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_text()).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::Peripheral;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frames = [
+            FrameInput {
+                joypad: ControllerState::A | ControllerState::RIGHT,
+                port_2: None,
+            },
+            FrameInput {
+                joypad: ControllerState::empty(),
+                port_2: Some(PortInput::Paddle {
+                    position: 0x80,
+                    fire: true,
+                }),
+            },
+            FrameInput {
+                joypad: ControllerState::START,
+                port_2: Some(PortInput::Mouse {
+                    delta_x: -300,
+                    delta_y: 5,
+                    left: false,
+                    right: true,
+                }),
+            },
+        ];
+        let mut bytes = Vec::new();
+        for frame in frames {
+            frame.encode(&mut bytes);
+        }
+        let mut pos = 0;
+        let mut last_start = 0;
+        for frame in frames {
+            let (decoded, len) = FrameInput::decode(&bytes[pos..]).unwrap();
+            assert_eq!(frame, decoded);
+            last_start = pos;
+            pos += len;
+        }
+        assert_eq!(bytes.len(), pos);
+        assert!(FrameInput::decode(&bytes[last_start..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_and_newer_chunks() {
+        // A chunk with an unknown tag (e.g. a Zapper) before a paddle chunk
+        let bytes = [0x01, 2, 9, 1, 3, 10, 20, 1, PADDLE_TAG, 1, 2, 0x90, 0];
+        let (frame, len) = FrameInput::decode(&bytes).unwrap();
+        assert_eq!(bytes.len(), len);
+        assert_eq!(
+            Some(PortInput::Paddle {
+                position: 0x90,
+                fire: false
+            }),
+            frame.port_2
+        );
+        let newer = [0x01, 1, PADDLE_TAG, 2, 2, 0x90, 0];
+        assert!(FrameInput::decode(&newer).is_err());
+    }
+
+    #[test]
+    fn test_apply_replays_paddle() {
+        let mut nes = ActionNES::new();
+        let mut paddle = ArkanoidPaddle::new();
+        paddle.position = 0xC0;
+        paddle.fire = true;
+        nes.port_2 = PortDevice::Paddle(paddle);
+        let input = FrameInput::capture(&nes);
+
+        let mut replay = ActionNES::new();
+        input.apply(&mut replay);
+        nes.port_2.write(1);
+        replay.port_2.write(1);
+        nes.port_2.write(0);
+        replay.port_2.write(0);
+        for _ in 0..8 {
+            assert_eq!(nes.port_2.read(), replay.port_2.read());
+        }
+    }
+
+    #[test]
+    fn test_movie_text_round_trip() {
+        let movie = Movie {
+            rom_crc: 0xDEADBEEF,
+            frames: vec![
+                FrameInput {
+                    joypad: ControllerState::RIGHT | ControllerState::A,
+                    port_2: Some(PortInput::Joypad(ControllerState::START)),
+                },
+                FrameInput {
+                    joypad: ControllerState::empty(),
+                    port_2: Some(PortInput::Paddle {
+                        position: 192,
+                        fire: true,
+                    }),
+                },
+                FrameInput {
+                    joypad: ControllerState::DOWN,
+                    port_2: Some(PortInput::Mouse {
+                        delta_x: -3,
+                        delta_y: 5,
+                        left: true,
+                        right: false,
+                    }),
+                },
+                FrameInput {
+                    joypad: ControllerState::B,
+                    port_2: None,
+                },
+            ],
+        };
+        let text = movie.to_text();
+        assert_eq!(
+            "version 1\nromChecksum DEADBEEF\n|0|R......A|....T...|\n|0|........|192 F|\n\
+             |0|..D.....|-3 5 L.|\n|0|......B.||\n",
+            text
+        );
+        assert_eq!(movie, Movie::parse(&text).unwrap());
+    }
+
+    #[test]
+    fn test_movie_parse_errors() {
+        let frames = "|0|........||\n";
+        assert!(Movie::parse(&format!("version 1\ncomment hi\nromChecksum 1\n{}", frames)).is_ok());
+        assert!(Movie::parse(&format!("version 2\nromChecksum 1\n{}", frames)).is_err());
+        assert!(Movie::parse(&format!("version 1\n{}", frames)).is_err());
+        // Resets aren't supported
+        let err = Movie::parse("version 1\nromChecksum 1\n|1|........||\n").unwrap_err();
+        assert!(err.starts_with("Line 3"), "{}", err);
+        assert!(Movie::parse("version 1\nromChecksum 1\n|0|.......||\n").is_err());
+    }
+}
@@ -1,8 +1,34 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::iter;
+#[cfg(not(feature = "minimal"))]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(not(feature = "minimal"))]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::apu::{ApuAction, ApuState, MixerControls, Resampler};
+use crate::audit::DeterminismAudit;
+#[cfg(not(feature = "minimal"))]
+use crate::audit::Nondeterminism;
+use crate::common::{crc32, Memory};
 use crate::controller::{Controller, ControllerState};
-use crate::cpu::{CpuAction, CpuBus, CpuState, Instruction};
+use crate::cpu::{CpuAction, CpuBus, CpuState, Instruction, PRG_RAM_SIZE, PRG_RAM_START};
+use crate::frontend::CPU_CLOCK;
+use crate::history::{ExecutionHistory, HistoryEntry};
+use crate::memory_edit::{EditJournal, MemoryEditor};
+use crate::peripheral::{OutputLatch, PortDevice};
 // use crate::ppu::ppu_state::PpuState;
-use crate::ppu::{PpuAction, PpuState};
-use crate::rom::ROM;
+use crate::ppu::{Picture, PpuAction, PpuState, SpriteStats, DOTS_PER_SCANLINE, SCANLINES};
+use crate::profiler::MemoryProfile;
+use crate::region::Region;
+use crate::rom::mapper::ClockSource;
+use crate::rom::{ROM, TRAINER_ADDR};
+use crate::savestate;
+use crate::scheduler::{Scheduler, TimingEvent, DOTS_PER_CPU_CYCLE};
+use crate::screen::frame::Frame;
+use crate::snapshot::{Snapshot, SnapshotBaseline};
+use crate::stall::StallDetector;
 
 pub trait NES {
     // pub fn next_cpu_cycle();
@@ -13,7 +39,15 @@ pub trait NES {
     // Updates state to after next PPU cycle (next frame)
     fn next_ppu_frame(&mut self) -> Result<(), String>;
 
-    fn update_controller(&mut self, key: ControllerState, bit: bool);
+    // Updates state to after the PPU moves onto the next scanline
+    fn next_ppu_scanline(&mut self) -> Result<(), String>;
+
+    // Updates state to after at least `cycles` CPU cycles have been executed
+    fn next_cpu_cycles(&mut self, cycles: usize) -> Result<(), String>;
+
+    // Presses or releases buttons on a player's controller, 0 for player 1 and 1 for player 2.
+    // Nothing happens if there's no controller in that player's port.
+    fn update_controller(&mut self, player: usize, key: ControllerState, bit: bool);
 
     // Loads a program
     fn set_rom(&mut self, rom: ROM) -> Result<(), String>;
@@ -28,6 +62,109 @@ pub trait NES {
 
     // Look into PPU state
     fn peek_ppu_state(&self) -> PpuState;
+
+    // Renders the pixels the PPU has drawn into a frame
+    fn render_frame(&self, frame: &mut Frame);
+
+    // Frames completed since the ROM was loaded
+    fn frame_count(&self) -> usize;
+
+    // Console time those frames took, e.g. 60.1 frames make a second
+    fn emulated_duration(&self) -> Duration;
+
+    // Sprite evaluation counters of the last completed frame
+    fn sprite_stats(&self) -> SpriteStats;
+
+    // Savestate of the console and board, for the ROM that's loaded
+    fn save_state(&self) -> Vec<u8>;
+
+    // Most bytes save_state returns for the ROM that's loaded, for buffers sized once. States
+    // can be zero padded to it.
+    fn max_state_len(&self) -> usize;
+
+    // Loads a savestate taken with the same ROM, leaving the console as it was on errors
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String>;
+
+    /// Runs `frames` frames and renders the last one, for tests and scripts without a window
+    fn run_for_frames(&mut self, frames: usize) -> Result<Box<Frame>, String> {
+        for _ in 0..frames {
+            self.next_ppu_frame()?;
+        }
+        Ok(render_boxed(self))
+    }
+
+    /// Runs at least `cycles` CPU cycles and renders the frame as far as it's drawn
+    fn run_for_cpu_cycles(&mut self, cycles: usize) -> Result<Box<Frame>, String> {
+        self.next_cpu_cycles(cycles)?;
+        Ok(render_boxed(self))
+    }
+
+    /// Runs instructions until `predicate` holds, checked before each one, e.g. until the game
+    /// has written its title screen. Fails if it doesn't within `max_cycles` CPU cycles.
+    fn run_until(
+        &mut self,
+        max_cycles: usize,
+        mut predicate: impl FnMut(&Self) -> bool,
+    ) -> Result<Box<Frame>, String>
+    where
+        Self: Sized,
+    {
+        let mut cycles = 0;
+        while !predicate(self) {
+            if cycles >= max_cycles {
+                return Err(format!("Condition not met after {} CPU cycles", cycles));
+            }
+            cycles += self.next_cpu_instruction()?.meta.cycles as usize;
+        }
+        Ok(render_boxed(self))
+    }
+}
+
+// Boxed like the frames iterator's, a quarter megabyte is a lot to return by value
+fn render_boxed(nes: &(impl NES + ?Sized)) -> Box<Frame> {
+    let mut frame = Box::new(Frame::new());
+    nes.render_frame(&mut frame);
+    frame
+}
+
+// Dots in a frame, the dot skipped on odd frames isn't emulated
+const DOTS_PER_FRAME: u64 = (SCANLINES * DOTS_PER_SCANLINE) as u64;
+
+/// Console time `frames` frames take
+pub fn frames_duration(frames: usize) -> Duration {
+    let nanos = frames as u128 * DOTS_PER_FRAME as u128 * 1_000_000_000
+        / (DOTS_PER_CPU_CYCLE * CPU_CLOCK) as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Emulated time as minutes, seconds and milliseconds, e.g. 01:23.456
+pub fn format_emulated_time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{:02}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// Called once per frame when the PPU enters vblank, e.g. to latch frontend input
+#[cfg(not(feature = "minimal"))]
+type VblankFn = dyn FnMut(&mut Controller) + Send;
+
+// Minimal builds have no hook, frontends call set_inputs between frames instead
+#[cfg(feature = "minimal")]
+#[derive(Clone)]
+pub struct VblankHook;
+
+#[cfg(not(feature = "minimal"))]
+#[derive(Clone)]
+pub struct VblankHook(Arc<Mutex<VblankFn>>);
+
+impl fmt::Debug for VblankHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("VblankHook")
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -35,47 +172,482 @@ pub struct ActionNES {
     // TODO: change testing logic so that cpu_state doesn't have to be public!
     pub cpu_state: CpuState,
     pub ppu_state: PpuState,
+    pub apu_state: ApuState,
+    // Output of the PPU, rendered into frames by render_frame
+    picture: Picture,
     pub controller: Controller,
+    // Device plugged into the second controller port ($4017)
+    pub port_2: PortDevice,
     pub rom: ROM,
+    // Region the game was made for, the console is always timed as NTSC for now
+    pub region: Region,
+    on_vblank: Option<VblankHook>,
+    history: Option<ExecutionHistory>,
+    audit: Option<DeterminismAudit>,
+    profile: Option<MemoryProfile>,
+    stall_detector: Option<StallDetector>,
+    memory_edits: EditJournal,
+    mixer: MixerControls,
+    audio: Option<Resampler>,
+    // Part of snapshots, so loading a savestate goes back to its frame
+    pub(crate) frame_count: usize,
+    scheduler: Scheduler<TimingEvent>,
+    // Master clock time (PPU dots) the APU has been run up to
+    apu_clock: u64,
 }
 
 impl ActionNES {
     pub fn new() -> Self {
-        Self::default()
+        let mut nes = Self::default();
+        nes.sync_timing();
+        nes
     }
 
     // TODO: may want to revisit how this is done? Maybe implement From?
-    fn as_cpu_action(&mut self) -> CpuAction {
+    fn as_cpu_action(&mut self) -> CpuAction<'_, '_, '_, '_> {
         CpuAction::new(
             &mut self.cpu_state,
             &mut self.ppu_state,
+            &mut self.apu_state,
             &mut self.controller,
-            &self.rom,
+            &mut self.port_2,
+            &mut self.rom,
         )
+        .with_audit(self.audit.as_mut())
+        .with_profile(self.profile.as_mut())
     }
 
     // fn as_ppu_action(&mut self) -> PpuAction {}
 
     // TODO: change testing logic so that this doesn't have to be public!
-    pub fn as_cpu_bus(&mut self) -> CpuBus {
+    pub fn as_cpu_bus(&mut self) -> CpuBus<'_, '_, '_, '_> {
         CpuBus::new(
             &mut self.cpu_state,
             &mut self.ppu_state,
+            &mut self.apu_state,
             &mut self.controller,
-            &self.rom,
+            &mut self.port_2,
+            &mut self.rom,
         )
+        .with_audit(self.audit.as_mut())
+        .with_profile(self.profile.as_mut())
+    }
+
+    pub fn as_ppu_action(&mut self) -> PpuAction<'_, '_> {
+        PpuAction::new(&mut self.ppu_state, &mut self.rom).with_output(Some(&mut self.picture))
+    }
+
+    /// The pixels the PPU has drawn, the current frame down to the scanline it's on and the
+    /// last one below it
+    pub fn picture(&self) -> &Picture {
+        &self.picture
+    }
+
+    /// Peeks `length` bytes of CPU memory with no side effects, RAM mirrors ($0800-$1FFF)
+    /// return the same values as $0000-$07FF
+    pub fn peek_memory(&mut self, start: u16, length: usize) -> Vec<u8> {
+        self.as_cpu_bus().peek_range(start, length)
+    }
+
+    /// Reads a list of (possibly scattered) addresses, e.g. player position and score for a bot
+    pub fn observe(&mut self, addresses: &[u16]) -> Vec<u8> {
+        let bus = self.as_cpu_bus();
+        addresses.iter().map(|addr| bus.peek_byte(*addr)).collect()
+    }
+
+    /// Replaces the buttons held on controller 1
+    pub fn set_inputs(&mut self, state: ControllerState) {
+        self.controller.set_controller_state(state);
+    }
+
+    /// Controller of player 1 or 2, None when port 2 has another device or nothing in it
+    pub fn player_controller(&mut self, player: usize) -> Option<&mut Controller> {
+        match (player, &mut self.port_2) {
+            (0, _) => Some(&mut self.controller),
+            (1, PortDevice::Joypad(controller)) => Some(controller),
+            _ => None,
+        }
+    }
+
+    /// Runs `frames` frames with the current inputs held
+    pub fn step_frames(&mut self, frames: usize) -> Result<(), String> {
+        for _ in 0..frames {
+            self.next_ppu_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Runs a frame for each item and yields it rendered, e.g. `nes.frames().take(1000)` for the
+    /// first 1000 frames. Nothing runs until an item is asked for, and the frames end after an
+    /// error. Frames are boxed, a quarter megabyte each would be copied through every adapter.
+    pub fn frames(&mut self) -> impl Iterator<Item = Result<Box<Frame>, String>> + '_ {
+        let mut failed = false;
+        iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let result = self.next_ppu_frame().map(|()| render_boxed(self));
+            failed = result.is_err();
+            Some(result)
+        })
+    }
+
+    /// Registers a hook called at the start of every vblank, replacing any previous hook
+    #[cfg(not(feature = "minimal"))]
+    pub fn set_on_vblank(&mut self, hook: impl FnMut(&mut Controller) + Send + 'static) {
+        self.on_vblank = Some(VblankHook(Arc::new(Mutex::new(hook))));
+    }
+
+    pub fn clear_on_vblank(&mut self) {
+        self.on_vblank = None;
+    }
+
+    /// Edits RAM and PRG RAM, e.g. `nes.with_memory_edit(|mem| mem.write(0x075A, 9))`. Every byte
+    /// written is journaled, so the whole edit can be rolled back with undo_memory_edit.
+    pub fn with_memory_edit<R>(&mut self, edit: impl FnOnce(&mut MemoryEditor) -> R) -> R {
+        let mut editor = MemoryEditor::new(&mut self.cpu_state, self.rom.prg_ram_size);
+        let result = edit(&mut editor);
+        self.memory_edits.push(editor.into_journal());
+        result
+    }
+
+    /// Rolls back the last with_memory_edit, returning the addresses restored or None if there
+    /// were no edits left
+    pub fn undo_memory_edit(&mut self) -> Option<Vec<u16>> {
+        self.memory_edits
+            .undo(&mut self.cpu_state, self.rom.prg_ram_size)
+    }
+
+    pub fn memory_edits(&mut self) -> &mut EditJournal {
+        &mut self.memory_edits
+    }
+
+    /// Keeps the last `capacity` executed instructions, dumped to stderr if execution fails
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(ExecutionHistory::new(capacity));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&ExecutionHistory> {
+        self.history.as_ref()
+    }
+
+    /// Starts recording nondeterministic inputs (uninitialized RAM, open bus, vblank hooks),
+    /// should be called before the first instruction so power-on RAM is tracked
+    pub fn enable_audit(&mut self) {
+        self.audit = Some(DeterminismAudit::new());
+    }
+
+    pub fn disable_audit(&mut self) {
+        self.audit = None;
+    }
+
+    pub fn audit(&self) -> Option<&DeterminismAudit> {
+        self.audit.as_ref()
+    }
+
+    /// Starts counting CPU reads and writes per address, see profiler
+    pub fn enable_profiler(&mut self) {
+        self.profile = Some(MemoryProfile::new());
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.profile = None;
+    }
+
+    pub fn profile(&self) -> Option<&MemoryProfile> {
+        self.profile.as_ref()
+    }
+
+    /// Warns with a state dump when the game spends `frames` frames polling $2002 with NMI
+    /// disabled, see stall
+    pub fn enable_stall_detector(&mut self, frames: usize) {
+        self.stall_detector = Some(StallDetector::new(frames));
+    }
+
+    pub fn disable_stall_detector(&mut self) {
+        self.stall_detector = None;
+    }
+
+    pub fn stall_detector(&self) -> Option<&StallDetector> {
+        self.stall_detector.as_ref()
     }
 
-    pub fn as_ppu_action(&mut self) -> PpuAction {
-        PpuAction::new(&mut self.ppu_state, &self.rom)
+    /// Output pins set by the last $4016 write, see peripheral::OutputLatch
+    pub fn output_latch(&self) -> OutputLatch {
+        self.cpu_state.output_latch
+    }
+
+    /// Volume controls, clones can be moved to the UI or audio thread
+    pub fn mixer(&self) -> &MixerControls {
+        &self.mixer
+    }
+
+    /// Starts generating audio samples at `sample_rate`, mixed with mixer()
+    pub fn enable_audio(&mut self, sample_rate: u32) {
+        self.audio = Some(Resampler::new(sample_rate, self.mixer.clone()));
+    }
+
+    pub fn disable_audio(&mut self) {
+        self.audio = None;
+    }
+
+    /// Samples generated so far, to be taken by the audio device
+    pub fn audio(&mut self) -> Option<&mut Resampler> {
+        self.audio.as_mut()
+    }
+
+    /// Memory the cartridge keeps while the console is off, to write to a .sav file: the board's
+    /// own (an EEPROM, a real-time clock) or the PRG RAM of a cartridge with a battery. None if
+    /// there's neither.
+    pub fn battery_ram(&self) -> Option<Cow<'_, [u8]>> {
+        let prg_ram = &self.cpu_state.prg_ram[..self.rom.prg_ram_size.min(PRG_RAM_SIZE)];
+        self.rom
+            .board
+            .save_data()
+            .or_else(|| (self.rom.battery && !prg_ram.is_empty()).then_some(Cow::Borrowed(prg_ram)))
+    }
+
+    /// Loads a battery save into the memory battery_ram returns, after loading the ROM
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if self.rom.board.save_data().is_some() {
+            self.rom.board.load_save_data(data);
+        } else if self.rom.battery {
+            let length = data.len().min(self.rom.prg_ram_size.min(PRG_RAM_SIZE));
+            self.cpu_state.prg_ram[..length].copy_from_slice(&data[..length]);
+        }
+    }
+
+    /// Sets the clock a board's real-time clock follows, after loading the ROM. Boards start
+    /// with a VirtualClock, so runs replay the same unless they're given a HostClock.
+    pub fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        self.rom.board.set_clock_source(clock);
+    }
+
+    /// CRC-32 of the savestate bytes of the console, equal hashes mean equal emulator state
+    pub fn state_hash(&self) -> u32 {
+        let snapshot = Snapshot::capture(self, &SnapshotBaseline::power_on());
+        crc32(&snapshot.to_bytes())
+    }
+
+    /// Human readable dump of the registers, recent instructions and RAM, e.g. for bug reports
+    pub fn dump_state(&self) -> String {
+        let cpu = &self.cpu_state;
+        let ppu = &self.ppu_state;
+        let mut dump = format!(
+            "CPU: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}\n",
+            cpu.program_counter,
+            cpu.reg_a,
+            cpu.reg_x,
+            cpu.reg_y,
+            cpu.status.bits(),
+            cpu.stack_pointer,
+            cpu.cycle_counter
+        );
+        dump.push_str(&format!(
+            "PPU: scanline:{} cycle:{} CTRL:{:02X} MASK:{:02X} STATUS:{:02X} v:{:04X} t:{:04X}\n",
+            ppu.cur_scanline,
+            ppu.cycle_counter,
+            ppu.ppuctrl.bits(),
+            ppu.ppumask.bits(),
+            ppu.ppustatus.bits(),
+            ppu.loopy.v,
+            ppu.loopy.t
+        ));
+        if let Some(history) = &self.history {
+            dump.push_str(&format!("Last {} instructions:\n", history.len()));
+            dump.push_str(&history.dump());
+            dump.push('\n');
+        }
+        dump.push_str("RAM:\n");
+        for (row, bytes) in cpu.ram.chunks(16).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            dump.push_str(&format!("{:04X}: {}\n", row * 16, hex.join(" ")));
+        }
+        dump
+    }
+
+    fn execute_cpu_instruction(&mut self) -> Result<Instruction, String> {
+        let program_counter = self.cpu_state.program_counter;
+        let instruction = self.execute_with_history()?;
+        if let Some(detector) = &mut self.stall_detector {
+            detector.record(program_counter, &instruction);
+        }
+        Ok(instruction)
+    }
+
+    // Executes a CPU instruction, recording it in the history if enabled
+    fn execute_with_history(&mut self) -> Result<Instruction, String> {
+        if self.history.is_none() {
+            return self.as_cpu_action().next_cpu_instruction();
+        }
+        let mut entry = HistoryEntry::new(&self.cpu_state);
+        // Bus faults panic, so catch them long enough to dump the history
+        #[cfg(not(feature = "minimal"))]
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.as_cpu_action().next_cpu_instruction()
+        }));
+        // No unwinding, the history is only dumped on errors
+        #[cfg(feature = "minimal")]
+        let result: Result<_, std::convert::Infallible> =
+            Ok(self.as_cpu_action().next_cpu_instruction());
+        if let Ok(Ok(instruction)) = &result {
+            entry.raw_opcode = Some(instruction.meta.raw_opcode);
+        }
+        if let Some(history) = &mut self.history {
+            history.push(entry);
+        }
+        match result {
+            Ok(Ok(instruction)) => Ok(instruction),
+            Ok(Err(err)) => {
+                self.dump_history(&err);
+                Err(err)
+            }
+            #[cfg(not(feature = "minimal"))]
+            Err(payload) => {
+                self.dump_history("panic during CPU instruction");
+                panic::resume_unwind(payload)
+            }
+            #[cfg(feature = "minimal")]
+            Err(never) => match never {},
+        }
+    }
+
+    fn dump_history(&self, reason: &str) {
+        if let Some(history) = &self.history {
+            let dump = format!(
+                "{}, last {} instructions:\n{}",
+                reason,
+                history.len(),
+                history.dump()
+            );
+            log::error!("{}", dump);
+        }
+    }
+
+    fn check_stall(&mut self) {
+        let nmi_enabled = self.ppu_state.ppuctrl.is_generate_nmi();
+        let Some(stall) = self
+            .stall_detector
+            .as_mut()
+            .and_then(|detector| detector.end_frame(nmi_enabled))
+        else {
+            return;
+        };
+        let report = format!("{}\n{}", stall, self.dump_state());
+        log::warn!("{}", report);
+    }
+
+    /// Master clock time in PPU dots, the CPU is always ahead of the PPU and APU
+    pub fn master_clock(&self) -> u64 {
+        self.cpu_state.cycle_counter as u64 * DOTS_PER_CPU_CYCLE
+    }
+
+    /// Lines the APU up with the CPU and schedules every event again, for after the cycle
+    /// counters or the PPU and APU state are set directly
+    pub fn sync_timing(&mut self) {
+        let now = self.master_clock();
+        self.apu_clock = now;
+        self.scheduler.cancel(TimingEvent::ScanlineEnd);
+        self.schedule_scanline_end(now);
+        self.schedule_apu_events();
+    }
+
+    // The PPU counter is at `now`, the next scanline ends when it reaches the end of the line.
+    // Only scanline ends move it back.
+    fn schedule_scanline_end(&mut self, now: u64) {
+        let dots_left =
+            (DOTS_PER_SCANLINE as u64).saturating_sub(self.ppu_state.cycle_counter as u64);
+        self.scheduler
+            .schedule(now + dots_left, TimingEvent::ScanlineEnd);
+    }
+
+    // Frame counter steps and DMC bytes only move when their registers are written or the
+    // events run
+    fn schedule_apu_events(&mut self) {
+        self.apu_state.timing_changed = false;
+        let apu = ApuAction::new(&mut self.apu_state);
+        let to_dots = |cycles: usize| {
+            (cycles as u64)
+                .checked_mul(DOTS_PER_CPU_CYCLE)
+                .and_then(|dots| dots.checked_add(self.apu_clock))
+        };
+        let frame_step = to_dots(apu.cycles_until_frame_step());
+        let dmc_byte = apu.cycles_until_dmc_byte().and_then(to_dots);
+        self.scheduler
+            .reschedule(TimingEvent::ApuFrameStep, frame_step);
+        self.scheduler.reschedule(TimingEvent::DmcByte, dmc_byte);
+    }
+
+    // Runs the APU up to `at`, both are in dots
+    fn advance_apu(&mut self, at: u64) {
+        let cycles = (at.saturating_sub(self.apu_clock) / DOTS_PER_CPU_CYCLE) as usize;
+        ApuAction::new(&mut self.apu_state)
+            .with_output(self.audio.as_mut())
+            .advance(cycles);
+        self.apu_clock = at;
+    }
+
+    // Runs the events due by the end of a CPU instruction in time order, calling the vblank hook
+    // if vblank just started. Returns true if a new frame started.
+    fn run_events(&mut self) -> bool {
+        let now = self.master_clock();
+        self.apu_clock = self.apu_clock.min(now);
+        let mut is_new_frame = false;
+        if self.apu_state.timing_changed {
+            self.schedule_apu_events();
+        }
+        while let Some((at, event)) = self.scheduler.pop_due(now) {
+            match event {
+                TimingEvent::ScanlineEnd => {
+                    if self.as_ppu_action().end_scanline() {
+                        self.frame_count += 1;
+                        is_new_frame = true;
+                        self.check_stall();
+                    }
+                    #[cfg(not(feature = "minimal"))]
+                    if self.ppu_state.cur_scanline == crate::ppu::VBLANK_SCANLINE {
+                        self.call_vblank_hook();
+                    }
+                    self.schedule_scanline_end(now);
+                }
+                TimingEvent::ApuFrameStep => {
+                    self.advance_apu(at);
+                    ApuAction::new(&mut self.apu_state).frame_step();
+                    self.schedule_apu_events();
+                }
+                TimingEvent::DmcByte => {
+                    self.advance_apu(at);
+                    ApuAction::new(&mut self.apu_state).dmc_byte();
+                    self.schedule_apu_events();
+                }
+            }
+        }
+        self.as_ppu_action().catch_up();
+        self.advance_apu(now);
+        is_new_frame
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    fn call_vblank_hook(&mut self) {
+        if let Some(VblankHook(hook)) = &self.on_vblank {
+            if let Some(audit) = &mut self.audit {
+                audit.record(Nondeterminism::VblankHook, self.cpu_state.cycle_counter);
+            }
+            let mut hook = hook.lock().expect("vblank hook poisoned");
+            hook(&mut self.controller);
+        }
     }
 }
 
 impl NES for ActionNES {
     // Updates state to after next CPU instruction
     fn next_cpu_instruction(&mut self) -> Result<Instruction, String> {
-        let instruction = self.as_cpu_action().next_cpu_instruction()?;
-        self.as_ppu_action().update_ppu_and_check_for_new_frame();
+        let instruction = self.execute_cpu_instruction()?;
+        self.run_events();
         Ok(instruction)
     }
 
@@ -84,9 +656,9 @@ impl NES for ActionNES {
         // TODO: need to run CPU instructions until we're at the next frame
         // Some Rust while loop black magic
         // let mut count = 1;
-        let _instruction = self.as_cpu_action().next_cpu_instruction()?;
-        while !self.as_ppu_action().update_ppu_and_check_for_new_frame() {
-            let _instruction = self.as_cpu_action().next_cpu_instruction()?;
+        let _instruction = self.execute_cpu_instruction()?;
+        while !self.run_events() {
+            let _instruction = self.execute_cpu_instruction()?;
             // count += 1;
         }
         // println!("Executed {} instructions", count);
@@ -94,27 +666,66 @@ impl NES for ActionNES {
         Ok(())
     }
 
-    fn update_controller(&mut self, key: ControllerState, bit: bool) {
-        self.controller.controller_state.set(key, bit);
+    // Updates state to after the PPU moves onto the next scanline
+    fn next_ppu_scanline(&mut self) -> Result<(), String> {
+        let scanline = self.ppu_state.cur_scanline;
+        while self.ppu_state.cur_scanline == scanline {
+            self.next_cpu_instruction()?;
+        }
+        Ok(())
     }
 
-    // Loads a program
-    fn set_rom(&mut self, rom: ROM) -> Result<(), String> {
+    // Updates state to after at least `cycles` CPU cycles have been executed
+    fn next_cpu_cycles(&mut self, cycles: usize) -> Result<(), String> {
+        let target = self.cpu_state.cycle_counter + cycles;
+        while self.cpu_state.cycle_counter < target {
+            self.next_cpu_instruction()?;
+        }
+        Ok(())
+    }
+
+    fn update_controller(&mut self, player: usize, key: ControllerState, bit: bool) {
+        if let Some(controller) = self.player_controller(player) {
+            controller.controller_state.set(key, bit);
+        }
+    }
+
+    // Loads a program, like switching the console on with a new cartridge. Call reset to start it.
+    fn set_rom(&mut self, mut rom: ROM) -> Result<(), String> {
+        rom.load_board()?;
+        self.cpu_state.power_on();
+        self.cpu_state.prg_ram = [0; PRG_RAM_SIZE];
+        if let Some(trainer) = &rom.trainer {
+            let start = (TRAINER_ADDR - PRG_RAM_START) as usize;
+            self.cpu_state.prg_ram[start..start + trainer.len()].copy_from_slice(trainer);
+        }
+        // Frontends with the file name refine this with game_db::detect_region
+        self.region = rom.region.unwrap_or_default();
         self.rom = rom;
+        self.frame_count = 0;
         Ok(())
     }
 
+    #[cfg(not(feature = "minimal"))]
     fn load_from_path(&mut self, path: &str) -> Result<(), String> {
         self.set_rom(ROM::create_from_nes(path)?)
     }
 
-    // Resets the console
-    // TODO: this should trigger some interrupt right?
+    #[cfg(feature = "minimal")]
+    fn load_from_path(&mut self, path: &str) -> Result<(), String> {
+        Err(format!(
+            "Can't load {}, minimal builds have no file IO",
+            path
+        ))
+    }
+
+    // Resets the console, the reset button or the end of power on
     fn reset(&mut self) -> Result<(), String> {
         self.cpu_state.reset();
         self.cpu_state.program_counter = self.as_cpu_bus().read_two_bytes(0xFFFC);
         self.cpu_state.cycle_counter += 7;
         self.ppu_state.cycle_counter += 21;
+        self.sync_timing();
         Ok(())
     }
 
@@ -127,4 +738,41 @@ impl NES for ActionNES {
     fn peek_ppu_state(&self) -> PpuState {
         self.ppu_state
     }
+
+    // Renders the pixels the PPU has drawn into a frame
+    fn render_frame(&self, frame: &mut Frame) {
+        frame.render_picture(&self.picture, &self.ppu_state);
+    }
+
+    fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn emulated_duration(&self) -> Duration {
+        frames_duration(self.frame_count)
+    }
+
+    fn sprite_stats(&self) -> SpriteStats {
+        self.ppu_state.timing.last_sprites
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let snapshot = Snapshot::capture(self, &SnapshotBaseline::power_on());
+        savestate::encode(&self.rom, &snapshot.board_state(), &snapshot.to_bytes())
+    }
+
+    fn max_state_len(&self) -> usize {
+        savestate::HEADER_SIZE
+            + 4
+            + self.rom.board.save_state().len()
+            + Snapshot::max_bytes_len(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let (mapper_data, state) = savestate::decode(state, &self.rom)?;
+        let mut snapshot = Snapshot::from_bytes(state, self)?;
+        snapshot.load_board_state(&mapper_data)?;
+        snapshot.restore(self, &SnapshotBaseline::power_on());
+        Ok(())
+    }
 }
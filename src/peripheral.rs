@@ -0,0 +1,280 @@
+// Devices that can be plugged into the controller ports
+// https://www.nesdev.org/wiki/Input_devices
+use crate::controller::Controller;
+
+/// A device read through $4016/$4017, strobed by writes to $4016
+pub trait Peripheral {
+    /// Strobe write to $4016, seen by every port
+    fn write(&mut self, data: u8);
+
+    /// Read from the port register, only bits D0-D4 are driven by the device
+    fn read(&mut self) -> u8;
+
+    /// Same as read, but with no side effects
+    fn peek(&self) -> u8;
+}
+
+impl Peripheral for Controller {
+    fn write(&mut self, data: u8) {
+        Controller::write(self, data)
+    }
+
+    fn read(&mut self) -> u8 {
+        Controller::read(self)
+    }
+
+    fn peek(&self) -> u8 {
+        Controller::peek(self)
+    }
+}
+
+/// OUT0-OUT2, CPU output pins latched by writes to $4016. OUT0 strobes the controllers, OUT1 and
+/// OUT2 only reach the expansion port, where homebrew hardware uses them for e.g. rumble motors.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLatch(u8);
+
+impl OutputLatch {
+    pub fn write(&mut self, data: u8) {
+        self.0 = data & 0b111;
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// True while OUT`line` is high, for lines 0-2
+    pub fn is_set(&self, line: u8) -> bool {
+        line < 3 && self.0 & (1 << line) != 0
+    }
+}
+
+// Range of potentiometer values reported by the NES Vaus controller
+pub const PADDLE_MIN: u8 = 0x62;
+pub const PADDLE_MAX: u8 = 0xF2;
+
+// $4017 reads xxxDFxxx
+const PADDLE_DATA_BIT: u8 = 0b0001_0000;
+const PADDLE_FIRE_BIT: u8 = 0b0000_1000;
+
+/// Arkanoid Vaus controller (NES version), usually connected to port 2
+/// https://www.nesdev.org/wiki/Arkanoid_controller
+#[derive(Debug, Clone, Copy)]
+pub struct ArkanoidPaddle {
+    pub position: u8,
+    pub fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Default for ArkanoidPaddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArkanoidPaddle {
+    pub fn new() -> Self {
+        ArkanoidPaddle {
+            position: PADDLE_MIN,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    /// Maps a screen x coordinate in [0, width) to the potentiometer range
+    pub fn set_position_from_screen(&mut self, x: i32, width: u32) {
+        let width = width.max(1) as i32;
+        let x = x.clamp(0, width - 1);
+        let range = (PADDLE_MAX - PADDLE_MIN) as i32;
+        self.position = PADDLE_MIN + (x * range / (width - 1).max(1)) as u8;
+    }
+}
+
+impl Peripheral for ArkanoidPaddle {
+    fn write(&mut self, data: u8) {
+        self.strobe = (data & 1) == 1;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.peek();
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        value
+    }
+
+    fn peek(&self) -> u8 {
+        // Potentiometer value is shifted out MSB first and inverted on D4
+        let data = if self.shift & 0b1000_0000 == 0 {
+            PADDLE_DATA_BIT
+        } else {
+            0
+        };
+        let fire = if self.fire { PADDLE_FIRE_BIT } else { 0 };
+        data | fire
+    }
+}
+
+/// SNES mouse adapted to an NES port, reports 32 bits serially on D0
+/// https://www.nesdev.org/wiki/Super_NES_Mouse
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SnesMouse {
+    pub left: bool,
+    pub right: bool,
+    delta_x: i32,
+    delta_y: i32,
+    strobe: bool,
+    report: u32,
+    bits_read: u8,
+}
+
+impl SnesMouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accumulates mouse motion until the next strobe
+    pub fn add_motion(&mut self, delta_x: i32, delta_y: i32) {
+        self.delta_x += delta_x;
+        self.delta_y += delta_y;
+    }
+
+    /// Motion since the last strobe
+    pub fn motion(&self) -> (i32, i32) {
+        (self.delta_x, self.delta_y)
+    }
+
+    pub fn set_motion(&mut self, delta_x: i32, delta_y: i32) {
+        self.delta_x = delta_x;
+        self.delta_y = delta_y;
+    }
+
+    fn latch(&mut self) {
+        // Each axis is a direction bit followed by a 7 bit magnitude
+        let encode = |delta: i32| -> u32 {
+            let direction = if delta < 0 { 0b1000_0000 } else { 0 };
+            direction | delta.unsigned_abs().min(0x7F)
+        };
+        // Byte 1 is always 0, byte 2 has the buttons and the signature 0b0001
+        let buttons = ((self.right as u32) << 7) | ((self.left as u32) << 6) | 0b0001;
+        self.report = (buttons << 16) | (encode(self.delta_y) << 8) | encode(self.delta_x);
+        self.bits_read = 0;
+        self.delta_x = 0;
+        self.delta_y = 0;
+    }
+}
+
+impl Peripheral for SnesMouse {
+    fn write(&mut self, data: u8) {
+        self.strobe = (data & 1) == 1;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.peek();
+        if !self.strobe && self.bits_read < 32 {
+            self.bits_read += 1;
+        }
+        value
+    }
+
+    fn peek(&self) -> u8 {
+        if self.bits_read >= 32 {
+            return 1;
+        }
+        ((self.report >> (31 - self.bits_read)) & 1) as u8
+    }
+}
+
+/// Device connected to a controller port
+#[derive(Debug, Default, Clone, Copy)]
+pub enum PortDevice {
+    #[default]
+    Disconnected,
+    Joypad(Controller),
+    Paddle(ArkanoidPaddle),
+    Mouse(SnesMouse),
+}
+
+impl Peripheral for PortDevice {
+    fn write(&mut self, data: u8) {
+        match self {
+            PortDevice::Disconnected => {}
+            PortDevice::Joypad(device) => Peripheral::write(device, data),
+            PortDevice::Paddle(device) => device.write(data),
+            PortDevice::Mouse(device) => device.write(data),
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        match self {
+            PortDevice::Disconnected => 0,
+            PortDevice::Joypad(device) => Peripheral::read(device),
+            PortDevice::Paddle(device) => device.read(),
+            PortDevice::Mouse(device) => device.read(),
+        }
+    }
+
+    fn peek(&self) -> u8 {
+        match self {
+            PortDevice::Disconnected => 0,
+            PortDevice::Joypad(device) => Peripheral::peek(device),
+            PortDevice::Paddle(device) => device.peek(),
+            PortDevice::Mouse(device) => device.peek(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paddle_serial_read() {
+        let mut paddle = ArkanoidPaddle::new();
+        paddle.position = 0b1010_0110;
+        paddle.fire = true;
+        paddle.write(1);
+        paddle.write(0);
+        let mut value = 0u8;
+        for _ in 0..8 {
+            let bits = paddle.read();
+            // Fire on D3
+            assert_ne!(0, bits & 0x08);
+            // Data is inverted, on D4
+            value = (value << 1) | ((bits & 0x10 == 0) as u8);
+        }
+        assert_eq!(0b1010_0110, value);
+    }
+
+    #[test]
+    fn test_paddle_screen_mapping() {
+        let mut paddle = ArkanoidPaddle::new();
+        paddle.set_position_from_screen(-10, 768);
+        assert_eq!(PADDLE_MIN, paddle.position);
+        paddle.set_position_from_screen(767, 768);
+        assert_eq!(PADDLE_MAX, paddle.position);
+    }
+
+    #[test]
+    fn test_mouse_report() {
+        let mut mouse = SnesMouse::new();
+        mouse.left = true;
+        mouse.add_motion(-3, 5);
+        mouse.write(1);
+        mouse.write(0);
+        let mut report = 0u32;
+        for _ in 0..32 {
+            report = (report << 1) | mouse.read() as u32;
+        }
+        assert_eq!(0x0041_0583, report);
+        // Reads past the report return 1
+        assert_eq!(1, mouse.read());
+    }
+}
@@ -1,7 +1,14 @@
+mod picture;
 mod ppu_action;
 mod ppu_bus;
 mod ppu_state;
 
+pub use picture::{Picture, PpuPixel, PICTURE_HEIGHT, PICTURE_WIDTH};
 pub use ppu_action::PpuAction;
 pub use ppu_bus::PpuBus;
-pub use ppu_state::PpuState;
+pub use ppu_state::{
+    ChrWriteLog, LineSprite, LoopyRegisters, OamAddr, PpuControl, PpuMask, PpuState, PpuStatus,
+    RenderPipeline, ScanlinePhase, ScanlineTiming, SpriteStats, DOTS_PER_SCANLINE,
+    MAX_LINE_SPRITES, POST_RENDER_SCANLINE, PRE_RENDER_SCANLINE, SCANLINES, SPRITES_PER_SCANLINE,
+    VBLANK_SCANLINE, VRAM_SIZE,
+};
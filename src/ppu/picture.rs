@@ -0,0 +1,109 @@
+// The pixels the PPU outputs while it renders, a dot at a time
+//
+// Each pixel is the palette RAM address it was drawn with, colors are only looked up when a
+// frame is made from the picture (see Frame::render_picture). The rest of the byte keeps where
+// the pixel came from, for the debug overlays. PPUMASK is kept for each line, for its greyscale
+// and emphasis bits.
+use super::{PpuMask, POST_RENDER_SCANLINE};
+
+pub const PICTURE_WIDTH: usize = 256;
+pub const PICTURE_HEIGHT: usize = POST_RENDER_SCANLINE;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PpuPixel(u8);
+
+impl PpuPixel {
+    // 76543210
+    //  ||+++++- Palette RAM address, $3F00-$3F1F, $3F10 and up for sprites
+    //  |+------ The background pixel is opaque, even if a sprite was drawn over it
+    //  +------- Sprite pixel, behind the background
+    const PALETTE_ADDR: u8 = 0b0001_1111;
+    const BACKGROUND_OPAQUE: u8 = 0b0010_0000;
+    const BEHIND_BACKGROUND: u8 = 0b0100_0000;
+
+    /// Background color 0, also what's drawn with rendering off
+    pub fn backdrop() -> Self {
+        PpuPixel(0)
+    }
+
+    pub fn background(palette: u8, color: u8) -> Self {
+        match color {
+            0 => Self::backdrop(),
+            _ => PpuPixel(Self::BACKGROUND_OPAQUE | palette << 2 | color),
+        }
+    }
+
+    /// A sprite pixel drawn over `background`, `color` is never 0
+    pub fn sprite(palette: u8, color: u8, behind_background: bool, background: Self) -> Self {
+        let mut pixel = 0x10 | palette << 2 | color;
+        pixel |= background.0 & Self::BACKGROUND_OPAQUE;
+        if behind_background {
+            pixel |= Self::BEHIND_BACKGROUND;
+        }
+        PpuPixel(pixel)
+    }
+
+    /// Index into the palette table
+    pub fn palette_addr(self) -> usize {
+        (self.0 & Self::PALETTE_ADDR) as usize
+    }
+
+    pub fn is_sprite(self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    pub fn palette(self) -> u8 {
+        (self.0 >> 2) & 0b11
+    }
+
+    pub fn is_background_opaque(self) -> bool {
+        self.0 & Self::BACKGROUND_OPAQUE != 0
+    }
+
+    pub fn is_behind_background(self) -> bool {
+        self.0 & Self::BEHIND_BACKGROUND != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Picture {
+    // On the heap, the NES is cloned and moved around a lot
+    pixels: Vec<PpuPixel>,
+    // As it was when the line started
+    line_masks: Vec<PpuMask>,
+}
+
+impl Default for Picture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Picture {
+    pub fn new() -> Self {
+        Picture {
+            pixels: vec![PpuPixel::backdrop(); PICTURE_WIDTH * PICTURE_HEIGHT],
+            line_masks: vec![PpuMask::empty(); PICTURE_HEIGHT],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> PpuPixel {
+        self.pixels[PICTURE_WIDTH * y + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: PpuPixel) {
+        if x < PICTURE_WIDTH && y < PICTURE_HEIGHT {
+            self.pixels[PICTURE_WIDTH * y + x] = pixel;
+        }
+    }
+
+    pub fn line_mask(&self, y: usize) -> PpuMask {
+        self.line_masks[y]
+    }
+
+    pub fn set_line_mask(&mut self, y: usize, mask: PpuMask) {
+        if y < PICTURE_HEIGHT {
+            self.line_masks[y] = mask;
+        }
+    }
+}
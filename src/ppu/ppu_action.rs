@@ -1,53 +1,282 @@
 use crate::rom::ROM;
 
-use super::{ppu_state::PpuStatus, PpuBus, PpuState};
+use super::{
+    ppu_state::PpuStatus, LineSprite, Picture, PpuBus, PpuPixel, PpuState, ScanlinePhase,
+    DOTS_PER_SCANLINE, MAX_LINE_SPRITES, PICTURE_WIDTH, SCANLINES, SPRITES_PER_SCANLINE,
+    VBLANK_SCANLINE,
+};
 
 pub struct PpuAction<'a, 'b> {
     ppu_state: &'a mut PpuState,
-    rom: &'b ROM,
+    rom: &'b mut ROM,
+    output: Option<&'a mut Picture>,
+}
+
+// CHR byte for the rendering fetches, 0 (transparent) past the end of CHR like Frame's tiles
+fn fetch_chr(rom: &ROM, addr: u16) -> u8 {
+    rom.chr_rom.get(rom.map_chr(addr)).copied().unwrap_or(0)
 }
 
 impl<'a, 'b> PpuAction<'a, 'b> {
-    pub fn new(ppu_state: &'a mut PpuState, rom: &'b ROM) -> Self {
-        PpuAction { ppu_state, rom }
+    pub fn new(ppu_state: &'a mut PpuState, rom: &'b mut ROM) -> Self {
+        PpuAction {
+            ppu_state,
+            rom,
+            output: None,
+        }
     }
 
-    fn as_ppu_bus(&mut self) -> PpuBus {
+    /// Draws the pixels of the visible scanlines into `output`, with None they're only
+    /// evaluated as far as the rest of the PPU needs
+    pub fn with_output(mut self, output: Option<&'a mut Picture>) -> Self {
+        self.output = output;
+        self
+    }
+
+    fn as_ppu_bus(&mut self) -> PpuBus<'_, '_> {
         PpuBus::new(self.ppu_state, self.rom)
     }
 
     // Blatant violation of SRP, but easiest way to do this atm
     // Return true if on new frame
     pub fn update_ppu_and_check_for_new_frame(&mut self) -> bool {
-        if self.ppu_state.cycle_counter < 341 {
+        if self.ppu_state.cycle_counter < DOTS_PER_SCANLINE {
             return false;
         }
-        if self.is_sprite_zero_hit() {
-            // sprite zero hit flag is reset on vblank
+        self.end_scanline()
+    }
+
+    /// Runs the dots of the current scanline the CPU has caught up with, the CPU moves the
+    /// cycle counter along a whole instruction at a time
+    pub fn catch_up(&mut self) {
+        let dot = self.ppu_state.cycle_counter.min(DOTS_PER_SCANLINE);
+        while self.ppu_state.pipeline.dot < dot {
+            self.next_ppu_cycle();
+        }
+    }
+
+    /// Runs one dot of the scanline: the tile fetches, the shift registers, moving v along and
+    /// drawing a pixel, on the same dots as the hardware
+    pub fn next_ppu_cycle(&mut self) {
+        let dot = self.ppu_state.pipeline.dot;
+        let phase = self.ppu_state.scanline_phase();
+        let mask = self.ppu_state.ppumask;
+        if (mask.is_show_background() || mask.is_show_sprites()) && phase.is_rendering_line() {
+            self.run_pipeline(dot, phase);
+        }
+        // Pixel x is drawn on dot x + 1
+        if phase == ScanlinePhase::Visible && (1..=PICTURE_WIDTH).contains(&dot) {
+            self.draw_pixel(dot - 1);
+        }
+        self.ppu_state.pipeline.dot += 1;
+    }
+
+    // Fetches for the next tiles while the shifters feed pixels out, the first two tiles of
+    // the next scanline are fetched on dots 321-336. v moves along to the next tile after each
+    // fetch, then down a row at dot 256, and is reloaded from t at dots 257 and 280-304.
+    fn run_pipeline(&mut self, dot: usize, phase: ScanlinePhase) {
+        if matches!(dot, 2..=257 | 321..=337) {
+            self.ppu_state.pipeline.shift();
+            let loopy = self.ppu_state.loopy;
+            match (dot - 1) % 8 {
+                0 => {
+                    self.ppu_state.pipeline.load_shifters();
+                    self.ppu_state.pipeline.tile_id =
+                        self.as_ppu_bus().peek_byte(loopy.tile_addr());
+                }
+                2 => {
+                    let attribute = self.as_ppu_bus().peek_byte(loopy.attribute_addr());
+                    self.ppu_state.pipeline.attribute =
+                        (attribute >> loopy.attribute_shift()) & 0b11;
+                }
+                4 | 6 => {
+                    let plane = ((dot - 1) % 8 - 4) / 2;
+                    let addr = self.ppu_state.ppuctrl.get_background_pattern_addr()
+                        + 16 * self.ppu_state.pipeline.tile_id as u16
+                        + loopy.fine_y()
+                        + 8 * plane as u16;
+                    self.ppu_state.pipeline.pattern[plane] = fetch_chr(self.rom, addr);
+                }
+                7 => self.ppu_state.loopy.increment_x(),
+                _ => {}
+            }
+        }
+        match (dot, phase) {
+            (256, _) => self.ppu_state.loopy.increment_y(),
+            (257, ScanlinePhase::Visible) => {
+                self.ppu_state.loopy.copy_horizontal();
+                self.evaluate_sprites();
+            }
+            (257, _) => {
+                self.ppu_state.loopy.copy_horizontal();
+                // Nothing is evaluated on the pre-render line, no sprites are drawn on line 0
+                self.ppu_state.pipeline.clear_sprites();
+            }
+            (280..=304, ScanlinePhase::PreRender) => self.ppu_state.loopy.copy_vertical(),
+            _ => {}
+        }
+    }
+
+    // Picks pixel x from the background and sprites. Sprite 0 hit is checked here even without
+    // output, since games poll for it.
+    fn draw_pixel(&mut self, x: usize) {
+        let ppu = &*self.ppu_state;
+        let mask = ppu.ppumask;
+        let mut pixel = PpuPixel::backdrop();
+        if mask.is_show_background() && (x >= 8 || mask.is_show_background_leftmost()) {
+            let (color, palette) = ppu.pipeline.background_pixel(ppu.loopy.x);
+            pixel = PpuPixel::background(palette, color);
+        }
+        let sprite = ppu.pipeline.sprite_pixel(x);
+        let sprite = sprite
+            .filter(|_| mask.is_show_sprites() && (x >= 8 || mask.is_show_sprites_leftmost()));
+        // Opaque sprite 0 over opaque background, whatever its priority, but never at x = 255
+        let is_sprite_zero_hit = sprite.is_some_and(|(sprite, _)| sprite.is_sprite_zero)
+            && pixel.is_background_opaque()
+            && x != 255
+            && !ppu.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT);
+        if is_sprite_zero_hit {
             self.ppu_state.ppustatus.set_sprite_zero_hit(true);
+            // Pixel x is drawn on dot x + 1
+            self.ppu_state.timing.sprites.sprite_zero_hit =
+                Some((self.ppu_state.cur_scanline, x + 1));
         }
-        self.ppu_state.cycle_counter -= 341;
+        let ppu = &*self.ppu_state;
+        let Some(output) = self.output.as_deref_mut() else {
+            return;
+        };
+        if x == 0 {
+            output.set_line_mask(ppu.cur_scanline, mask);
+        }
+        if let Some((sprite, color)) = sprite {
+            // Sprites behind the background only show through transparent background pixels
+            let behind_background = sprite.attributes & 0b0010_0000 != 0;
+            if !behind_background || !pixel.is_background_opaque() {
+                let palette = sprite.attributes & 0b11;
+                pixel = PpuPixel::sprite(palette, color, behind_background, pixel);
+            }
+        }
+        output.set_pixel(x, ppu.cur_scanline, pixel);
+    }
+
+    /// Moves onto the next scanline, the NES schedules this for when the cycle counter reaches
+    /// 341. Returns true if a new frame started.
+    pub fn end_scanline(&mut self) -> bool {
+        self.catch_up();
+        self.clock_board_scanline();
+        self.ppu_state.cycle_counter -= DOTS_PER_SCANLINE;
+        self.ppu_state.pipeline.dot = 0;
         self.ppu_state.cur_scanline += 1;
+        let is_new_frame = self.ppu_state.cur_scanline >= SCANLINES;
+        if is_new_frame {
+            self.ppu_state.cur_scanline = 0;
+            self.ppu_state.timing.finish_frame();
+        }
+        self.start_scanline();
+        is_new_frame
+    }
 
-        if self.ppu_state.cur_scanline == 241 {
-            self.ppu_state.ppustatus.set_vblank_started(true);
-            self.ppu_state.ppustatus.set_sprite_zero_hit(false);
-            if self.ppu_state.ppuctrl.is_generate_nmi() {
-                self.ppu_state.nmi_interrupt_poll = Some(());
+    // Dot 1 of the new scanline, the only dot with side effects outside of rendering
+    fn start_scanline(&mut self) {
+        let scanline = self.ppu_state.cur_scanline;
+        match self.ppu_state.scanline_phase() {
+            ScanlinePhase::VBlank if scanline == VBLANK_SCANLINE => {
+                self.ppu_state.ppustatus.set_vblank_started(true);
+                if self.ppu_state.ppuctrl.is_generate_nmi() {
+                    self.ppu_state.nmi_interrupt_poll = Some(());
+                }
             }
-        } else if self.ppu_state.cur_scanline >= 262 {
-            self.ppu_state.cur_scanline = 0;
-            self.ppu_state.nmi_interrupt_poll = None;
-            self.ppu_state.ppustatus.set_vblank_started(false);
-            self.ppu_state.ppustatus.set_sprite_zero_hit(false);
-            return true;
+            ScanlinePhase::PreRender => {
+                self.ppu_state.nmi_interrupt_poll = None;
+                self.ppu_state.ppustatus.set_vblank_started(false);
+                self.ppu_state.ppustatus.set_sprite_zero_hit(false);
+                self.ppu_state.ppustatus.set_sprite_overflow(false);
+            }
+            _ => {}
         }
-        false
+    }
+
+    // Picks the sprites in range of the scanline, to be drawn on the next one since OAM Y is
+    // one less than the sprite's top row, and fetches their row of pattern. The hardware spreads
+    // this over dots 65-320, it's all done at dot 257 here.
+    //
+    // Sprite overflow is set when there are more than 8. The hardware's buggy overflow check,
+    // with its false positives and negatives, isn't emulated.
+    fn evaluate_sprites(&mut self) {
+        let scanline = self.ppu_state.cur_scanline;
+        let limit = match self.ppu_state.sprite_limit {
+            true => SPRITES_PER_SCANLINE,
+            false => MAX_LINE_SPRITES,
+        };
+        let ppuctrl = self.ppu_state.ppuctrl;
+        let height = ppuctrl.get_sprite_size().1 as usize;
+        let oam_data = self.ppu_state.oam_data;
+        self.ppu_state.pipeline.clear_sprites();
+        let mut in_range = 0;
+        for (index, sprite) in oam_data.chunks_exact(4).enumerate() {
+            let row = scanline.wrapping_sub(sprite[0] as usize);
+            if row >= height {
+                continue;
+            }
+            in_range += 1;
+            if in_range > limit {
+                continue;
+            }
+            // 76543210
+            // ||||||||
+            // ||||||++- Palette (4 to 7) of sprite
+            // |||+++--- Unimplemented (read 0)
+            // ||+------ Priority (0: in front of background; 1: behind background)
+            // |+------- Flip sprite horizontally
+            // +-------- Flip sprite vertically
+            let attributes = sprite[2];
+            // Flipping an 8x16 sprite swaps its two tiles too
+            let row = if attributes & 0b1000_0000 != 0 {
+                height - 1 - row
+            } else {
+                row
+            };
+            let addr = ppuctrl.get_sprite_tile_addr(sprite[1], row) + (row % 8) as u16;
+            let mut pattern = [fetch_chr(self.rom, addr), fetch_chr(self.rom, addr + 8)];
+            if attributes & 0b0100_0000 != 0 {
+                pattern = pattern.map(u8::reverse_bits);
+            }
+            self.ppu_state.pipeline.sprites[in_range - 1] = LineSprite {
+                pattern,
+                attributes,
+                x: sprite[3],
+                is_sprite_zero: index == 0,
+            };
+        }
+        self.ppu_state.pipeline.sprite_count = in_range.min(limit);
+        let sprites = &mut self.ppu_state.timing.sprites;
+        sprites.max_per_scanline = sprites.max_per_scanline.max(in_range as u8);
+        if in_range > SPRITES_PER_SCANLINE {
+            sprites.dropped += in_range - SPRITES_PER_SCANLINE;
+            self.ppu_state.ppustatus.set_sprite_overflow(true);
+        }
+    }
+
+    // Scanline counters on the cartridge (MMC3) count the lines the PPU fetches patterns on
+    fn clock_board_scanline(&mut self) {
+        let mask = self.ppu_state.ppumask;
+        let is_rendering = mask.is_show_background() || mask.is_show_sprites();
+        if is_rendering && self.ppu_state.scanline_phase().is_rendering_line() {
+            self.rom.board.end_scanline();
+        }
+    }
+
+    /// Returns true if vblank starts with NMI enabled within the next `cpu_cycles` CPU cycles
+    pub fn is_nmi_within(&self, cpu_cycles: usize) -> bool {
+        self.ppu_state.scanline_phase() == ScanlinePhase::PostRender
+            && self.ppu_state.ppuctrl.is_generate_nmi()
+            && self.ppu_state.cycle_counter + 3 * cpu_cycles >= DOTS_PER_SCANLINE
     }
 
     pub fn write_ppuctrl(&mut self, data: u8) {
         let prev_is_generate_nmi = self.ppu_state.ppuctrl.is_generate_nmi();
         self.ppu_state.ppuctrl.write(data);
+        self.ppu_state.loopy.write_ppuctrl(data);
         let is_vblank_started = self.ppu_state.ppustatus.is_vblank_started();
         let cur_is_generate_nmi = self.ppu_state.ppuctrl.is_generate_nmi();
         // Set NMI Interrupt signal if PPU is in VBLANK and GENERATE_NMI changes from 0 to 1
@@ -63,8 +292,7 @@ impl<'a, 'b> PpuAction<'a, 'b> {
     pub fn read_ppustatus(&mut self) -> u8 {
         let bits = self.ppu_state.ppustatus.bits();
         self.ppu_state.ppustatus.remove(PpuStatus::VBLANK_STARTED);
-        self.ppu_state.ppuscroll.reset();
-        self.ppu_state.ppuaddr.reset();
+        self.ppu_state.loopy.reset_toggle();
         bits
     }
 
@@ -77,6 +305,8 @@ impl<'a, 'b> PpuAction<'a, 'b> {
         self.ppu_state.oamaddr.increment();
     }
 
+    /// Copies a page into OAM starting at OAMADDR, wrapping around the end of OAM, so a nonzero
+    /// OAMADDR shifts the sprite table. OAMADDR ends up back where it started.
     pub fn write_oamdma(&mut self, data: &[u8; 256]) {
         for byte in data.iter() {
             self.ppu_state.oam_data[self.ppu_state.oamaddr.read() as usize] = *byte;
@@ -89,39 +319,264 @@ impl<'a, 'b> PpuAction<'a, 'b> {
     }
 
     pub fn write_ppuscroll(&mut self, data: u8) {
-        self.ppu_state.ppuscroll.write(data);
+        self.ppu_state.loopy.write_ppuscroll(data);
     }
 
     pub fn write_ppuaddr(&mut self, data: u8) {
-        self.ppu_state.ppuaddr.write(data);
+        self.ppu_state.loopy.write_ppuaddr(data);
     }
 
     pub fn read_ppudata(&mut self) -> u8 {
-        let addr = self.ppu_state.ppuaddr.read();
+        let addr = self.ppu_state.loopy.vram_addr();
         // Retrieve previous value in buffer
         let result = self.ppu_state.ppudata;
         // Store in ppudata as buffer
         self.ppu_state.ppudata = self.as_ppu_bus().read_byte(addr);
         // Increment address
         let inc_value = self.ppu_state.ppuctrl.get_vram_addr_inc_value();
-        self.ppu_state.ppuaddr.increment(inc_value);
+        self.ppu_state.loopy.increment(inc_value);
         result
     }
 
     pub fn write_ppudata(&mut self, data: u8) {
-        let addr = self.ppu_state.ppuaddr.read();
+        let addr = self.ppu_state.loopy.vram_addr();
         self.as_ppu_bus().write_byte(addr, data);
         // Increment address
         let inc_value = self.ppu_state.ppuctrl.get_vram_addr_inc_value();
-        self.ppu_state.ppuaddr.increment(inc_value);
+        self.ppu_state.loopy.increment(inc_value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::ppu_state::PpuMask;
+    use crate::ppu::SpriteStats;
+
+    const SHOW_ALL: u8 = PpuMask::SHOW_BACKGROUND.bits()
+        | PpuMask::SHOW_SPRITES.bits()
+        | PpuMask::BACKGROUND_LEFTMOST.bits()
+        | PpuMask::SPRITES_LEFTMOST.bits();
+
+    // Tile 1 is solid color 3, tile 0 is transparent
+    fn solid_tile_rom() -> ROM {
+        let mut rom = ROM::new();
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..32].fill(0xFF);
+        rom
+    }
+
+    // Runs the scanline sprite 0 is evaluated on and the one below it, where it's drawn, over a
+    // background of `background_tile`. Returns whether sprite 0 hit was set.
+    fn is_hit(x: u8, mask: u8, sprite_tile: u8, background_tile: u8) -> bool {
+        let mut ppu_state = PpuState::new();
+        ppu_state.oam_data[0..4].copy_from_slice(&[20, sprite_tile, 0, x]);
+        ppu_state.ram[..0x3C0].fill(background_tile);
+        ppu_state.ppumask.write(mask);
+        ppu_state.cur_scanline = 20;
+        let mut rom = solid_tile_rom();
+        finish_scanline_with(&mut ppu_state, &mut rom);
+        assert!(!ppu_state.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT));
+        finish_scanline_with(&mut ppu_state, &mut rom);
+        ppu_state.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT)
+    }
+
+    fn is_hit_at(x: u8, mask: u8) -> bool {
+        is_hit(x, mask, 1, 1)
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        assert!(is_hit_at(100, SHOW_ALL));
+        assert!(is_hit_at(0, SHOW_ALL));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_on_transparent_pixels() {
+        assert!(!is_hit(100, SHOW_ALL, 0, 1));
+        assert!(!is_hit(100, SHOW_ALL, 1, 0));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_set_on_the_overlapping_dot() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.oam_data[0..4].copy_from_slice(&[20, 1, 0, 100]);
+        // Only the tile under the right half of the sprite is opaque, v starts at the top row
+        ppu_state.ram[13] = 1;
+        ppu_state.ppumask.write(SHOW_ALL);
+        ppu_state.cur_scanline = 20;
+        let mut rom = solid_tile_rom();
+        finish_scanline_with(&mut ppu_state, &mut rom);
+        // Pixel 104 is drawn on dot 105, catching up runs the dots before the counter
+        ppu_state.cycle_counter = 105;
+        PpuAction::new(&mut ppu_state, &mut rom).catch_up();
+        assert!(!ppu_state.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT));
+        ppu_state.cycle_counter = 106;
+        PpuAction::new(&mut ppu_state, &mut rom).catch_up();
+        assert!(ppu_state.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT));
+        assert_eq!(Some((21, 105)), ppu_state.timing.sprites.sprite_zero_hit);
+    }
+
+    #[test]
+    fn test_scanline_timing_swapped_at_end_of_frame() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.cur_scanline = 261;
+        ppu_state.cycle_counter = 341;
+        ppu_state.timing.add_cpu_cycles(5, 100);
+        ppu_state.timing.nmi_scanline = Some(241);
+        let mut rom = ROM::new();
+        assert!(PpuAction::new(&mut ppu_state, &mut rom).update_ppu_and_check_for_new_frame());
+        assert_eq!(100, ppu_state.timing.last_frame[5]);
+        assert_eq!(0, ppu_state.timing.cpu_cycles[5]);
+        assert_eq!(Some(241), ppu_state.timing.last_nmi_scanline);
+        assert_eq!(None, ppu_state.timing.nmi_scanline);
+    }
+
+    #[test]
+    fn test_sprite_evaluation_counters() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.ppumask.write(SHOW_ALL);
+        ppu_state.ram[..0x3C0].fill(1);
+        // Ten sprites on scanlines 21-28, sprite 0 among them at x = 16
+        for sprite in ppu_state.oam_data.chunks_exact_mut(4).take(10) {
+            sprite.copy_from_slice(&[20, 1, 0, 16]);
+        }
+        ppu_state.oam_data[40] = 30;
+        ppu_state.cur_scanline = 20;
+        let mut rom = solid_tile_rom();
+        finish_scanline_with(&mut ppu_state, &mut rom);
+        assert!(ppu_state.ppustatus.contains(PpuStatus::SPRITE_OVERFLOW));
+        for _ in 21..SCANLINES {
+            finish_scanline_with(&mut ppu_state, &mut rom);
+        }
+        let stats = ppu_state.timing.last_sprites;
+        assert_eq!(10, stats.max_per_scanline);
+        assert_eq!(2 * 8, stats.dropped);
+        assert_eq!(Some((21, 17)), stats.sprite_zero_hit);
+        assert_eq!(SpriteStats::default(), ppu_state.timing.sprites);
+    }
+
+    #[test]
+    fn test_oam_dma_from_nonzero_oamaddr() {
+        let mut ppu_state = PpuState::new();
+        let mut rom = ROM::new();
+        let mut page = [0; 256];
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut ppu_action = PpuAction::new(&mut ppu_state, &mut rom);
+        ppu_action.write_oamaddr(0xFC);
+        ppu_action.write_oamdma(&page);
+        assert_eq!(0xFC, ppu_state.oamaddr.read());
+        // The first sprite lands in the last OAM entry, the rest wrap around
+        assert_eq!([0, 1, 2, 3], ppu_state.oam_data[0xFC..]);
+        assert_eq!(4, ppu_state.oam_data[0]);
+        assert_eq!(0xFB, ppu_state.oam_data[0xF7]);
+    }
+
+    #[test]
+    fn test_scanline_phases() {
+        assert_eq!(ScanlinePhase::Visible, ScanlinePhase::of(239));
+        assert_eq!(ScanlinePhase::PostRender, ScanlinePhase::of(240));
+        assert_eq!(ScanlinePhase::VBlank, ScanlinePhase::of(260));
+        assert_eq!(ScanlinePhase::PreRender, ScanlinePhase::of(261));
+
+        let mut ppu_state = PpuState::new();
+        ppu_state.ppuctrl.write(0x80);
+        ppu_state.ppustatus.set_sprite_zero_hit(true);
+        ppu_state.ppustatus.set_sprite_overflow(true);
+        ppu_state.cur_scanline = 239;
+        let mut rom = ROM::new();
+        // The flags are only set on entering vblank
+        finish_scanline(&mut ppu_state);
+        assert!(!ppu_state.ppustatus.is_vblank_started());
+        finish_scanline(&mut ppu_state);
+        assert!(ppu_state.ppustatus.is_vblank_started());
+        assert!(ppu_state.nmi_interrupt_poll.is_some());
+        // Sprite 0 hit and overflow last until the pre-render line
+        assert!(ppu_state.ppustatus.contains(PpuStatus::SPRITE_ZERO_HIT));
+        ppu_state.cur_scanline = 260;
+        finish_scanline(&mut ppu_state);
+        assert_eq!(0, ppu_state.ppustatus.bits());
+        assert!(ppu_state.nmi_interrupt_poll.is_none());
+        ppu_state.cycle_counter = DOTS_PER_SCANLINE;
+        assert!(PpuAction::new(&mut ppu_state, &mut rom).end_scanline());
+        assert_eq!(ScanlinePhase::Visible, ppu_state.scanline_phase());
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_at_x_255() {
+        assert!(!is_hit_at(255, SHOW_ALL));
+        assert!(is_hit_at(254, SHOW_ALL));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_in_clipped_left_column() {
+        let background_clipped = SHOW_ALL & !PpuMask::BACKGROUND_LEFTMOST.bits();
+        let sprites_clipped = SHOW_ALL & !PpuMask::SPRITES_LEFTMOST.bits();
+        assert!(!is_hit_at(0, background_clipped));
+        assert!(!is_hit_at(0, sprites_clipped));
+        // The sprite's last pixel reaches x = 8
+        assert!(is_hit_at(1, background_clipped));
+        assert!(is_hit_at(1, sprites_clipped));
+    }
+
+    // Runs the PPU to the end of the current scanline
+    fn finish_scanline(ppu_state: &mut PpuState) {
+        finish_scanline_with(ppu_state, &mut ROM::new());
+    }
+
+    fn finish_scanline_with(ppu_state: &mut PpuState, rom: &mut ROM) {
+        ppu_state.cycle_counter = 341;
+        PpuAction::new(ppu_state, rom).update_ppu_and_check_for_new_frame();
+    }
+
+    #[test]
+    fn test_mid_frame_nametable_switch() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.ppumask.write(SHOW_ALL);
+        ppu_state.cur_scanline = 100;
+        let mut rom = ROM::new();
+        PpuAction::new(&mut ppu_state, &mut rom).write_ppuctrl(0b01);
+        // Only t changes right away, v picks up the horizontal nametable at the end of the line
+        assert_eq!(0x2000, ppu_state.loopy.get_name_table_addr());
+        finish_scanline(&mut ppu_state);
+        assert_eq!(0x2400, ppu_state.loopy.get_name_table_addr());
+    }
+
+    #[test]
+    fn test_vertical_nametable_applied_on_pre_render_line() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.ppumask.write(SHOW_ALL);
+        ppu_state.cur_scanline = 100;
+        let mut rom = ROM::new();
+        PpuAction::new(&mut ppu_state, &mut rom).write_ppuctrl(0b10);
+        finish_scanline(&mut ppu_state);
+        assert_eq!(0x2000, ppu_state.loopy.get_name_table_addr());
+        ppu_state.cur_scanline = 261;
+        finish_scanline(&mut ppu_state);
+        assert_eq!(0x2800, ppu_state.loopy.get_name_table_addr());
+    }
+
+    #[test]
+    fn test_nametable_write_with_rendering_disabled() {
+        let mut ppu_state = PpuState::new();
+        ppu_state.cur_scanline = 100;
+        let mut rom = ROM::new();
+        let mut ppu_action = PpuAction::new(&mut ppu_state, &mut rom);
+        ppu_action.write_ppuctrl(0b11);
+        ppu_action.write_ppuscroll(0x10);
+        ppu_action.write_ppuscroll(0x20);
+        finish_scanline(&mut ppu_state);
+        // v is only updated from t while rendering
+        assert_eq!(0x2000, ppu_state.loopy.get_name_table_addr());
+        assert_eq!(0b11 << 10, ppu_state.loopy.t & 0x0C00);
+        assert_eq!((0x10, 0x20), ppu_state.loopy.get_scroll());
     }
 
-    fn is_sprite_zero_hit(&self) -> bool {
-        let y = self.ppu_state.oam_data[0] as usize;
-        let x = self.ppu_state.oam_data[3] as usize;
-        // we check <= cycle_counter because ppu is not being simulated tick by tick
-        (y == self.ppu_state.cur_scanline)
-            && (x <= self.ppu_state.cycle_counter)
-            && self.ppu_state.ppumask.is_show_sprites()
+    #[test]
+    fn test_no_sprite_zero_hit_with_rendering_disabled() {
+        assert!(!is_hit_at(100, SHOW_ALL & !PpuMask::SHOW_BACKGROUND.bits()));
+        assert!(!is_hit_at(100, SHOW_ALL & !PpuMask::SHOW_SPRITES.bits()));
     }
 }
@@ -1,79 +1,197 @@
+use crate::common::Memory;
 use crate::rom::{Mirroring, ROM};
 
 use super::PpuState;
 
 pub struct PpuBus<'a, 'b> {
     ppu_state: &'a mut PpuState,
-    rom: &'b ROM,
+    rom: &'b mut ROM,
 }
 
 impl<'a, 'b> PpuBus<'a, 'b> {
-    pub fn new(ppu_state: &'a mut PpuState, rom: &'b ROM) -> Self {
+    pub fn new(ppu_state: &'a mut PpuState, rom: &'b mut ROM) -> Self {
         PpuBus { ppu_state, rom }
     }
 
     pub fn read_byte(&mut self, index: u16) -> u8 {
+        self.peek_byte(index)
+    }
+
+    /// Reads with no side effects, the PPUDATA read buffer lives in PpuAction
+    pub fn peek_byte(&self, index: u16) -> u8 {
         match index {
-            0x0000..=0x1FFF => self.rom.chr_rom[index as usize],
+            0x0000..=0x1FFF => self.rom.chr_rom[self.rom.map_chr(index)],
             0x2000..=0x2FFF => {
-                let vram_index = self.mirror_vram_addr(index);
+                let vram_index = Self::mirror_vram_addr(self.rom.current_mirroring(), index);
                 self.ppu_state.ram[vram_index as usize]
             }
             0x3000..=0x3EFF => {
                 // map to 0x2000...0x2EFF
                 let masked_index = index & 0b1110_1111_1111_1111;
-                let vram_index = self.mirror_vram_addr(masked_index);
+                let vram_index = Self::mirror_vram_addr(self.rom.current_mirroring(), masked_index);
                 self.ppu_state.ram[vram_index as usize]
             }
-            0x3F00..=0x3F1F => todo!(),
-            0x3F20..=0x3FFF => todo!(),
+            0x3F00..=0x3FFF => self.ppu_state.palette_table[Self::palette_index(index)],
             _ => panic!("Unexpected address"),
         }
     }
 
     pub fn write_byte(&mut self, index: u16, value: u8) {
         match index {
-            0x0000..=0x1FFF => println!("CHR_ROM is read only"),
-            // 0x0000..=0x1FFF => panic!("CHR_ROM is read only"),
+            0x0000..=0x1FFF if self.rom.chr_ram => {
+                let offset = self.rom.map_chr(index);
+                self.rom.chr_rom[offset] = value;
+            }
+            0x0000..=0x1FFF => {
+                if self.ppu_state.chr_writes.record(index) {
+                    log::warn!(
+                        "Ignored write of {:02x} to CHR ROM {:04x}, PC {:04x}, scanline {}",
+                        value,
+                        index,
+                        self.ppu_state.chr_writes.pc,
+                        self.ppu_state.cur_scanline
+                    );
+                }
+            }
             0x2000..=0x2FFF => {
-                let vram_index = self.mirror_vram_addr(index);
+                let vram_index = Self::mirror_vram_addr(self.rom.current_mirroring(), index);
                 self.ppu_state.ram[vram_index as usize] = value;
             }
             0x3000..=0x3EFF => {
                 // map to 0x2000...0x2EFF
                 let masked_index = index & 0b1110_1111_1111_1111;
-                let vram_index = self.mirror_vram_addr(masked_index);
+                let vram_index = Self::mirror_vram_addr(self.rom.current_mirroring(), masked_index);
                 self.ppu_state.ram[vram_index as usize] = value;
             }
             0x3F00..=0x3FFF => {
-                // 0x3F20..=0x3FFF mirrors 0x3F00..=0x3FFF
-                let masked_index = index & 0b0000_0000_0001_1111;
-                let palette_index = match masked_index {
-                    0x0010 | 0x0014 | 0x0018 | 0x001C => masked_index - 0x10,
-                    _ => masked_index,
-                };
-                self.ppu_state.palette_table[palette_index as usize] = value;
+                // Palette RAM is only 6 bits wide
+                self.ppu_state.palette_table[Self::palette_index(index)] = value & 0b0011_1111;
             }
             _ => panic!("Unexpected address"),
         }
     }
 
-    fn mirror_vram_addr(&self, addr: u16) -> u16 {
+    fn palette_index(addr: u16) -> usize {
+        // 0x3F20..=0x3FFF mirrors 0x3F00..=0x3F1F
+        let masked_index = addr & 0b0000_0000_0001_1111;
+        // Sprite palette color 0 entries are shared with the background
+        let palette_index = match masked_index {
+            0x0010 | 0x0014 | 0x0018 | 0x001C => masked_index - 0x10,
+            _ => masked_index,
+        };
+        palette_index as usize
+    }
+
+    /// Index into VRAM for a nametable address in $2000-$2FFF. Only four-screen boards use the
+    /// upper 2KB.
+    pub fn mirror_vram_addr(mirroring: Mirroring, addr: u16) -> u16 {
         let vram_index = addr - 0x2000;
         let nametable_index = vram_index / 0x400;
 
-        let mirror_nametable_index = match (&self.rom.mirroring, nametable_index) {
-            (Mirroring::Horizontal, 0) => 0,
-            (Mirroring::Horizontal, 1) => 0,
-            (Mirroring::Horizontal, 2) => 1,
-            (Mirroring::Horizontal, 3) => 1,
-            (Mirroring::Vertical, 0) => 0,
-            (Mirroring::Vertical, 1) => 1,
-            (Mirroring::Vertical, 2) => 0,
-            (Mirroring::Vertical, 3) => 1,
-            _ => panic!("Unexpected mirroring, nametable_index pair"),
+        let mirror_nametable_index = match mirroring {
+            Mirroring::Horizontal => nametable_index / 2,
+            Mirroring::Vertical => nametable_index % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => nametable_index,
         };
 
         (vram_index & 0b1111_0011_1111_1111) | (mirror_nametable_index << 10)
     }
 }
+
+impl Memory for PpuBus<'_, '_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.write_byte(addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.peek_byte(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::hexdump;
+
+    #[test]
+    fn test_palette_mirroring() {
+        let mut ppu_state = PpuState::new();
+        let mut rom = ROM::new();
+        let mut bus = PpuBus::new(&mut ppu_state, &mut rom);
+        bus.write(0x3F10, 0x2A);
+        bus.write(0x3F25, 0xFF);
+        assert_eq!(0x2A, bus.peek(0x3F00));
+        assert_eq!(0x2A, bus.read(0x3F30));
+        assert_eq!(0x3F, bus.peek(0x3F05));
+        assert_eq!("3F00: 2A 00 00 00 00 3F\n", hexdump(&bus, 0x3F00, 6));
+    }
+
+    #[test]
+    fn test_chr_rom_writes_ignored_and_counted() {
+        let mut ppu_state = PpuState::new();
+        let mut rom = ROM::new();
+        rom.chr_rom = vec![0x55; 0x2000];
+        let mut bus = PpuBus::new(&mut ppu_state, &mut rom);
+        bus.write(0x0010, 0xAA);
+        bus.write(0x0010, 0xAA);
+        bus.write(0x1FFF, 0xAA);
+        assert_eq!(0x55, bus.peek(0x0010));
+
+        let chr_writes = &mut ppu_state.chr_writes;
+        assert_eq!(3, chr_writes.count);
+        assert_eq!(2, chr_writes.addresses());
+        assert!(!chr_writes.record(0x1FFF));
+        assert!(chr_writes.record(0x0000));
+    }
+
+    #[test]
+    fn test_chr_ram_writes() {
+        let mut ppu_state = PpuState::new();
+        let mut rom = ROM::new();
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_ram = true;
+        let mut bus = PpuBus::new(&mut ppu_state, &mut rom);
+        bus.write(0x1FFF, 0xAA);
+        assert_eq!(0xAA, bus.peek(0x1FFF));
+        assert_eq!(0, ppu_state.chr_writes.count);
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        // VRAM nametable each of $2000, $2400, $2800 and $2C00 lands in
+        let cases = [
+            (Mirroring::Horizontal, [0, 0, 1, 1]),
+            (Mirroring::Vertical, [0, 1, 0, 1]),
+            (Mirroring::SingleScreenLower, [0, 0, 0, 0]),
+            (Mirroring::SingleScreenUpper, [1, 1, 1, 1]),
+            (Mirroring::FourScreen, [0, 1, 2, 3]),
+        ];
+        for (mirroring, nametables) in cases {
+            for (i, nametable) in nametables.into_iter().enumerate() {
+                let addr = 0x2000 + 0x400 * i as u16 + 0x3C5;
+                assert_eq!(
+                    0x400 * nametable + 0x3C5,
+                    PpuBus::mirror_vram_addr(mirroring, addr),
+                    "{:?} {:04X}",
+                    mirroring,
+                    addr
+                );
+            }
+        }
+        // $3000-$3EFF mirrors them
+        let mut ppu_state = PpuState::new();
+        let mut rom = ROM::new();
+        rom.mirroring = Mirroring::FourScreen;
+        let mut bus = PpuBus::new(&mut ppu_state, &mut rom);
+        bus.write(0x3C01, 0xAB);
+        assert_eq!(0xAB, bus.peek(0x2C01));
+        assert_eq!(0, bus.peek(0x2001));
+        assert_eq!(0xAB, ppu_state.ram[0xC01]);
+    }
+}
@@ -1,8 +1,11 @@
 use bitflags::bitflags;
 
+// 2KB in the console, and the 2KB four-screen boards add
+pub const VRAM_SIZE: usize = 0x1000;
+
 #[derive(Debug, Clone, Copy)]
 pub struct PpuState {
-    pub ram: [u8; 0x800],
+    pub ram: [u8; VRAM_SIZE],
     pub oam_data: [u8; 256],
     pub palette_table: [u8; 32],
 
@@ -11,9 +14,9 @@ pub struct PpuState {
     pub ppumask: PpuMask,
     pub ppustatus: PpuStatus,
     pub oamaddr: OamAddr,
-    pub ppuscroll: PpuScroll,
-    pub ppuaddr: PpuAddr,
+    pub loopy: LoopyRegisters,
     pub ppudata: PpuData,
+    pub pipeline: RenderPipeline,
 
     // signals
     pub nmi_interrupt_poll: Option<()>,
@@ -21,6 +24,11 @@ pub struct PpuState {
     // metadata
     pub cycle_counter: usize,
     pub cur_scanline: usize,
+    pub timing: ScanlineTiming,
+    pub chr_writes: ChrWriteLog,
+
+    // Configuration, only draw the first 8 sprites on each scanline like the hardware
+    pub sprite_limit: bool,
 }
 
 impl Default for PpuState {
@@ -32,21 +40,28 @@ impl Default for PpuState {
 impl PpuState {
     pub fn new() -> Self {
         PpuState {
-            ram: [0; 0x800],
+            ram: [0; VRAM_SIZE],
             oam_data: [0; 256],
             palette_table: [0; 32],
             ppuctrl: PpuControl::from_bits_retain(0),
             ppumask: PpuMask::from_bits_retain(0),
             ppustatus: PpuStatus::from_bits_retain(0),
             oamaddr: OamAddr::new(),
-            ppuscroll: PpuScroll::new(),
-            ppuaddr: PpuAddr::new(),
+            loopy: LoopyRegisters::new(),
             ppudata: 0,
+            pipeline: RenderPipeline::new(),
+            sprite_limit: false,
             cycle_counter: 0,
             cur_scanline: 0,
             nmi_interrupt_poll: None,
+            timing: ScanlineTiming::new(),
+            chr_writes: ChrWriteLog::new(),
         }
     }
+
+    pub fn scanline_phase(&self) -> ScanlinePhase {
+        ScanlinePhase::of(self.cur_scanline)
+    }
 }
 
 bitflags! {
@@ -124,6 +139,19 @@ impl PpuControl {
         }
     }
 
+    /// Address of the 8x8 tile with `row` (0-15, counted after flipping) of sprite `tile`. 8x16
+    /// sprites ignore the sprite pattern table, bit 0 of the tile picks it and the rest is the
+    /// top tile of the pair.
+    pub fn get_sprite_tile_addr(&self, tile: u8, row: usize) -> u16 {
+        if self.contains(PpuControl::SPRITE_SIZE) {
+            let bank = (tile as u16 & 1) * 0x1000;
+            let top = (tile & !1) as u16;
+            bank + 16 * (top + (row / 8) as u16)
+        } else {
+            self.get_sprite_pattern_addr() + 16 * tile as u16
+        }
+    }
+
     pub fn is_master_slave_select(&self) -> bool {
         self.contains(PpuControl::MASTER_SLAVE_SELECT)
     }
@@ -237,6 +265,149 @@ impl PpuStatus {
     }
 }
 
+pub const SCANLINES: usize = 262;
+pub const DOTS_PER_SCANLINE: usize = 341;
+pub const POST_RENDER_SCANLINE: usize = 240;
+pub const VBLANK_SCANLINE: usize = 241;
+pub const PRE_RENDER_SCANLINE: usize = 261;
+
+/// What the PPU does on a scanline, see https://www.nesdev.org/wiki/PPU_rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanlinePhase {
+    // 0-239, drawn to the screen
+    Visible,
+    // 240, idle
+    PostRender,
+    // 241-260, vblank is set at dot 1 of the first one
+    VBlank,
+    // 261, fetches for the first visible line, the status flags are cleared at dot 1
+    PreRender,
+}
+
+impl ScanlinePhase {
+    pub fn of(scanline: usize) -> Self {
+        match scanline {
+            0..=239 => ScanlinePhase::Visible,
+            POST_RENDER_SCANLINE => ScanlinePhase::PostRender,
+            VBLANK_SCANLINE..=260 => ScanlinePhase::VBlank,
+            _ => ScanlinePhase::PreRender,
+        }
+    }
+
+    /// Visible and pre-render lines fetch tiles and move v along when rendering is on
+    pub fn is_rendering_line(self) -> bool {
+        matches!(self, ScanlinePhase::Visible | ScanlinePhase::PreRender)
+    }
+}
+
+// Per-scanline bookkeeping for the timing HUD, the current frame is swapped into
+// last_frame when the PPU wraps back to scanline 0
+#[derive(Debug, Clone, Copy)]
+pub struct ScanlineTiming {
+    // CPU cycles of the instructions that started on each scanline
+    pub cpu_cycles: [u16; SCANLINES],
+    // Scanline where the CPU started servicing the NMI
+    pub nmi_scanline: Option<usize>,
+    pub sprites: SpriteStats,
+    pub last_frame: [u16; SCANLINES],
+    pub last_nmi_scanline: Option<usize>,
+    pub last_sprites: SpriteStats,
+}
+
+// The PPU only draws the first 8 sprites in OAM order on each scanline
+pub const SPRITES_PER_SCANLINE: usize = 8;
+
+// Sprite evaluation over a frame, tells flicker from the 8 sprites per scanline limit apart
+// from sprites the emulator lost
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteStats {
+    // Most sprites in range of one scanline
+    pub max_per_scanline: u8,
+    // Sprites past the 8th on their scanline, which the hardware doesn't draw
+    pub dropped: usize,
+    // Scanline and dot sprite 0 hit was set on
+    pub sprite_zero_hit: Option<(usize, usize)>,
+}
+
+impl Default for ScanlineTiming {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScanlineTiming {
+    pub fn new() -> Self {
+        ScanlineTiming {
+            cpu_cycles: [0; SCANLINES],
+            nmi_scanline: None,
+            sprites: SpriteStats::default(),
+            last_frame: [0; SCANLINES],
+            last_nmi_scanline: None,
+            last_sprites: SpriteStats::default(),
+        }
+    }
+
+    pub fn add_cpu_cycles(&mut self, scanline: usize, cycles: u8) {
+        if let Some(count) = self.cpu_cycles.get_mut(scanline) {
+            *count = count.saturating_add(cycles as u16);
+        }
+    }
+
+    pub fn finish_frame(&mut self) {
+        self.last_frame = std::mem::replace(&mut self.cpu_cycles, [0; SCANLINES]);
+        self.last_nmi_scanline = self.nmi_scanline.take();
+        self.last_sprites = std::mem::take(&mut self.sprites);
+    }
+}
+
+const CHR_SIZE: usize = 0x2000;
+
+// Writes to CHR ROM, which are ignored. Each address is only logged the first time it's written,
+// a game doing this usually needs CHR RAM or a different mapper.
+#[derive(Debug, Clone, Copy)]
+pub struct ChrWriteLog {
+    // Address of the instruction the CPU is running, set by the CPU
+    pub pc: u16,
+    // Every write, including repeats
+    pub count: usize,
+    // One bit per CHR address written so far
+    written: [u64; CHR_SIZE / 64],
+}
+
+impl Default for ChrWriteLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChrWriteLog {
+    pub fn new() -> Self {
+        ChrWriteLog {
+            pc: 0,
+            count: 0,
+            written: [0; CHR_SIZE / 64],
+        }
+    }
+
+    /// Counts a write to `addr`, true if it's the first one there
+    pub fn record(&mut self, addr: u16) -> bool {
+        self.count += 1;
+        let addr = addr as usize % CHR_SIZE;
+        let bit = 1 << (addr % 64);
+        let first = self.written[addr / 64] & bit == 0;
+        self.written[addr / 64] |= bit;
+        first
+    }
+
+    /// Number of different addresses written
+    pub fn addresses(&self) -> usize {
+        self.written
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct OamAddr {
     data: u8,
@@ -255,102 +426,263 @@ impl OamAddr {
     }
 
     pub fn increment(&mut self) {
-        // TODO: check this is correct
+        // OAM is 256 bytes, the address wraps
         self.data = self.data.wrapping_add(1);
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct PpuScroll {
-    cam_position_x: u8,
-    cam_position_y: u8,
-    is_set_position_x: bool,
+// Internal registers shared by $2000, $2002, $2005 and $2006
+// Ref: https://www.nesdev.org/wiki/PPU_scrolling
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LoopyRegisters {
+    // Current VRAM address
+    pub v: u16,
+    // Temporary VRAM address, the top left onscreen tile
+    pub t: u16,
+    // Fine X scroll
+    pub x: u8,
+    // Write toggle shared by $2005 and $2006, false on the first write
+    pub w: bool,
 }
 
-impl Default for PpuScroll {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-// Horizontal offsets range from 0 to 255. "Normal" vertical offsets range from 0 to 239, while values of 240 to 255 are treated as -16 through -1 in a way, but tile data is incorrectly fetched from the attribute table.
-// Implies that reading from this is different
-// TODO: check this
-impl PpuScroll {
+const COARSE_X_MASK: u16 = 0x001F;
+const COARSE_Y_MASK: u16 = 0x03E0;
+const NAMETABLE_MASK: u16 = 0x0C00;
+const NAMETABLE_X_MASK: u16 = 0x0400;
+const FINE_Y_MASK: u16 = 0x7000;
+
+impl LoopyRegisters {
     pub fn new() -> Self {
-        PpuScroll {
-            cam_position_x: 0,
-            cam_position_y: 0,
-            is_set_position_x: true,
+        Self::default()
+    }
+
+    pub fn write_ppuctrl(&mut self, data: u8) {
+        self.t = (self.t & !NAMETABLE_MASK) | (((data & 0b11) as u16) << 10);
+    }
+
+    pub fn write_ppuscroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !COARSE_X_MASK) | (data >> 3) as u16;
+            self.x = data & 0b111;
+        } else {
+            self.t = (self.t & !(COARSE_Y_MASK | FINE_Y_MASK))
+                | (((data & 0b111) as u16) << 12)
+                | (((data >> 3) as u16) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_ppuaddr(&mut self, data: u8) {
+        if !self.w {
+            // Bit 14 is cleared as well
+            self.t = (self.t & 0x00FF) | (((data & 0b0011_1111) as u16) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    // Reading $2002 clears the write toggle
+    pub fn reset_toggle(&mut self) {
+        self.w = false;
+    }
+
+    // VRAM address used by $2007
+    pub fn vram_addr(&self) -> u16 {
+        self.v & 0x3FFF
+    }
+
+    pub fn increment(&mut self, inc: u8) {
+        self.v = self.v.wrapping_add(inc as u16) & 0x7FFF;
+    }
+
+    pub fn get_scroll(&self) -> (u8, u8) {
+        let coarse_x = (self.t & COARSE_X_MASK) as u8;
+        let coarse_y = ((self.t & COARSE_Y_MASK) >> 5) as u8;
+        let fine_y = ((self.t & FINE_Y_MASK) >> 12) as u8;
+        ((coarse_x << 3) | self.x, (coarse_y << 3) | fine_y)
+    }
+
+    pub fn get_name_table_addr(&self) -> u16 {
+        0x2000 | (self.v & NAMETABLE_MASK)
+    }
+
+    // Nametable byte of the tile v points at
+    pub fn tile_addr(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    // Attribute byte covering the tile v points at, one per 4x4 tiles
+    pub fn attribute_addr(&self) -> u16 {
+        0x23C0 | (self.v & NAMETABLE_MASK) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    // Bits of the attribute byte for the 2x2 tile quadrant v is in
+    pub fn attribute_shift(&self) -> u8 {
+        (((self.v >> 4) & 0b100) | (self.v & 0b10)) as u8
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.v & FINE_Y_MASK) >> 12
+    }
+
+    // Done after each tile fetch while rendering, dots 8, 16, ..., 256, 328 and 336
+    pub fn increment_x(&mut self) {
+        if self.v & COARSE_X_MASK == COARSE_X_MASK {
+            self.v &= !COARSE_X_MASK;
+            // Switch horizontal nametable
+            self.v ^= NAMETABLE_X_MASK;
+        } else {
+            self.v += 1;
         }
     }
 
-    pub fn write(&mut self, byte: u8) {
-        if self.is_set_position_x {
-            self.cam_position_x = byte;
+    // Done at dot 256 of every rendered scanline
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y_MASK != FINE_Y_MASK {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y_MASK;
+        let mut coarse_y = (self.v & COARSE_Y_MASK) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            // Switch vertical nametable
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
         } else {
-            self.cam_position_y = byte;
+            coarse_y += 1;
         }
-        self.is_set_position_x = !self.is_set_position_x; // flip the bool
+        self.v = (self.v & !COARSE_Y_MASK) | (coarse_y << 5);
     }
 
-    pub fn read(&self) -> (u8, u8) {
-        // Returns (cam_position_x, cam_position_y)
-        todo!()
+    // Done at dot 257 of every rendered scanline
+    pub fn copy_horizontal(&mut self) {
+        let mask = COARSE_X_MASK | NAMETABLE_X_MASK;
+        self.v = (self.v & !mask) | (self.t & mask);
     }
 
-    pub fn reset(&mut self) {
-        self.is_set_position_x = true;
+    // Done during dots 280-304 of the pre-render scanline
+    pub fn copy_vertical(&mut self) {
+        let mask = COARSE_Y_MASK | FINE_Y_MASK | (NAMETABLE_MASK & !NAMETABLE_X_MASK);
+        self.v = (self.v & !mask) | (self.t & mask);
     }
 }
 
+type PpuData = u8;
+
+// Most sprites a scanline can hold, all of OAM when the 8 sprite limit is off
+pub const MAX_LINE_SPRITES: usize = 64;
+
+// A sprite picked by evaluation for the next scanline, with its row of pattern already fetched
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineSprite {
+    // Low and high bit planes, already flipped horizontally if the sprite is
+    pub pattern: [u8; 2],
+    pub attributes: u8,
+    pub x: u8,
+    pub is_sprite_zero: bool,
+}
+
+// Latches and shift registers of the dot by dot rendering pipeline
+// Ref: https://www.nesdev.org/wiki/PPU_rendering
 #[derive(Debug, Clone, Copy)]
-pub struct PpuAddr {
-    data: (u8, u8),
-    is_set_msb: bool,
+pub struct RenderPipeline {
+    // Dots of the current scanline run so far, catches up with cycle_counter after the CPU
+    // runs an instruction
+    pub dot: usize,
+    // Filled by the four fetches of each tile, over 8 dots
+    pub tile_id: u8,
+    pub attribute: u8,
+    pub pattern: [u8; 2],
+    // Bit planes of two tiles, the high byte is the one being drawn
+    pub pattern_shift: [u16; 2],
+    // The tile's palette, each bit spread over 8 pixels like the patterns
+    pub attribute_shift: [u16; 2],
+    // Sprites drawn on this scanline, picked on the one before
+    pub sprite_count: usize,
+    pub sprites: [LineSprite; MAX_LINE_SPRITES],
 }
 
-impl Default for PpuAddr {
+impl Default for RenderPipeline {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl PpuAddr {
+impl RenderPipeline {
     pub fn new() -> Self {
-        PpuAddr {
-            data: (0, 0),
-            is_set_msb: true,
+        RenderPipeline {
+            dot: 0,
+            tile_id: 0,
+            attribute: 0,
+            pattern: [0; 2],
+            pattern_shift: [0; 2],
+            attribute_shift: [0; 2],
+            sprite_count: 0,
+            sprites: [LineSprite::default(); MAX_LINE_SPRITES],
         }
     }
 
-    pub fn write(&mut self, byte: u8) {
-        if self.is_set_msb {
-            self.data.1 = byte & 0b0011_1111;
-        } else {
-            self.data.0 = byte;
+    pub fn line_sprites(&self) -> &[LineSprite] {
+        &self.sprites[..self.sprite_count]
+    }
+
+    // Empties the sprites entirely, so savestates don't need the unused ones
+    pub fn clear_sprites(&mut self) {
+        self.sprite_count = 0;
+        self.sprites = [LineSprite::default(); MAX_LINE_SPRITES];
+    }
+
+    // Moves the next tile into the low bytes of the shifters, done every 8 dots
+    pub fn load_shifters(&mut self) {
+        for plane in 0..2 {
+            self.pattern_shift[plane] =
+                (self.pattern_shift[plane] & 0xFF00) | self.pattern[plane] as u16;
+            let bits = if self.attribute >> plane & 1 != 0 {
+                0xFF
+            } else {
+                0
+            };
+            self.attribute_shift[plane] = (self.attribute_shift[plane] & 0xFF00) | bits;
         }
-        self.is_set_msb = !self.is_set_msb; // flip the bool
     }
 
-    pub fn read(&self) -> u16 {
-        let msb = self.data.1 as u16;
-        let lsb = self.data.0 as u16;
-        (msb << 8) + lsb
+    pub fn shift(&mut self) {
+        for plane in 0..2 {
+            self.pattern_shift[plane] <<= 1;
+            self.attribute_shift[plane] <<= 1;
+        }
     }
 
-    pub fn increment(&mut self, inc: u8) {
-        let result = self.read() + (inc as u16);
-        self.data.1 = ((result >> 8) & 0b0011_1111) as u8;
-        self.data.0 = result as u8;
+    // Color (0 to 3) and palette of the background pixel fine X scroll `x` selects
+    pub fn background_pixel(&self, x: u8) -> (u8, u8) {
+        let bit = 15 - x as u16;
+        let plane = |shift: [u16; 2]| ((shift[0] >> bit) & 1 | ((shift[1] >> bit) & 1) << 1) as u8;
+        (plane(self.pattern_shift), plane(self.attribute_shift))
     }
 
-    pub fn reset(&mut self) {
-        self.is_set_msb = true;
+    // First opaque sprite pixel at `x` in OAM order with its color, later sprites are hidden
+    // behind it even where it's behind the background
+    pub fn sprite_pixel(&self, x: usize) -> Option<(LineSprite, u8)> {
+        self.line_sprites().iter().find_map(|sprite| {
+            let column = x
+                .checked_sub(sprite.x as usize)
+                .filter(|column| *column < 8)?;
+            let bit = 7 - column;
+            let color = (sprite.pattern[0] >> bit) & 1 | ((sprite.pattern[1] >> bit) & 1) << 1;
+            (color != 0).then_some((*sprite, color))
+        })
     }
 }
 
-type PpuData = u8;
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -360,4 +692,63 @@ mod tests {
         let ppu_state: PpuState = PpuState::new();
         assert_eq!([0; 256], ppu_state.oam_data)
     }
+
+    #[test]
+    fn test_loopy_scroll_writes() {
+        let mut loopy = LoopyRegisters::new();
+        loopy.write_ppuctrl(0b10);
+        loopy.write_ppuscroll(0x7D);
+        loopy.write_ppuscroll(0x5E);
+        assert_eq!((0x7D, 0x5E), loopy.get_scroll());
+        // Nametable bits are untouched by $2005
+        assert_eq!(0b10, (loopy.t & NAMETABLE_MASK) >> 10);
+        assert_eq!(0, loopy.v);
+        assert!(!loopy.w);
+    }
+
+    #[test]
+    fn test_loopy_addr_writes() {
+        let mut loopy = LoopyRegisters::new();
+        loopy.write_ppuctrl(0b11);
+        loopy.write_ppuaddr(0x21);
+        // First $2006 write overwrites the nametable bits set by $2000
+        assert_eq!(0b00, (loopy.t & NAMETABLE_MASK) >> 10);
+        assert_eq!(0, loopy.v);
+        loopy.write_ppuaddr(0x08);
+        assert_eq!(0x2108, loopy.vram_addr());
+        assert_eq!(loopy.t, loopy.v);
+    }
+
+    #[test]
+    fn test_sprite_tile_addr() {
+        let ppuctrl = PpuControl::SPRITE_PATTERN_ADDR;
+        assert_eq!(0x1050, ppuctrl.get_sprite_tile_addr(5, 3));
+        // 8x16 sprites pick the table with bit 0 of the tile, the bottom tile follows the top one
+        let ppuctrl = PpuControl::SPRITE_SIZE;
+        assert_eq!(0x0040, ppuctrl.get_sprite_tile_addr(4, 7));
+        assert_eq!(0x0050, ppuctrl.get_sprite_tile_addr(4, 8));
+        assert_eq!(0x1040, ppuctrl.get_sprite_tile_addr(5, 0));
+        assert_eq!(0x1050, ppuctrl.get_sprite_tile_addr(5, 15));
+    }
+
+    #[test]
+    fn test_loopy_increment_y() {
+        let mut loopy = LoopyRegisters::new();
+        // Fine Y 7, coarse Y 29
+        loopy.v = FINE_Y_MASK | (29 << 5);
+        loopy.increment_y();
+        assert_eq!(NAMETABLE_MASK & !NAMETABLE_X_MASK, loopy.v);
+    }
+
+    #[test]
+    fn test_loopy_increment_x() {
+        let mut loopy = LoopyRegisters::new();
+        loopy.v = 30;
+        loopy.increment_x();
+        assert_eq!(0x2000 | 31, loopy.tile_addr());
+        // Wraps into the next nametable across
+        loopy.increment_x();
+        assert_eq!(0x2400, loopy.tile_addr());
+        assert_eq!(0x27C0, loopy.attribute_addr());
+    }
 }
@@ -0,0 +1,229 @@
+// Memory access profiler, counts CPU bus reads and writes per address
+//
+// Counting is a single increment per access, regions are only worked out when reporting.
+// DMA reads go through the bus and are counted, peeks (debugger, HUD) aren't.
+use std::collections::BTreeMap;
+use std::fmt;
+
+const ADDRESS_SPACE: usize = 0x10000;
+const PRG_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryRegion {
+    // Internal RAM, split up since games use these very differently
+    ZeroPage,
+    Stack,
+    Ram,
+    PpuRegisters,
+    // APU and controller registers
+    ApuIo,
+    // $4020-$5FFF, unused by most cartridges
+    Expansion,
+    PrgRam,
+    // 16KB bank of PRG ROM, by offset into the ROM
+    PrgBank(usize),
+}
+
+impl MemoryRegion {
+    /// Region of a CPU address, `prg_rom_len` maps $8000-$FFFF to banks (mirrored for 16KB ROMs)
+    pub fn of(addr: u16, prg_rom_len: usize) -> Self {
+        match addr {
+            0x0000..=0x1FFF => match addr & 0x07FF {
+                0x0000..=0x00FF => MemoryRegion::ZeroPage,
+                0x0100..=0x01FF => MemoryRegion::Stack,
+                _ => MemoryRegion::Ram,
+            },
+            0x2000..=0x3FFF => MemoryRegion::PpuRegisters,
+            0x4000..=0x401F => MemoryRegion::ApuIo,
+            0x4020..=0x5FFF => MemoryRegion::Expansion,
+            0x6000..=0x7FFF => MemoryRegion::PrgRam,
+            0x8000..=0xFFFF => {
+                let offset = (addr - 0x8000) as usize % prg_rom_len.max(PRG_BANK_SIZE);
+                MemoryRegion::PrgBank(offset / PRG_BANK_SIZE)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryRegion::ZeroPage => write!(f, "zero page"),
+            MemoryRegion::Stack => write!(f, "stack"),
+            MemoryRegion::Ram => write!(f, "RAM"),
+            MemoryRegion::PpuRegisters => write!(f, "PPU registers"),
+            MemoryRegion::ApuIo => write!(f, "APU/IO registers"),
+            MemoryRegion::Expansion => write!(f, "expansion"),
+            MemoryRegion::PrgRam => write!(f, "PRG RAM"),
+            MemoryRegion::PrgBank(bank) => write!(f, "PRG bank {}", bank),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl AccessCounts {
+    pub fn total(&self) -> u64 {
+        self.reads + self.writes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryProfile {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl Default for MemoryProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryProfile {
+    pub fn new() -> Self {
+        MemoryProfile {
+            reads: vec![0; ADDRESS_SPACE],
+            writes: vec![0; ADDRESS_SPACE],
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) {
+        self.reads[addr as usize] += 1;
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        self.writes[addr as usize] += 1;
+    }
+
+    pub fn counts(&self, addr: u16) -> AccessCounts {
+        AccessCounts {
+            reads: self.reads[addr as usize],
+            writes: self.writes[addr as usize],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.fill(0);
+        self.writes.fill(0);
+    }
+
+    /// Accesses per region, in address order
+    pub fn regions(&self, prg_rom_len: usize) -> Vec<(MemoryRegion, AccessCounts)> {
+        let mut regions = BTreeMap::new();
+        for addr in 0..ADDRESS_SPACE {
+            let counts = self.counts(addr as u16);
+            if counts.total() == 0 {
+                continue;
+            }
+            let region: &mut AccessCounts = regions
+                .entry(MemoryRegion::of(addr as u16, prg_rom_len))
+                .or_default();
+            region.reads += counts.reads;
+            region.writes += counts.writes;
+        }
+        regions.into_iter().collect()
+    }
+
+    /// The `count` most accessed addresses, busiest first
+    pub fn hot_addresses(&self, count: usize) -> Vec<(u16, AccessCounts)> {
+        let mut addresses: Vec<(u16, AccessCounts)> = (0..ADDRESS_SPACE)
+            .map(|addr| (addr as u16, self.counts(addr as u16)))
+            .filter(|(_, counts)| counts.total() > 0)
+            .collect();
+        addresses.sort_by_key(|(addr, counts)| (std::cmp::Reverse(counts.total()), *addr));
+        addresses.truncate(count);
+        addresses
+    }
+
+    pub fn report(&self, prg_rom_len: usize) -> String {
+        let regions = self.regions(prg_rom_len);
+        let total: u64 = regions.iter().map(|(_, counts)| counts.total()).sum();
+        let mut report = format!("Memory profile: {} accesses\n", total);
+        for (region, counts) in &regions {
+            report.push_str(&format!(
+                "  {:16} {:>5.1}%  reads {:<10} writes {}\n",
+                region.to_string(),
+                100.0 * counts.total() as f64 / total.max(1) as f64,
+                counts.reads,
+                counts.writes
+            ));
+        }
+        report.push_str("Hot addresses:\n");
+        for (addr, counts) in self.hot_addresses(10) {
+            report.push_str(&format!(
+                "  ${:04X}  reads {:<10} writes {}\n",
+                addr, counts.reads, counts.writes
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        assert_eq!(MemoryRegion::ZeroPage, MemoryRegion::of(0x0810, 0x8000));
+        assert_eq!(MemoryRegion::Stack, MemoryRegion::of(0x01FF, 0x8000));
+        assert_eq!(MemoryRegion::ApuIo, MemoryRegion::of(0x4016, 0x8000));
+        assert_eq!(MemoryRegion::PrgBank(1), MemoryRegion::of(0xC000, 0x8000));
+        // 16KB ROMs are mirrored
+        assert_eq!(MemoryRegion::PrgBank(0), MemoryRegion::of(0xC000, 0x4000));
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut profile = MemoryProfile::new();
+        for _ in 0..3 {
+            profile.read(0x2002);
+        }
+        profile.write(0x0000);
+        profile.write(0x0800);
+        profile.read(0x8000);
+
+        let regions = profile.regions(0x4000);
+        assert_eq!(
+            vec![
+                (
+                    MemoryRegion::ZeroPage,
+                    AccessCounts {
+                        reads: 0,
+                        writes: 2
+                    }
+                ),
+                (
+                    MemoryRegion::PpuRegisters,
+                    AccessCounts {
+                        reads: 3,
+                        writes: 0
+                    }
+                ),
+                (
+                    MemoryRegion::PrgBank(0),
+                    AccessCounts {
+                        reads: 1,
+                        writes: 0
+                    }
+                ),
+            ],
+            regions
+        );
+        let hot: Vec<u16> = profile
+            .hot_addresses(2)
+            .iter()
+            .map(|(addr, _)| *addr)
+            .collect();
+        assert_eq!(vec![0x2002, 0x0000], hot);
+        assert!(profile.report(0x4000).contains("PPU registers     50.0%"));
+
+        profile.clear();
+        assert!(profile.regions(0x4000).is_empty());
+    }
+}
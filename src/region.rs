@@ -0,0 +1,100 @@
+// Console region a game was made for, detected from the ROM header, file name or game database
+//
+// Only NTSC timing is emulated so far, the region is detected ahead of PAL and Dendy timing so
+// games don't need configuring once it lands.
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // Famiclone common in Russia, PAL frame rate with NTSC-like CPU timing
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region {}", name)),
+        }
+    }
+
+    /// NES 2.0 CPU/PPU timing (byte 12, bits 0-1), None for multi-region games
+    pub fn from_nes2_timing(byte: u8) -> Option<Self> {
+        match byte & 0b11 {
+            0 => Some(Region::Ntsc),
+            1 => Some(Region::Pal),
+            3 => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    /// Reads No-Intro and GoodNES style tags, e.g. "Game (Europe).nes" or "Game (U) [!].nes".
+    /// The first known region wins for multi-region tags like "(USA, Europe)".
+    pub fn from_file_name(path: &str) -> Option<Self> {
+        let file_name = Path::new(path)
+            .file_stem()?
+            .to_string_lossy()
+            .to_lowercase();
+        file_name
+            .split(['(', ')', '[', ']', ','])
+            // Skips the title
+            .skip(1)
+            .map(str::trim)
+            .find_map(|tag| match tag {
+                "u" | "usa" | "j" | "japan" | "ju" | "ntsc" => Some(Region::Ntsc),
+                "e" | "europe" | "pal" | "australia" | "germany" | "france" | "spain" | "italy"
+                | "sweden" => Some(Region::Pal),
+                "dendy" | "r" | "russia" => Some(Region::Dendy),
+                _ => None,
+            })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+            Region::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file_name() {
+        assert_eq!(
+            Some(Region::Pal),
+            Region::from_file_name("roms/Super Mario Bros. (Europe).nes")
+        );
+        assert_eq!(
+            Some(Region::Ntsc),
+            Region::from_file_name("Zelda (U) [!].nes")
+        );
+        assert_eq!(
+            Some(Region::Ntsc),
+            Region::from_file_name("Tetris (USA, Europe).nes")
+        );
+        // Only whole tags count
+        assert_eq!(None, Region::from_file_name("roms/europe/Excitebike.nes"));
+        assert_eq!(None, Region::from_file_name("pal (World).nes"));
+        assert_eq!(None, Region::from_file_name("Duck Hunt (World).nes"));
+    }
+
+    #[test]
+    fn test_nes2_timing() {
+        assert_eq!(Some(Region::Pal), Region::from_nes2_timing(0xF1));
+        assert_eq!(None, Region::from_nes2_timing(2));
+        assert_eq!(Ok(Region::Dendy), Region::parse("Dendy"));
+        assert!(Region::parse("secam").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+// Rewind, a ring buffer of snapshots taken every few frames as the game runs
+//
+// Restoring a snapshot doesn't bring back the picture the PPU drew, so rewinding goes back to a
+// snapshot before the frame asked for and replays the frames in between with the buttons held
+// now. With snapshots a couple of frames apart that's at most a frame or two of replay, and the
+// picture is always one the console drew.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::controller::ControllerState;
+use crate::cpu::{CpuState, Instruction};
+use crate::nes::{ActionNES, NES};
+use crate::ppu::{PpuState, SpriteStats};
+use crate::rom::ROM;
+use crate::screen::frame::Frame;
+use crate::snapshot::{Snapshot, SnapshotBaseline};
+
+// Frames between snapshots, and 10 seconds of them
+pub const DEFAULT_REWIND_INTERVAL: usize = 2;
+pub const DEFAULT_REWIND_CAPACITY: usize = 300;
+
+/// Snapshots of the last few seconds, oldest first
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    interval: usize,
+    capacity: usize,
+    baseline: SnapshotBaseline,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_CAPACITY)
+    }
+}
+
+impl RewindBuffer {
+    /// Keeps a snapshot every `interval` frames, `capacity` of them
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            baseline: SnapshotBaseline::power_on(),
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Takes a snapshot once `interval` frames have passed since the last one. Call it as often
+    /// as you like, e.g. after every frame or audio buffer.
+    pub fn record(&mut self, nes: &ActionNES) {
+        let frame = nes.frame_count();
+        // The console went back, e.g. a savestate was loaded or it was rewound
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|last| last.frame_count() > frame)
+        {
+            self.snapshots.pop_back();
+        }
+        if let Some(last) = self.snapshots.back() {
+            if frame < last.frame_count() + self.interval {
+                return;
+            }
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots
+            .push_back(Snapshot::capture(nes, &self.baseline));
+    }
+
+    /// Goes back `frames` frames, or as far as the buffer reaches, and returns the number of
+    /// frames gone back. Snapshots after the frame it went back to are dropped.
+    pub fn rewind(&mut self, nes: &mut ActionNES, frames: usize) -> Result<usize, String> {
+        let current = nes.frame_count();
+        let target = current.saturating_sub(frames);
+        // At least a frame is replayed, to draw the picture
+        let Some(start) = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.frame_count() < target)
+            .or_else(|| self.snapshots.front().map(|_| 0))
+            .filter(|&index| self.snapshots[index].frame_count() + 1 < current)
+        else {
+            return Ok(0);
+        };
+        self.snapshots.truncate(start + 1);
+        self.snapshots[start].restore(nes, &self.baseline);
+        loop {
+            nes.next_ppu_frame()?;
+            self.record(nes);
+            if nes.frame_count() >= target {
+                break;
+            }
+        }
+        Ok(current - nes.frame_count())
+    }
+
+    /// Frames the buffer can go back from the last snapshot
+    pub fn reach(&self) -> usize {
+        match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(first), Some(last)) => last.frame_count() - first.frame_count(),
+            _ => 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Approximate size of the snapshots in bytes
+    pub fn size(&self) -> usize {
+        self.snapshots.iter().map(Snapshot::size).sum()
+    }
+}
+
+/// ActionNES recording a RewindBuffer as it runs, stepped through the NES trait
+#[derive(Default)]
+pub struct RewindNes {
+    nes: ActionNES,
+    buffer: RewindBuffer,
+}
+
+impl RewindNes {
+    pub fn new(nes: ActionNES, buffer: RewindBuffer) -> Self {
+        RewindNes { nes, buffer }
+    }
+
+    /// Goes back `frames` frames, see RewindBuffer::rewind
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, String> {
+        self.buffer.rewind(&mut self.nes, frames)
+    }
+
+    pub fn nes(&self) -> &ActionNES {
+        &self.nes
+    }
+
+    /// The console, e.g. to set the controllers. Loading a savestate through here drops the
+    /// snapshots after it on the next frame.
+    pub fn nes_mut(&mut self) -> &mut ActionNES {
+        &mut self.nes
+    }
+
+    pub fn buffer(&self) -> &RewindBuffer {
+        &self.buffer
+    }
+}
+
+impl NES for RewindNes {
+    fn next_cpu_instruction(&mut self) -> Result<Instruction, String> {
+        let instruction = self.nes.next_cpu_instruction()?;
+        self.buffer.record(&self.nes);
+        Ok(instruction)
+    }
+
+    fn next_ppu_frame(&mut self) -> Result<(), String> {
+        self.nes.next_ppu_frame()?;
+        self.buffer.record(&self.nes);
+        Ok(())
+    }
+
+    fn next_ppu_scanline(&mut self) -> Result<(), String> {
+        self.nes.next_ppu_scanline()?;
+        self.buffer.record(&self.nes);
+        Ok(())
+    }
+
+    fn next_cpu_cycles(&mut self, cycles: usize) -> Result<(), String> {
+        self.nes.next_cpu_cycles(cycles)?;
+        self.buffer.record(&self.nes);
+        Ok(())
+    }
+
+    fn update_controller(&mut self, player: usize, key: ControllerState, bit: bool) {
+        self.nes.update_controller(player, key, bit);
+    }
+
+    // Snapshots of another game can't be restored into this one
+    fn set_rom(&mut self, rom: ROM) -> Result<(), String> {
+        self.buffer.clear();
+        self.nes.set_rom(rom)
+    }
+
+    fn load_from_path(&mut self, path: &str) -> Result<(), String> {
+        self.buffer.clear();
+        self.nes.load_from_path(path)
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        self.nes.reset()
+    }
+
+    fn peek_cpu_state(&self) -> CpuState {
+        self.nes.peek_cpu_state()
+    }
+
+    fn peek_ppu_state(&self) -> PpuState {
+        self.nes.peek_ppu_state()
+    }
+
+    fn render_frame(&self, frame: &mut Frame) {
+        self.nes.render_frame(frame);
+    }
+
+    fn frame_count(&self) -> usize {
+        self.nes.frame_count()
+    }
+
+    fn emulated_duration(&self) -> Duration {
+        self.nes.emulated_duration()
+    }
+
+    fn sprite_stats(&self) -> SpriteStats {
+        self.nes.sprite_stats()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.nes.save_state()
+    }
+
+    fn max_state_len(&self) -> usize {
+        self.nes.max_state_len()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.nes.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_nestest(buffer: RewindBuffer) -> RewindNes {
+        let mut nes = RewindNes::new(ActionNES::new(), buffer);
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+
+    #[test]
+    fn test_rewind_replays_to_the_frame() {
+        let mut nes = create_nestest(RewindBuffer::new(4, 100));
+        let mut hashes = Vec::new();
+        for _ in 0..30 {
+            nes.next_ppu_frame().unwrap();
+            hashes.push((nes.frame_count(), nes.nes().state_hash()));
+        }
+        let frame = nes.run_for_frames(0).unwrap();
+
+        assert_eq!(7, nes.rewind(7).unwrap());
+        assert_eq!(23, nes.frame_count());
+        assert!(hashes.contains(&(23, nes.nes().state_hash())));
+        // Running again ends up where it was, with the same picture
+        assert!(nes.run_for_frames(7).unwrap().data == frame.data);
+        assert_eq!(
+            (30, hashes[29].1),
+            (nes.frame_count(), nes.nes().state_hash())
+        );
+    }
+
+    #[test]
+    fn test_rewind_is_bounded() {
+        let mut nes = create_nestest(RewindBuffer::new(2, 5));
+        nes.run_for_frames(40).unwrap();
+        assert_eq!(8, nes.buffer().reach());
+        // Only as far back as the oldest snapshot, plus the frame replayed from it
+        assert_eq!(8, nes.rewind(100).unwrap());
+        assert_eq!(32, nes.frame_count());
+        assert_eq!(0, nes.rewind(100).unwrap());
+        assert_eq!(1, nes.buffer().snapshots.len());
+    }
+}
@@ -10,21 +10,38 @@
 // $8000–$FFFF = Usual ROM, commonly with Mapper Registers (see MMC1 and UxROM for example)
 // UxROM Ref: https://www.nesdev.org/wiki/UxROM
 
-use std::fs::read;
+#[cfg(not(feature = "minimal"))]
+use std::fs::{read, write};
+
+pub mod mapper;
+
+use crate::region::Region;
+use mapper::{create_mapper, Mapper, Nrom};
 
 const HEADER_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384; // 16 KB page size
 const CHR_ROM_PAGE_SIZE: usize = 8192; // 8 KB page size
+                                       // Boards with no CHR ROM have this much CHR RAM
+pub const CHR_RAM_SIZE: usize = 0x2000;
+// Largest CHR RAM of any board, snapshots address it with 16 bits
+const MAX_CHR_RAM_SIZE: usize = 0x8000;
+pub const TRAINER_SIZE: usize = 512;
+// Where the trainer goes in the CPU address space, $7000-$71FF
+pub const TRAINER_ADDR: u16 = 0x7000;
 
 // For flag 6
 const MIRROR_MASK: u8 = 0b0000_0001;
-const CARTRIDGE_MASK: u8 = 0b0000_0010;
+const BATTERY_MASK: u8 = 0b0000_0010;
 const TRAINER_MASK: u8 = 0b0000_0100;
 const FOUR_SCREEN_MASK: u8 = 0b0000_1000;
 
 // For flag 7
 const VS_UNISYS_MASK: u8 = 0b0000_0001;
 const PLAYCHOICE_MASK: u8 = 0b0000_0010;
+const NES2_FORMAT: u8 = 2;
+
+// For flag 9
+const TV_SYSTEM_PAL_MASK: u8 = 0b0000_0001;
 
 pub const PRG_ROM_SIZE: usize = PRG_ROM_PAGE_SIZE * u8::MAX as usize;
 pub const CHR_ROM_SIZE: usize = CHR_ROM_PAGE_SIZE * u8::MAX as usize;
@@ -33,6 +50,10 @@ pub const CHR_ROM_SIZE: usize = CHR_ROM_PAGE_SIZE * u8::MAX as usize;
 pub enum Mirroring {
     Vertical,
     Horizontal,
+    // Every nametable shows the same 1KB of VRAM, picked by boards like MMC1
+    SingleScreenLower,
+    SingleScreenUpper,
+    // The cartridge has 2KB more VRAM, so all four nametables are separate
     FourScreen,
 }
 
@@ -41,8 +62,23 @@ pub enum Mirroring {
 pub struct ROM {
     pub mirroring: Mirroring,
     pub mapper: u8,
+    // Board variant from an NES 2.0 header, 0 for iNES ones
+    pub submapper: u8,
     pub prg_rom: Vec<u8>,
+    // CHR RAM when chr_ram is set, the PPU can write to it
     pub chr_rom: Vec<u8>,
+    pub chr_ram: bool,
+    // Bank switching hardware for `mapper`, made by load_board when a console loads the ROM
+    pub board: Box<dyn Mapper>,
+    // PRG RAM at $6000-$7FFF is kept by a battery, games save to it
+    pub battery: bool,
+    // Bytes of PRG RAM at $6000-$7FFF, mirrored when smaller than 8KB. No supported board banks
+    // it, so past 8KB only the first 8KB is used.
+    pub prg_ram_size: usize,
+    // 512 bytes loaded into PRG RAM at $7000 on power on, mostly found in hacked or pirate dumps
+    pub trainer: Option<Vec<u8>>,
+    // Region the header asks for, None if it doesn't say
+    pub region: Option<Region>,
     // pub prg_rom: [u8; PRG_ROM_SIZE],
     // pub chr_rom: [u8; CHR_ROM_SIZE],
 }
@@ -59,13 +95,21 @@ impl ROM {
         ROM {
             mirroring: Mirroring::Horizontal,
             mapper: 0,
+            submapper: 0,
             prg_rom: vec![],
             chr_rom: vec![],
+            chr_ram: false,
+            board: Box::new(Nrom::new(0, 0)),
+            battery: false,
+            prg_ram_size: mapper::default_prg_ram_size(0),
+            trainer: None,
+            region: None,
             // prg_rom: [0; PRG_ROM_SIZE],
             // chr_rom: [0; CHR_ROM_SIZE],
         }
     }
 
+    #[cfg(not(feature = "minimal"))]
     pub fn create_from_nes(path: &str) -> Result<Self, String> {
         // Creates a ROM with data loaded from a .nes file
         let program = read(path).expect("Path does not exist");
@@ -86,13 +130,19 @@ impl ROM {
         // 10	Flags 10 – TV system, PRG-RAM presence (unofficial, rarely used extension)
         // 11-15	Unused padding (should be filled with zero, but some rippers put their name across bytes 7-15)
         // TODO: only handling flag 6 and 7, since 8, 9, 10 are rarely used, may need to implement in future
+        // NES 2.0 headers also give the PRG and CHR RAM sizes in bytes 10 and 11
 
         if raw[..4] != HEADER_TAG {
             return Err("Header tag invalid".to_string());
         }
         let prg_rom_size = PRG_ROM_PAGE_SIZE * (raw[4] as usize);
         let chr_rom_size = CHR_ROM_PAGE_SIZE * (raw[5] as usize);
-        println! {"Found prg_rom_size of {:x}, or {} pages", prg_rom_size, raw[4]}
+        let chr_ram = chr_rom_size == 0;
+        log::debug!(
+            "Found prg_rom_size of {:x}, or {} pages",
+            prg_rom_size,
+            raw[4]
+        );
         // ~~FLAG 6:
         // 76543210
         // ||||||||
@@ -102,10 +152,10 @@ impl ROM {
         // |||||+--- 1: 512-byte trainer at $7000-$71FF (stored before PRG data)
         // ||||+---- 1: Ignore mirroring control or above mirroring bit; instead provide four-screen VRAM
         // ++++----- Lower nybble of mapper number
-        // Right now, only checking for mirror, four screen flags
+        // Right now, only checking for mirror, battery, trainer and four screen flags
         let flag_6_byte = raw[6];
         let mirror = flag_6_byte & MIRROR_MASK != 0;
-        let _cartridge = flag_6_byte & CARTRIDGE_MASK != 0;
+        let battery = flag_6_byte & BATTERY_MASK != 0;
         let trainer = flag_6_byte & TRAINER_MASK != 0;
         let four_screen = flag_6_byte & FOUR_SCREEN_MASK != 0;
         let mapper_number_lsb = (flag_6_byte >> 4) & 0b0000_1111;
@@ -123,9 +173,15 @@ impl ROM {
         let nes_format = (flag_7_byte >> 2) & 0b0000_0011;
         let mapper_number_msb = flag_7_byte & 0b1111_0000; // Don't shift this
 
-        if nes_format != 0 {
-            return Err("Currently do not support NES2.0 format".to_string());
-        }
+        // NES 2.0 headers are read like iNES ones plus the region, as long as they don't need
+        // the extended mapper number or ROM sizes
+        let region = match nes_format {
+            0 => Self::ines_region(&raw),
+            NES2_FORMAT if raw[8] & 0b0000_1111 == 0 && raw[9] == 0 => {
+                Region::from_nes2_timing(raw[12])
+            }
+            _ => return Err("Currently do not support NES2.0 format".to_string()),
+        };
 
         let mirroring = match (four_screen, mirror) {
             (true, _) => Mirroring::FourScreen,
@@ -133,18 +189,241 @@ impl ROM {
             (_, _) => Mirroring::Horizontal,
         };
         let mapper = mapper_number_msb + mapper_number_lsb;
+        // ~~NES 2.0 BYTE 8
+        // 76543210
+        // ||||||||
+        // ||||++++- Mapper number bits 8-11, only 0 is supported
+        // ++++----- Submapper
+        let submapper = match nes_format {
+            NES2_FORMAT => raw[8] >> 4,
+            _ => 0,
+        };
+        // ~~NES 2.0 BYTES 10 AND 11
+        // 76543210
+        // ||||||||
+        // ||||++++- PRG RAM (byte 10) or CHR RAM (byte 11) shift count, 64 << shift bytes
+        // ++++----- Same for the battery-backed PRG NVRAM or CHR NVRAM
+        // iNES 1.0 headers don't say, the board decides
+        let (prg_ram_size, chr_ram_size) = match nes_format {
+            NES2_FORMAT => (
+                nes2_ram_size(raw[10] & 0b0000_1111) + nes2_ram_size(raw[10] >> 4),
+                nes2_ram_size(raw[11] & 0b0000_1111) + nes2_ram_size(raw[11] >> 4),
+            ),
+            _ => (mapper::default_prg_ram_size(mapper), CHR_RAM_SIZE),
+        };
+        if chr_ram && chr_ram_size > MAX_CHR_RAM_SIZE {
+            return Err(format!(
+                "{:x} bytes of CHR RAM is not supported",
+                chr_ram_size
+            ));
+        }
         // If there is a trainer, then the trainer block is 512, otherwise 0
-        let prg_rom_start = 16 + if trainer { 512 } else { 0 };
+        let prg_rom_start = 16 + if trainer { TRAINER_SIZE } else { 0 };
         // chr_rom starts after prg_rom
         let chr_rom_start = prg_rom_start + prg_rom_size;
 
         Ok(ROM {
             mirroring,
             mapper,
+            submapper,
             prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            chr_rom: match chr_ram {
+                // Headers without CHR ROM that don't size the CHR RAM get the usual 8KB
+                true if chr_ram_size == 0 => vec![0; CHR_RAM_SIZE],
+                true => vec![0; chr_ram_size],
+                false => raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            },
+            chr_ram,
+            board: Box::new(Nrom::new(0, 0)),
+            battery,
+            prg_ram_size,
+            trainer: trainer.then(|| raw[16..prg_rom_start].to_vec()),
+            region,
         })
     }
+
+    // Flags 9 bit 0 is only trusted when the padding is clean, rippers often left their name
+    // across bytes 7-15
+    fn ines_region(raw: &[u8]) -> Option<Region> {
+        if raw[10..16].iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        (raw[9] & TV_SYSTEM_PAL_MASK != 0).then_some(Region::Pal)
+    }
+
+    pub fn set_mapper(&mut self, mapper: u8) {
+        self.mapper = mapper;
+    }
+
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    /// Replaces the board with a freshly powered on one for the mapper number, Err if the
+    /// mapper isn't supported
+    pub fn load_board(&mut self) -> Result<(), String> {
+        self.board = create_mapper(
+            self.mapper,
+            self.submapper,
+            self.prg_rom.len(),
+            self.chr_rom.len(),
+        )?;
+        Ok(())
+    }
+
+    /// Offset into prg_rom for a CPU read of $6000-$FFFF, None if the board maps nothing there
+    pub fn map_prg(&self, addr: u16) -> Option<usize> {
+        self.board.map_prg(addr)
+    }
+
+    /// Offset into chr_rom for a PPU pattern table address, $0000-$1FFF
+    pub fn map_chr(&self, addr: u16) -> usize {
+        self.board.map_chr(addr)
+    }
+
+    /// Nametable mirroring, which some boards switch at runtime
+    pub fn current_mirroring(&self) -> Mirroring {
+        self.board.mirroring().unwrap_or(self.mirroring)
+    }
+
+    // CHR as stored in the .nes file, nothing for boards with CHR RAM
+    fn chr_rom_data(&self) -> &[u8] {
+        match self.chr_ram {
+            true => &[],
+            false => &self.chr_rom,
+        }
+    }
+
+    /// CRC32 of the PRG and CHR ROM, the same as the CRC of a headerless .nes file
+    pub fn crc32(&self) -> u32 {
+        crate::common::crc32(self.prg_rom.iter().chain(self.chr_rom_data()))
+    }
+
+    /// Header details for printing, e.g.
+    /// "Mapper 0, 2x16KB PRG, 1x8KB CHR, 8KB PRG RAM, vertical mirroring"
+    pub fn info(&self) -> String {
+        let mirroring = match self.mirroring {
+            Mirroring::Vertical => "vertical",
+            Mirroring::Horizontal => "horizontal",
+            Mirroring::SingleScreenLower | Mirroring::SingleScreenUpper => "single-screen",
+            Mirroring::FourScreen => "four-screen",
+        };
+        let chr = match self.chr_ram {
+            true => format!("{} CHR RAM", format_size(self.chr_rom.len())),
+            false => format!("{}x8KB CHR", self.chr_rom.len() / CHR_ROM_PAGE_SIZE),
+        };
+        let prg_ram = match self.prg_ram_size {
+            0 => String::new(),
+            size => format!(", {} PRG RAM", format_size(size)),
+        };
+        let mapper = match self.submapper {
+            0 => self.mapper.to_string(),
+            submapper => format!("{} (submapper {})", self.mapper, submapper),
+        };
+        let mut info = format!(
+            "Mapper {}, {}x16KB PRG, {}{}, {} mirroring",
+            mapper,
+            self.prg_rom.len() / PRG_ROM_PAGE_SIZE,
+            chr,
+            prg_ram,
+            mirroring
+        );
+        if let Some(region) = self.region {
+            info.push_str(&format!(", {}", region));
+        }
+        if self.battery {
+            info.push_str(", battery");
+        }
+        if self.trainer.is_some() {
+            info.push_str(", trainer at $7000");
+        }
+        info
+    }
+
+    /// Encodes the ROM back into the iNES file format
+    pub fn to_ines(&self) -> Result<Vec<u8>, String> {
+        if !self.prg_rom.len().is_multiple_of(PRG_ROM_PAGE_SIZE) {
+            return Err(format!(
+                "PRG ROM size {:x} is not a multiple of 16 KB",
+                self.prg_rom.len()
+            ));
+        }
+        let chr_rom = self.chr_rom_data();
+        if !chr_rom.len().is_multiple_of(CHR_ROM_PAGE_SIZE) {
+            return Err(format!(
+                "CHR ROM size {:x} is not a multiple of 8 KB",
+                chr_rom.len()
+            ));
+        }
+        let prg_rom_pages = u8::try_from(self.prg_rom.len() / PRG_ROM_PAGE_SIZE)
+            .map_err(|_| "Too many PRG ROM pages for iNES header".to_string())?;
+        let chr_rom_pages = u8::try_from(chr_rom.len() / CHR_ROM_PAGE_SIZE)
+            .map_err(|_| "Too many CHR ROM pages for iNES header".to_string())?;
+
+        let mut flag_6_byte = (self.mapper & 0b0000_1111) << 4;
+        if self.battery {
+            flag_6_byte |= BATTERY_MASK;
+        }
+        let trainer = match &self.trainer {
+            Some(trainer) if trainer.len() != TRAINER_SIZE => {
+                return Err(format!("Trainer is {:x} bytes, not 512", trainer.len()))
+            }
+            Some(trainer) => {
+                flag_6_byte |= TRAINER_MASK;
+                trainer.as_slice()
+            }
+            None => &[],
+        };
+        match self.mirroring {
+            Mirroring::Vertical => flag_6_byte |= MIRROR_MASK,
+            Mirroring::Horizontal => {}
+            Mirroring::FourScreen => flag_6_byte |= FOUR_SCREEN_MASK,
+            // Only the board picks it
+            Mirroring::SingleScreenLower | Mirroring::SingleScreenUpper => {
+                return Err("Single-screen mirroring can't be written in a header".to_string())
+            }
+        }
+        if self.submapper != 0 {
+            return Err("Submappers can't be written in an iNES header".to_string());
+        }
+        let flag_7_byte = self.mapper & 0b1111_0000;
+        // Dendy can't be written in an iNES header
+        let flag_9_byte = match self.region {
+            Some(Region::Pal) => TV_SYSTEM_PAL_MASK,
+            _ => 0,
+        };
+
+        let mut raw = Vec::with_capacity(16 + trainer.len() + self.prg_rom.len() + chr_rom.len());
+        raw.extend_from_slice(&HEADER_TAG);
+        raw.extend_from_slice(&[prg_rom_pages, chr_rom_pages, flag_6_byte, flag_7_byte]);
+        raw.extend_from_slice(&[0, flag_9_byte, 0, 0, 0, 0, 0, 0]);
+        raw.extend_from_slice(trainer);
+        raw.extend_from_slice(&self.prg_rom);
+        raw.extend_from_slice(chr_rom);
+        Ok(raw)
+    }
+
+    /// Writes the ROM to a .nes file, useful for repairing bad headers
+    #[cfg(not(feature = "minimal"))]
+    pub fn write_ines(&self, path: &str) -> Result<(), String> {
+        write(path, self.to_ines()?).map_err(|e| e.to_string())
+    }
+}
+
+// NES 2.0 RAM sizes are shift counts, 0 for none
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+// In KB when it's a whole number of them, e.g. 8KB or 512 bytes
+fn format_size(bytes: usize) -> String {
+    match bytes % 1024 {
+        0 => format!("{}KB", bytes / 1024),
+        _ => format!("{} bytes", bytes),
+    }
 }
 
 #[cfg(test)]
@@ -156,4 +435,161 @@ mod tests {
         let rom = ROM::new();
         assert_eq!(0, rom.mapper)
     }
+
+    #[cfg(not(feature = "minimal"))]
+    #[test]
+    fn test_to_ines_round_trip() {
+        let raw = read("test_roms/nestest.nes").unwrap();
+        let rom = ROM::from(raw.clone()).unwrap();
+        assert_eq!(raw, rom.to_ines().unwrap());
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    #[test]
+    fn test_crc32() {
+        let rom = ROM::create_from_nes("test_roms/nestest.nes").unwrap();
+        assert_eq!(0x158B0388, rom.crc32());
+        assert_eq!(0, ROM::new().crc32());
+    }
+
+    #[test]
+    fn test_to_ines_header_edits() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        rom.set_mapper(0x42);
+        rom.set_mirroring(Mirroring::Vertical);
+        let edited = ROM::from(rom.to_ines().unwrap()).unwrap();
+        assert_eq!(0x42, edited.mapper);
+        assert_eq!(Mirroring::Vertical, edited.mirroring);
+        assert_eq!(rom.prg_rom, edited.prg_rom);
+
+        rom.prg_rom.push(0);
+        assert!(rom.to_ines().is_err());
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0xEA; PRG_ROM_PAGE_SIZE];
+        rom.trainer = Some(vec![0x42; TRAINER_SIZE]);
+        let raw = rom.to_ines().unwrap();
+        assert_eq!(TRAINER_MASK, raw[6] & TRAINER_MASK);
+        let loaded = ROM::from(raw).unwrap();
+        assert_eq!(rom.trainer, loaded.trainer);
+        // The trainer isn't part of the PRG ROM
+        assert_eq!(rom.prg_rom, loaded.prg_rom);
+        assert!(loaded.info().ends_with("trainer at $7000"));
+
+        rom.trainer = Some(vec![0; 16]);
+        assert!(rom.to_ines().is_err());
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        let raw = rom.to_ines().unwrap();
+        let mut loaded = ROM::from(raw.clone()).unwrap();
+        assert!(loaded.chr_ram);
+        assert_eq!(CHR_RAM_SIZE, loaded.chr_rom.len());
+        assert!(loaded.info().contains("8KB CHR RAM"));
+        // What the game wrote isn't part of the file or its CRC
+        loaded.chr_rom[0] = 0xFF;
+        assert_eq!(raw, loaded.to_ines().unwrap());
+        assert_eq!(rom.crc32(), loaded.crc32());
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        rom.battery = true;
+        let raw = rom.to_ines().unwrap();
+        assert_eq!(BATTERY_MASK, raw[6] & BATTERY_MASK);
+        let loaded = ROM::from(raw).unwrap();
+        assert!(loaded.battery);
+        assert!(loaded.info().ends_with(", battery"));
+    }
+
+    #[test]
+    fn test_ram_sizes() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        rom.set_mapper(16);
+        let mut raw = rom.to_ines().unwrap();
+        // iNES 1.0 sizes come from the board, the Bandai FCG has registers at $6000
+        let loaded = ROM::from(raw.clone()).unwrap();
+        assert_eq!(
+            (0, CHR_RAM_SIZE),
+            (loaded.prg_ram_size, loaded.chr_rom.len())
+        );
+        assert!(!loaded.info().contains("PRG RAM"));
+
+        // NES 2.0: 2KB PRG RAM and 8KB PRG NVRAM, 32KB CHR RAM
+        raw[7] |= NES2_FORMAT << 2;
+        raw[10] = 0x75;
+        raw[11] = 0x09;
+        let loaded = ROM::from(raw.clone()).unwrap();
+        assert_eq!(0x2800, loaded.prg_ram_size);
+        assert_eq!(0x8000, loaded.chr_rom.len());
+        assert!(loaded
+            .info()
+            .contains("32KB CHR RAM, 10KB PRG RAM, horizontal mirroring"));
+        raw[10] = 0x03;
+        assert!(ROM::from(raw.clone())
+            .unwrap()
+            .info()
+            .contains(", 512 bytes PRG RAM"));
+        raw[11] = 0x0A;
+        assert!(ROM::from(raw).is_err());
+    }
+
+    #[test]
+    fn test_submapper() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        rom.set_mapper(16);
+        let mut raw = rom.to_ines().unwrap();
+        // Only NES 2.0 headers have one
+        raw[8] = 0xF0;
+        assert_eq!(0, ROM::from(raw.clone()).unwrap().submapper);
+        raw[7] |= NES2_FORMAT << 2;
+        let mut loaded = ROM::from(raw).unwrap();
+        assert_eq!(15, loaded.submapper);
+        assert!(loaded.info().starts_with("Mapper 16 (submapper 15), "));
+        loaded.load_board().unwrap();
+        assert!(loaded.to_ines().is_err());
+    }
+
+    #[test]
+    fn test_load_board() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+        rom.load_board().unwrap();
+        assert_eq!(Some(0x7FFF), rom.map_prg(0xFFFF));
+        rom.set_mapper(0x42);
+        assert!(rom.load_board().is_err());
+    }
+
+    #[test]
+    fn test_header_region() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        rom.region = Some(Region::Pal);
+        let mut raw = rom.to_ines().unwrap();
+        assert_eq!(Some(Region::Pal), ROM::from(raw.clone()).unwrap().region);
+
+        // Junk in the padding
+        raw[12] = b'X';
+        assert_eq!(None, ROM::from(raw.clone()).unwrap().region);
+
+        // NES 2.0, Dendy timing
+        raw[7] |= NES2_FORMAT << 2;
+        raw[9] = 0;
+        raw[12] = 3;
+        assert_eq!(Some(Region::Dendy), ROM::from(raw.clone()).unwrap().region);
+        // Extended ROM sizes aren't supported
+        raw[9] = 0x10;
+        assert!(ROM::from(raw).is_err());
+    }
 }
@@ -0,0 +1,704 @@
+use std::borrow::Cow;
+
+use super::rtc::Rtc;
+use super::{ClockSource, Mapper, StateReader, CHR_BANK_SIZE};
+use crate::rom::Mirroring;
+
+// Bandai FCG-1/FCG-2 and LZ93D50 (mapper 16)
+// Ref: https://www.nesdev.org/wiki/INES_Mapper_016
+//
+// FCG-1/2 boards decode the registers at $6000-$7FFF and the LZ93D50 at $8000-$FFFF, both
+// ranges are accepted since mapper 16 doesn't tell them apart. The LZ93D50 boards with a
+// 24C02 EEPROM read it back through $6000-$7FFF. The RTC variant adds a real-time clock (see
+// rtc.rs): $x00E selects one of its registers, $x00F writes it and $6000-$7FFF reads it back on
+// D0-D3, next to the EEPROM on D4. Its clock follows the emulation unless the frontend gives it
+// the host's, and the time set by the game is kept in battery saves after the EEPROM.
+const PRG_BANK_16K: usize = 0x4000;
+
+#[derive(Debug, Clone)]
+pub struct BandaiFcg {
+    prg_len: usize,
+    chr_len: usize,
+    prg_bank: u8,
+    chr_banks: [u8; 8],
+    mirroring: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    // LZ93D50 reloads the counter from the latch when $x00A is written
+    irq_latch: u16,
+    irq_pending: bool,
+    eeprom: Eeprom24C02,
+    rtc: Option<Rtc>,
+}
+
+impl BandaiFcg {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        BandaiFcg {
+            prg_len,
+            chr_len,
+            prg_bank: 0,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            eeprom: Eeprom24C02::new(),
+            rtc: None,
+        }
+    }
+
+    /// NES 2.0 submapper this emulator gives the RTC variant
+    pub const RTC_SUBMAPPER: u8 = 15;
+
+    pub fn with_rtc(prg_len: usize, chr_len: usize) -> Self {
+        BandaiFcg {
+            rtc: Some(Rtc::new()),
+            ..Self::new(prg_len, chr_len)
+        }
+    }
+}
+
+impl Mapper for BandaiFcg {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        let banks = (self.prg_len / PRG_BANK_16K).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+            0xC000..=0xFFFF => banks - 1,
+            _ => return None,
+        };
+        Some(bank * PRG_BANK_16K + (addr & 0x3FFF) as usize)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        // Boards with CHR RAM don't bank it
+        if self.chr_len == 0 {
+            return addr;
+        }
+        let bank = self.chr_banks[addr / CHR_BANK_SIZE] as usize;
+        (bank * CHR_BANK_SIZE + addr % CHR_BANK_SIZE) % self.chr_len
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if addr < 0x6000 {
+            return;
+        }
+        match addr & 0x000F {
+            register @ 0x0..=0x7 => self.chr_banks[register as usize] = data,
+            0x8 => self.prg_bank = data & 0x0F,
+            0x9 => self.mirroring = data & 0b11,
+            0xA => {
+                self.irq_enabled = data & 1 != 0;
+                self.irq_counter = self.irq_latch;
+                self.irq_pending = false;
+            }
+            // FCG-1/2 write the counter directly, the LZ93D50 writes the latch
+            0xB => {
+                self.irq_latch = (self.irq_latch & 0xFF00) | data as u16;
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+            }
+            0xC => {
+                self.irq_latch = (self.irq_latch & 0x00FF) | ((data as u16) << 8);
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8);
+            }
+            // 76543210
+            // |||
+            // ||+------ SCL
+            // |+------- SDA
+            // +-------- Enable reading SDA back through $6000-$7FFF
+            0xD => self.eeprom.write_lines(
+                data & 0b0010_0000 != 0,
+                data & 0b0100_0000 != 0,
+                data & 0b1000_0000 != 0,
+            ),
+            0xE => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.select(data);
+                }
+            }
+            0xF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write(data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            // Only bit 4 (and bits 0-3 with an RTC) are driven, the rest is open bus
+            0x6000..=0x7FFF => {
+                let rtc = self.rtc.as_ref().map_or(0, |rtc| rtc.read());
+                Some((self.eeprom.read_sda() as u8) << 4 | rtc)
+            }
+            _ => None,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        match self.mirroring {
+            0 => Some(Mirroring::Vertical),
+            1 => Some(Mirroring::Horizontal),
+            // TODO: single-screen mirroring, keeps the header's until Mirroring supports it
+            _ => None,
+        }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn tick_cpu_cycles(&mut self, cycles: usize) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick_cpu_cycles(cycles);
+        }
+        if !self.irq_enabled {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn save_data(&self) -> Option<Cow<'_, [u8]>> {
+        match &self.rtc {
+            Some(rtc) => Some([&self.eeprom.data[..], &rtc.save_data()].concat().into()),
+            None => Some(Cow::Borrowed(&self.eeprom.data)),
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let length = data.len().min(self.eeprom.data.len());
+        self.eeprom.data[..length].copy_from_slice(&data[..length]);
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_save_data(&data[length..]);
+        }
+    }
+
+    fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock_source(clock);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.prg_bank];
+        state.extend_from_slice(&self.chr_banks);
+        state.extend([self.mirroring, self.irq_enabled as u8]);
+        state.extend_from_slice(&self.irq_counter.to_le_bytes());
+        state.extend_from_slice(&self.irq_latch.to_le_bytes());
+        state.push(self.irq_pending as u8);
+        self.eeprom.save_state(&mut state);
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(&mut state);
+        }
+        state
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader(data);
+        self.prg_bank = reader.u8()?;
+        self.chr_banks = reader.array()?;
+        self.mirroring = reader.u8()?;
+        self.irq_enabled = reader.bool()?;
+        self.irq_counter = reader.u16()?;
+        self.irq_latch = reader.u16()?;
+        self.irq_pending = reader.bool()?;
+        self.eeprom.load_state(&mut reader)?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(&mut reader)?;
+        }
+        reader.finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EepromState {
+    Idle,
+    // Receiving the device select byte (1010 xxx R/W)
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+impl EepromState {
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            0 => Ok(EepromState::Idle),
+            1 => Ok(EepromState::Device),
+            2 => Ok(EepromState::Address),
+            3 => Ok(EepromState::Write),
+            4 => Ok(EepromState::Read),
+            _ => Err(format!("Unknown EEPROM state {}", tag)),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            EepromState::Idle => 0,
+            EepromState::Device => 1,
+            EepromState::Address => 2,
+            EepromState::Write => 3,
+            EepromState::Read => 4,
+        }
+    }
+}
+
+// 256 byte I2C EEPROM, data bits are latched on rising SCL edges and driven after falling ones
+// Ref: https://www.nesdev.org/wiki/Bandai_FCG_board#EEPROM
+#[derive(Debug, Clone)]
+struct Eeprom24C02 {
+    data: [u8; 256],
+    state: EepromState,
+    // State to switch to after the acknowledge clock
+    next_state: EepromState,
+    scl: bool,
+    sda: bool,
+    read_enabled: bool,
+    // Rising SCL edges seen in the current byte, the 9th is the acknowledge
+    bits: u8,
+    shift: u8,
+    address: u8,
+    // Line driven by the EEPROM, high when released
+    output: bool,
+}
+
+impl Eeprom24C02 {
+    fn new() -> Self {
+        Eeprom24C02 {
+            data: [0xFF; 256],
+            state: EepromState::Idle,
+            next_state: EepromState::Idle,
+            scl: false,
+            sda: false,
+            read_enabled: false,
+            bits: 0,
+            shift: 0,
+            address: 0,
+            output: true,
+        }
+    }
+
+    // The contents too, a state loaded mid-game should see what the game had written by then
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.data);
+        state.extend([
+            self.state.tag(),
+            self.next_state.tag(),
+            self.scl as u8,
+            self.sda as u8,
+            self.read_enabled as u8,
+            self.bits,
+            self.shift,
+            self.address,
+            self.output as u8,
+        ]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.data = reader.array()?;
+        self.state = EepromState::from_tag(reader.u8()?)?;
+        self.next_state = EepromState::from_tag(reader.u8()?)?;
+        self.scl = reader.bool()?;
+        self.sda = reader.bool()?;
+        self.read_enabled = reader.bool()?;
+        self.bits = reader.u8()?;
+        self.shift = reader.u8()?;
+        self.address = reader.u8()?;
+        self.output = reader.bool()?;
+        Ok(())
+    }
+
+    fn read_sda(&self) -> bool {
+        // With reading disabled the line reads low
+        self.read_enabled && self.output && self.sda
+    }
+
+    fn write_lines(&mut self, scl: bool, sda: bool, read_enabled: bool) {
+        self.read_enabled = read_enabled;
+        if self.scl && scl && self.sda && !sda {
+            // Start, SDA falls while SCL is high
+            self.state = EepromState::Device;
+            self.bits = 0;
+            self.output = true;
+        } else if self.scl && scl && !self.sda && sda {
+            // Stop
+            self.state = EepromState::Idle;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.rising_edge(sda);
+        } else if self.scl && !scl {
+            self.falling_edge();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn rising_edge(&mut self, sda: bool) {
+        if self.state == EepromState::Idle {
+            return;
+        }
+        self.bits += 1;
+        if self.bits > 8 {
+            // Acknowledge clock, when reading the master asks for more with a low SDA
+            if self.state == EepromState::Read && sda {
+                self.next_state = EepromState::Idle;
+            }
+            return;
+        }
+        if self.state == EepromState::Read {
+            return;
+        }
+        self.shift = (self.shift << 1) | sda as u8;
+        if self.bits == 8 {
+            self.receive_byte(self.shift);
+        }
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        self.next_state = match self.state {
+            EepromState::Device if byte & 0xF0 == 0xA0 => match byte & 1 != 0 {
+                true => EepromState::Read,
+                false => EepromState::Address,
+            },
+            // Not addressed to us, no acknowledge
+            EepromState::Device => EepromState::Idle,
+            EepromState::Address => {
+                self.address = byte;
+                EepromState::Write
+            }
+            EepromState::Write => {
+                self.data[self.address as usize] = byte;
+                // Writes wrap inside 8 byte pages
+                self.address = (self.address & 0xF8) | (self.address.wrapping_add(1) & 0x07);
+                EepromState::Write
+            }
+            EepromState::Read | EepromState::Idle => EepromState::Idle,
+        };
+    }
+
+    fn falling_edge(&mut self) {
+        match (self.state, self.bits) {
+            (EepromState::Idle, _) => {}
+            // Drive the next data bit while the master clocks it in
+            (EepromState::Read, 0..=7) => {
+                let byte = self.data[self.address as usize];
+                self.output = byte & (0x80 >> self.bits) != 0;
+            }
+            // Release SDA for the master's acknowledge
+            (EepromState::Read, 8) => self.output = true,
+            (_, 0..=7) => {}
+            // Acknowledge a received byte by pulling SDA low
+            (_, 8) => self.output = self.next_state == EepromState::Idle,
+            // End of the acknowledge clock
+            (state, _) => {
+                if state == EepromState::Read {
+                    self.address = self.address.wrapping_add(1);
+                }
+                self.state = self.next_state;
+                self.bits = 0;
+                self.output = true;
+                if self.state == EepromState::Read {
+                    self.output = self.data[self.address as usize] & 0x80 != 0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::super::{VirtualClock, VIRTUAL_CLOCK_START};
+    use super::*;
+    use crate::frontend::CPU_CLOCK;
+
+    const PRG_LEN: usize = 0x40000;
+    const CHR_LEN: usize = 0x40000;
+
+    fn create_harness() -> MapperHarness<BandaiFcg> {
+        MapperHarness::new(BandaiFcg::new(PRG_LEN, CHR_LEN), PRG_LEN, CHR_LEN)
+    }
+
+    // $800D writes for SCL and SDA with reading enabled
+    fn lines(scl: bool, sda: bool) -> Access {
+        Access::CpuWrite(0x800D, 0x80 | (sda as u8) << 6 | (scl as u8) << 5)
+    }
+
+    fn start() -> Vec<Access> {
+        vec![
+            lines(false, true),
+            lines(true, true),
+            lines(true, false),
+            lines(false, false),
+        ]
+    }
+
+    fn stop() -> Vec<Access> {
+        vec![lines(false, false), lines(true, false), lines(true, true)]
+    }
+
+    // Clocks out a byte and the acknowledge clock, leaving SCL high on the acknowledge
+    fn send_byte(byte: u8) -> Vec<Access> {
+        let mut script = Vec::new();
+        for bit in (0..8).rev() {
+            let sda = byte & (1 << bit) != 0;
+            script.extend([lines(false, sda), lines(true, sda), lines(false, sda)]);
+        }
+        script.extend([lines(false, true), lines(true, true)]);
+        script
+    }
+
+    fn read_sda(harness: &mut MapperHarness<BandaiFcg>) -> bool {
+        harness.cpu_read(0x6000).unwrap() & 0x10 != 0
+    }
+
+    #[test]
+    fn test_prg_and_chr_banks() {
+        let mut harness = create_harness();
+        harness.assert_prg_banks(&[0, 1, 30, 31]);
+        let mut script: Vec<Access> = (0..8)
+            .map(|register| Access::CpuWrite(0x8000 + register, 0x10 + register as u8))
+            .collect();
+        script.push(Access::CpuWrite(0x8008, 0x03));
+        harness.run(&script);
+        harness.assert_prg_banks(&[6, 7, 30, 31]);
+        harness.assert_chr_banks(&[0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]);
+        // FCG-1/2 register mirror at $6000
+        harness.run(&[Access::CpuWrite(0x6008, 0x01)]);
+        harness.assert_prg_banks(&[2, 3, 30, 31]);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut harness = create_harness();
+        harness.assert_mirroring(Some(Mirroring::Vertical));
+        harness.run(&[Access::CpuWrite(0x8009, 1)]);
+        harness.assert_mirroring(Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut harness = create_harness();
+        harness.run(&[
+            Access::CpuWrite(0x800B, 100),
+            Access::CpuWrite(0x800C, 0),
+            Access::CpuWrite(0x800A, 1),
+            Access::CpuCycles(100),
+        ]);
+        harness.assert_irq(false);
+        harness.run(&[Access::CpuCycles(1)]);
+        harness.assert_irq(true);
+        // Writing the control register acknowledges
+        harness.run(&[Access::CpuWrite(0x800A, 0)]);
+        harness.assert_irq(false);
+        harness.run(&[Access::CpuCycles(1000)]);
+        harness.assert_irq(false);
+    }
+
+    #[test]
+    fn test_eeprom_write_then_read() {
+        let mut harness = create_harness();
+        let mut script = start();
+        for byte in [0xA0, 0x10, 0x5A, 0xC3] {
+            script.extend(send_byte(byte));
+        }
+        script.extend(stop());
+        harness.run(&script);
+        assert_eq!(0x5A, harness.mapper.save_data().unwrap()[0x10]);
+        assert_eq!(0xC3, harness.mapper.save_data().unwrap()[0x11]);
+
+        // Random read: set the address, restart, then read two bytes
+        let mut script = start();
+        script.extend(send_byte(0xA0));
+        script.extend(send_byte(0x10));
+        script.extend(start());
+        script.extend(send_byte(0xA1));
+        script.push(lines(false, true));
+        harness.run(&script);
+        let mut bytes = Vec::new();
+        for index in 0..2 {
+            let mut byte = 0u8;
+            for _ in 0..8 {
+                harness.run(&[lines(true, true)]);
+                byte = (byte << 1) | read_sda(&mut harness) as u8;
+                harness.run(&[lines(false, true)]);
+            }
+            bytes.push(byte);
+            // Acknowledge the first byte, not the last
+            let nack = index == 1;
+            harness.run(&[lines(false, nack), lines(true, nack), lines(false, true)]);
+        }
+        assert_eq!(vec![0x5A, 0xC3], bytes);
+    }
+
+    #[test]
+    fn test_eeprom_acknowledge() {
+        let mut harness = create_harness();
+        let mut script = start();
+        script.extend(send_byte(0xA0));
+        harness.run(&script);
+        assert!(!read_sda(&mut harness));
+        // Another device address gets no acknowledge
+        let mut script = start();
+        script.extend(send_byte(0xB0));
+        harness.run(&script);
+        assert!(read_sda(&mut harness));
+    }
+
+    fn create_rtc_harness() -> MapperHarness<BandaiFcg> {
+        MapperHarness::new(BandaiFcg::with_rtc(PRG_LEN, CHR_LEN), PRG_LEN, CHR_LEN)
+    }
+
+    fn read_rtc(harness: &mut MapperHarness<BandaiFcg>, register: u8) -> u8 {
+        harness.run(&[Access::CpuWrite(0x800E, register)]);
+        harness.cpu_read(0x6000).unwrap() & 0x0F
+    }
+
+    // Seconds, minutes, hours, day, month and year as the game reads them
+    fn read_time(harness: &mut MapperHarness<BandaiFcg>) -> Vec<u8> {
+        (0..12)
+            .step_by(2)
+            .map(|register| read_rtc(harness, register + 1) * 10 + read_rtc(harness, register))
+            .collect()
+    }
+
+    #[test]
+    fn test_rtc_set_and_run() {
+        let mut harness = create_rtc_harness();
+        // The virtual clock starts on 2000-01-01, a Saturday
+        assert_eq!(vec![0, 0, 0, 1, 1, 0], read_time(&mut harness));
+        assert_eq!(6, read_rtc(&mut harness, 0xC));
+
+        // 1999-12-31 23:59:58 written while held, the year digits keep the century
+        let digits = [8, 5, 9, 5, 3, 2, 1, 3, 2, 1, 9, 9];
+        let mut script = vec![Access::CpuWrite(0x800E, 0xD), Access::CpuWrite(0x800F, 1)];
+        for (register, digit) in digits.into_iter().enumerate() {
+            script.push(Access::CpuWrite(0x800E, register as u8));
+            script.push(Access::CpuWrite(0x800F, digit));
+        }
+        script.push(Access::CpuCycles(CPU_CLOCK as usize * 5));
+        harness.run(&script);
+        assert_eq!(vec![58, 59, 23, 31, 12, 99], read_time(&mut harness));
+        harness.run(&[Access::CpuWrite(0x800E, 0xD), Access::CpuWrite(0x800F, 0)]);
+        assert_eq!(vec![58, 59, 23, 31, 12, 99], read_time(&mut harness));
+        harness.run(&[Access::CpuCycles(CPU_CLOCK as usize * 2)]);
+        assert_eq!(vec![0, 0, 0, 1, 1, 0], read_time(&mut harness));
+
+        // The EEPROM is still read on D4
+        let mut script = start();
+        script.extend(send_byte(0xA0));
+        harness.run(&script);
+        assert!(!read_sda(&mut harness));
+    }
+
+    #[test]
+    fn test_rtc_clock_source() {
+        let mut harness = create_rtc_harness();
+        // 2023-11-14 22:13:20
+        harness
+            .mapper
+            .set_clock_source(Box::new(VirtualClock::new(1_700_000_000)));
+        assert_eq!(vec![20, 13, 22, 14, 11, 23], read_time(&mut harness));
+        // Ignored without an RTC
+        let mut plain = create_harness();
+        plain
+            .mapper
+            .set_clock_source(Box::new(VirtualClock::new(0)));
+        assert_eq!(0, plain.cpu_read(0x6000).unwrap() & 0x0F);
+    }
+
+    #[test]
+    fn test_rtc_save_data() {
+        let mut harness = create_rtc_harness();
+        // An hour later
+        harness.run(&[Access::CpuWrite(0x800E, 4), Access::CpuWrite(0x800F, 1)]);
+        let save = harness.mapper.save_data().unwrap().into_owned();
+        assert_eq!(264, save.len());
+        assert_eq!(&3600i64.to_le_bytes(), &save[256..]);
+
+        // The next session's clock has moved on by a minute
+        let mut next = create_rtc_harness();
+        next.mapper
+            .set_clock_source(Box::new(VirtualClock::new(VIRTUAL_CLOCK_START + 60)));
+        next.mapper.load_save_data(&save);
+        assert_eq!(vec![0, 1, 1, 1, 1, 0], read_time(&mut next));
+        // Saves from before the RTC, or from the plain board, keep the clock's time
+        let mut old = create_rtc_harness();
+        old.mapper.load_save_data(&save[..256]);
+        assert_eq!(vec![0, 0, 0, 1, 1, 0], read_time(&mut old));
+    }
+
+    #[test]
+    fn test_rtc_state_round_trip() {
+        let mut harness = create_rtc_harness();
+        harness.run(&[
+            Access::CpuWrite(0x800E, 2),
+            Access::CpuWrite(0x800F, 5),
+            Access::CpuCycles((CPU_CLOCK + CPU_CLOCK / 2) as usize),
+        ]);
+        let state = harness.mapper.save_state();
+        // Held in the middle of setting the date
+        harness.run(&[
+            Access::CpuWrite(0x800E, 0xD),
+            Access::CpuWrite(0x800F, 1),
+            Access::CpuWrite(0x800E, 7),
+            Access::CpuWrite(0x800F, 3),
+            Access::CpuWrite(0x800E, 6),
+            Access::CpuWrite(0x800F, 1),
+        ]);
+        let held = harness.mapper.save_state();
+        let mut loaded = create_rtc_harness();
+        loaded.mapper.load_state(&held).unwrap();
+        assert_eq!(vec![1, 5, 0, 31, 1, 0], read_time(&mut loaded));
+
+        let mut loaded = create_rtc_harness();
+        loaded.mapper.load_state(&state).unwrap();
+        assert_eq!(vec![1, 5, 0, 1, 1, 0], read_time(&mut loaded));
+        // Half a second in, like the saved clock
+        loaded.run(&[Access::CpuCycles((CPU_CLOCK - CPU_CLOCK / 2) as usize)]);
+        assert_eq!(vec![2, 5, 0, 1, 1, 0], read_time(&mut loaded));
+        assert!(loaded.mapper.load_state(&state[..state.len() - 1]).is_err());
+        // The plain board's state is shorter
+        assert!(create_harness().mapper.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_load_save_data() {
+        let mut mapper = BandaiFcg::new(PRG_LEN, CHR_LEN);
+        mapper.load_save_data(&[1, 2, 3]);
+        assert_eq!(&[1, 2, 3, 0xFF], &mapper.save_data().unwrap()[..4]);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut harness = create_harness();
+        let mut script = vec![
+            Access::CpuWrite(0x8008, 0x03),
+            Access::CpuWrite(0x800B, 10),
+            Access::CpuWrite(0x800A, 1),
+            Access::CpuCycles(5),
+        ];
+        // Halfway through a transfer, acknowledging the device address
+        script.extend(start());
+        script.extend(send_byte(0xA0));
+        harness.run(&script);
+        let state = harness.mapper.save_state();
+
+        let mut loaded = create_harness();
+        loaded.mapper.load_state(&state).unwrap();
+        loaded.assert_prg_banks(&[6, 7, 30, 31]);
+        assert!(!read_sda(&mut loaded));
+        loaded.run(&[Access::CpuCycles(5)]);
+        loaded.assert_irq(false);
+        loaded.run(&[Access::CpuCycles(1)]);
+        loaded.assert_irq(true);
+        assert!(loaded.mapper.load_state(&state[..state.len() - 1]).is_err());
+    }
+}
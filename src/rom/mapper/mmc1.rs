@@ -0,0 +1,213 @@
+use super::{Mapper, StateReader};
+use crate::rom::Mirroring;
+
+// MMC1 (mapper 1), SxROM boards
+// Ref: https://www.nesdev.org/wiki/MMC1
+//
+// Registers are loaded one bit at a time through a 5 bit shift register, written LSB first at
+// $8000-$FFFF. The fifth write copies it into the register picked by bits 13-14 of its address.
+// MMC1 ignores writes on consecutive cycles (the second write of a read-modify-write), that
+// isn't emulated since games only rely on it with a reset write, which resets either way.
+const PRG_BANK_16K: usize = 0x4000;
+const CHR_BANK_4K: usize = 0x1000;
+// SUROM and SXROM have 512KB of PRG, bit 4 of the CHR registers picks the 256KB half
+const PRG_OUTER_BANK: usize = 0x40000;
+
+// Shift register value with no bits written, the 1 reaches bit 0 after 5 writes
+const SHIFT_RESET: u8 = 0b1_0000;
+// Control value after a reset write, $C000 fixed to the last bank
+const CONTROL_RESET: u8 = 0b0_1100;
+
+#[derive(Debug, Clone)]
+pub struct Mmc1 {
+    prg_len: usize,
+    chr_len: usize,
+    shift: u8,
+    // 43210
+    // |||++- Mirroring: 0: one-screen lower, 1: one-screen upper, 2: vertical, 3: horizontal
+    // |++--- PRG mode: 0, 1: 32KB at $8000, 2: first bank fixed at $8000, 3: last bank fixed
+    // |                at $C000
+    // +----- CHR mode: 0: 8KB, 1: two 4KB banks
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        Mmc1 {
+            prg_len,
+            chr_len,
+            shift: SHIFT_RESET,
+            control: CONTROL_RESET,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+        }
+    }
+
+    fn load_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_banks[0] = value,
+            0xC000..=0xDFFF => self.chr_banks[1] = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // Start of the 256KB half of PRG in use
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg_len > PRG_OUTER_BANK {
+            (self.chr_banks[0] & 0b1_0000) as usize * (PRG_OUTER_BANK / 0b1_0000)
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        let banks = (self.prg_len.min(PRG_OUTER_BANK) / PRG_BANK_16K).max(1);
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            (_, 0x0000..=0x7FFF) => return None,
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => banks - 1,
+        };
+        Some(self.prg_outer_bank() + (bank % banks) * PRG_BANK_16K + (addr & 0x3FFF) as usize)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        let bank = if self.control & 0b1_0000 == 0 {
+            // 8KB mode ignores the low bit
+            (self.chr_banks[0] & !1) as usize + addr / CHR_BANK_4K
+        } else {
+            self.chr_banks[addr / CHR_BANK_4K] as usize
+        };
+        (bank * CHR_BANK_4K + addr % CHR_BANK_4K) % self.chr_len.max(1)
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        if data & 0b1000_0000 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= CONTROL_RESET;
+            return;
+        }
+        let is_full = self.shift & 1 != 0;
+        self.shift = (self.shift >> 1) | ((data & 1) << 4);
+        if is_full {
+            self.load_register(addr, self.shift);
+            self.shift = SHIFT_RESET;
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let [chr_0, chr_1] = self.chr_banks;
+        vec![self.shift, self.control, chr_0, chr_1, self.prg_bank]
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader(data);
+        [
+            self.shift,
+            self.control,
+            self.chr_banks[0],
+            self.chr_banks[1],
+            self.prg_bank,
+        ] = reader.array()?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::*;
+
+    // The five writes loading `value` into the register at `addr`
+    fn load(addr: u16, value: u8) -> Vec<Access> {
+        (0..5)
+            .map(|bit| Access::CpuWrite(addr, (value >> bit) & 1))
+            .collect()
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        // 128KB, eight 16KB banks
+        let mut harness = MapperHarness::new(Mmc1::new(0x20000, 0x2000), 0x20000, 0x2000);
+        // Powers on with the last bank fixed at $C000
+        harness.assert_prg_banks(&[0, 1, 14, 15]);
+        harness.run(&load(0xE000, 3));
+        harness.assert_prg_banks(&[6, 7, 14, 15]);
+        // First bank fixed at $8000
+        harness.run(&load(0x8000, 0b0_1000));
+        harness.assert_prg_banks(&[0, 1, 6, 7]);
+        // 32KB, the low bit is ignored
+        harness.run(&load(0x8000, 0b0_0000));
+        harness.assert_prg_banks(&[4, 5, 6, 7]);
+        harness.assert_unmapped(0x6000);
+    }
+
+    #[test]
+    fn test_reset_write() {
+        let mut harness = MapperHarness::new(Mmc1::new(0x20000, 0x2000), 0x20000, 0x2000);
+        harness.run(&load(0x8000, 0b0_0010));
+        harness.assert_mirroring(Some(Mirroring::Vertical));
+        // A reset halfway through a load throws the bits away and fixes the last bank again
+        let mut script = load(0xE000, 0b1_1111)[..3].to_vec();
+        script.push(Access::CpuWrite(0x8000, 0x80));
+        script.extend(load(0xE000, 2));
+        harness.run(&script);
+        harness.assert_prg_banks(&[4, 5, 14, 15]);
+        // Mirroring bits are kept
+        harness.assert_mirroring(Some(Mirroring::Vertical));
+    }
+
+    #[test]
+    fn test_single_screen_mirroring() {
+        let mut harness = MapperHarness::new(Mmc1::new(0x20000, 0x2000), 0x20000, 0x2000);
+        harness.run(&load(0x8000, 0b0_1100));
+        harness.assert_mirroring(Some(Mirroring::SingleScreenLower));
+        harness.run(&load(0x8000, 0b0_1101));
+        harness.assert_mirroring(Some(Mirroring::SingleScreenUpper));
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        // 128KB of CHR ROM, 32 4KB banks
+        let mut harness = MapperHarness::new(Mmc1::new(0x8000, 0x20000), 0x8000, 0x20000);
+        harness.run(&[load(0xA000, 5), load(0xC000, 9)].concat());
+        // 8KB mode uses the first register without its low bit
+        harness.assert_chr_banks(&[16, 17, 18, 19, 20, 21, 22, 23]);
+        harness.run(&load(0x8000, 0b1_1111));
+        harness.assert_chr_banks(&[20, 21, 22, 23, 36, 37, 38, 39]);
+        harness.assert_mirroring(Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_chr_ram_and_512k_prg() {
+        // SUROM, 512KB PRG and 8KB of CHR RAM
+        let mut harness = MapperHarness::new(Mmc1::new(0x80000, 0x2000), 0x80000, 0x2000);
+        harness.assert_prg_banks(&[0, 1, 30, 31]);
+        // Bit 4 of the first CHR register selects the upper 256KB, which has its own last bank
+        harness.run(&[load(0x8000, 0b1_1100), load(0xA000, 0x10)].concat());
+        harness.assert_prg_banks(&[32, 33, 62, 63]);
+        harness.assert_chr_banks(&[0, 1, 2, 3, 0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,300 @@
+use super::{Mapper, StateReader, CHR_BANK_SIZE, PRG_BANK_SIZE};
+use crate::rom::Mirroring;
+
+// MMC3 (mapper 4), TxROM boards
+// Ref: https://www.nesdev.org/wiki/MMC3
+//
+// The scanline counter is clocked by A12 rising as the PPU fetches sprite patterns from $1000
+// after the background's from $0000. The PPU's fetches aren't watched for A12, it's clocked once
+// per rendered scanline like A12 does with that usual layout.
+
+// Bank registers selected through $8000 and written through $8001, shared with the boards
+// (Namco 108 and relatives) that copied them
+#[derive(Debug, Clone)]
+pub(super) struct BankRegisters {
+    prg_len: usize,
+    chr_len: usize,
+    // 76543210
+    // ||   |||
+    // ||   +++- Register written by $8001
+    // |+------- PRG mode: 0: $8000 switchable, $C000 fixed to the second last bank, 1: swapped
+    // +-------- CHR mode: 0: 2KB banks at $0000, 1: 2KB banks at $1000
+    select: u8,
+    // R0-R1 2KB CHR banks, R2-R5 1KB CHR banks, R6-R7 8KB PRG banks
+    registers: [u8; 8],
+}
+
+impl BankRegisters {
+    pub(super) fn new(prg_len: usize, chr_len: usize) -> Self {
+        BankRegisters {
+            prg_len,
+            chr_len,
+            select: 0,
+            registers: [0; 8],
+        }
+    }
+
+    pub(super) fn write_select(&mut self, data: u8) {
+        self.select = data;
+    }
+
+    pub(super) fn write_bank(&mut self, data: u8) {
+        self.registers[(self.select & 0b111) as usize] = data;
+    }
+
+    pub(super) fn map_prg(&self, addr: u16) -> Option<usize> {
+        let banks = (self.prg_len / PRG_BANK_SIZE).max(2);
+        let swapped = self.select & 0b0100_0000 != 0;
+        let bank = match (addr, swapped) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => banks - 2,
+            (0xE000..=0xFFFF, _) => banks - 1,
+            _ => return None,
+        };
+        Some((bank % banks) * PRG_BANK_SIZE + (addr & 0x1FFF) as usize)
+    }
+
+    pub(super) fn map_chr(&self, addr: u16) -> usize {
+        let mut window = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        // CHR inversion swaps the 2KB and 1KB halves
+        if self.select & 0b1000_0000 != 0 {
+            window ^= 0b100;
+        }
+        let bank = match window {
+            // 2KB banks ignore the low bit
+            0..=3 => (self.registers[window / 2] & !1) as usize + window % 2,
+            _ => self.registers[window - 2] as usize,
+        };
+        (bank * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE) % self.chr_len.max(1)
+    }
+
+    pub(super) fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.select);
+        state.extend_from_slice(&self.registers);
+    }
+
+    pub(super) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.select = reader.u8()?;
+        self.registers = reader.array()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mmc3 {
+    banks: BankRegisters,
+    mirroring: Mirroring,
+    irq_latch: u8,
+    irq_counter: u8,
+    // Set by $C001, reloads the counter on the next clock
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        Mmc3 {
+            banks: BankRegisters::new(prg_len, chr_len),
+            mirroring: Mirroring::Vertical,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        self.banks.map_prg(addr)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        self.banks.map_chr(addr)
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        // Each register is mirrored over its 8KB, even addresses and odd ones pick between pairs
+        match addr & 0xE001 {
+            0x8000 => self.banks.write_select(data),
+            0x8001 => self.banks.write_bank(data),
+            0xA000 => {
+                self.mirroring = match data & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            0xC000 => self.irq_latch = data,
+            0xC001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE001 => self.irq_enabled = true,
+            // $A001 protects PRG RAM, which is always writable here
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn end_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.banks.save_state(&mut state);
+        state.extend([
+            (self.mirroring == Mirroring::Horizontal) as u8,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_reload as u8,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+        ]);
+        state
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader(data);
+        self.banks.load_state(&mut reader)?;
+        self.mirroring = match reader.bool()? {
+            false => Mirroring::Vertical,
+            true => Mirroring::Horizontal,
+        };
+        self.irq_latch = reader.u8()?;
+        self.irq_counter = reader.u8()?;
+        self.irq_reload = reader.bool()?;
+        self.irq_enabled = reader.bool()?;
+        self.irq_pending = reader.bool()?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::*;
+
+    // 128KB PRG (16 8KB banks) and 128KB CHR (128 1KB banks)
+    fn harness() -> MapperHarness<Mmc3> {
+        MapperHarness::new(Mmc3::new(0x20000, 0x20000), 0x20000, 0x20000)
+    }
+
+    // Bank select then bank data for each register
+    fn load_banks(banks: &[u8; 8]) -> Vec<Access> {
+        (0..8)
+            .flat_map(|register| {
+                [
+                    Access::CpuWrite(0x8000, register as u8),
+                    Access::CpuWrite(0x8001, banks[register]),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut harness = harness();
+        harness.run(&load_banks(&[0, 0, 0, 0, 0, 0, 3, 5]));
+        harness.assert_prg_banks(&[3, 5, 14, 15]);
+        // Swapping the $8000 and $C000 windows, through a mirror of $8000
+        harness.run(&[Access::CpuWrite(0x9FFE, 0b0100_0000)]);
+        harness.assert_prg_banks(&[14, 5, 3, 15]);
+        harness.assert_unmapped(0x6000);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut harness = harness();
+        harness.run(&load_banks(&[9, 12, 40, 41, 42, 43, 0, 0]));
+        // 2KB banks ignore the low bit of R0
+        harness.assert_chr_banks(&[8, 9, 12, 13, 40, 41, 42, 43]);
+        harness.run(&[Access::CpuWrite(0x8000, 0b1000_0000)]);
+        harness.assert_chr_banks(&[40, 41, 42, 43, 8, 9, 12, 13]);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut harness = harness();
+        harness.assert_mirroring(Some(Mirroring::Vertical));
+        harness.run(&[Access::CpuWrite(0xBFFE, 1)]);
+        harness.assert_mirroring(Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut harness = harness();
+        harness.run(&[
+            Access::CpuWrite(0xC000, 3),
+            Access::CpuWrite(0xC001, 0),
+            Access::CpuWrite(0xE001, 0),
+        ]);
+        // The first clock reloads the counter, then it counts down to 0 on the 4th
+        harness.run(&[Access::Scanlines(3)]);
+        harness.assert_irq(false);
+        harness.run(&[Access::Scanlines(1)]);
+        harness.assert_irq(true);
+        // $E000 acknowledges and disables it, the counter keeps going
+        harness.run(&[Access::CpuWrite(0xE000, 0), Access::Scanlines(4)]);
+        harness.assert_irq(false);
+        // Enabled again, the reloaded counter reaches 0 after another 4 scanlines
+        harness.run(&[Access::CpuWrite(0xE001, 0), Access::Scanlines(3)]);
+        harness.assert_irq(false);
+        harness.run(&[Access::Scanlines(1)]);
+        harness.assert_irq(true);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut harness = harness();
+        harness.run(&load_banks(&[9, 12, 40, 41, 42, 43, 3, 5]));
+        harness.run(&[
+            Access::CpuWrite(0x8000, 0b1100_0000),
+            Access::CpuWrite(0xA000, 1),
+            Access::CpuWrite(0xC000, 3),
+            Access::CpuWrite(0xC001, 0),
+            Access::CpuWrite(0xE001, 0),
+            Access::Scanlines(2),
+        ]);
+        let state = harness.mapper.save_state();
+
+        let mut loaded = self::harness();
+        loaded.mapper.load_state(&state).unwrap();
+        loaded.assert_prg_banks(&[14, 5, 3, 15]);
+        loaded.assert_chr_banks(&[40, 41, 42, 43, 8, 9, 12, 13]);
+        loaded.assert_mirroring(Some(Mirroring::Horizontal));
+        // The counter carries on where it was
+        loaded.run(&[Access::Scanlines(1)]);
+        loaded.assert_irq(false);
+        loaded.run(&[Access::Scanlines(1)]);
+        loaded.assert_irq(true);
+
+        assert!(loaded.mapper.load_state(&state[1..]).is_err());
+        assert!(loaded
+            .mapper
+            .load_state(&[&state[..], &[0]].concat())
+            .is_err());
+    }
+}
@@ -0,0 +1,213 @@
+// Cartridge boards, translating CPU and PPU addresses into PRG and CHR offsets
+//
+// A mapper only holds its bank registers, the ROM data stays in ROM so mappers are cheap to
+// clone for snapshots. Ref: https://www.nesdev.org/wiki/Mapper
+use std::borrow::Cow;
+use std::fmt::Debug;
+
+use super::Mirroring;
+
+mod bandai_fcg;
+mod mmc1;
+mod mmc3;
+mod namco108;
+mod nrom;
+mod rtc;
+#[cfg(test)]
+pub(crate) mod test_kit;
+mod uxrom;
+
+pub use bandai_fcg::BandaiFcg;
+pub use mmc1::Mmc1;
+pub use mmc3::Mmc3;
+pub use namco108::Namco108;
+pub use nrom::Nrom;
+#[cfg(not(feature = "minimal"))]
+pub use rtc::HostClock;
+pub use rtc::{ClockSource, VirtualClock, VIRTUAL_CLOCK_START};
+pub use uxrom::Uxrom;
+
+pub const PRG_BANK_SIZE: usize = 0x2000;
+pub const CHR_BANK_SIZE: usize = 0x0400;
+
+pub trait Mapper: Debug + Send + Sync + CloneMapper {
+    /// Offset into PRG ROM for a CPU read at $6000-$FFFF, None if nothing is mapped there
+    fn map_prg(&self, addr: u16) -> Option<usize>;
+
+    /// Offset into CHR ROM/RAM for a PPU access at $0000-$1FFF
+    fn map_chr(&self, addr: u16) -> usize;
+
+    /// CPU write to cartridge space ($4020-$FFFF), bank switching registers live here
+    fn write_register(&mut self, addr: u16, data: u8);
+
+    /// CPU read of a board register in cartridge space, None to read PRG through map_prg
+    fn read_register(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Nametable mirroring set by the board, None to use the header's
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    /// True while the board is asserting the CPU IRQ line
+    fn is_irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Called at the end of every rendered scanline, for scanline counters
+    fn end_scanline(&mut self) {}
+
+    /// Called once per CPU cycle, for cycle counters
+    fn tick_cpu_cycles(&mut self, _cycles: usize) {}
+
+    /// Memory kept when the console is off (battery RAM, EEPROM, a real-time clock), saved with
+    /// battery saves
+    fn save_data(&self) -> Option<Cow<'_, [u8]>> {
+        None
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    /// Clock the board's real-time clock follows, boards without one ignore it
+    fn set_clock_source(&mut self, _clock: Box<dyn ClockSource>) {}
+
+    /// Registers and counters for savestates, in the layout given by state_version. The length
+    /// doesn't change while the board runs, frontends size savestate buffers once.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Loads data written by save_state. On errors the mapper may be partly loaded, so load
+    /// into a clone.
+    fn load_state(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Reads back what a mapper's save_state wrote, in the same order
+pub(super) struct StateReader<'a>(pub(super) &'a [u8]);
+
+impl<'a> StateReader<'a> {
+    pub(super) fn u8(&mut self) -> Result<u8, String> {
+        let (byte, rest) = self
+            .0
+            .split_first()
+            .ok_or_else(|| "Mapper state is truncated".to_string())?;
+        self.0 = rest;
+        Ok(*byte)
+    }
+
+    pub(super) fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(super) fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    pub(super) fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < length {
+            return Err("Mapper state is truncated".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(super) fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut bytes = [0; N];
+        for byte in &mut bytes {
+            *byte = self.u8()?;
+        }
+        Ok(bytes)
+    }
+
+    /// Checks all of the state was read
+    pub(super) fn finish(self) -> Result<(), String> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err("Mapper state has trailing bytes".to_string()),
+        }
+    }
+}
+
+// Lets ROM (and so ActionNES) derive Clone while holding a Box<dyn Mapper>
+pub trait CloneMapper {
+    fn clone_mapper(&self) -> Box<dyn Mapper>;
+}
+
+impl<M: Mapper + Clone + 'static> CloneMapper for M {
+    fn clone_mapper(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.clone_mapper()
+    }
+}
+
+/// Creates the mapper for an iNES mapper number and NES 2.0 submapper (0 for iNES headers),
+/// sized for the ROM's PRG and CHR lengths
+pub fn create_mapper(
+    number: u8,
+    submapper: u8,
+    prg_len: usize,
+    chr_len: usize,
+) -> Result<Box<dyn Mapper>, String> {
+    match number {
+        0 => Ok(Box::new(Nrom::new(prg_len, chr_len))),
+        1 => Ok(Box::new(Mmc1::new(prg_len, chr_len))),
+        2 => Ok(Box::new(Uxrom::new(prg_len, chr_len))),
+        4 => Ok(Box::new(Mmc3::new(prg_len, chr_len))),
+        16 if submapper == BandaiFcg::RTC_SUBMAPPER => {
+            Ok(Box::new(BandaiFcg::with_rtc(prg_len, chr_len)))
+        }
+        16 => Ok(Box::new(BandaiFcg::new(prg_len, chr_len))),
+        88 => Ok(Box::new(Namco108::with_split_chr(prg_len, chr_len))),
+        206 => Ok(Box::new(Namco108::new(prg_len, chr_len))),
+        _ => Err(format!("Mapper {} is not supported", number)),
+    }
+}
+
+/// Bytes of PRG RAM at $6000-$7FFF for iNES 1.0 headers, which don't give the size. Boards
+/// with registers there have none.
+pub fn default_prg_ram_size(number: u8) -> usize {
+    match number {
+        16 => 0,
+        _ => 0x2000,
+    }
+}
+
+/// Layout version of a mapper's savestate data, bump it when a refactor changes what the mapper
+/// saves and add the conversion from the old layout to migrate_state
+pub fn state_version(number: u8) -> u16 {
+    match number {
+        0 => 1,
+        // 2 added the registers, savestates before it kept the board's current ones
+        1 | 2 | 4 | 16 | 88 | 206 => 2,
+        _ => 0,
+    }
+}
+
+/// Best effort upgrade of mapper savestate data saved with an older state_version
+// A Vec so migrations can change the length
+#[allow(clippy::ptr_arg)]
+pub fn migrate_state(number: u8, from_version: u16, data: &mut Vec<u8>) -> Result<(), String> {
+    let version = state_version(number);
+    match from_version {
+        _ if from_version == version => Ok(()),
+        // Nothing was saved, loading keeps the board's registers like it always did
+        1 if data.is_empty() => Ok(()),
+        _ if from_version > version => Err(format!(
+            "Savestate is from a newer version of mapper {} ({} > {})",
+            number, from_version, version
+        )),
+        _ => Err(format!(
+            "Mapper {} savestates from version {} can't be migrated to version {}",
+            number, from_version, version
+        )),
+    }
+}
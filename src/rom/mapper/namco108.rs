@@ -0,0 +1,114 @@
+use super::mmc3::BankRegisters;
+use super::{Mapper, StateReader};
+
+// Namco 108 family (mapper 206, and 88 with its split CHR), the bank switching MMC3 was based on
+// Ref: https://www.nesdev.org/wiki/INES_Mapper_206
+// Ref: https://www.nesdev.org/wiki/INES_Mapper_088
+//
+// Only the MMC3's $8000/$8001 pair exists, mirrored over $8000-$9FFF, without the PRG and CHR
+// mode bits, mirroring control or IRQ. Mirroring is hardwired and comes from the header.
+const CHR_HALF: usize = 0x10000;
+
+#[derive(Debug, Clone)]
+pub struct Namco108 {
+    banks: BankRegisters,
+    chr_len: usize,
+    // Mapper 88 wires CHR A16 to PPU A12, the pattern tables come from different 64KB halves
+    split_chr: bool,
+}
+
+impl Namco108 {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        Namco108 {
+            banks: BankRegisters::new(prg_len, chr_len),
+            chr_len,
+            split_chr: false,
+        }
+    }
+
+    pub fn with_split_chr(prg_len: usize, chr_len: usize) -> Self {
+        Namco108 {
+            split_chr: true,
+            ..Self::new(prg_len, chr_len)
+        }
+    }
+}
+
+impl Mapper for Namco108 {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        self.banks.map_prg(addr)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let offset = self.banks.map_chr(addr);
+        if !self.split_chr {
+            return offset;
+        }
+        let half = if addr & 0x1000 != 0 { CHR_HALF } else { 0 };
+        (offset % CHR_HALF + half) % self.chr_len.max(1)
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            // Only the register number, the mode bits aren't there
+            0x8000..=0x9FFF if addr & 1 == 0 => self.banks.write_select(data & 0b111),
+            0x8000..=0x9FFF => self.banks.write_bank(data),
+            _ => {}
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.banks.save_state(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader(data);
+        self.banks.load_state(&mut reader)?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::*;
+
+    fn load_banks(banks: &[u8; 8]) -> Vec<Access> {
+        (0..8)
+            .flat_map(|register| {
+                [
+                    Access::CpuWrite(0x8000, register as u8),
+                    Access::CpuWrite(0x8001, banks[register]),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mode_bits_ignored() {
+        // 128KB PRG and 64KB CHR
+        let mut harness = MapperHarness::new(Namco108::new(0x20000, 0x10000), 0x20000, 0x10000);
+        harness.run(&load_banks(&[2, 6, 8, 9, 10, 11, 4, 5]));
+        harness.assert_prg_banks(&[4, 5, 14, 15]);
+        harness.assert_chr_banks(&[2, 3, 6, 7, 8, 9, 10, 11]);
+        // The MMC3 PRG swap and CHR inversion bits do nothing
+        harness.run(&[Access::CpuWrite(0x8000, 0b1100_0000)]);
+        harness.assert_prg_banks(&[4, 5, 14, 15]);
+        harness.assert_chr_banks(&[2, 3, 6, 7, 8, 9, 10, 11]);
+        // Nor do writes past $9FFF
+        harness.run(&[Access::CpuWrite(0xA000, 0), Access::CpuWrite(0xC001, 0)]);
+        harness.assert_mirroring(None);
+        harness.assert_irq(false);
+    }
+
+    #[test]
+    fn test_split_chr() {
+        // 128KB of CHR, 64 1KB banks in each half
+        let mut harness =
+            MapperHarness::new(Namco108::with_split_chr(0x8000, 0x20000), 0x8000, 0x20000);
+        harness.run(&load_banks(&[0, 2, 1, 2, 3, 0x44, 0, 0]));
+        harness.assert_chr_banks(&[0, 1, 2, 3, 65, 66, 67, 68]);
+    }
+}
@@ -0,0 +1,52 @@
+use super::Mapper;
+
+// NROM (mapper 0), no bank switching, 16KB PRG ROM is mirrored into $C000-$FFFF
+// Ref: https://www.nesdev.org/wiki/NROM
+#[derive(Debug, Clone, Copy)]
+pub struct Nrom {
+    prg_len: usize,
+}
+
+impl Nrom {
+    pub fn new(prg_len: usize, _chr_len: usize) -> Self {
+        Nrom { prg_len }
+    }
+}
+
+impl Mapper for Nrom {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF if self.prg_len > 0 => Some((addr - 0x8000) as usize % self.prg_len),
+            _ => None,
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize & 0x1FFF
+    }
+
+    fn write_register(&mut self, _addr: u16, _data: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::*;
+
+    #[test]
+    fn test_nrom_128_mirrors_prg() {
+        let mut harness = MapperHarness::new(Nrom::new(0x4000, 0x2000), 0x4000, 0x2000);
+        harness.assert_prg_banks(&[0, 1, 0, 1]);
+        // Writes to ROM don't switch anything
+        harness.run(&[Access::CpuWrite(0x8000, 0xFF)]);
+        harness.assert_prg_banks(&[0, 1, 0, 1]);
+        harness.assert_chr_banks(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        harness.assert_unmapped(0x6000);
+    }
+
+    #[test]
+    fn test_nrom_256() {
+        let harness = MapperHarness::new(Nrom::new(0x8000, 0x2000), 0x8000, 0x2000);
+        harness.assert_prg_banks(&[0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,376 @@
+// Real-time clock on Bandai FCG RTC boards, and the clocks it can follow
+//
+// The chip is an MSM6242 style one with sixteen 4-bit registers, the time and date as BCD
+// digits and three control registers. It keeps time as an offset from its clock source: setting
+// the time changes the offset, reading it adds the offset to the clock's time. The offset is all
+// a battery save needs, with the host clock the time keeps running while the emulator is closed.
+// Games set the HOLD bit while they write the digits, which holds them as written until it's
+// cleared, so a date isn't wrapped around halfway through (e.g. the 31st before the month).
+use std::fmt::Debug;
+#[cfg(not(feature = "minimal"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::StateReader;
+use crate::frontend::CPU_CLOCK;
+
+/// Where a board's real-time clock gets the time from
+pub trait ClockSource: Debug + Send + Sync + CloneClock {
+    /// Seconds since the Unix epoch
+    fn now(&self) -> i64;
+
+    /// Called with the CPU cycles run, for clocks that follow the emulation
+    fn tick_cpu_cycles(&mut self, _cycles: usize) {}
+
+    /// What a savestate needs to put the clock back, empty for clocks outside the emulation
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Loads data written by save_state, possibly by another kind of clock
+    fn load_state(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Lets boards holding a Box<dyn ClockSource> derive Clone, like CloneMapper
+pub trait CloneClock {
+    fn clone_clock(&self) -> Box<dyn ClockSource>;
+}
+
+impl<C: ClockSource + Clone + 'static> CloneClock for C {
+    fn clone_clock(&self) -> Box<dyn ClockSource> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ClockSource> {
+    fn clone(&self) -> Self {
+        self.clone_clock()
+    }
+}
+
+/// The host's system time, so the game sees the real date. Runs don't replay the same with it.
+#[cfg(not(feature = "minimal"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostClock;
+
+#[cfg(not(feature = "minimal"))]
+impl ClockSource for HostClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64)
+    }
+}
+
+/// 2000-01-01 00:00:00, where boards' virtual clocks start
+pub const VIRTUAL_CLOCK_START: i64 = 946_684_800;
+
+/// Counts emulated CPU cycles from a start time, a second per NTSC CPU clock. Time only passes
+/// while the emulation runs, so runs with the same input read the same times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualClock {
+    seconds: i64,
+    // Cycles into the current second
+    cycles: u64,
+}
+
+impl VirtualClock {
+    pub fn new(start: i64) -> Self {
+        VirtualClock {
+            seconds: start,
+            cycles: 0,
+        }
+    }
+}
+
+impl ClockSource for VirtualClock {
+    fn now(&self) -> i64 {
+        self.seconds
+    }
+
+    fn tick_cpu_cycles(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+        self.seconds += (self.cycles / CPU_CLOCK) as i64;
+        self.cycles %= CPU_CLOCK;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.seconds.to_le_bytes().to_vec();
+        state.extend_from_slice(&self.cycles.to_le_bytes());
+        state
+    }
+
+    // A state saved with the host clock has nothing to load, the virtual time keeps going
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut reader = StateReader(data);
+        self.seconds = i64::from_le_bytes(reader.array()?);
+        self.cycles = u64::from_le_bytes(reader.array()?);
+        reader.finish()
+    }
+}
+
+// The date and time in the registers' units, the year in full
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl DateTime {
+    // Ref: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    fn from_timestamp(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(86400) + 719_468;
+        let seconds = timestamp.rem_euclid(86400);
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Months counted from March, so the leap day comes last
+        let march_month = (5 * day_of_year + 2) / 153;
+        let month = if march_month < 10 {
+            march_month + 3
+        } else {
+            march_month - 9
+        };
+        DateTime {
+            year: year_of_era + era * 400 + (month <= 2) as i64,
+            month,
+            day: day_of_year - (153 * march_month + 2) / 5 + 1,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+
+    // Out of range days, hours, minutes and seconds carry into the next unit, like the chip
+    // counting past them
+    fn timestamp(&self) -> i64 {
+        let month = self.month.clamp(1, 12);
+        let year = self.year - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + self.day.max(1) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * 86400 + self.hour * 3600 + self.minute * 60 + self.second
+    }
+
+    // Registers 0-11 are the ones and tens digits of these, in order
+    fn field(&mut self, index: u8) -> &mut i64 {
+        match index {
+            0 => &mut self.second,
+            1 => &mut self.minute,
+            2 => &mut self.hour,
+            3 => &mut self.day,
+            4 => &mut self.month,
+            _ => &mut self.year,
+        }
+    }
+}
+
+// Registers past the date: the day of the week and the control registers CD, CE and CF
+const WEEKDAY_REGISTER: u8 = 0xC;
+const CONTROL_REGISTERS: u8 = 0xD;
+// In CD
+const HOLD_BIT: u8 = 0b0001;
+
+#[derive(Debug, Clone)]
+pub(super) struct Rtc {
+    clock: Box<dyn ClockSource>,
+    // Seconds the chip's time is ahead of the clock's
+    offset: i64,
+    // Register read and written through the board
+    register: u8,
+    control: [u8; 3],
+    // Digits as read and written while HOLD is set
+    held: Option<DateTime>,
+}
+
+impl Rtc {
+    pub(super) fn new() -> Self {
+        Rtc {
+            clock: Box::new(VirtualClock::new(VIRTUAL_CLOCK_START)),
+            offset: 0,
+            register: 0,
+            control: [0; 3],
+            held: None,
+        }
+    }
+
+    pub(super) fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        self.clock = clock;
+    }
+
+    fn date_time(&self) -> DateTime {
+        self.held
+            .unwrap_or_else(|| DateTime::from_timestamp(self.clock.now() + self.offset))
+    }
+
+    fn set_date_time(&mut self, date_time: DateTime) {
+        self.offset = date_time.timestamp() - self.clock.now();
+    }
+
+    pub(super) fn select(&mut self, register: u8) {
+        self.register = register & 0x0F;
+    }
+
+    pub(super) fn read(&self) -> u8 {
+        let mut date_time = self.date_time();
+        match self.register {
+            // Sunday is 0
+            WEEKDAY_REGISTER => (date_time.timestamp().div_euclid(86400) + 4).rem_euclid(7) as u8,
+            register if register >= CONTROL_REGISTERS => {
+                self.control[(register - CONTROL_REGISTERS) as usize]
+            }
+            register => {
+                let value = date_time.field(register / 2).rem_euclid(100);
+                match register % 2 {
+                    0 => (value % 10) as u8,
+                    _ => (value / 10) as u8,
+                }
+            }
+        }
+    }
+
+    pub(super) fn write(&mut self, data: u8) {
+        let digit = (data & 0x0F) as i64;
+        let mut date_time = self.date_time();
+        match self.register {
+            // Follows from the date
+            WEEKDAY_REGISTER => {}
+            CONTROL_REGISTERS => {
+                self.control[0] = data & 0x0F;
+                match (data & HOLD_BIT != 0, self.held) {
+                    (true, None) => self.held = Some(date_time),
+                    (false, Some(held)) => {
+                        self.held = None;
+                        self.set_date_time(held);
+                    }
+                    _ => {}
+                }
+            }
+            register if register > CONTROL_REGISTERS => {
+                self.control[(register - CONTROL_REGISTERS) as usize] = data & 0x0F;
+            }
+            register => {
+                let field = date_time.field(register / 2);
+                // The year keeps its century
+                let century = *field - field.rem_euclid(100);
+                let value = field.rem_euclid(100);
+                let value = match register % 2 {
+                    0 => value / 10 * 10 + digit,
+                    _ => digit * 10 + value % 10,
+                };
+                *field = century + value;
+                match self.held {
+                    Some(_) => self.held = Some(date_time),
+                    None => self.set_date_time(date_time),
+                }
+            }
+        }
+    }
+
+    pub(super) fn tick_cpu_cycles(&mut self, cycles: usize) {
+        self.clock.tick_cpu_cycles(cycles);
+    }
+
+    // Kept in battery saves after the EEPROM
+    pub(super) fn save_data(&self) -> [u8; 8] {
+        self.offset.to_le_bytes()
+    }
+
+    pub(super) fn load_save_data(&mut self, data: &[u8]) {
+        if let Ok(offset) = data.try_into() {
+            self.offset = i64::from_le_bytes(offset);
+        }
+    }
+
+    pub(super) fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.offset.to_le_bytes());
+        state.push(self.register);
+        state.extend_from_slice(&self.control);
+        // Written either way, so the state's length doesn't change
+        state.push(self.held.is_some() as u8);
+        let mut held = self.held.unwrap_or_else(|| DateTime::from_timestamp(0));
+        for index in 0..6 {
+            state.extend_from_slice(&held.field(index).to_le_bytes());
+        }
+        let clock = self.clock.save_state();
+        state.push(clock.len() as u8);
+        state.extend(clock);
+    }
+
+    pub(super) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.offset = i64::from_le_bytes(reader.array()?);
+        self.register = reader.u8()?;
+        self.control = reader.array()?;
+        let is_held = reader.bool()?;
+        let mut held = DateTime::from_timestamp(0);
+        for index in 0..6 {
+            *held.field(index) = i64::from_le_bytes(reader.array()?);
+        }
+        self.held = is_held.then_some(held);
+        let length = reader.u8()? as usize;
+        self.clock.load_state(reader.bytes(length)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_time() {
+        // 2024-02-29 13:45:30, a leap day
+        let date_time = DateTime::from_timestamp(1_709_214_330);
+        assert_eq!(
+            DateTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 13,
+                minute: 45,
+                second: 30,
+            },
+            date_time
+        );
+        assert_eq!(1_709_214_330, date_time.timestamp());
+        assert_eq!(2000, DateTime::from_timestamp(VIRTUAL_CLOCK_START).year);
+        assert_eq!(1969, DateTime::from_timestamp(-1).year);
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = VirtualClock::new(100);
+        clock.tick_cpu_cycles(CPU_CLOCK as usize - 1);
+        assert_eq!(100, clock.now());
+        clock.tick_cpu_cycles(1);
+        assert_eq!(101, clock.now());
+        clock.tick_cpu_cycles(CPU_CLOCK as usize * 3 + 5);
+        assert_eq!(104, clock.now());
+
+        let mut loaded = VirtualClock::new(0);
+        loaded.load_state(&clock.save_state()).unwrap();
+        assert_eq!(clock, loaded);
+        // States from the host clock leave it running
+        loaded.load_state(&[]).unwrap();
+        assert_eq!(clock, loaded);
+        assert!(loaded.load_state(&[0; 3]).is_err());
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    #[test]
+    fn test_host_clock() {
+        // Some time after this was written
+        assert!(HostClock.now() > 1_700_000_000);
+    }
+}
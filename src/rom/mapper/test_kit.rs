@@ -0,0 +1,184 @@
+// Harness for mapper unit tests, feeds a mapper scripted bus accesses and checks the
+// resulting bank layout, mirroring and IRQ line without running a game
+use super::{Mapper, CHR_BANK_SIZE, PRG_BANK_SIZE};
+use crate::rom::Mirroring;
+
+const PRG_WINDOWS: [u16; 4] = [0x8000, 0xA000, 0xC000, 0xE000];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Access {
+    CpuWrite(u16, u8),
+    CpuCycles(usize),
+    // End of rendered scanlines
+    Scanlines(usize),
+}
+
+pub struct MapperHarness<M: Mapper> {
+    pub mapper: M,
+    prg_len: usize,
+    chr_len: usize,
+}
+
+impl<M: Mapper> MapperHarness<M> {
+    pub fn new(mapper: M, prg_len: usize, chr_len: usize) -> Self {
+        MapperHarness {
+            mapper,
+            prg_len,
+            chr_len,
+        }
+    }
+
+    pub fn run(&mut self, script: &[Access]) {
+        for access in script {
+            match *access {
+                Access::CpuWrite(addr, data) => self.mapper.write_register(addr, data),
+                Access::CpuCycles(cycles) => self.mapper.tick_cpu_cycles(cycles),
+                Access::Scanlines(count) => {
+                    for _ in 0..count {
+                        self.mapper.end_scanline();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a board register, None if the address reads PRG instead
+    pub fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.mapper.read_register(addr)
+    }
+
+    /// 8KB PRG bank mapped at `addr`, panics if the mapper points outside of PRG ROM
+    pub fn prg_bank_at(&self, addr: u16) -> Option<usize> {
+        let offset = self.mapper.map_prg(addr)?;
+        assert!(
+            offset < self.prg_len,
+            "${:04X} maps to {:x}, past the end of PRG ROM",
+            addr,
+            offset
+        );
+        Some(offset / PRG_BANK_SIZE)
+    }
+
+    /// 1KB CHR bank mapped at `addr`
+    pub fn chr_bank_at(&self, addr: u16) -> usize {
+        let offset = self.mapper.map_chr(addr);
+        assert!(
+            offset < self.chr_len,
+            "PPU ${:04X} maps to {:x}, past the end of CHR",
+            addr,
+            offset
+        );
+        offset / CHR_BANK_SIZE
+    }
+
+    /// Checks the 8KB banks at $8000, $A000, $C000 and $E000, including the last byte of each
+    pub fn assert_prg_banks(&self, expected: &[usize; 4]) {
+        for (window, bank) in PRG_WINDOWS.iter().zip(expected) {
+            for addr in [*window, window + 0x1FFF] {
+                assert_eq!(Some(*bank), self.prg_bank_at(addr), "PRG at ${:04X}", addr);
+            }
+        }
+    }
+
+    /// Checks the eight 1KB banks at PPU $0000-$1FFF
+    pub fn assert_chr_banks(&self, expected: &[usize; 8]) {
+        for (index, bank) in expected.iter().enumerate() {
+            let window = (index * CHR_BANK_SIZE) as u16;
+            for addr in [window, window + 0x3FF] {
+                assert_eq!(*bank, self.chr_bank_at(addr), "CHR at ${:04X}", addr);
+            }
+        }
+    }
+
+    pub fn assert_unmapped(&self, addr: u16) {
+        assert_eq!(None, self.mapper.map_prg(addr), "${:04X} is mapped", addr);
+    }
+
+    pub fn assert_mirroring(&self, expected: Option<Mirroring>) {
+        assert_eq!(expected, self.mapper.mirroring());
+    }
+
+    pub fn assert_irq(&self, expected: bool) {
+        assert_eq!(expected, self.mapper.is_irq_pending(), "IRQ line");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Switches the $8000 bank and mirroring on writes, IRQ after 2 scanlines or 100 cycles
+    #[derive(Debug, Default, Clone)]
+    struct ToyMapper {
+        bank: usize,
+        vertical: bool,
+        scanlines: usize,
+        cycles: usize,
+    }
+
+    impl Mapper for ToyMapper {
+        fn map_prg(&self, addr: u16) -> Option<usize> {
+            match addr {
+                0x8000..=0x9FFF => Some(self.bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize),
+                0xA000..=0xFFFF => Some((addr - 0x8000) as usize),
+                _ => None,
+            }
+        }
+
+        fn map_chr(&self, addr: u16) -> usize {
+            addr as usize
+        }
+
+        fn write_register(&mut self, _addr: u16, data: u8) {
+            self.bank = (data & 0b11) as usize;
+            self.vertical = data & 0x80 != 0;
+        }
+
+        fn mirroring(&self) -> Option<Mirroring> {
+            Some(match self.vertical {
+                true => Mirroring::Vertical,
+                false => Mirroring::Horizontal,
+            })
+        }
+
+        fn is_irq_pending(&self) -> bool {
+            self.scanlines >= 2 || self.cycles >= 100
+        }
+
+        fn end_scanline(&mut self) {
+            self.scanlines += 1;
+        }
+
+        fn tick_cpu_cycles(&mut self, cycles: usize) {
+            self.cycles += cycles;
+        }
+    }
+
+    #[test]
+    fn test_scripted_accesses() {
+        let mut harness = MapperHarness::new(ToyMapper::default(), 0x8000, 0x2000);
+        harness.assert_prg_banks(&[0, 1, 2, 3]);
+        harness.assert_mirroring(Some(Mirroring::Horizontal));
+        harness.run(&[Access::CpuWrite(0x8000, 0x82), Access::Scanlines(1)]);
+        harness.assert_prg_banks(&[2, 1, 2, 3]);
+        harness.assert_mirroring(Some(Mirroring::Vertical));
+        harness.assert_irq(false);
+        harness.run(&[Access::Scanlines(1)]);
+        harness.assert_irq(true);
+
+        let mut harness = MapperHarness::new(ToyMapper::default(), 0x8000, 0x2000);
+        harness.run(&[Access::CpuCycles(99)]);
+        harness.assert_irq(false);
+        harness.run(&[Access::CpuCycles(1)]);
+        harness.assert_irq(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "past the end of PRG ROM")]
+    fn test_bank_out_of_range() {
+        // Bank 3 doesn't exist in 24KB of PRG
+        let mut harness = MapperHarness::new(ToyMapper::default(), 0x6000, 0x2000);
+        harness.run(&[Access::CpuWrite(0x8000, 0x03)]);
+        harness.prg_bank_at(0x8000);
+    }
+}
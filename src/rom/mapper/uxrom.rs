@@ -0,0 +1,80 @@
+use super::{Mapper, StateReader};
+
+// UxROM (mapper 2), switchable 16KB PRG bank at $8000, the last bank fixed at $C000, 8KB of
+// CHR RAM
+// Ref: https://www.nesdev.org/wiki/UxROM
+//
+// Writes anywhere in $8000-$FFFF select the bank. The boards have bus conflicts (the value
+// written is ANDed with the ROM byte at the address), which aren't emulated since games write
+// through a table holding the same value to avoid them.
+const PRG_BANK_16K: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Uxrom {
+    prg_len: usize,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(prg_len: usize, _chr_len: usize) -> Self {
+        Uxrom {
+            prg_len,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn map_prg(&self, addr: u16) -> Option<usize> {
+        let banks = (self.prg_len / PRG_BANK_16K).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+            0xC000..=0xFFFF => banks - 1,
+            _ => return None,
+        };
+        Some(bank * PRG_BANK_16K + (addr & 0x3FFF) as usize)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize & 0x1FFF
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = data;
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.prg_bank]
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader(data);
+        self.prg_bank = reader.u8()?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_kit::{Access, MapperHarness};
+    use super::*;
+
+    #[test]
+    fn test_switches_bank_at_8000() {
+        // 256KB, sixteen 16KB banks like Mega Man and Castlevania
+        let mut harness = MapperHarness::new(Uxrom::new(0x40000, 0), 0x40000, 0x2000);
+        harness.assert_prg_banks(&[0, 1, 30, 31]);
+        harness.run(&[Access::CpuWrite(0xFFF5, 5)]);
+        harness.assert_prg_banks(&[10, 11, 30, 31]);
+        // Bank numbers past the end wrap
+        harness.run(&[Access::CpuWrite(0x8000, 17)]);
+        harness.assert_prg_banks(&[2, 3, 30, 31]);
+        // Writes below $8000 don't reach the register
+        harness.run(&[Access::CpuWrite(0x6000, 3)]);
+        harness.assert_prg_banks(&[2, 3, 30, 31]);
+        harness.assert_chr_banks(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        harness.assert_unmapped(0x6000);
+    }
+}
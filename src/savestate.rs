@@ -0,0 +1,170 @@
+// Container for savestates written to disk, binding them to the game and mapper they came from
+//
+//     "NESS"  format  mapper  state version (u16)  ROM CRC (u32)
+//     mapper data length (u32)  mapper data  console state
+//
+// Multi-byte fields are little endian. Loading checks the ROM CRC and mapper number, and runs
+// the mapper's migration when the state was saved by an older version of the mapper.
+use std::path::{Path, PathBuf};
+
+use crate::rom::mapper;
+use crate::rom::ROM;
+
+const MAGIC: [u8; 4] = *b"NESS";
+// Bumped when the layout of the container changes
+const FORMAT: u8 = 1;
+pub const HEADER_SIZE: usize = 12;
+
+/// Identifies the game and mapper state layout a savestate was taken with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavestateHeader {
+    pub rom_crc: u32,
+    pub mapper: u8,
+    pub mapper_state_version: u16,
+}
+
+impl SavestateHeader {
+    pub fn for_rom(rom: &ROM) -> Self {
+        SavestateHeader {
+            rom_crc: rom.crc32(),
+            mapper: rom.mapper,
+            mapper_state_version: mapper::state_version(rom.mapper),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = FORMAT;
+        bytes[5] = self.mapper;
+        bytes[6..8].copy_from_slice(&self.mapper_state_version.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.rom_crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err("Not a savestate".to_string());
+        }
+        if bytes[4] != FORMAT {
+            return Err(format!("Unsupported savestate format {}", bytes[4]));
+        }
+        Ok(SavestateHeader {
+            rom_crc: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            mapper: bytes[5],
+            mapper_state_version: u16::from_le_bytes([bytes[6], bytes[7]]),
+        })
+    }
+
+    /// Checks the state can be loaded with `rom`, migrating `mapper_data` if it was saved by
+    /// an older version of the mapper
+    pub fn validate(&self, rom: &ROM, mapper_data: &mut Vec<u8>) -> Result<(), String> {
+        let rom_crc = rom.crc32();
+        if self.rom_crc != rom_crc {
+            return Err(format!(
+                "Savestate is for a different game (ROM CRC {:08X}, loaded ROM is {:08X})",
+                self.rom_crc, rom_crc
+            ));
+        }
+        if self.mapper != rom.mapper {
+            return Err(format!(
+                "Savestate is for mapper {}, loaded ROM uses mapper {}",
+                self.mapper, rom.mapper
+            ));
+        }
+        mapper::migrate_state(self.mapper, self.mapper_state_version, mapper_data)
+    }
+}
+
+/// Where the frontend's quick savestate for the ROM at `rom_path` goes, `game.nes` -> `game.state`
+pub fn savestate_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("state")
+}
+
+/// Wraps the mapper data and console state of a savestate taken with `rom`
+pub fn encode(rom: &ROM, mapper_data: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut bytes = SavestateHeader::for_rom(rom).to_bytes().to_vec();
+    bytes.extend_from_slice(&(mapper_data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(mapper_data);
+    bytes.extend_from_slice(state);
+    bytes
+}
+
+/// Unwraps a savestate for `rom`, returning the (migrated) mapper data and the console state
+pub fn decode<'a>(bytes: &'a [u8], rom: &ROM) -> Result<(Vec<u8>, &'a [u8]), String> {
+    let header = SavestateHeader::from_bytes(bytes)?;
+    let truncated = || "Savestate is truncated".to_string();
+    let length = bytes
+        .get(HEADER_SIZE..HEADER_SIZE + 4)
+        .ok_or_else(truncated)?;
+    let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    let data_start = HEADER_SIZE + 4;
+    let mut mapper_data = bytes
+        .get(data_start..data_start + length)
+        .ok_or_else(truncated)?
+        .to_vec();
+    header.validate(rom, &mut mapper_data)?;
+    Ok((mapper_data, &bytes[data_start + length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_rom(mapper: u8) -> ROM {
+        let mut rom = ROM::new();
+        rom.mapper = mapper;
+        rom.prg_rom = vec![0xEA; 0x4000];
+        rom
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rom = create_rom(16);
+        let bytes = encode(&rom, &[1, 2], &[3, 4, 5]);
+        assert_eq!(b"NESS", &bytes[..4]);
+        let (mapper_data, state) = decode(&bytes, &rom).unwrap();
+        assert_eq!(vec![1, 2], mapper_data);
+        assert_eq!(&[3, 4, 5], state);
+        assert!(decode(&bytes[..14], &rom).is_err());
+    }
+
+    #[test]
+    fn test_refuses_other_game() {
+        let bytes = encode(&create_rom(0), &[], &[]);
+        let mut other = create_rom(0);
+        other.prg_rom[0] = 0x00;
+        let err = decode(&bytes, &other).unwrap_err();
+        assert!(err.contains("different game"));
+    }
+
+    #[test]
+    fn test_refuses_newer_mapper_version() {
+        let rom = create_rom(16);
+        let header = SavestateHeader {
+            mapper_state_version: mapper::state_version(16) + 1,
+            ..SavestateHeader::for_rom(&rom)
+        };
+        let err = header.validate(&rom, &mut Vec::new()).unwrap_err();
+        assert!(err.contains("newer version"));
+    }
+
+    #[test]
+    fn test_migrates_states_without_mapper_data() {
+        let rom = create_rom(4);
+        let header = SavestateHeader {
+            mapper_state_version: 1,
+            ..SavestateHeader::for_rom(&rom)
+        };
+        assert!(header.validate(&rom, &mut Vec::new()).is_ok());
+        assert!(header.validate(&rom, &mut vec![0]).is_err());
+    }
+
+    #[test]
+    fn test_savestate_path() {
+        assert_eq!(
+            PathBuf::from("roms/mario.state"),
+            savestate_path("roms/mario.nes")
+        );
+    }
+}
@@ -0,0 +1,134 @@
+// Event scheduler, a min-heap of the upcoming timing events on the master clock
+//
+// Times are in PPU dots, 3 per CPU cycle. Instead of every component checking its counters
+// after each instruction, each one schedules its next event and the NES runs whatever is due in
+// time order, so events landing in the same instruction (vblank, APU frame steps, DMC bytes)
+// happen in the right order. Events due at the same dot run in the order they were scheduled.
+//
+// Each event schedules the next one when it runs, and writes to the APU registers that move the
+// frame counter or DMC flag them to be scheduled again (ApuState::timing_changed). The counters
+// aren't checked in between, so code that edits them directly calls ActionNES::sync_timing.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+pub const DOTS_PER_CPU_CYCLE: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimingEvent {
+    // The PPU finishes a scanline, vblank starts and ends on these
+    ScanlineEnd,
+    // The APU frame counter clocks the length counters, raises its IRQ or wraps around
+    ApuFrameStep,
+    // The DMC finishes playing a sample byte
+    DmcByte,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scheduler<E: Ord> {
+    // (time, sequence, event), the sequence keeps events at the same time in FIFO order
+    queue: BinaryHeap<Reverse<(u64, u64, E)>>,
+    sequence: u64,
+}
+
+impl<E: Ord> Default for Scheduler<E> {
+    fn default() -> Self {
+        Scheduler {
+            queue: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+}
+
+impl<E: Ord + Copy> Scheduler<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule(&mut self, at: u64, event: E) {
+        self.queue.push(Reverse((at, self.sequence, event)));
+        self.sequence += 1;
+    }
+
+    /// Removes every pending occurrence of `event`
+    pub fn cancel(&mut self, event: E) {
+        self.queue
+            .retain(|Reverse((_, _, queued))| *queued != event);
+    }
+
+    /// When `event` is next due, None if it isn't scheduled
+    pub fn time_of(&self, event: E) -> Option<u64> {
+        self.queue
+            .iter()
+            .filter(|Reverse((_, _, queued))| *queued == event)
+            .map(|Reverse((at, _, _))| *at)
+            .min()
+    }
+
+    /// Moves `event` to `at`, or cancels it for None. Does nothing if it's already due then, so
+    /// callers can re-derive their event times as often as they like.
+    pub fn reschedule(&mut self, event: E, at: Option<u64>) {
+        if self.time_of(event) == at {
+            return;
+        }
+        self.cancel(event);
+        if let Some(at) = at {
+            self.schedule(at, event);
+        }
+    }
+
+    pub fn next_time(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse((at, _, _))| *at)
+    }
+
+    /// Takes the earliest event due at or before `now`
+    pub fn pop_due(&mut self, now: u64) -> Option<(u64, E)> {
+        if self.next_time()? > now {
+            return None;
+        }
+        self.queue.pop().map(|Reverse((at, _, event))| (at, event))
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_run_in_time_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(300, TimingEvent::DmcByte);
+        scheduler.schedule(100, TimingEvent::ScanlineEnd);
+        scheduler.schedule(300, TimingEvent::ApuFrameStep);
+        assert_eq!(Some(100), scheduler.next_time());
+
+        assert_eq!(
+            Some((100, TimingEvent::ScanlineEnd)),
+            scheduler.pop_due(300)
+        );
+        // Same time, scheduled first
+        assert_eq!(Some((300, TimingEvent::DmcByte)), scheduler.pop_due(300));
+        assert_eq!(None, scheduler.pop_due(299));
+        assert_eq!(1, scheduler.len());
+    }
+
+    #[test]
+    fn test_reschedule() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(50, TimingEvent::ScanlineEnd);
+        scheduler.reschedule(TimingEvent::ScanlineEnd, Some(40));
+        assert_eq!(Some(40), scheduler.time_of(TimingEvent::ScanlineEnd));
+        assert_eq!(1, scheduler.len());
+        scheduler.reschedule(TimingEvent::ApuFrameStep, None);
+        assert_eq!(1, scheduler.len());
+        scheduler.reschedule(TimingEvent::ScanlineEnd, None);
+        assert!(scheduler.is_empty());
+    }
+}
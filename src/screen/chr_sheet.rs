@@ -0,0 +1,211 @@
+// Pattern table sheets for ROM hackers, each 8KB of CHR drawn as a 128x256 image
+//
+// The $0000 pattern table is the top half and $1000 the bottom half, 16 tiles per row. Banks are
+// in ROM order, and the CHR the mapper has switched in at power on gets its own sheet.
+#[cfg(not(feature = "minimal"))]
+use std::path::Path;
+
+use crate::ppu::PpuState;
+use crate::rom::mapper::{create_mapper, Mapper};
+use crate::rom::ROM;
+
+#[cfg(not(feature = "minimal"))]
+use super::frame::write_rgb_png;
+use super::frame::TILE_SIZE;
+use super::palette;
+
+pub const SHEET_WIDTH: usize = 128;
+pub const SHEET_HEIGHT: usize = 256;
+
+const CHR_BANK_SIZE: usize = 0x2000;
+const TILES_PER_ROW: usize = SHEET_WIDTH / 8;
+
+/// Built-in palettes (system palette indexes) for ROMs that aren't running, 0 is greyscale
+pub const DEFAULT_PALETTES: [[u8; 4]; 4] = [
+    [0x0F, 0x00, 0x10, 0x30],
+    [0x0F, 0x16, 0x27, 0x18],
+    [0x0F, 0x09, 0x19, 0x29],
+    [0x0F, 0x01, 0x21, 0x31],
+];
+
+pub type SheetColors = [(u8, u8, u8); 4];
+
+pub fn default_palette(palette_idx: usize) -> Result<SheetColors, String> {
+    let palette = DEFAULT_PALETTES.get(palette_idx).ok_or_else(|| {
+        format!(
+            "Palette {} doesn't exist, pick 0-{}",
+            palette_idx,
+            DEFAULT_PALETTES.len() - 1
+        )
+    })?;
+    Ok(palette.map(|index| palette::get_color(index as usize)))
+}
+
+/// Colors of PPU palette `palette_idx`, 0-3 for the background and 4-7 for sprites
+pub fn ppu_palette(ppu: &PpuState, palette_idx: usize) -> SheetColors {
+    let start = 4 * (palette_idx % 8);
+    [0, 1, 2, 3].map(|entry| {
+        // Color 0 is always the backdrop
+        let index = if entry == 0 { 0 } else { start + entry };
+        palette::get_color(ppu.palette_table[index] as usize)
+    })
+}
+
+pub struct ChrSheet {
+    pub data: Vec<(u8, u8, u8)>,
+}
+
+impl ChrSheet {
+    /// Draws up to 8KB of CHR, missing tiles are left as color 0
+    pub fn render(chr: &[u8], colors: &SheetColors) -> Self {
+        let mut sheet = ChrSheet {
+            data: vec![colors[0]; SHEET_WIDTH * SHEET_HEIGHT],
+        };
+        for (tile_n, tile) in chr.chunks_exact(TILE_SIZE).take(512).enumerate() {
+            let (left, top) = (8 * (tile_n % TILES_PER_ROW), 8 * (tile_n / TILES_PER_ROW));
+            for row in 0..8 {
+                for column in 0..8 {
+                    let bit = 7 - column;
+                    let color_idx = ((tile[row] >> bit) & 1) | (((tile[row + 8] >> bit) & 1) << 1);
+                    sheet.data[SHEET_WIDTH * (top + row) + left + column] =
+                        colors[color_idx as usize];
+                }
+            }
+        }
+        sheet
+    }
+
+    /// Draws the 8KB the mapper has switched into $0000-$1FFF
+    pub fn render_mapped(rom: &ROM, mapper: &dyn Mapper, colors: &SheetColors) -> Self {
+        let chr: Vec<u8> = (0..CHR_BANK_SIZE as u16)
+            .map(|addr| rom.chr_rom.get(mapper.map_chr(addr)).copied().unwrap_or(0))
+            .collect();
+        ChrSheet::render(&chr, colors)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        self.data[SHEET_WIDTH * y + x]
+    }
+
+    /// Saves the sheet as an RGB PNG image
+    #[cfg(not(feature = "minimal"))]
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = self.data.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+        write_rgb_png(path, SHEET_WIDTH, SHEET_HEIGHT, &bytes, &[])
+    }
+}
+
+/// One sheet per 8KB bank of the ROM, plus the power on mapping for ROMs with several banks if
+/// the mapper is supported. Each sheet comes with the suffix for its file name. CHR RAM has the
+/// tiles the game wrote so far.
+pub fn render_chr_sheets(
+    rom: &ROM,
+    colors: &SheetColors,
+) -> Result<Vec<(String, ChrSheet)>, String> {
+    let is_blank = rom.chr_rom.iter().all(|&byte| byte == 0);
+    if rom.chr_rom.is_empty() || rom.chr_ram && is_blank {
+        return Err("ROM has CHR RAM, there are no tiles until the game writes them".to_string());
+    }
+    let banks: Vec<&[u8]> = rom.chr_rom.chunks(CHR_BANK_SIZE).collect();
+    if banks.len() == 1 {
+        return Ok(vec![(String::new(), ChrSheet::render(banks[0], colors))]);
+    }
+    let mut sheets: Vec<(String, ChrSheet)> = banks
+        .iter()
+        .enumerate()
+        .map(|(bank, chr)| (format!("_bank{}", bank), ChrSheet::render(chr, colors)))
+        .collect();
+    if let Ok(mapper) = create_mapper(
+        rom.mapper,
+        rom.submapper,
+        rom.prg_rom.len(),
+        rom.chr_rom.len(),
+    ) {
+        sheets.push((
+            "_mapped".to_string(),
+            ChrSheet::render_mapped(rom, mapper.as_ref(), colors),
+        ));
+    }
+    Ok(sheets)
+}
+
+/// Writes the sheets of render_chr_sheets next to `path`, e.g. tiles.png becomes
+/// tiles_bank0.png, tiles_bank1.png ... Returns the paths written.
+#[cfg(not(feature = "minimal"))]
+pub fn export_chr_sheets(
+    rom: &ROM,
+    colors: &SheetColors,
+    path: &str,
+) -> Result<Vec<String>, String> {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .ok_or("Sheet path needs a file name")?
+        .to_string_lossy();
+    let mut written = Vec::new();
+    for (suffix, sheet) in render_chr_sheets(rom, colors)? {
+        let sheet_path = path.with_file_name(format!("{}{}.png", stem, suffix));
+        let sheet_path = sheet_path.to_string_lossy().to_string();
+        sheet.save_png(&sheet_path)?;
+        written.push(sheet_path);
+    }
+    Ok(written)
+}
+
+/// export_chr_sheets with one of DEFAULT_PALETTES
+#[cfg(not(feature = "minimal"))]
+pub fn export_chr_sheet(rom: &ROM, palette_idx: usize, path: &str) -> Result<Vec<String>, String> {
+    export_chr_sheets(rom, &default_palette(palette_idx)?, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chr_sheet() {
+        let colors = default_palette(0).unwrap();
+        let mut chr = vec![0; CHR_BANK_SIZE];
+        // Tile 1 is color 1 on its top row, the first tile of $1000 is solid color 3
+        chr[16] = 0xFF;
+        chr[0x1000..0x1010].fill(0xFF);
+        let sheet = ChrSheet::render(&chr, &colors);
+        assert_eq!(colors[1], sheet.pixel(8, 0));
+        assert_eq!(colors[0], sheet.pixel(8, 1));
+        assert_eq!(colors[3], sheet.pixel(7, 128 + 7));
+        assert_eq!(colors[0], sheet.pixel(0, 0));
+        assert!(default_palette(DEFAULT_PALETTES.len()).is_err());
+    }
+
+    #[test]
+    fn test_sheets_per_bank() {
+        let colors = default_palette(1).unwrap();
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0; 0x8000];
+        rom.chr_rom = vec![0; 2 * CHR_BANK_SIZE];
+        let names: Vec<String> = render_chr_sheets(&rom, &colors)
+            .unwrap()
+            .into_iter()
+            .map(|(suffix, _)| suffix)
+            .collect();
+        assert_eq!(vec!["_bank0", "_bank1", "_mapped"], names);
+
+        rom.chr_rom.truncate(CHR_BANK_SIZE);
+        assert_eq!(1, render_chr_sheets(&rom, &colors).unwrap().len());
+        rom.chr_rom.clear();
+        assert!(render_chr_sheets(&rom, &colors).is_err());
+    }
+
+    #[test]
+    fn test_chr_ram_sheets() {
+        let colors = default_palette(0).unwrap();
+        let mut rom = ROM::new();
+        rom.chr_ram = true;
+        rom.chr_rom = vec![0; CHR_BANK_SIZE];
+        // Nothing written yet, like a ROM just loaded
+        assert!(render_chr_sheets(&rom, &colors).is_err());
+        rom.chr_rom[16] = 0xFF;
+        let sheets = render_chr_sheets(&rom, &colors).unwrap();
+        assert_eq!(colors[1], sheets[0].1.pixel(8, 0));
+    }
+}
@@ -0,0 +1,177 @@
+// Contents of the debugger window, redrawn every frame:
+//
+//     +----------------------+----------+
+//     |  nametables (half    | pattern  |
+//     |  size, see           | tables   |
+//     |  NametableMap)       |          |
+//     +--------+-------------+          |
+//     |  OAM   |  registers  |          |
+//     +--------+-------------+----------+
+//
+// OAM is the 64 sprites in an 8x8 grid, in their palettes. Registers are rows of bit lamps, most
+// significant bit on the left: PPUCTRL, PPUMASK, PPUSTATUS, A, X, Y, P, SP, PC high, PC low.
+use crate::cpu::CpuState;
+use crate::ppu::PpuState;
+use crate::rom::ROM;
+
+use super::chr_sheet::{ppu_palette, ChrSheet, SHEET_HEIGHT, SHEET_WIDTH};
+use super::frame::{tile_bytes, HEIGHT, WIDTH};
+use super::nametable_map::{NametableMap, MAP_WIDTH};
+
+const GAP: usize = 8;
+const OAM_TOP: usize = HEIGHT + GAP;
+const OAM_SIZE: usize = 64;
+const REGISTERS_LEFT: usize = OAM_SIZE + GAP;
+const LAMP_SIZE: usize = 6;
+const LAMP_PITCH: usize = LAMP_SIZE + 2;
+const REGISTER_ROWS: usize = 10;
+
+pub const VIEW_WIDTH: usize = WIDTH + SHEET_WIDTH;
+pub const VIEW_HEIGHT: usize = OAM_TOP + REGISTER_ROWS * LAMP_PITCH;
+
+const BACKGROUND_COLOR: (u8, u8, u8) = (0x20, 0x20, 0x20);
+const LAMP_ON_COLOR: (u8, u8, u8) = (0x30, 0xE0, 0x30);
+const LAMP_OFF_COLOR: (u8, u8, u8) = (0x50, 0x50, 0x50);
+
+pub struct DebugView {
+    pub data: Vec<(u8, u8, u8)>,
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugView {
+    pub fn new() -> Self {
+        DebugView {
+            data: vec![BACKGROUND_COLOR; VIEW_WIDTH * VIEW_HEIGHT],
+        }
+    }
+
+    pub fn render(&mut self, cpu: &CpuState, ppu: &PpuState, rom: &ROM) {
+        self.data.fill(BACKGROUND_COLOR);
+        let map = NametableMap::render(ppu, rom);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                self.set_pixel(x, y, map.data[MAP_WIDTH * 2 * y + 2 * x]);
+            }
+        }
+        let sheet = ChrSheet::render_mapped(rom, rom.board.as_ref(), &ppu_palette(ppu, 0));
+        for y in 0..SHEET_HEIGHT {
+            for x in 0..SHEET_WIDTH {
+                self.set_pixel(WIDTH + x, y, sheet.pixel(x, y));
+            }
+        }
+        self.draw_oam(ppu, rom);
+        let registers = [
+            ppu.ppuctrl.bits(),
+            ppu.ppumask.bits(),
+            ppu.ppustatus.bits(),
+            cpu.reg_a,
+            cpu.reg_x,
+            cpu.reg_y,
+            cpu.status.bits(),
+            cpu.stack_pointer,
+            (cpu.program_counter >> 8) as u8,
+            cpu.program_counter as u8,
+        ];
+        for (row, value) in registers.into_iter().enumerate() {
+            for bit in 0..8 {
+                let color = if value & (0x80 >> bit) != 0 {
+                    LAMP_ON_COLOR
+                } else {
+                    LAMP_OFF_COLOR
+                };
+                let (left, top) = (
+                    REGISTERS_LEFT + bit * LAMP_PITCH,
+                    OAM_TOP + row * LAMP_PITCH,
+                );
+                for y in top..top + LAMP_SIZE {
+                    for x in left..left + LAMP_SIZE {
+                        self.set_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // Every sprite's tile from the sprite pattern table, flipped like on screen, the top tile of
+    // 8x16 ones
+    fn draw_oam(&mut self, ppu: &PpuState, rom: &ROM) {
+        for (sprite, entry) in ppu.oam_data.chunks_exact(4).enumerate() {
+            let tile_addr = ppu.ppuctrl.get_sprite_tile_addr(entry[1], 0) as usize;
+            let tile = tile_bytes(rom, tile_addr);
+            let colors = ppu_palette(ppu, 4 + (entry[2] & 0b11) as usize);
+            let (flip_h, flip_v) = (entry[2] & 0x40 != 0, entry[2] & 0x80 != 0);
+            let (left, top) = (8 * (sprite % 8), OAM_TOP + 8 * (sprite / 8));
+            for row in 0..8 {
+                for column in 0..8 {
+                    let bit = if flip_h { column } else { 7 - column };
+                    let tile_row = if flip_v { 7 - row } else { row };
+                    let color_idx =
+                        ((tile[tile_row] >> bit) & 1) | (((tile[tile_row + 8] >> bit) & 1) << 1);
+                    self.set_pixel(left + column, top + row, colors[color_idx as usize]);
+                }
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        self.data[VIEW_WIDTH * y + x] = color;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        self.data[VIEW_WIDTH * y + x]
+    }
+
+    /// RGB24 bytes for a texture, VIEW_WIDTH * 3 bytes per row
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|&(r, g, b)| [r, g, b]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_debug_view() {
+        let mut rom = ROM::new();
+        rom.mirroring = Mirroring::Vertical;
+        // Tile 1 has color 1 in its left column only
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..24].fill(0x80);
+        let mut ppu = PpuState::new();
+        ppu.palette_table[17] = 0x30;
+        // Sprite 9 uses tile 1, flipped horizontally
+        ppu.oam_data[9 * 4 + 1] = 1;
+        ppu.oam_data[9 * 4 + 2] = 0x40;
+        let mut cpu = CpuState::new();
+        cpu.reg_a = 0x81;
+        let mut view = DebugView::new();
+        view.render(&cpu, &ppu, &rom);
+
+        let white = crate::screen::palette::get_color(0x30);
+        let backdrop = crate::screen::palette::get_color(0);
+        // Sprite 9 is the second in the second row, its column ends up on the right
+        assert_eq!(white, view.pixel(15, OAM_TOP + 8));
+        assert_eq!(backdrop, view.pixel(8, OAM_TOP + 8));
+        let lamp = |bit: usize, row: usize| {
+            view.pixel(
+                REGISTERS_LEFT + bit * LAMP_PITCH,
+                OAM_TOP + row * LAMP_PITCH,
+            )
+        };
+        assert_eq!(LAMP_ON_COLOR, lamp(0, 3));
+        assert_eq!(LAMP_OFF_COLOR, lamp(1, 3));
+        assert_eq!(LAMP_ON_COLOR, lamp(7, 3));
+        assert_eq!(
+            BACKGROUND_COLOR,
+            view.pixel(VIEW_WIDTH - 1, VIEW_HEIGHT - 1)
+        );
+        assert_eq!(3 * VIEW_WIDTH * VIEW_HEIGHT, view.to_bytes().len());
+    }
+}
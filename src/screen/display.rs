@@ -0,0 +1,214 @@
+use super::frame::{Frame, HEIGHT, WIDTH};
+
+// Rows hidden by most NTSC TVs at the top and bottom of the picture
+const OVERSCAN_ROWS: usize = 8;
+// PAL consoles draw a black border over the top row and the two leftmost and rightmost columns
+const PAL_BORDER_COLUMNS: usize = 2;
+const PAL_BORDER_ROWS: usize = 1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Overscan {
+    // Draw all 256x240 pixels
+    #[default]
+    Full,
+    // Show 256x224, covering the top and bottom 8 rows with bars
+    Crop,
+}
+
+// Rotation of the picture in the window, for vertical ("TATE") games on a rotated monitor
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise,
+    CounterClockwise,
+}
+
+impl Rotation {
+    pub fn degrees(&self) -> f64 {
+        match self {
+            Rotation::None => 0.0,
+            Rotation::Clockwise => 90.0,
+            Rotation::CounterClockwise => 270.0,
+        }
+    }
+
+    fn is_sideways(&self) -> bool {
+        *self != Rotation::None
+    }
+}
+
+// How the window waits for the next frame
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    // Presenting waits for vblank, frames run at the display's refresh rate
+    #[default]
+    Vsync,
+    // Like vsync, but a frame that misses vblank is shown right away with tearing instead of
+    // waiting a whole refresh
+    Adaptive,
+    // Presents right away and sleeps until the next frame is due, see frame_pacing
+    Sleep,
+}
+
+/// Where the picture lands in the window, before rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayConfig {
+    pub overscan: Overscan,
+    pub pal_border: bool,
+    // Color used for the overscan bars and the PAL border
+    pub border_color: (u8, u8, u8),
+    pub rotation: Rotation,
+    pub sync: SyncMode,
+    // Paces at the display's refresh rate when it's close to the NES's, with SyncMode::Sleep
+    pub smoothing: bool,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            overscan: Overscan::Full,
+            pal_border: false,
+            border_color: (0, 0, 0),
+            rotation: Rotation::None,
+            sync: SyncMode::Vsync,
+            smoothing: false,
+        }
+    }
+}
+
+impl DisplayConfig {
+    /// Masks the parts of the frame a TV wouldn't show, apply before displaying or saving a frame
+    pub fn apply(&self, frame: &mut Frame) {
+        if self.overscan == Overscan::Crop {
+            for y in (0..OVERSCAN_ROWS).chain(HEIGHT - OVERSCAN_ROWS..HEIGHT) {
+                self.fill_row(frame, y);
+            }
+        }
+        if self.pal_border {
+            for y in 0..PAL_BORDER_ROWS {
+                self.fill_row(frame, y);
+            }
+            for y in 0..HEIGHT {
+                for x in (0..PAL_BORDER_COLUMNS).chain(WIDTH - PAL_BORDER_COLUMNS..WIDTH) {
+                    frame.set_pixel(x, y, self.border_color);
+                }
+            }
+        }
+    }
+
+    /// Largest rect keeping the aspect ratio that fits in the window once rotated, centered
+    /// with letterbox bars. The rect is unrotated, rotating it around its center fills the window.
+    pub fn target_rect(&self, window_width: u32, window_height: u32) -> TargetRect {
+        let (frame_width, frame_height) = (WIDTH as f64, HEIGHT as f64);
+        let (shown_width, shown_height) = match self.rotation.is_sideways() {
+            true => (frame_height, frame_width),
+            false => (frame_width, frame_height),
+        };
+        let scale = (window_width as f64 / shown_width).min(window_height as f64 / shown_height);
+        let width = (frame_width * scale).round() as u32;
+        let height = (frame_height * scale).round() as u32;
+        TargetRect {
+            x: (window_width as i32 - width as i32) / 2,
+            y: (window_height as i32 - height as i32) / 2,
+            width,
+            height,
+        }
+    }
+
+    fn fill_row(&self, frame: &mut Frame, y: usize) {
+        for x in 0..WIDTH {
+            frame.set_pixel(x, y, self.border_color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+
+    fn white_frame() -> Frame {
+        let mut frame = Frame::new();
+        frame.data = [WHITE; WIDTH * HEIGHT];
+        frame
+    }
+
+    #[test]
+    fn test_full_overscan_unchanged() {
+        let mut frame = white_frame();
+        DisplayConfig::default().apply(&mut frame);
+        assert!(frame.data.iter().all(|pixel| *pixel == WHITE));
+    }
+
+    #[test]
+    fn test_crop_overscan() {
+        let mut frame = white_frame();
+        let config = DisplayConfig {
+            overscan: Overscan::Crop,
+            border_color: (1, 2, 3),
+            ..Default::default()
+        };
+        config.apply(&mut frame);
+        assert_eq!((1, 2, 3), frame.data[WIDTH * 7]);
+        assert_eq!(WHITE, frame.data[WIDTH * 8]);
+        assert_eq!(WHITE, frame.data[WIDTH * 231 + 255]);
+        assert_eq!((1, 2, 3), frame.data[WIDTH * 232]);
+    }
+
+    #[test]
+    fn test_target_rect_letterbox() {
+        let config = DisplayConfig::default();
+        let rect = config.target_rect(768, 720);
+        assert_eq!((0, 0, 768, 720), (rect.x, rect.y, rect.width, rect.height));
+        // Wide window, bars on the sides
+        let rect = config.target_rect(1000, 480);
+        assert_eq!(
+            (244, 0, 512, 480),
+            (rect.x, rect.y, rect.width, rect.height)
+        );
+        // Tall window, bars on the top and bottom
+        let rect = config.target_rect(512, 1000);
+        assert_eq!(
+            (0, 260, 512, 480),
+            (rect.x, rect.y, rect.width, rect.height)
+        );
+    }
+
+    #[test]
+    fn test_target_rect_rotated() {
+        let config = DisplayConfig {
+            rotation: Rotation::Clockwise,
+            ..Default::default()
+        };
+        // Rotated the picture is 240x256, so a 480x512 window is filled at 2x
+        let rect = config.target_rect(480, 512);
+        assert_eq!((512, 480), (rect.width, rect.height));
+        // Centered, so the rotated picture lands at (0, 0)
+        assert_eq!((-16, 16), (rect.x, rect.y));
+    }
+
+    #[test]
+    fn test_pal_border() {
+        let mut frame = white_frame();
+        let config = DisplayConfig {
+            pal_border: true,
+            ..Default::default()
+        };
+        config.apply(&mut frame);
+        assert_eq!((0, 0, 0), frame.data[5]);
+        assert_eq!((0, 0, 0), frame.data[WIDTH * 100 + 1]);
+        assert_eq!(WHITE, frame.data[WIDTH * 100 + 2]);
+        assert_eq!((0, 0, 0), frame.data[WIDTH * 100 + 254]);
+        assert_eq!(WHITE, frame.data[WIDTH * 239 + 100]);
+    }
+}
@@ -1,16 +1,133 @@
+#[cfg(not(feature = "minimal"))]
+use std::fs::File;
+#[cfg(not(feature = "minimal"))]
+use std::io::{BufWriter, Write};
 use std::mem::transmute;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // use crate::ppu::PPU;
 
-use crate::{ppu::PpuState, rom::ROM};
+use crate::{
+    ppu::{Picture, PpuBus, PpuState, SPRITES_PER_SCANLINE},
+    rom::{Mirroring, ROM},
+};
 
-use super::palette;
+use super::palette::MaskedPalette;
 
 pub const WIDTH: usize = 256;
 pub const HEIGHT: usize = 240;
 
+pub(super) const TILE_SIZE: usize = 16;
+static CHR_OUT_OF_RANGE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the 16 bytes of the tile at a pattern table address, through the board's CHR banks,
+/// or a transparent tile if the ROM doesn't have it
+pub(super) fn tile_bytes(rom: &ROM, addr: usize) -> [u8; TILE_SIZE] {
+    let mut tile = [0; TILE_SIZE];
+    // Tiles never straddle banks, they're 16 byte aligned
+    let start = rom.map_chr(addr as u16);
+    let chr_rom = &rom.chr_rom;
+    match chr_rom.get(start..start + TILE_SIZE) {
+        Some(bytes) => tile.copy_from_slice(bytes),
+        None => {
+            if !CHR_OUT_OF_RANGE_WARNED.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Tile at {:x} is outside of CHR ROM (size {:x}), rendering as transparent",
+                    start,
+                    chr_rom.len()
+                );
+            }
+        }
+    }
+    tile
+}
+
+/// Writes packed RGB bytes to a PNG file, with (keyword, text) pairs in tEXt chunks
+#[cfg(not(feature = "minimal"))]
+pub(super) fn write_rgb_png(
+    path: &str,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+    text: &[(&str, String)],
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    encode_rgb_png(BufWriter::new(file), width, height, rgb, text)
+}
+
+#[cfg(not(feature = "minimal"))]
+pub(super) fn encode_rgb_png(
+    output: impl Write,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+    text: &[(&str, String)],
+) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(output, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, value) in text {
+        encoder
+            .add_text_chunk(keyword.to_string(), value.clone())
+            .map_err(|e| e.to_string())?;
+    }
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(rgb).map_err(|e| e.to_string())
+}
+
+// Debug colors for colorize_priority, indexed by palette
+const BACKDROP_TINT: (u8, u8, u8) = (0x40, 0x40, 0x40);
+const BACKGROUND_TINTS: [(u8, u8, u8); 4] = [
+    (0x20, 0x40, 0xE0),
+    (0x20, 0xB0, 0xE0),
+    (0x20, 0xC0, 0x60),
+    (0x90, 0xD0, 0x20),
+];
+const SPRITE_TINTS: [(u8, u8, u8); 4] = [
+    (0xE0, 0x20, 0x20),
+    (0xF0, 0x80, 0x20),
+    (0xE0, 0x20, 0xA0),
+    (0xF0, 0xE0, 0x20),
+];
+const BEHIND_BACKGROUND_TINT: (u8, u8, u8) = (0xA0, 0x50, 0xF0);
+
+/// Where a rendered pixel came from, with the palette (0 to 3) it used
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PixelSource {
+    // Background color 0, nothing opaque on this pixel
+    #[default]
+    Backdrop,
+    Background(u8),
+    Sprite(u8),
+    // A sprite with the priority bit set, showing through transparent background
+    SpriteBehindBackground(u8),
+}
+
+impl PixelSource {
+    fn tint(&self) -> (u8, u8, u8) {
+        match *self {
+            PixelSource::Backdrop => BACKDROP_TINT,
+            PixelSource::Background(palette) => BACKGROUND_TINTS[palette as usize & 0b11],
+            PixelSource::Sprite(palette) => SPRITE_TINTS[palette as usize & 0b11],
+            PixelSource::SpriteBehindBackground(_) => BEHIND_BACKGROUND_TINT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SpritePixel {
+    color: (u8, u8, u8),
+    palette: u8,
+    behind_background: bool,
+}
+
 pub struct Frame {
     pub data: [(u8, u8, u8); WIDTH * HEIGHT],
+    // true where the background pixel is not color 0, used for sprite priority
+    pub background_opaque: [bool; WIDTH * HEIGHT],
+    // Filled in by render, used by the priority debug colors. On the heap since frames are
+    // usually kept on the stack.
+    pub source: Vec<PixelSource>,
 }
 
 impl Default for Frame {
@@ -23,28 +140,98 @@ impl Frame {
     pub fn new() -> Self {
         Frame {
             data: [(0, 0, 0); WIDTH * HEIGHT],
+            background_opaque: [false; WIDTH * HEIGHT],
+            source: vec![PixelSource::Backdrop; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn source(&self, x: usize, y: usize) -> PixelSource {
+        self.source[WIDTH * y + x]
+    }
+
+    fn set_source(&mut self, x: usize, y: usize, source: PixelSource) {
+        if x < WIDTH && y < HEIGHT {
+            self.source[WIDTH * y + x] = source;
+        }
+    }
+
+    pub fn is_background_opaque(&self, x: usize, y: usize) -> bool {
+        x < WIDTH && y < HEIGHT && self.background_opaque[WIDTH * y + x]
+    }
+
+    fn set_background_opaque(&mut self, x: usize, y: usize, opaque: bool) {
+        if x < WIDTH && y < HEIGHT {
+            self.background_opaque[WIDTH * y + x] = opaque;
+        }
+    }
+
+    /// Debug overlay, paints every transparent (color 0) background pixel with `color`
+    pub fn highlight_background_transparency(&mut self, color: (u8, u8, u8)) {
+        for (pixel, opaque) in self.data.iter_mut().zip(self.background_opaque.iter()) {
+            if !opaque {
+                *pixel = color;
+            }
+        }
+    }
+
+    /// Debug overlay, tints every pixel by its PixelSource so priority and palette mistakes
+    /// stand out. The brightness of the original pixel is kept so the picture is recognizable.
+    pub fn colorize_priority(&mut self) {
+        for (pixel, source) in self.data.iter_mut().zip(self.source.iter()) {
+            let luma = (pixel.0 as u32 + pixel.1 as u32 + pixel.2 as u32) / 3;
+            // Between half and full brightness of the tint
+            let scale = |channel: u8| (channel as u32 * (0x100 + luma) / 0x200) as u8;
+            let tint = source.tint();
+            *pixel = (scale(tint.0), scale(tint.1), scale(tint.2));
         }
     }
 
     pub fn set_pixel(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
-        let index = WIDTH * y + x;
-        if index < WIDTH * HEIGHT {
+        // Pixels off the right or bottom edge are clipped instead of wrapping onto the next line
+        if x < WIDTH && y < HEIGHT {
             self.data[WIDTH * y + x] = color;
         }
     }
 
+    /// Colors in the pixels the PPU drew, with the palettes as they are now and the greyscale and
+    /// emphasis bits each line was drawn with
+    pub fn render_picture(&mut self, picture: &Picture, ppu: &PpuState) {
+        let mut colors = MaskedPalette::new(picture.line_mask(0));
+        for y in 0..HEIGHT {
+            if !colors.is_for(picture.line_mask(y)) {
+                colors = MaskedPalette::new(picture.line_mask(y));
+            }
+            for x in 0..WIDTH {
+                let pixel = picture.pixel(x, y);
+                let color = ppu.palette_table[pixel.palette_addr()] as usize;
+                self.set_pixel(x, y, colors.get_color(color));
+                self.set_background_opaque(x, y, pixel.is_background_opaque());
+                let source = match (pixel.is_sprite(), pixel.is_behind_background()) {
+                    (true, true) => PixelSource::SpriteBehindBackground(pixel.palette()),
+                    (true, false) => PixelSource::Sprite(pixel.palette()),
+                    _ if pixel.is_background_opaque() => PixelSource::Background(pixel.palette()),
+                    _ => PixelSource::Backdrop,
+                };
+                self.set_source(x, y, source);
+            }
+        }
+    }
+
+    /// Renders the first nametable and OAM as they are now, without scrolling or anything
+    /// changed mid-frame. The NES renders frames from the picture its PPU drew instead.
     // TODO: first few rendered lines are usually invisible, maybe implement that?
     pub fn render(&mut self, ppu: &PpuState, rom: &ROM) {
+        let colors = MaskedPalette::new(ppu.ppumask);
         // Renders the background
         let bank = ppu.ppuctrl.get_background_pattern_addr() as usize;
+        let mirroring = rom.current_mirroring();
         for i in 0..0x03C0 {
-            let tile_n = ppu.ram[i] as usize;
-            let tile_range = (bank + 16 * tile_n)..(bank + 16 * (tile_n + 1));
-            let tile = &rom.chr_rom[tile_range];
+            let tile_n = ppu.ram[PpuBus::mirror_vram_addr(mirroring, 0x2000 + i) as usize] as usize;
+            let tile = tile_bytes(rom, bank + TILE_SIZE * tile_n);
 
-            let (tile_x, tile_y) = (i % 32, i / 32);
+            let (tile_x, tile_y) = (i as usize % 32, i as usize / 32);
 
-            let palette = Frame::background_palette(ppu, tile_x, tile_y);
+            let (palette_idx, palette) = Frame::background_palette(ppu, mirroring, tile_x, tile_y);
 
             // Render tile
             let (upper, lower) = tile.split_at(8);
@@ -58,22 +245,68 @@ impl Frame {
                     lo >>= 1;
 
                     let rgb = match (lo_bit, hi_bit) {
-                        (false, false) => palette::SYSTEM_PALLETE[palette[0]],
-                        (false, true) => palette::SYSTEM_PALLETE[palette[1]],
-                        (true, false) => palette::SYSTEM_PALLETE[palette[2]],
-                        (true, true) => palette::SYSTEM_PALLETE[palette[3]],
+                        (false, false) => colors.get_color(palette[0]),
+                        (false, true) => colors.get_color(palette[1]),
+                        (true, false) => colors.get_color(palette[2]),
+                        (true, true) => colors.get_color(palette[3]),
                     };
                     self.set_pixel(8 * tile_x + x, 8 * tile_y + y, rgb);
+                    let opaque = lo_bit || hi_bit;
+                    self.set_background_opaque(8 * tile_x + x, 8 * tile_y + y, opaque);
+                    let source = if opaque {
+                        PixelSource::Background(palette_idx)
+                    } else {
+                        PixelSource::Backdrop
+                    };
+                    self.set_source(8 * tile_x + x, 8 * tile_y + y, source);
+                }
+            }
+        }
+
+        // Render sprites one scanline at a time, on overlaps the opaque pixel of the sprite
+        // with the lowest OAM index wins, even if it's behind the background
+        for y in 0..HEIGHT {
+            for (x, pixel) in Frame::sprite_line(ppu, rom, &colors, y).iter().enumerate() {
+                let Some(pixel) = pixel else {
+                    continue;
+                };
+                // Sprites behind the background only show through transparent background pixels
+                if pixel.behind_background && self.is_background_opaque(x, y) {
+                    continue;
                 }
+                self.set_pixel(x, y, pixel.color);
+                let source = if pixel.behind_background {
+                    PixelSource::SpriteBehindBackground(pixel.palette)
+                } else {
+                    PixelSource::Sprite(pixel.palette)
+                };
+                self.set_source(x, y, source);
             }
         }
+    }
 
-        // Render sprites
-        for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-            let tile_y = ppu.oam_data[i] as usize;
-            let tile_n = ppu.oam_data[i + 1] as u16;
-            let tile_attributes = ppu.oam_data[i + 2];
-            let tile_x = ppu.oam_data[i + 3] as usize;
+    // Opaque sprite pixels on scanline `y`, picked from the first sprite in OAM order
+    fn sprite_line(
+        ppu: &PpuState,
+        rom: &ROM,
+        colors: &MaskedPalette,
+        y: usize,
+    ) -> [Option<SpritePixel>; WIDTH] {
+        let mut line = [None; WIDTH];
+        let height = ppu.ppuctrl.get_sprite_size().1 as usize;
+        let mut sprites_on_line = 0;
+        for sprite in ppu.oam_data.chunks_exact(4) {
+            let tile_y = sprite[0] as usize;
+            if y < tile_y || y >= tile_y + height {
+                continue;
+            }
+            // Sprites after the 8th are dropped, which games use for flicker
+            if ppu.sprite_limit && sprites_on_line == SPRITES_PER_SCANLINE {
+                break;
+            }
+            sprites_on_line += 1;
+            let tile_attributes = sprite[2];
+            let tile_x = sprite[3] as usize;
 
             // 76543210
             // ||||||||
@@ -84,51 +317,93 @@ impl Frame {
             // +-------- Flip sprite vertically
             let flip_vertical = tile_attributes & 0b1000_0000 != 0;
             let flip_horizontal = tile_attributes & 0b0100_0000 != 0;
-            let priority = tile_attributes & 0b0010_0000 != 0;
-            let palette_idx = tile_attributes & 0b11;
-
-            let palette = Frame::sprite_palette(ppu, palette_idx);
-            let bank = ppu.ppuctrl.get_sprite_pattern_addr();
-
-            // TODO: if it's behind background, then isn't it just never shown?
-            if !priority {
-                let tile_range = (bank + 16 * tile_n) as usize..(bank + 16 * (tile_n + 1)) as usize;
-                let tile = &rom.chr_rom[tile_range];
-                let (upper, lower) = tile.split_at(8);
-                for y in 0..=7 {
-                    let mut hi = upper[y];
-                    let mut lo = lower[y];
-                    'inner: for x in (0..=7).rev() {
-                        let hi_bit = (hi & 1) == 1;
-                        let lo_bit = (lo & 1) == 1;
-                        hi >>= 1;
-                        lo >>= 1;
-                        let rgb = match (lo_bit, hi_bit) {
-                            (false, false) => continue 'inner,
-                            (false, true) => palette::SYSTEM_PALLETE[palette[1]],
-                            (true, false) => palette::SYSTEM_PALLETE[palette[2]],
-                            (true, true) => palette::SYSTEM_PALLETE[palette[3]],
-                        };
-                        match (flip_horizontal, flip_vertical) {
-                            (false, false) => self.set_pixel(tile_x + x, tile_y + y, rgb),
-                            (false, true) => self.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
-                            (true, false) => self.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
-                            (true, true) => self.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
-                        }
-                    }
+            let behind_background = tile_attributes & 0b0010_0000 != 0;
+            let palette = Frame::sprite_palette(ppu, tile_attributes & 0b11);
+
+            let row = if flip_vertical {
+                height - 1 - (y - tile_y)
+            } else {
+                y - tile_y
+            };
+            let tile_addr = ppu.ppuctrl.get_sprite_tile_addr(sprite[1], row) as usize;
+            let tile = tile_bytes(rom, tile_addr);
+            let (upper, lower) = (tile[row % 8], tile[row % 8 + 8]);
+            for column in 0..8 {
+                let x = tile_x + column;
+                // Clipped at the right edge, and earlier sprites keep their pixels
+                if x >= WIDTH || line[x].is_some() {
+                    continue;
+                }
+                let bit = if flip_horizontal { column } else { 7 - column };
+                let color_idx = ((upper >> bit) & 1) | (((lower >> bit) & 1) << 1);
+                if color_idx == 0 {
+                    continue;
                 }
+                line[x] = Some(SpritePixel {
+                    color: colors.get_color(palette[color_idx as usize]),
+                    palette: tile_attributes & 0b11,
+                    behind_background,
+                });
             }
         }
+        line
     }
 
     pub fn as_bytes_ref(&self) -> &[u8; 3 * WIDTH * HEIGHT] {
         unsafe { transmute(&self.data) }
     }
 
-    fn background_palette(ppu: &PpuState, tile_x: usize, tile_y: usize) -> [usize; 4] {
-        // Gets the palette for a background tile
-        let attribute_offset = 8 * (tile_y / 4) + (tile_x / 4);
-        let palette_byte = ppu.ram[0x03C0 + attribute_offset];
+    /// Saves the frame as an RGB PNG image
+    #[cfg(not(feature = "minimal"))]
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        self.save_png_with_text(path, &[])
+    }
+
+    /// The frame as an RGB PNG image in memory, e.g. to send over the network
+    #[cfg(not(feature = "minimal"))]
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut png = Vec::new();
+        encode_rgb_png(&mut png, WIDTH, HEIGHT, self.as_bytes_ref(), &[])?;
+        Ok(png)
+    }
+
+    /// Saves the frame with (keyword, text) pairs in tEXt chunks, e.g. where it came from
+    #[cfg(not(feature = "minimal"))]
+    pub fn save_png_with_text(&self, path: &str, text: &[(&str, String)]) -> Result<(), String> {
+        write_rgb_png(path, WIDTH, HEIGHT, self.as_bytes_ref(), text)
+    }
+
+    /// Loads a frame saved with save_png
+    #[cfg(not(feature = "minimal"))]
+    pub fn load_png(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut reader = png::Decoder::new(file)
+            .read_info()
+            .map_err(|e| e.to_string())?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+        if (info.width, info.height) != (WIDTH as u32, HEIGHT as u32)
+            || info.color_type != png::ColorType::Rgb
+            || info.bit_depth != png::BitDepth::Eight
+        {
+            return Err(format!("{} isn't a {}x{} RGB frame", path, WIDTH, HEIGHT));
+        }
+        let mut frame = Frame::new();
+        for (pixel, rgb) in frame.data.iter_mut().zip(buffer.chunks_exact(3)) {
+            *pixel = (rgb[0], rgb[1], rgb[2]);
+        }
+        Ok(frame)
+    }
+
+    fn background_palette(
+        ppu: &PpuState,
+        mirroring: Mirroring,
+        tile_x: usize,
+        tile_y: usize,
+    ) -> (u8, [usize; 4]) {
+        // Gets the palette number and colors for a background tile
+        let attribute_addr = 0x23C0 + 8 * (tile_y / 4) as u16 + (tile_x / 4) as u16;
+        let palette_byte = ppu.ram[PpuBus::mirror_vram_addr(mirroring, attribute_addr) as usize];
         let background_palette = match ((tile_x % 4) / 2, (tile_y % 4) / 2) {
             (0, 0) => palette_byte & 0b11,
             (1, 0) => (palette_byte >> 2) & 0b11,
@@ -141,12 +416,13 @@ impl Frame {
         // $3F09-$3F0B	Background palette 2
         // $3F0D-$3F0F	Background palette 3
         let palette_offset = 4 * (background_palette as usize);
-        [
+        let colors = [
             ppu.palette_table[0] as usize,
             ppu.palette_table[palette_offset + 1] as usize,
             ppu.palette_table[palette_offset + 2] as usize,
             ppu.palette_table[palette_offset + 3] as usize,
-        ]
+        ];
+        (background_palette, colors)
     }
 
     fn sprite_palette(ppu: &PpuState, pallete_idx: u8) -> [usize; 4] {
@@ -160,3 +436,171 @@ impl Frame {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::palette;
+    use super::*;
+    use crate::ppu::PpuControl;
+
+    // Tile 0 is fully transparent, tile 1 is fully color 1
+    fn test_rom() -> ROM {
+        let mut rom = ROM::new();
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..24].copy_from_slice(&[0xFF; 8]);
+        rom
+    }
+
+    #[cfg(not(feature = "minimal"))]
+    #[test]
+    fn test_png_round_trip() {
+        let mut frame = Frame::new();
+        frame.set_pixel(3, 4, (0x12, 0x34, 0x56));
+        let path = std::env::temp_dir().join("nes_frame_round_trip.png");
+        let path = path.to_str().unwrap();
+        frame.save_png(path).unwrap();
+        let loaded = Frame::load_png(path).unwrap();
+        assert_eq!(frame.data, loaded.data);
+    }
+
+    #[test]
+    fn test_background_opaque_mask() {
+        let mut ppu = PpuState::new();
+        ppu.ram[0] = 1;
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert!(frame.is_background_opaque(0, 0));
+        assert!(frame.is_background_opaque(7, 7));
+        assert!(!frame.is_background_opaque(8, 0));
+
+        frame.highlight_background_transparency((1, 2, 3));
+        assert_eq!((1, 2, 3), frame.data[8]);
+        assert_ne!((1, 2, 3), frame.data[0]);
+    }
+
+    #[test]
+    fn test_behind_background_sprite() {
+        let mut ppu = PpuState::new();
+        ppu.ram[0] = 1;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0x11] = 0x16;
+        // Sprite 0 behind background, straddling the opaque and transparent tiles
+        ppu.oam_data[0..4].copy_from_slice(&[0, 1, 0b0010_0000, 4]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(palette::SYSTEM_PALLETE[0x30], frame.data[4]);
+        assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[8]);
+        assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[11]);
+        assert_eq!(palette::SYSTEM_PALLETE[0], frame.data[12]);
+    }
+
+    #[test]
+    fn test_pixel_sources() {
+        let mut ppu = PpuState::new();
+        // All black, so the debug colors are at half brightness
+        ppu.palette_table = [0x0D; 32];
+        ppu.ram[0] = 1;
+        ppu.ram[0x03C0] = 0b01;
+        // Sprite 0 with palette 2 in front, sprite 1 behind the background
+        ppu.oam_data[0..4].copy_from_slice(&[0, 1, 0b10, 16]);
+        ppu.oam_data[4..8].copy_from_slice(&[0, 1, 0b0010_0011, 4]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(PixelSource::Background(1), frame.source(4, 0));
+        assert_eq!(PixelSource::SpriteBehindBackground(3), frame.source(8, 0));
+        assert_eq!(PixelSource::Sprite(2), frame.source(16, 0));
+        assert_eq!(PixelSource::Backdrop, frame.source(30, 0));
+
+        frame.colorize_priority();
+        assert_eq!(SPRITE_TINTS[2].0 / 2, frame.data[16].0);
+        assert_eq!(BACKDROP_TINT.0 / 2, frame.data[30].0);
+        assert_eq!(BACKGROUND_TINTS[1].2 / 2, frame.data[4].2);
+    }
+
+    #[test]
+    fn test_render_missing_chr_rom() {
+        let mut ppu = PpuState::new();
+        ppu.ram[0] = 0xFF;
+        ppu.oam_data[0..4].copy_from_slice(&[0, 0xFF, 0, 0]);
+        let mut frame = Frame::new();
+        // CHR RAM carts have no CHR ROM, rendering must not panic
+        frame.render(&ppu, &ROM::new());
+        assert!(!frame.is_background_opaque(0, 0));
+    }
+
+    #[test]
+    fn test_render_corrupted_palette() {
+        let mut ppu = PpuState::new();
+        ppu.palette_table[0] = 0xFF;
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!((0, 0, 0), frame.data[0]);
+    }
+
+    #[test]
+    fn test_lower_oam_index_wins() {
+        let mut ppu = PpuState::new();
+        ppu.palette_table[0x11] = 0x16;
+        ppu.palette_table[0x15] = 0x2A;
+        // Sprite 0 with palette 0 overlaps sprite 1 with palette 1 on x = 4..8
+        ppu.oam_data[0..8].copy_from_slice(&[0, 1, 0, 0, 0, 1, 1, 4]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[4]);
+        assert_eq!(palette::SYSTEM_PALLETE[0x2A], frame.data[8]);
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let mut ppu = PpuState::new();
+        ppu.palette_table[0x11] = 0x16;
+        // Sprites 0-7 are transparent but still fill the line, sprite 8 is solid at x = 100
+        ppu.oam_data[32..36].copy_from_slice(&[0, 1, 0, 100]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[100]);
+        ppu.sprite_limit = true;
+        frame.render(&ppu, &test_rom());
+        assert_eq!(PixelSource::Backdrop, frame.source(100, 0));
+    }
+
+    #[test]
+    fn test_behind_background_sprite_hides_later_sprites() {
+        let mut ppu = PpuState::new();
+        ppu.ram[0] = 1;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0x11] = 0x16;
+        ppu.palette_table[0x15] = 0x2A;
+        // Sprite 0 is behind the background but still wins over sprite 1 in front of it
+        ppu.oam_data[0..8].copy_from_slice(&[0, 1, 0b0010_0000, 0, 0, 1, 1, 0]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(palette::SYSTEM_PALLETE[0x30], frame.data[0]);
+    }
+
+    #[test]
+    fn test_sprite_clipped_at_right_edge() {
+        let mut ppu = PpuState::new();
+        ppu.palette_table[0x11] = 0x16;
+        ppu.oam_data[0..4].copy_from_slice(&[0, 1, 0, 252]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[255]);
+        // Does not wrap onto the start of the next line
+        assert_eq!(palette::SYSTEM_PALLETE[0], frame.data[WIDTH]);
+    }
+
+    #[test]
+    fn test_8x16_sprite() {
+        let mut ppu = PpuState::new();
+        ppu.ppuctrl = PpuControl::SPRITE_SIZE;
+        // Tiles 0 and 1, the blank one on top
+        ppu.oam_data[0..4].copy_from_slice(&[0, 0, 0, 0]);
+        let mut frame = Frame::new();
+        frame.render(&ppu, &test_rom());
+        assert_eq!(PixelSource::Backdrop, frame.source(0, 7));
+        assert_eq!(PixelSource::Sprite(0), frame.source(0, 8));
+        assert_eq!(PixelSource::Sprite(0), frame.source(0, 15));
+        assert_eq!(PixelSource::Backdrop, frame.source(0, 16));
+    }
+}
@@ -0,0 +1,107 @@
+// Pixel diffs between two frames, for reviewing renderer changes
+use std::fmt;
+
+use super::frame::{Frame, HEIGHT, WIDTH};
+
+const CHANGED_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffReport {
+    pub changed_pixels: usize,
+    // Smallest rectangle with every changed pixel, (left, top, right, bottom) inclusive
+    pub bounds: Option<(usize, usize, usize, usize)>,
+    // Largest difference of a single color channel
+    pub max_delta: u8,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.changed_pixels == 0
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bounds {
+            None => write!(f, "Frames are identical"),
+            Some((left, top, right, bottom)) => write!(
+                f,
+                "{} pixels changed ({:.2}%) in ({}, {})-({}, {}), max channel delta {}",
+                self.changed_pixels,
+                100.0 * self.changed_pixels as f64 / (WIDTH * HEIGHT) as f64,
+                left,
+                top,
+                right,
+                bottom,
+                self.max_delta
+            ),
+        }
+    }
+}
+
+pub fn frame_diff(a: &Frame, b: &Frame) -> DiffReport {
+    let mut report = DiffReport {
+        changed_pixels: 0,
+        bounds: None,
+        max_delta: 0,
+    };
+    for (index, (pixel_a, pixel_b)) in a.data.iter().zip(b.data.iter()).enumerate() {
+        if pixel_a == pixel_b {
+            continue;
+        }
+        let (x, y) = (index % WIDTH, index / WIDTH);
+        report.changed_pixels += 1;
+        report.bounds = Some(match report.bounds {
+            None => (x, y, x, y),
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+        });
+        let delta = [
+            pixel_a.0.abs_diff(pixel_b.0),
+            pixel_a.1.abs_diff(pixel_b.1),
+            pixel_a.2.abs_diff(pixel_b.2),
+        ];
+        report.max_delta = report.max_delta.max(*delta.iter().max().unwrap());
+    }
+    report
+}
+
+/// `b` dimmed to grey with the changed pixels in magenta
+pub fn diff_image(a: &Frame, b: &Frame) -> Frame {
+    let mut image = Frame::new();
+    for (index, pixel) in image.data.iter_mut().enumerate() {
+        let (pixel_a, pixel_b) = (a.data[index], b.data[index]);
+        *pixel = if pixel_a == pixel_b {
+            let luma = (pixel_b.0 as u16 + pixel_b.1 as u16 + pixel_b.2 as u16) / 3;
+            let grey = (luma / 3) as u8;
+            (grey, grey, grey)
+        } else {
+            CHANGED_COLOR
+        };
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_diff() {
+        let a = Frame::new();
+        let mut b = Frame::new();
+        assert!(frame_diff(&a, &b).is_identical());
+
+        b.set_pixel(10, 20, (0x30, 0x00, 0x00));
+        b.set_pixel(40, 5, (0x00, 0x00, 0x80));
+        let report = frame_diff(&a, &b);
+        assert_eq!(2, report.changed_pixels);
+        assert_eq!(Some((10, 5, 40, 20)), report.bounds);
+        assert_eq!(0x80, report.max_delta);
+
+        let image = diff_image(&a, &b);
+        assert_eq!(CHANGED_COLOR, image.data[WIDTH * 20 + 10]);
+        assert_eq!((0, 0, 0), image.data[0]);
+    }
+}
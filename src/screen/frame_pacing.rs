@@ -0,0 +1,150 @@
+// Frame pacing for the window when presenting doesn't wait for vsync
+//
+// Frames are due on a fixed schedule, so one that runs late is made up for by waiting less
+// before the next. Sleeps wake up late by up to a couple of milliseconds, so the pacer sleeps
+// until shortly before a frame is due and spins the rest. The spin margin follows a smoothed
+// estimate of how late sleeps wake up, staying short on systems with precise timers.
+//
+// The NES runs at 60.1 frames per second. Paced at exactly that on a 60Hz display, a frame is
+// shown twice every few seconds, which is seen as judder. Smoothing paces at the display's
+// refresh rate instead when it's close enough, the audio resampler makes up the difference.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::frontend::CPU_FREQUENCY;
+
+// CPU cycles in an NTSC frame, 341 * 262 - 0.5 PPU dots
+const CPU_CYCLES_PER_FRAME: f64 = 29780.5;
+// Furthest the display's refresh rate can be from the NES's for smoothing to pace at it, the
+// resampler only bends the audio rate half a percent
+const MAX_SMOOTHING_DIFFERENCE: f64 = 0.005;
+// Bounds of the spin margin, twice the smoothed oversleep
+const MIN_SPIN_MARGIN: Duration = Duration::from_micros(200);
+const MAX_SPIN_MARGIN: Duration = Duration::from_millis(4);
+// The newest oversleep counts for an eighth of the smoothed one
+const OVERSLEEP_SMOOTHING: u32 = 8;
+
+pub fn nes_frame_rate() -> f64 {
+    CPU_FREQUENCY / CPU_CYCLES_PER_FRAME
+}
+
+/// Frames per second to pace at, the display's `refresh_rate` with smoothing when it's within
+/// half a percent of the NES frame rate
+pub fn pacing_rate(refresh_rate: Option<f64>, smoothing: bool) -> f64 {
+    let nes_rate = nes_frame_rate();
+    match refresh_rate {
+        Some(rate) if smoothing && (rate / nes_rate - 1.0).abs() <= MAX_SMOOTHING_DIFFERENCE => {
+            rate
+        }
+        _ => nes_rate,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    period: Duration,
+    // When the frame being run is due
+    deadline: Option<Instant>,
+    // Smoothed time sleeps wake up after they were asked to
+    oversleep: Duration,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: f64) -> Self {
+        FramePacer {
+            period: Duration::from_secs_f64(1.0 / frame_rate),
+            deadline: None,
+            oversleep: Duration::ZERO,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// When the next frame is due, with the last one finished at `now`. A frame more than a
+    /// whole period late restarts the schedule rather than rushing the ones after it.
+    pub fn next_deadline(&mut self, now: Instant) -> Instant {
+        let deadline = match self.deadline {
+            Some(deadline) if now < deadline + self.period => deadline + self.period,
+            Some(_) => now,
+            None => now + self.period,
+        };
+        self.deadline = Some(deadline);
+        deadline
+    }
+
+    /// Time left to spin after sleeping
+    pub fn spin_margin(&self) -> Duration {
+        (2 * self.oversleep).clamp(MIN_SPIN_MARGIN, MAX_SPIN_MARGIN)
+    }
+
+    /// Adds how late a sleep woke up to the smoothed oversleep
+    pub fn record_oversleep(&mut self, oversleep: Duration) {
+        let weight = OVERSLEEP_SMOOTHING;
+        self.oversleep = (self.oversleep * (weight - 1) + oversleep) / weight;
+    }
+
+    /// Blocks until the next frame is due
+    pub fn wait(&mut self) {
+        let deadline = self.next_deadline(Instant::now());
+        if let Some(wake) = deadline.checked_sub(self.spin_margin()) {
+            let now = Instant::now();
+            if wake > now {
+                thread::sleep(wake - now);
+                self.record_oversleep(Instant::now().saturating_duration_since(wake));
+            }
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing_rate() {
+        let nes_rate = nes_frame_rate();
+        assert!((nes_rate - 60.0988).abs() < 0.001);
+        assert_eq!(60.0, pacing_rate(Some(60.0), true));
+        assert_eq!(nes_rate, pacing_rate(Some(60.0), false));
+        // 59.94Hz is a quarter percent off, 75Hz isn't close
+        assert_eq!(59.94, pacing_rate(Some(59.94), true));
+        assert_eq!(nes_rate, pacing_rate(Some(75.0), true));
+        assert_eq!(nes_rate, pacing_rate(None, true));
+    }
+
+    #[test]
+    fn test_deadlines_keep_schedule() {
+        let mut pacer = FramePacer::new(50.0);
+        let period = pacer.period();
+        let start = Instant::now();
+        let first = pacer.next_deadline(start);
+        assert_eq!(start + period, first);
+        // A frame finishing a little late doesn't push the schedule back
+        let second = pacer.next_deadline(first + Duration::from_millis(5));
+        assert_eq!(first + period, second);
+        // One more than a period late starts it over
+        let late = second + 2 * period;
+        assert_eq!(late, pacer.next_deadline(late));
+        assert_eq!(late + period, pacer.next_deadline(late));
+    }
+
+    #[test]
+    fn test_spin_margin_follows_oversleep() {
+        let mut pacer = FramePacer::new(60.0);
+        assert_eq!(MIN_SPIN_MARGIN, pacer.spin_margin());
+        for _ in 0..100 {
+            pacer.record_oversleep(Duration::from_millis(1));
+        }
+        let margin = pacer.spin_margin();
+        assert!(margin > Duration::from_micros(1900) && margin <= Duration::from_millis(2));
+        for _ in 0..100 {
+            pacer.record_oversleep(Duration::from_millis(10));
+        }
+        assert_eq!(MAX_SPIN_MARGIN, pacer.spin_margin());
+    }
+}
@@ -0,0 +1,179 @@
+// Per-frame timings of the frontend loop, optionally logged to a CSV file for performance reports
+//
+//     frame,emulation_us,render_us,present_us,sleep_us,paused
+//     0,812,143,95,15612,0
+//
+// Paused frames are logged but left out of the averages, they only redraw the last frame. The
+// spread of the frame times is kept too, smooth pacing shows up as a small standard deviation.
+use std::io::Write;
+use std::time::Duration;
+
+const CSV_HEADER: &str = "frame,emulation_us,render_us,present_us,sleep_us,paused";
+
+/// Time spent in each part of one frame
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameTimings {
+    // Running the CPU and PPU up to the next frame
+    pub emulation: Duration,
+    // Drawing the frame and overlays into the frame buffer
+    pub render: Duration,
+    // Uploading the frame to the GPU and drawing it
+    pub present: Duration,
+    // Waiting for vsync or for the next frame to be due
+    pub sleep: Duration,
+    // Emulation was paused, nothing ran this frame
+    pub paused: bool,
+}
+
+impl FrameTimings {
+    pub fn total(&self) -> Duration {
+        self.emulation + self.render + self.present + self.sleep
+    }
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    frame_count: usize,
+    paused_count: usize,
+    totals: FrameTimings,
+    // Sum of the squared frame times in seconds, for the standard deviation
+    total_squares: f64,
+    csv: Option<Box<dyn Write + Send>>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs every recorded frame as a CSV row to `writer`, starting with the header
+    pub fn log_to(&mut self, mut writer: impl Write + Send + 'static) -> Result<(), String> {
+        writeln!(writer, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+        self.csv = Some(Box::new(writer));
+        Ok(())
+    }
+
+    pub fn record(&mut self, timings: FrameTimings) -> Result<(), String> {
+        if let Some(csv) = &mut self.csv {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{}",
+                self.frame_count,
+                timings.emulation.as_micros(),
+                timings.render.as_micros(),
+                timings.present.as_micros(),
+                timings.sleep.as_micros(),
+                timings.paused as u8
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        self.frame_count += 1;
+        if timings.paused {
+            self.paused_count += 1;
+            return Ok(());
+        }
+        self.totals.emulation += timings.emulation;
+        self.totals.render += timings.render;
+        self.totals.present += timings.present;
+        self.totals.sleep += timings.sleep;
+        self.total_squares += timings.total().as_secs_f64().powi(2);
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn paused_count(&self) -> usize {
+        self.paused_count
+    }
+
+    /// Mean timings over the frames that weren't paused
+    pub fn average(&self) -> FrameTimings {
+        let frames = (self.frame_count - self.paused_count).max(1) as u32;
+        FrameTimings {
+            emulation: self.totals.emulation / frames,
+            render: self.totals.render / frames,
+            present: self.totals.present / frames,
+            sleep: self.totals.sleep / frames,
+            paused: false,
+        }
+    }
+
+    /// Standard deviation of the frame times over the frames that weren't paused
+    pub fn deviation(&self) -> Duration {
+        let frames = (self.frame_count - self.paused_count).max(1) as f64;
+        let mean = self.average().total().as_secs_f64();
+        let variance = self.total_squares / frames - mean * mean;
+        Duration::from_secs_f64(variance.max(0.0).sqrt())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        match &mut self.csv {
+            Some(csv) => csv.flush().map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Shares the written bytes with the test after the stats take ownership
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_csv_log() {
+        let buffer = SharedBuffer::default();
+        let mut stats = FrameStats::new();
+        stats.log_to(buffer.clone()).unwrap();
+        let timings = FrameTimings {
+            emulation: Duration::from_micros(800),
+            render: Duration::from_micros(150),
+            present: Duration::from_micros(90),
+            sleep: Duration::from_micros(15_000),
+            paused: false,
+        };
+        stats.record(timings).unwrap();
+        stats
+            .record(FrameTimings {
+                emulation: Duration::from_micros(1200),
+                ..timings
+            })
+            .unwrap();
+        stats
+            .record(FrameTimings {
+                emulation: Duration::ZERO,
+                paused: true,
+                ..timings
+            })
+            .unwrap();
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(CSV_HEADER, lines[0]);
+        assert_eq!("0,800,150,90,15000,0", lines[1]);
+        assert_eq!("1,1200,150,90,15000,0", lines[2]);
+        assert_eq!("2,0,150,90,15000,1", lines[3]);
+        assert_eq!(3, stats.frame_count());
+        assert_eq!(1, stats.paused_count());
+        assert_eq!(Duration::from_micros(1000), stats.average().emulation);
+        assert_eq!(Duration::from_micros(16_240), stats.average().total());
+        // Frames of 16040us and 16440us
+        let deviation = stats.deviation().as_secs_f64();
+        assert!((deviation - 200e-6).abs() < 1e-7);
+    }
+}
@@ -0,0 +1,122 @@
+// Game controllers through SDL's GameController API, which maps every supported pad to an Xbox
+// style layout
+//
+// The NES buttons go where they sit on an SNES pad: B on the bottom face button and A on the
+// right one, Back and Start for Select and Start. The D-pad and the left stick both steer. Pads
+// take the first free player as they're plugged in, the keyboard keeps working for both players
+// so one can use a pad and the other the keyboard.
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::{GameControllerSubsystem, Sdl};
+
+use crate::controller::ControllerState;
+
+// Stick travel before it counts as a direction, half way
+const STICK_DEADZONE: i16 = i16::MAX / 2;
+
+/// NES button for a pad button, None for the ones that aren't used
+pub fn button_state(button: Button) -> Option<ControllerState> {
+    match button {
+        Button::B => Some(ControllerState::A),
+        Button::A => Some(ControllerState::B),
+        Button::Back => Some(ControllerState::SELECT),
+        Button::Start => Some(ControllerState::START),
+        Button::DPadUp => Some(ControllerState::UP),
+        Button::DPadDown => Some(ControllerState::DOWN),
+        Button::DPadLeft => Some(ControllerState::LEFT),
+        Button::DPadRight => Some(ControllerState::RIGHT),
+        _ => None,
+    }
+}
+
+/// Directions the left stick (presses, lets go of) when `axis` moves to `value`
+pub fn stick_state(axis: Axis, value: i16) -> Option<(ControllerState, ControllerState)> {
+    let (negative, positive) = match axis {
+        Axis::LeftX => (ControllerState::LEFT, ControllerState::RIGHT),
+        Axis::LeftY => (ControllerState::UP, ControllerState::DOWN),
+        _ => return None,
+    };
+    Some(match value {
+        value if value < -STICK_DEADZONE => (negative, positive),
+        value if value > STICK_DEADZONE => (positive, negative),
+        _ => (ControllerState::empty(), negative | positive),
+    })
+}
+
+/// Open pads of the two players
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    players: [Option<GameController>; 2],
+}
+
+impl Gamepads {
+    /// Opens the pads already connected, the first two go to players 1 and 2
+    pub fn open(sdl_context: &Sdl) -> Result<Self, String> {
+        let mut gamepads = Gamepads {
+            subsystem: sdl_context.game_controller()?,
+            players: [None, None],
+        };
+        for index in 0..gamepads.subsystem.num_joysticks()? {
+            gamepads.connect(index);
+        }
+        Ok(gamepads)
+    }
+
+    /// Opens a pad that was plugged in, returns the player it's for and its name. SDL also
+    /// reports the pads open already, those are skipped.
+    pub fn connect(&mut self, joystick_index: u32) -> Option<(usize, String)> {
+        if !self.subsystem.is_game_controller(joystick_index) {
+            return None;
+        }
+        let controller = self.subsystem.open(joystick_index).ok()?;
+        if self.player(controller.instance_id()).is_some() {
+            return None;
+        }
+        let player = self.players.iter().position(Option::is_none)?;
+        let name = controller.name();
+        self.players[player] = Some(controller);
+        Some((player, name))
+    }
+
+    /// Closes a pad that was unplugged, returns the player it was for
+    pub fn disconnect(&mut self, instance_id: u32) -> Option<usize> {
+        let player = self.player(instance_id)?;
+        self.players[player] = None;
+        Some(player)
+    }
+
+    /// Player using the pad SDL reports events for
+    pub fn player(&self, instance_id: u32) -> Option<usize> {
+        self.players.iter().position(|controller| {
+            controller
+                .as_ref()
+                .is_some_and(|controller| controller.instance_id() == instance_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_positions() {
+        // SNES layout, B on the bottom face button
+        assert_eq!(Some(ControllerState::B), button_state(Button::A));
+        assert_eq!(Some(ControllerState::A), button_state(Button::B));
+        assert_eq!(None, button_state(Button::Guide));
+    }
+
+    #[test]
+    fn test_stick_deadzone() {
+        let (pressed, released) = stick_state(Axis::LeftX, i16::MIN).unwrap();
+        assert_eq!(
+            (ControllerState::LEFT, ControllerState::RIGHT),
+            (pressed, released)
+        );
+        // Resting near the middle lets go of both directions
+        let (pressed, released) = stick_state(Axis::LeftY, 1000).unwrap();
+        assert!(pressed.is_empty());
+        assert_eq!(ControllerState::UP | ControllerState::DOWN, released);
+        assert!(stick_state(Axis::RightX, i16::MAX).is_none());
+    }
+}
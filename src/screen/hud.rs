@@ -0,0 +1,278 @@
+// Developer overlay plotting the CPU cycles run on each scanline of the last frame
+//
+// Every row of the graph is one scanline (262 scanlines squeezed into 240 rows), with a bar
+// as long as the CPU cycles that started on it. Vblank start and the scanline where the NMI
+// was serviced are marked across the whole graph.
+//
+// Below the graph, a bar one pixel per CHR ROM address the game tried to write to (see
+// ChrWriteLog), which stays empty for games that run fine without CHR RAM. Above it, a bar one
+// pixel per sprite dropped by the 8 sprites per scanline limit, so flicker the game gets from
+// the hardware can be told apart from sprites the emulator lost.
+use crate::ppu::{ChrWriteLog, ScanlineTiming, SpriteStats, SCANLINES, VBLANK_SCANLINE};
+use crate::profiler::{AccessCounts, MemoryRegion};
+
+use super::frame::{Frame, HEIGHT, WIDTH};
+
+const GRAPH_WIDTH: usize = 64;
+// A scanline is 341 PPU cycles, 113.67 CPU cycles
+const CYCLES_PER_SCANLINE: u16 = 114;
+
+const PANEL_COLOR: (u8, u8, u8) = (0x10, 0x10, 0x10);
+const BAR_COLOR: (u8, u8, u8) = (0x30, 0xC0, 0x30);
+const OVER_BUDGET_COLOR: (u8, u8, u8) = (0xE0, 0x30, 0x30);
+const BUDGET_COLOR: (u8, u8, u8) = (0x60, 0x60, 0x60);
+const VBLANK_COLOR: (u8, u8, u8) = (0xE0, 0xE0, 0x30);
+const NMI_COLOR: (u8, u8, u8) = (0xE0, 0x30, 0xE0);
+const CHR_WRITE_COLOR: (u8, u8, u8) = (0xE0, 0x80, 0x20);
+const SPRITE_ZERO_COLOR: (u8, u8, u8) = (0x30, 0xE0, 0xE0);
+const DROPPED_SPRITE_COLOR: (u8, u8, u8) = (0xE0, 0x30, 0x30);
+// Rows at the bottom of the panel used by the CHR write counter
+const CHR_WRITE_ROWS: usize = 2;
+// Rows above them used by the dropped sprite counter
+const DROPPED_SPRITE_ROWS: usize = 2;
+
+// Memory profile panel on the left edge, one bar per region with reads then writes
+const PROFILE_WIDTH: usize = 64;
+const PROFILE_BAR_HEIGHT: usize = 6;
+const PROFILE_BAR_GAP: usize = 2;
+const PROFILE_READ_COLOR: (u8, u8, u8) = (0x30, 0x90, 0xE0);
+const PROFILE_WRITE_COLOR: (u8, u8, u8) = (0xE0, 0x60, 0x30);
+// Bar order, all PRG ROM banks share the last bar
+const PROFILE_ROWS: usize = 8;
+
+// Pause sign at the top middle, clear of the panels on both edges
+const PAUSE_SIZE: usize = 16;
+const PAUSE_TOP: usize = 8;
+const PAUSE_BAR_WIDTH: usize = 4;
+// Left column of each bar within the sign
+const PAUSE_BARS: [usize; 2] = [3, 9];
+const PAUSE_COLOR: (u8, u8, u8) = (0xF0, 0xF0, 0xF0);
+
+fn row_of(scanline: usize) -> usize {
+    scanline * HEIGHT / SCANLINES
+}
+
+fn bar_length(cycles: u16) -> usize {
+    (cycles as usize * GRAPH_WIDTH / (2 * CYCLES_PER_SCANLINE as usize)).min(GRAPH_WIDTH)
+}
+
+/// Draws the timing graph over the right edge of `frame`
+pub fn draw_timing_hud(frame: &mut Frame, timing: &ScanlineTiming) {
+    let left = WIDTH - GRAPH_WIDTH;
+    for y in 0..HEIGHT {
+        for x in left..WIDTH {
+            frame.set_pixel(x, y, PANEL_COLOR);
+        }
+    }
+    for (scanline, cycles) in timing.last_frame.iter().enumerate() {
+        let color = if *cycles > CYCLES_PER_SCANLINE {
+            OVER_BUDGET_COLOR
+        } else {
+            BAR_COLOR
+        };
+        for x in left..left + bar_length(*cycles) {
+            frame.set_pixel(x, row_of(scanline), color);
+        }
+    }
+    // Budget of one scanline
+    let budget_x = left + bar_length(CYCLES_PER_SCANLINE);
+    for y in 0..HEIGHT {
+        frame.set_pixel(budget_x, y, BUDGET_COLOR);
+    }
+    let mut markers = vec![(VBLANK_SCANLINE, VBLANK_COLOR)];
+    if let Some(scanline) = timing.last_nmi_scanline {
+        markers.push((scanline, NMI_COLOR));
+    }
+    for (scanline, color) in markers {
+        for x in left..WIDTH {
+            frame.set_pixel(x, row_of(scanline), color);
+        }
+    }
+}
+
+/// Draws the CHR ROM write counter along the bottom of the timing graph
+pub fn draw_chr_write_counter(frame: &mut Frame, chr_writes: &ChrWriteLog) {
+    let left = WIDTH - GRAPH_WIDTH;
+    let length = chr_writes.addresses().min(GRAPH_WIDTH);
+    for y in HEIGHT - CHR_WRITE_ROWS..HEIGHT {
+        for x in left..left + length {
+            frame.set_pixel(x, y, CHR_WRITE_COLOR);
+        }
+    }
+}
+
+/// Marks the sprite 0 hit scanline on the timing graph and draws the dropped sprite counter
+/// above the CHR write counter
+pub fn draw_sprite_counters(frame: &mut Frame, sprites: &SpriteStats) {
+    let left = WIDTH - GRAPH_WIDTH;
+    if let Some((scanline, _)) = sprites.sprite_zero_hit {
+        for x in left..WIDTH {
+            frame.set_pixel(x, row_of(scanline), SPRITE_ZERO_COLOR);
+        }
+    }
+    let length = sprites.dropped.min(GRAPH_WIDTH);
+    let bottom = HEIGHT - CHR_WRITE_ROWS;
+    for y in bottom - DROPPED_SPRITE_ROWS..bottom {
+        for x in left..left + length {
+            frame.set_pixel(x, y, DROPPED_SPRITE_COLOR);
+        }
+    }
+}
+
+fn profile_row(region: MemoryRegion) -> usize {
+    match region {
+        MemoryRegion::ZeroPage => 0,
+        MemoryRegion::Stack => 1,
+        MemoryRegion::Ram => 2,
+        MemoryRegion::PpuRegisters => 3,
+        MemoryRegion::ApuIo => 4,
+        MemoryRegion::Expansion => 5,
+        MemoryRegion::PrgRam => 6,
+        MemoryRegion::PrgBank(_) => 7,
+    }
+}
+
+/// Draws each region's share of the memory accesses over the left edge of `frame`, in the order
+/// zero page, stack, RAM, PPU registers, APU/IO, expansion, PRG RAM, PRG ROM
+pub fn draw_profile_hud(frame: &mut Frame, regions: &[(MemoryRegion, AccessCounts)]) {
+    let mut rows = [AccessCounts::default(); PROFILE_ROWS];
+    for (region, counts) in regions {
+        let row = &mut rows[profile_row(*region)];
+        row.reads += counts.reads;
+        row.writes += counts.writes;
+    }
+    let total = rows.iter().map(AccessCounts::total).sum::<u64>().max(1);
+    let width = |count: u64| (count * PROFILE_WIDTH as u64 / total) as usize;
+    let height = PROFILE_ROWS * (PROFILE_BAR_HEIGHT + PROFILE_BAR_GAP);
+    for y in 0..height {
+        for x in 0..PROFILE_WIDTH {
+            frame.set_pixel(x, y, PANEL_COLOR);
+        }
+    }
+    for (row, counts) in rows.iter().enumerate() {
+        let top = row * (PROFILE_BAR_HEIGHT + PROFILE_BAR_GAP);
+        let reads = width(counts.reads);
+        let writes = width(counts.writes);
+        for y in top..top + PROFILE_BAR_HEIGHT {
+            for x in 0..reads {
+                frame.set_pixel(x, y, PROFILE_READ_COLOR);
+            }
+            for x in reads..(reads + writes).min(PROFILE_WIDTH) {
+                frame.set_pixel(x, y, PROFILE_WRITE_COLOR);
+            }
+        }
+    }
+}
+
+/// Draws a pause sign, two bars on a dark square, over the top middle of `frame`
+pub fn draw_pause_icon(frame: &mut Frame) {
+    let left = (WIDTH - PAUSE_SIZE) / 2;
+    for y in 0..PAUSE_SIZE {
+        for x in 0..PAUSE_SIZE {
+            let is_bar = (2..PAUSE_SIZE - 2).contains(&y)
+                && PAUSE_BARS
+                    .iter()
+                    .any(|bar| (*bar..bar + PAUSE_BAR_WIDTH).contains(&x));
+            let color = if is_bar { PAUSE_COLOR } else { PANEL_COLOR };
+            frame.set_pixel(left + x, PAUSE_TOP + y, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_timing_hud() {
+        let mut timing = ScanlineTiming::new();
+        timing.last_frame[0] = 114;
+        timing.last_frame[10] = 200;
+        timing.last_nmi_scanline = Some(242);
+        let mut frame = Frame::new();
+        draw_timing_hud(&mut frame, &timing);
+
+        let left = WIDTH - GRAPH_WIDTH;
+        let pixel = |x: usize, y: usize| frame.data[WIDTH * y + x];
+        assert_eq!(BAR_COLOR, pixel(left, 0));
+        assert_eq!(PANEL_COLOR, pixel(left + 33, 0));
+        assert_eq!(OVER_BUDGET_COLOR, pixel(left + 40, row_of(10)));
+        assert_eq!(VBLANK_COLOR, pixel(WIDTH - 1, row_of(241)));
+        assert_eq!(NMI_COLOR, pixel(WIDTH - 1, row_of(242)));
+        // Game area is untouched
+        assert_eq!((0, 0, 0), pixel(left - 1, 0));
+    }
+
+    #[test]
+    fn test_draw_chr_write_counter() {
+        let mut chr_writes = ChrWriteLog::new();
+        for addr in 0..3 {
+            chr_writes.record(addr);
+        }
+        chr_writes.record(0);
+        let mut frame = Frame::new();
+        draw_chr_write_counter(&mut frame, &chr_writes);
+
+        let left = WIDTH - GRAPH_WIDTH;
+        let pixel = |x: usize, y: usize| frame.data[WIDTH * y + x];
+        assert_eq!(CHR_WRITE_COLOR, pixel(left + 2, HEIGHT - 1));
+        assert_eq!((0, 0, 0), pixel(left + 3, HEIGHT - 1));
+        assert_eq!((0, 0, 0), pixel(left, HEIGHT - 1 - CHR_WRITE_ROWS));
+    }
+
+    #[test]
+    fn test_draw_sprite_counters() {
+        let sprites = SpriteStats {
+            max_per_scanline: 10,
+            dropped: 2,
+            sprite_zero_hit: Some((30, 9)),
+        };
+        let mut frame = Frame::new();
+        draw_sprite_counters(&mut frame, &sprites);
+
+        let left = WIDTH - GRAPH_WIDTH;
+        let pixel = |x: usize, y: usize| frame.data[WIDTH * y + x];
+        assert_eq!(SPRITE_ZERO_COLOR, pixel(WIDTH - 1, row_of(30)));
+        let y = HEIGHT - CHR_WRITE_ROWS - 1;
+        assert_eq!(DROPPED_SPRITE_COLOR, pixel(left + 1, y));
+        assert_eq!((0, 0, 0), pixel(left + 2, y));
+        assert_eq!((0, 0, 0), pixel(left, HEIGHT - 1));
+    }
+
+    #[test]
+    fn test_draw_profile_hud() {
+        let counts = |reads, writes| AccessCounts { reads, writes };
+        let regions = [
+            (MemoryRegion::ZeroPage, counts(1, 1)),
+            (MemoryRegion::PrgBank(0), counts(1, 0)),
+            (MemoryRegion::PrgBank(1), counts(1, 0)),
+        ];
+        let mut frame = Frame::new();
+        draw_profile_hud(&mut frame, &regions);
+
+        let pixel = |x: usize, y: usize| frame.data[WIDTH * y + x];
+        let prg_row = 7 * (PROFILE_BAR_HEIGHT + PROFILE_BAR_GAP);
+        // A quarter of the accesses are zero page reads, another quarter writes
+        assert_eq!(PROFILE_READ_COLOR, pixel(15, 0));
+        assert_eq!(PROFILE_WRITE_COLOR, pixel(16, 0));
+        assert_eq!(PANEL_COLOR, pixel(32, 0));
+        // Both banks count towards PRG ROM
+        assert_eq!(PROFILE_READ_COLOR, pixel(31, prg_row));
+        assert_eq!(PANEL_COLOR, pixel(0, PROFILE_BAR_HEIGHT));
+    }
+
+    #[test]
+    fn test_draw_pause_icon() {
+        let mut frame = Frame::new();
+        draw_pause_icon(&mut frame);
+
+        let left = (WIDTH - PAUSE_SIZE) / 2;
+        let pixel = |x: usize| frame.data[WIDTH * (PAUSE_TOP + PAUSE_SIZE / 2) + left + x];
+        assert_eq!(PANEL_COLOR, pixel(0));
+        assert_eq!(PAUSE_COLOR, pixel(3));
+        assert_eq!(PANEL_COLOR, pixel(7));
+        assert_eq!(PAUSE_COLOR, pixel(12));
+        assert_eq!(PANEL_COLOR, pixel(13));
+        assert_eq!((0, 0, 0), frame.data[WIDTH * PAUSE_TOP + left - 1]);
+    }
+}
@@ -0,0 +1,131 @@
+// Keyboard bindings for the two controllers, remappable from the window and saved to a config
+// file for each player
+//
+// Keys are stored by their SDL names ("A", "Left", "Right Shift") so this doesn't depend on
+// SDL, the window turns them into keycodes.
+use crate::controller::ControllerState;
+
+/// Controller buttons in the order they're remapped, with their config names
+pub const BUTTONS: [(ControllerState, &str); 8] = [
+    (ControllerState::A, "a"),
+    (ControllerState::B, "b"),
+    (ControllerState::SELECT, "select"),
+    (ControllerState::START, "start"),
+    (ControllerState::UP, "up"),
+    (ControllerState::DOWN, "down"),
+    (ControllerState::LEFT, "left"),
+    (ControllerState::RIGHT, "right"),
+];
+
+const DEFAULT_KEYS: [&str; 8] = ["A", "S", "Q", "W", "Up", "Down", "Left", "Right"];
+// Player 2 gets the right hand side of the keyboard, IJKL for the D-pad
+const DEFAULT_KEYS_2: [&str; 8] = ["H", "G", "T", "Y", "I", "K", "J", "L"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    // Key name for each entry of BUTTONS
+    keys: [String; 8],
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keys: DEFAULT_KEYS.map(str::to_string),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults for player 2, none of them used by player 1's
+    pub fn player_2() -> Self {
+        KeyBindings {
+            keys: DEFAULT_KEYS_2.map(str::to_string),
+        }
+    }
+
+    pub fn key(&self, button: usize) -> &str {
+        &self.keys[button]
+    }
+
+    /// Binds `key` to BUTTONS[button]. A button already using the key takes this button's old
+    /// key instead, so no two buttons share a key.
+    pub fn set_key(&mut self, button: usize, key: &str) {
+        if let Some(other) = self.keys.iter().position(|bound| bound == key) {
+            self.keys[other] = self.keys[button].clone();
+        }
+        self.keys[button] = key.to_string();
+    }
+
+    /// (button, key name) pairs
+    pub fn iter(&self) -> impl Iterator<Item = (ControllerState, &str)> {
+        BUTTONS
+            .iter()
+            .zip(self.keys.iter())
+            .map(|((button, _), key)| (*button, key.as_str()))
+    }
+
+    /// "button=key" lines, e.g. "start=Return"
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+        for ((_, name), key) in BUTTONS.iter().zip(self.keys.iter()) {
+            config.push_str(&format!("{}={}\n", name, key));
+        }
+        config
+    }
+
+    /// Loads bindings written by to_config, buttons missing from the config keep their key
+    pub fn load_config(&mut self, config: &str) -> Result<(), String> {
+        for line in config.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, key) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid key binding {}", line))?;
+            let button = BUTTONS
+                .iter()
+                .position(|(_, button_name)| *button_name == name.trim())
+                .ok_or_else(|| format!("Unknown button {}", name))?;
+            self.keys[button] = key.trim().to_string();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_key_swaps_duplicates() {
+        let mut bindings = KeyBindings::new();
+        // Start takes Q, which Select had
+        bindings.set_key(3, "Q");
+        assert_eq!("Q", bindings.key(3));
+        assert_eq!("W", bindings.key(2));
+        let keys: Vec<&str> = bindings.iter().map(|(_, key)| key).collect();
+        assert_eq!(
+            vec!["A", "S", "W", "Q", "Up", "Down", "Left", "Right"],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_player_2_keys_are_free() {
+        let player_1 = KeyBindings::new();
+        for (_, key) in KeyBindings::player_2().iter() {
+            assert!(player_1.iter().all(|(_, other)| other != key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut bindings = KeyBindings::new();
+        bindings.set_key(0, "Right Shift");
+        let mut loaded = KeyBindings::new();
+        loaded.load_config(&bindings.to_config()).unwrap();
+        assert_eq!(bindings, loaded);
+        assert!(loaded.load_config("turbo=T").is_err());
+    }
+}
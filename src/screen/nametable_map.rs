@@ -0,0 +1,158 @@
+// Debug view of the four logical nametables as one 512x480 image
+//
+//     +-----------+-----------+
+//     |   $2000   |   $2400   |
+//     +-----------+-----------+
+//     |   $2800   |   $2C00   |
+//     +-----------+-----------+
+//
+// Mirrored nametables show the same VRAM twice. The area the next frame starts scrolled to is
+// outlined, wrapping around the edges like the scroll does.
+use crate::ppu::{PpuBus, PpuState};
+use crate::rom::ROM;
+
+#[cfg(not(feature = "minimal"))]
+use super::frame::write_rgb_png;
+use super::frame::{tile_bytes, HEIGHT, TILE_SIZE, WIDTH};
+use super::palette;
+
+pub const MAP_WIDTH: usize = 2 * WIDTH;
+pub const MAP_HEIGHT: usize = 2 * HEIGHT;
+
+const NAMETABLE_SIZE: u16 = 0x400;
+const ATTRIBUTE_OFFSET: u16 = 0x3C0;
+const VIEWPORT_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+
+pub struct NametableMap {
+    pub data: Vec<(u8, u8, u8)>,
+}
+
+impl NametableMap {
+    /// Renders every nametable with the current palettes and background pattern table, and
+    /// outlines the viewport
+    pub fn render(ppu: &PpuState, rom: &ROM) -> Self {
+        let mirroring = rom.current_mirroring();
+        let mut map = NametableMap {
+            data: vec![(0, 0, 0); MAP_WIDTH * MAP_HEIGHT],
+        };
+        let bank = ppu.ppuctrl.get_background_pattern_addr() as usize;
+        let vram = |addr: u16| ppu.ram[PpuBus::mirror_vram_addr(mirroring, addr) as usize];
+        for nametable in 0..4u16 {
+            let base = 0x2000 + nametable * NAMETABLE_SIZE;
+            let (left, top) = (
+                WIDTH * (nametable % 2) as usize,
+                HEIGHT * (nametable / 2) as usize,
+            );
+            for tile_y in 0..30 {
+                for tile_x in 0..32 {
+                    let tile_n = vram(base + (32 * tile_y + tile_x) as u16) as usize;
+                    let tile = tile_bytes(rom, bank + TILE_SIZE * tile_n);
+                    let attribute =
+                        vram(base + ATTRIBUTE_OFFSET + (8 * (tile_y / 4) + tile_x / 4) as u16);
+                    // Each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                    let shift = 4 * ((tile_y % 4) / 2) + 2 * ((tile_x % 4) / 2);
+                    let palette = 4 * ((attribute >> shift) & 0b11) as usize;
+                    for row in 0..8 {
+                        for column in 0..8 {
+                            let bit = 7 - column;
+                            let color_idx =
+                                ((tile[row] >> bit) & 1) | (((tile[row + 8] >> bit) & 1) << 1);
+                            // Color 0 of every background palette is the backdrop
+                            let entry = if color_idx == 0 {
+                                0
+                            } else {
+                                palette + color_idx as usize
+                            };
+                            let color = palette::get_color(ppu.palette_table[entry] as usize);
+                            let (x, y) = (left + 8 * tile_x + column, top + 8 * tile_y + row);
+                            map.data[MAP_WIDTH * y + x] = color;
+                        }
+                    }
+                }
+            }
+        }
+        map.draw_viewport(ppu);
+        map
+    }
+
+    /// Top left corner of the screen in the map, from the scroll and nametable in t
+    pub fn viewport(ppu: &PpuState) -> (usize, usize) {
+        let (scroll_x, scroll_y) = ppu.loopy.get_scroll();
+        let nametable = ((ppu.loopy.t >> 10) & 0b11) as usize;
+        (
+            WIDTH * (nametable % 2) + scroll_x as usize,
+            HEIGHT * (nametable / 2) + scroll_y as usize,
+        )
+    }
+
+    fn draw_viewport(&mut self, ppu: &PpuState) {
+        let (left, top) = NametableMap::viewport(ppu);
+        for offset in 0..WIDTH {
+            self.set_wrapped(left + offset, top);
+            self.set_wrapped(left + offset, top + HEIGHT - 1);
+        }
+        for offset in 0..HEIGHT {
+            self.set_wrapped(left, top + offset);
+            self.set_wrapped(left + WIDTH - 1, top + offset);
+        }
+    }
+
+    fn set_wrapped(&mut self, x: usize, y: usize) {
+        let (x, y) = (x % MAP_WIDTH, y % MAP_HEIGHT);
+        self.data[MAP_WIDTH * y + x] = VIEWPORT_COLOR;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        self.data[MAP_WIDTH * y + x]
+    }
+
+    /// Saves the map as an RGB PNG image
+    #[cfg(not(feature = "minimal"))]
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = self.data.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+        write_rgb_png(path, MAP_WIDTH, MAP_HEIGHT, &bytes, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_nametable_map() {
+        let mut rom = ROM::new();
+        rom.mirroring = Mirroring::Vertical;
+        // Tile 1 is solid color 3
+        rom.chr_rom = vec![0; 0x2000];
+        rom.chr_rom[16..32].fill(0xFF);
+        let mut ppu = PpuState::new();
+        ppu.palette_table[0] = 0x0F;
+        ppu.palette_table[7] = 0x16;
+        // Top left tile of $2400, palette 1 in its attribute byte
+        ppu.ram[0x400] = 1;
+        ppu.ram[0x400 + 0x3C0] = 0b01;
+        // Scrolled 8 pixels right and down in $2400
+        ppu.loopy.write_ppuctrl(0b01);
+        ppu.loopy.write_ppuscroll(8);
+        ppu.loopy.write_ppuscroll(8);
+
+        let map = NametableMap::render(&ppu, &rom);
+        let red = palette::get_color(0x16);
+        assert_eq!(red, map.pixel(WIDTH + 4, 4));
+        // Vertical mirroring, $2C00 shows $2400
+        assert_eq!(red, map.pixel(WIDTH + 4, HEIGHT + 4));
+        assert_eq!(palette::get_color(0x0F), map.pixel(4, 4));
+        assert_eq!((WIDTH + 8, 8), NametableMap::viewport(&ppu));
+        assert_eq!(VIEWPORT_COLOR, map.pixel(WIDTH + 8, 8));
+        assert_eq!(VIEWPORT_COLOR, map.pixel(WIDTH + 8, HEIGHT + 7));
+        // The right edge wraps around to the left of the map
+        assert_eq!(VIEWPORT_COLOR, map.pixel(7, 20));
+
+        // Four-screen, $2C00 is its own
+        rom.mirroring = Mirroring::FourScreen;
+        let map = NametableMap::render(&ppu, &rom);
+        assert_eq!(red, map.pixel(WIDTH + 4, 4));
+        assert_eq!(palette::get_color(0x0F), map.pixel(WIDTH + 4, HEIGHT + 4));
+    }
+}
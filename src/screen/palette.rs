@@ -1,3 +1,88 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::ppu::PpuMask;
+
+static PALETTE_OUT_OF_RANGE_WARNED: AtomicBool = AtomicBool::new(false);
+
+// The PPUMASK bits that change colors
+const COLOR_BITS: PpuMask = PpuMask::GREYSCALE
+    .union(PpuMask::EMPHASIZE_RED)
+    .union(PpuMask::EMPHASIZE_GREEN)
+    .union(PpuMask::EMPHASIZE_BLUE);
+// Emphasizing a channel dims the other two by about this much
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+/// Looks up a system palette color, out of range indices render as black instead of panicking
+pub fn get_color(index: usize) -> (u8, u8, u8) {
+    match SYSTEM_PALLETE.get(index) {
+        Some(color) => *color,
+        None => {
+            if !PALETTE_OUT_OF_RANGE_WARNED.swap(true, Ordering::Relaxed) {
+                log::warn!("Palette index {:x} out of range, rendering as black", index);
+            }
+            (0, 0, 0)
+        }
+    }
+}
+
+/// The system palette as it looks with the greyscale and emphasis bits of a PPUMASK value
+#[derive(Debug, Clone)]
+pub struct MaskedPalette {
+    mask: PpuMask,
+    colors: [(u8, u8, u8); 64],
+}
+
+impl MaskedPalette {
+    pub fn new(mask: PpuMask) -> Self {
+        let mask = mask & COLOR_BITS;
+        let dim = |value: u8, is_dimmed: bool| match is_dimmed {
+            true => (value as f32 * EMPHASIS_ATTENUATION).round() as u8,
+            false => value,
+        };
+        let colors = std::array::from_fn(|index| {
+            // Greyscale keeps the brightness and drops the hue, the grey column
+            let index = match mask.contains(PpuMask::GREYSCALE) {
+                true => index & 0x30,
+                false => index,
+            };
+            let (red, green, blue) = SYSTEM_PALLETE[index];
+            // The blacks in columns $xE and $xF aren't affected
+            if index & 0x0F >= 0x0E {
+                return (red, green, blue);
+            }
+            let emphasized = |bit| mask.contains(bit);
+            (
+                dim(
+                    red,
+                    emphasized(PpuMask::EMPHASIZE_GREEN) || emphasized(PpuMask::EMPHASIZE_BLUE),
+                ),
+                dim(
+                    green,
+                    emphasized(PpuMask::EMPHASIZE_RED) || emphasized(PpuMask::EMPHASIZE_BLUE),
+                ),
+                dim(
+                    blue,
+                    emphasized(PpuMask::EMPHASIZE_RED) || emphasized(PpuMask::EMPHASIZE_GREEN),
+                ),
+            )
+        });
+        MaskedPalette { mask, colors }
+    }
+
+    /// Whether this palette is the one for `mask`
+    pub fn is_for(&self, mask: PpuMask) -> bool {
+        self.mask.bits() == (mask & COLOR_BITS).bits()
+    }
+
+    /// Like get_color, black for out of range indices
+    pub fn get_color(&self, index: usize) -> (u8, u8, u8) {
+        match self.colors.get(index) {
+            Some(color) => *color,
+            None => get_color(index),
+        }
+    }
+}
+
 // Shamelessly stolen from here: https://bugzmanov.github.io/nes_ebook/chapter_6_3.html
 pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x80, 0x80, 0x80),
@@ -65,3 +150,29 @@ pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
     (0x11, 0x11, 0x11),
     (0x11, 0x11, 0x11),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masked_palette() {
+        let plain = MaskedPalette::new(PpuMask::SHOW_BACKGROUND);
+        assert_eq!(SYSTEM_PALLETE[0x16], plain.get_color(0x16));
+        assert!(plain.is_for(PpuMask::empty()));
+
+        let grey = MaskedPalette::new(PpuMask::GREYSCALE);
+        assert_eq!(SYSTEM_PALLETE[0x10], grey.get_color(0x16));
+        assert_eq!(SYSTEM_PALLETE[0x30], grey.get_color(0x3A));
+
+        // Red emphasis dims green and blue
+        let red = MaskedPalette::new(PpuMask::EMPHASIZE_RED);
+        assert_eq!((0xFF, 0xBE, 0xBE), red.get_color(0x30));
+        assert_eq!(SYSTEM_PALLETE[0x0F], red.get_color(0x0F));
+        let all = MaskedPalette::new(
+            PpuMask::EMPHASIZE_RED | PpuMask::EMPHASIZE_GREEN | PpuMask::EMPHASIZE_BLUE,
+        );
+        assert_eq!((0xBE, 0xBE, 0xBE), all.get_color(0x30));
+        assert_eq!((0, 0, 0), all.get_color(64));
+    }
+}
@@ -0,0 +1,100 @@
+// Screenshots named and tagged with the emulator state they were taken at
+//
+//     {rom name}_{frame number}_{state hash}.png, e.g. smb_000420_1A2B3C4D.png
+//
+// The same details go in PNG tEXt chunks, so renamed files can still be traced back, along with
+// the emulated time the frame was drawn at.
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::nes::{format_emulated_time, ActionNES, NES};
+
+use super::frame::Frame;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenshotInfo {
+    pub rom_name: String,
+    pub frame: usize,
+    pub emulated_duration: Duration,
+    pub state_hash: u32,
+}
+
+impl ScreenshotInfo {
+    /// Describes the current state of `nes`, which was loaded from `rom_path`
+    pub fn for_nes(nes: &ActionNES, rom_path: &str) -> Self {
+        let rom_name = Path::new(rom_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "nes".to_string());
+        ScreenshotInfo {
+            rom_name,
+            frame: nes.frame_count(),
+            emulated_duration: nes.emulated_duration(),
+            state_hash: nes.state_hash(),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}_{:06}_{:08X}.png",
+            self.rom_name, self.frame, self.state_hash
+        )
+    }
+
+    /// (keyword, text) pairs for the PNG
+    pub fn text_chunks(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("ROM", self.rom_name.clone()),
+            ("Frame", self.frame.to_string()),
+            (
+                "Emulated time",
+                format_emulated_time(self.emulated_duration),
+            ),
+            ("State hash", format!("{:08X}", self.state_hash)),
+        ]
+    }
+}
+
+/// Saves `frame` in `dir`, returns the path it was written to
+pub fn save_screenshot(
+    frame: &Frame,
+    dir: &Path,
+    info: &ScreenshotInfo,
+) -> Result<PathBuf, String> {
+    let path = dir.join(info.file_name());
+    let path_str = path.to_str().ok_or("Screenshot path isn't valid UTF-8")?;
+    frame.save_png_with_text(path_str, &info.text_chunks())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_screenshot_metadata() {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        nes.step_frames(2).unwrap();
+        let info = ScreenshotInfo::for_nes(&nes, "test_roms/nestest.nes");
+        assert_eq!(2, info.frame);
+        assert_eq!(
+            format!("nestest_000002_{:08X}.png", info.state_hash),
+            info.file_name()
+        );
+        // Same state, same name
+        assert_eq!(info, ScreenshotInfo::for_nes(&nes.clone(), "nestest.nes"));
+
+        let path = save_screenshot(&Frame::new(), &std::env::temp_dir(), &info).unwrap();
+        let reader = png::Decoder::new(File::open(&path).unwrap())
+            .read_info()
+            .unwrap();
+        let text = &reader.info().uncompressed_latin1_text;
+        assert_eq!("Frame", text[1].keyword);
+        assert_eq!("2", text[1].text);
+        assert_eq!("00:00.033", text[2].text);
+        assert_eq!(format!("{:08X}", info.state_hash), text[3].text);
+    }
+}
@@ -0,0 +1,1316 @@
+// SDL window frontend
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::{read, read_to_string, write, File};
+use std::io::BufWriter;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use sdl2::audio::{AudioCallback, AudioQueue, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::{SwapInterval, Window};
+use sdl2::Sdl;
+
+use crate::nes::NES;
+use crate::nes::{format_emulated_time, ActionNES};
+
+use crate::accuracy::AccuracyPreset;
+use crate::apu::{MixerControls, CHANNEL_NAMES};
+use crate::autosave::{self, autosave_path, AutosaveKind};
+use crate::battery::{sav_path, write_atomic, BatterySave, DEFAULT_FLUSH_INTERVAL};
+use crate::controller::ControllerState;
+use crate::debugger::{Debugger, StopReason};
+use crate::frontend::{ButtonLatch, CycleBudget, InputLatch, InputPort, OutputPort, StreamInput};
+use crate::game_db::{detect_input_latch, detect_port_2, detect_region};
+use crate::movie::Movie;
+use crate::peripheral::{OutputLatch, PortDevice};
+use crate::region::Region;
+use crate::rewind::RewindBuffer;
+use crate::rom::mapper::HostClock;
+use crate::savestate::savestate_path;
+use crate::snapshot::{Snapshot, SnapshotBaseline};
+use crate::stall::DEFAULT_STALL_FRAMES;
+use crate::wav::BackgroundWavWriter;
+
+use super::chr_sheet::{export_chr_sheets, ppu_palette};
+use super::debug_view::{DebugView, VIEW_HEIGHT, VIEW_WIDTH};
+use super::display::{DisplayConfig, Rotation, SyncMode};
+use super::frame::Frame;
+use super::frame_pacing::{pacing_rate, FramePacer};
+use super::frame_stats::{FrameStats, FrameTimings};
+use super::gamepad::{button_state, stick_state, Gamepads};
+use super::hud::{
+    draw_chr_write_counter, draw_pause_icon, draw_profile_hud, draw_sprite_counters,
+    draw_timing_hud,
+};
+use super::key_bindings::{KeyBindings, BUTTONS};
+use super::screenshot::{save_screenshot, ScreenshotInfo};
+
+/// Where controller 1 input comes from
+#[derive(Debug, Default, Clone)]
+pub enum InputSource {
+    #[default]
+    Keyboard,
+    // Bitmask lines from an external program, see frontend::StreamInput
+    Stdin,
+    Fifo {
+        input: String,
+        output: String,
+    },
+}
+
+/// Options for the SDL frontend
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    // Device plugged into the second controller port, detected from the game database if None
+    pub port_2: Option<PortDevice>,
+    // Extra game database entries, see game_db
+    pub game_db: Option<String>,
+    // Overrides the detected region
+    pub region: Option<Region>,
+    pub display: DisplayConfig,
+    pub input: InputSource,
+    // Prints a determinism audit report on exit
+    pub audit: bool,
+    // Paces emulation with the audio device instead of the display refresh
+    pub audio_sync: bool,
+    // WAV file recording everything sent to the audio device, implies audio_sync
+    pub record_audio: Option<String>,
+    // CSV file logging how long each part of every frame took, see frame_stats
+    pub frame_stats: Option<String>,
+    // Loads the autosave written when the last session exited or crashed
+    pub resume: bool,
+    // Counts memory accesses from the start, printed on exit (F6 toggles it while running)
+    pub profile_memory: bool,
+    // OUT line (1 or 2) that rumbles the first game controller while high
+    pub rumble_line: Option<u8>,
+    // Overrides the preset saved in nes_accuracy.cfg (F10 cycles it while running)
+    pub accuracy: Option<AccuracyPreset>,
+    // Pauses while the window isn't focused
+    pub pause_on_focus_loss: bool,
+    // Overrides when input is latched, detected from the game database if None
+    pub input_latch: Option<InputLatch>,
+    // Opens the debugger window at start (F8 toggles it while running)
+    pub debug_window: bool,
+    // Warns when the game is stuck polling $2002, the console's `stalls` command toggles it
+    pub detect_stalls: bool,
+    // Movie file the input of every frame is written to on exit, see movie
+    pub record_movie: Option<String>,
+}
+
+// Instructions kept for the state dump when the core fails
+const HISTORY_SIZE: usize = 64;
+const DUMP_PATH: &str = "nes_dump.txt";
+const MIXER_CONFIG_PATH: &str = "nes_mixer.cfg";
+// Key bindings of player 1 and 2
+const KEY_BINDINGS_PATHS: [&str; 2] = ["nes_keys.cfg", "nes_keys_2.cfg"];
+const ACCURACY_CONFIG_PATH: &str = "nes_accuracy.cfg";
+// Frames between the snapshots kept for crash autosaves
+const CRASH_SNAPSHOT_INTERVAL: usize = 60;
+// Frames gone back for each frame shown while rewinding, twice the speed they were played at
+const REWIND_SPEED: usize = 2;
+// Master volume step for the - and = keys
+const VOLUME_STEP: i16 = 10;
+const SAMPLE_RATE: i32 = 44100;
+// About 12ms per buffer, small enough that input latency isn't noticeable
+const AUDIO_BUFFER_SAMPLES: u16 = 512;
+// Without audio sync the sample rate is nudged to keep the queue around this long (~46ms)
+const TARGET_QUEUED_SAMPLES: usize = 4 * AUDIO_BUFFER_SAMPLES as usize;
+// Queues longer than this (~250ms, e.g. after the window was dragged) are dropped to catch up
+const MAX_QUEUED_SAMPLES: usize = SAMPLE_RATE as usize / 4;
+// Longest rumble SDL takes, the motor is stopped explicitly when the line goes low
+const RUMBLE_DURATION_MS: u32 = 0xFFFF;
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// Runs a frame, turning bus faults (panics) into errors so the window survives them
+fn next_frame_guarded(nes: &mut ActionNES, debugger: &Debugger) -> Result<StopReason, String> {
+    let run = || {
+        // Checking for breakpoints every instruction is slower, only done when there are some
+        if debugger.has_breakpoints() {
+            debugger.run_frame(nes)
+        } else {
+            nes.next_ppu_frame().map(|_| StopReason::FrameDone)
+        }
+    };
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(result) => result,
+        Err(payload) => Err(panic_message(payload)),
+    }
+}
+
+// Audio callback that emulates exactly enough cycles to fill each buffer, so emulation runs
+// at the audio device's rate
+struct AudioPacer {
+    nes: Arc<Mutex<ActionNES>>,
+    budget: CycleBudget,
+    // Cleared by the main loop while paused. It's only checked between buffers, and a buffer
+    // runs whole instructions, so a pause never splits an instruction's bus accesses.
+    is_running: Arc<AtomicBool>,
+    // Last sample played, faded out from when pausing
+    last_sample: i16,
+    error: Arc<Mutex<Option<String>>>,
+    recorder: Option<BackgroundWavWriter>,
+}
+
+impl AudioCallback for AudioPacer {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        out.fill(0);
+        if self.is_running.load(Ordering::Relaxed) {
+            self.run(out);
+        } else if self.last_sample != 0 {
+            // First buffer since pausing, cutting straight to silence would pop
+            MixerControls::fade_out(out, self.last_sample);
+        } else {
+            return;
+        }
+        self.last_sample = out.last().copied().unwrap_or(0);
+        if let Some(recorder) = &self.recorder {
+            recorder.push(out);
+        }
+    }
+}
+
+impl AudioPacer {
+    fn run(&mut self, out: &mut [i16]) {
+        let mut nes = self.nes.lock().unwrap();
+        let budget = &mut self.budget;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            budget.run_for_samples(&mut *nes, out.len())
+        }));
+        let result = match result {
+            Ok(result) => result.map(|_| ()),
+            Err(payload) => Err(panic_message(payload)),
+        };
+        if let Err(err) = result {
+            self.is_running.store(false, Ordering::Relaxed);
+            *self.error.lock().unwrap() = Some(err);
+        }
+        // The budget and the resampler carry the same fractions, so this is within a sample of
+        // a full buffer. Samples are mixed with the master volume already.
+        if let Some(audio) = nes.audio() {
+            audio.fill(out);
+        }
+    }
+}
+
+// Queue fed from the emulation loop when it's paced by the display. Audio is optional there,
+// the game runs silently if there's no device to play it on.
+fn open_audio_queue(sdl_context: &Sdl) -> Result<AudioQueue<i16>, String> {
+    let spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(AUDIO_BUFFER_SAMPLES),
+    };
+    let queue = sdl_context.audio()?.open_queue(None, &spec)?;
+    queue.resume();
+    Ok(queue)
+}
+
+// Queues the samples of the last frame, adjusting the sample rate to keep the queue from
+// running dry or growing with the difference between the display's and the audio clock
+fn queue_frame_audio(queue: &AudioQueue<i16>, nes: &mut ActionNES) {
+    let Some(audio) = nes.audio() else {
+        return;
+    };
+    let mut queued = queue.size() as usize / 2;
+    if queued > MAX_QUEUED_SAMPLES {
+        queue.clear();
+        queued = 0;
+    }
+    audio.adjust_for_queue(queued, TARGET_QUEUED_SAMPLES);
+    if let Err(err) = queue.queue_audio(&audio.take()) {
+        eprintln!("Failed to queue audio: {}", err);
+    }
+}
+
+// Handles the volume keys, returns the message to show
+fn adjust_mixer(mixer: &MixerControls, keycode: Keycode) -> Option<String> {
+    let message = match keycode {
+        Keycode::Minus | Keycode::Equals => {
+            let delta = if keycode == Keycode::Minus {
+                -VOLUME_STEP
+            } else {
+                VOLUME_STEP
+            };
+            format!("Volume {}%", mixer.adjust_master(delta))
+        }
+        Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 | Keycode::Num5 => {
+            let channel = keycode as usize - Keycode::Num1 as usize;
+            match mixer.toggle_mute(channel) {
+                true => format!("{} muted", CHANNEL_NAMES[channel]),
+                false => format!("{} unmuted", CHANNEL_NAMES[channel]),
+            }
+        }
+        _ => return None,
+    };
+    if let Err(err) = write(MIXER_CONFIG_PATH, mixer.to_config()) {
+        eprintln!("Failed to save {}: {}", MIXER_CONFIG_PATH, err);
+    }
+    Some(message)
+}
+
+fn console_title(input: &str, output: &str) -> String {
+    format!("NES console> {}_  {}", input, output)
+}
+
+// Where the game was paused, e.g. to note down a frame to come back to with a savestate
+fn paused_title(nes: &ActionNES) -> String {
+    format!(
+        "NES - Paused at frame {} ({})",
+        nes.frame_count(),
+        format_emulated_time(nes.emulated_duration())
+    )
+}
+
+// Saved bindings of a player, or their defaults
+fn load_bindings(player: usize) -> KeyBindings {
+    let mut bindings = match player {
+        0 => KeyBindings::new(),
+        _ => KeyBindings::player_2(),
+    };
+    if let Ok(config) = read_to_string(KEY_BINDINGS_PATHS[player]) {
+        if let Err(err) = bindings.load_config(&config) {
+            eprintln!("Ignoring {}: {}", KEY_BINDINGS_PATHS[player], err);
+        }
+    }
+    bindings
+}
+
+fn create_key_map(bindings: &KeyBindings, player: usize) -> HashMap<Keycode, ControllerState> {
+    let mut key_map = HashMap::new();
+    for (button, key) in bindings.iter() {
+        match Keycode::from_name(key) {
+            Some(keycode) => {
+                key_map.insert(keycode, button);
+            }
+            None => eprintln!("Unknown key {} in {}", key, KEY_BINDINGS_PATHS[player]),
+        }
+    }
+    key_map
+}
+
+fn remap_title(player: usize, button: usize) -> String {
+    format!(
+        "NES - Press key for player {} {} (Esc to cancel)",
+        player + 1,
+        BUTTONS[button].1.to_uppercase()
+    )
+}
+
+// Rumbles a game controller while an output line is high
+struct Rumble {
+    controller: GameController,
+    line: u8,
+    is_on: bool,
+}
+
+impl Rumble {
+    fn open(sdl_context: &Sdl, line: u8) -> Result<Self, String> {
+        let subsystem = sdl_context.game_controller()?;
+        let count = subsystem.num_joysticks()?;
+        let controller = (0..count)
+            .filter(|index| subsystem.is_game_controller(*index))
+            .find_map(|index| subsystem.open(index).ok())
+            .ok_or("No game controller connected")?;
+        Ok(Rumble {
+            controller,
+            line,
+            is_on: false,
+        })
+    }
+}
+
+impl OutputPort for Rumble {
+    fn update_outputs(&mut self, latch: OutputLatch) {
+        let is_on = latch.is_set(self.line);
+        if is_on == self.is_on {
+            return;
+        }
+        self.is_on = is_on;
+        let (strength, duration) = if is_on {
+            (u16::MAX, RUMBLE_DURATION_MS)
+        } else {
+            (0, 0)
+        };
+        if let Err(err) = self.controller.set_rumble(strength, strength, duration) {
+            eprintln!("Failed to rumble {}: {}", self.controller.name(), err);
+        }
+    }
+}
+
+fn create_canvas(window: Window, sync: SyncMode) -> Canvas<Window> {
+    let builder = window.into_canvas();
+    let builder = match sync {
+        SyncMode::Vsync => builder.present_vsync(),
+        SyncMode::Adaptive | SyncMode::Sleep => builder,
+    };
+    let canvas = builder.build().expect("Failed to create renderer");
+    if sync == SyncMode::Adaptive {
+        // Only OpenGL renderers have a swap interval
+        let video = canvas.window().subsystem();
+        if let Err(err) = video.gl_set_swap_interval(SwapInterval::LateSwapTearing) {
+            eprintln!("Adaptive vsync isn't available ({}), using vsync", err);
+            return create_canvas(canvas.into_window(), SyncMode::Vsync);
+        }
+    }
+    canvas
+}
+
+// Not synced to vblank, presenting the game window already waits for it
+fn create_debug_canvas(window: Window) -> Canvas<Window> {
+    let mut canvas = window
+        .into_canvas()
+        .build()
+        .expect("Failed to create debugger renderer");
+    canvas
+        .set_logical_size(VIEW_WIDTH as u32, VIEW_HEIGHT as u32)
+        .expect("Failed to size debugger renderer");
+    canvas
+}
+
+// Make this function runnable with an NES object as an input
+#[allow(unused)]
+pub fn run(path: &str, options: RunOptions) {
+    // Initialize sdl display, 3x scale to start with
+    let (window_width, window_height) = match options.display.rotation {
+        Rotation::None => (256 * 3, 240 * 3),
+        _ => (240 * 3, 256 * 3),
+    };
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("NES", window_width, window_height)
+        .position_centered()
+        .resizable()
+        .build()
+        .unwrap();
+
+    let mut canvas = create_canvas(window, options.display.sync);
+    // Second window with the PPU viewers and registers, hidden until F8 or --debug-window
+    let mut debug_window = video_subsystem
+        .window(
+            "NES Debugger",
+            2 * VIEW_WIDTH as u32,
+            2 * VIEW_HEIGHT as u32,
+        )
+        .resizable()
+        .hidden()
+        .build()
+        .unwrap();
+    let debug_window_id = debug_window.id();
+    let mut show_debug_window = options.debug_window;
+    if show_debug_window {
+        debug_window.show();
+    }
+    let mut debug_canvas = create_debug_canvas(debug_window);
+    let mut debug_view = DebugView::new();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut rumble = options
+        .rumble_line
+        .and_then(|line| match Rumble::open(&sdl_context, line) {
+            Ok(rumble) => Some(rumble),
+            Err(err) => {
+                eprintln!("Rumble disabled: {}", err);
+                None
+            }
+        });
+
+    let mut gamepads = match Gamepads::open(&sdl_context) {
+        Ok(gamepads) => Some(gamepads),
+        Err(err) => {
+            eprintln!("Game controllers disabled: {}", err);
+            None
+        }
+    };
+
+    // Key mapping, for each player
+    let mut bindings = [load_bindings(0), load_bindings(1)];
+    let mut key_maps = [
+        create_key_map(&bindings[0], 0),
+        create_key_map(&bindings[1], 1),
+    ];
+    // Player and button being remapped and the bindings so far, emulation is paused meanwhile
+    // (F2, Shift+F2 for player 2)
+    let mut remapping: Option<(usize, usize, KeyBindings)> = None;
+    // Create a frame
+    let mut frame = Frame::new();
+    let mut nes = ActionNES::new();
+    if options.audit {
+        nes.enable_audit();
+    }
+    if options.profile_memory {
+        nes.enable_profiler();
+    }
+    if options.detect_stalls {
+        nes.enable_stall_detector(DEFAULT_STALL_FRAMES);
+    }
+    nes.load_from_path(path);
+    // Boards with a real-time clock show the real date, except in movies which have to replay
+    // the same
+    if options.record_movie.is_none() {
+        nes.set_clock_source(Box::new(HostClock));
+    }
+    // Battery RAM from the last session, kept in sync with game.sav while running
+    let mut battery_save = nes
+        .battery_ram()
+        .map(|_| BatterySave::new(sav_path(path), DEFAULT_FLUSH_INTERVAL));
+    if let Some(save) = &mut battery_save {
+        match save.load() {
+            Ok(Some(data)) => nes.load_battery_ram(&data),
+            Ok(None) => {}
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+    nes.reset();
+    nes.port_2 = match options.port_2 {
+        Some(device) => device,
+        None => detect_port_2(&nes.rom, path, options.game_db.as_deref()),
+    };
+    nes.region = match options.region {
+        Some(region) => region,
+        None => detect_region(&nes.rom, path, options.game_db.as_deref()),
+    };
+    let mut accuracy = options.accuracy.unwrap_or_else(|| {
+        read_to_string(ACCURACY_CONFIG_PATH)
+            .ok()
+            .and_then(|config| match AccuracyPreset::parse(config.trim()) {
+                Ok(preset) => Some(preset),
+                Err(err) => {
+                    eprintln!("Ignoring {}: {}", ACCURACY_CONFIG_PATH, err);
+                    None
+                }
+            })
+            .unwrap_or_default()
+    });
+    accuracy.settings().apply(&mut nes);
+    nes.enable_history(HISTORY_SIZE);
+    let autosave_path = autosave_path(path);
+    // Movies start at power on, and frames run by the audio callback can't be recorded
+    let mut movie = match &options.record_movie {
+        Some(_) if options.audio_sync || options.record_audio.is_some() => {
+            eprintln!("Not recording a movie, it can't be recorded with audio sync");
+            None
+        }
+        Some(_) if options.resume => {
+            eprintln!("Not resuming, movies are recorded from power on");
+            Some(Movie::new(&nes.rom))
+        }
+        Some(_) => Some(Movie::new(&nes.rom)),
+        None => None,
+    };
+    if options.resume && movie.is_none() {
+        match autosave::restore(&autosave_path, &mut nes) {
+            Ok(kind) => eprintln!("Resumed from the {} autosave", kind),
+            Err(err) => eprintln!("Can't resume: {}", err),
+        }
+    }
+    // A recent good state for the panic hook, the state at the panic may be the broken one
+    let baseline = SnapshotBaseline::power_on();
+    let crash_snapshot = Arc::new(Mutex::new(Snapshot::capture(&nes, &baseline)));
+    let hook_snapshot = Arc::clone(&crash_snapshot);
+    let hook_rom = nes.rom.clone();
+    let hook_path = autosave_path.clone();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        // try_lock, the panicking thread might be the one holding it
+        if let Ok(snapshot) = hook_snapshot.try_lock() {
+            match autosave::write(&hook_path, &hook_rom, &snapshot, AutosaveKind::Crash) {
+                Ok(()) => eprintln!("Wrote crash autosave to {}", hook_path.display()),
+                Err(err) => eprintln!("Failed to write crash autosave: {}", err),
+            }
+        }
+    }));
+    let mut frame_number = 0;
+    let mixer = nes.mixer().clone();
+    if let Ok(config) = read_to_string(MIXER_CONFIG_PATH) {
+        if let Err(err) = mixer.load_config(&config) {
+            eprintln!("Ignoring {}: {}", MIXER_CONFIG_PATH, err);
+        }
+    }
+    // Set while emulation is paused after an error
+    let mut error: Option<String> = None;
+    // Toggled with F3
+    let mut show_timing_hud = false;
+    let mut show_priority_colors = false;
+    // Input line while the debug console is open (toggled with `), emulation is paused meanwhile
+    let mut console: Option<String> = None;
+    let mut debugger = Debugger::new();
+    // Toggled with P or Pause, and set while the window is unfocused with pause_on_focus_loss
+    let mut paused = false;
+    let mut focus_lost = false;
+    // Held with Backspace, goes back through the last 10 seconds
+    let mut rewind = RewindBuffer::default();
+    let mut rewinding = false;
+    // Address of the loop the game was last reported stuck in
+    let mut reported_stall = None;
+    let mut frame_stats = FrameStats::new();
+    if let Some(path) = &options.frame_stats {
+        let file = File::create(path).expect("Failed to create frame stats file");
+        frame_stats.log_to(BufWriter::new(file)).unwrap();
+    }
+    // Without vsync the loop sleeps between frames itself
+    let mut pacer = (options.display.sync == SyncMode::Sleep).then(|| {
+        let refresh_rate = canvas.window().display_mode().ok();
+        let refresh_rate = refresh_rate
+            .filter(|mode| mode.refresh_rate > 0)
+            .map(|mode| mode.refresh_rate as f64);
+        FramePacer::new(pacing_rate(refresh_rate, options.display.smoothing))
+    });
+
+    // Input is latched into the controller once per frame, with every key pressed since the last
+    // frame even if it's been released already. By default that's at vblank, or with the
+    // immediate latch at the start of the frame, right after reading events.
+    let input_state = Arc::new(Mutex::new(ButtonLatch::new()));
+    // Player 2's, latched at the start of every frame
+    let input_state_2 = Arc::new(Mutex::new(ButtonLatch::new()));
+    // By player, for game controllers
+    let latches = [Arc::clone(&input_state), Arc::clone(&input_state_2)];
+    // Movies replay each frame's input from its start
+    let input_latch = match movie {
+        Some(_) => InputLatch::Immediate,
+        None => options
+            .input_latch
+            .unwrap_or_else(|| detect_input_latch(&nes.rom, path, options.game_db.as_deref())),
+    };
+    if input_latch == InputLatch::Vblank {
+        let hook_input_state = Arc::clone(&input_state);
+        nes.set_on_vblank(move |controller| {
+            controller.set_controller_state(hook_input_state.lock().unwrap().poll_input());
+        });
+    }
+
+    // With audio sync the audio callback runs the emulation and this loop only draws frames
+    let shared_nes = Arc::new(Mutex::new(nes));
+    let is_running = Arc::new(AtomicBool::new(true));
+    let audio_error = Arc::new(Mutex::new(None));
+    let audio_device = if options.audio_sync || options.record_audio.is_some() {
+        let audio_subsystem = sdl_context.audio().unwrap();
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(AUDIO_BUFFER_SAMPLES),
+        };
+        let device = audio_subsystem
+            .open_playback(None, &spec, |spec| {
+                shared_nes.lock().unwrap().enable_audio(spec.freq as u32);
+                AudioPacer {
+                    nes: Arc::clone(&shared_nes),
+                    budget: CycleBudget::new(spec.freq as u32),
+                    is_running: Arc::clone(&is_running),
+                    last_sample: 0,
+                    error: Arc::clone(&audio_error),
+                    recorder: options.record_audio.as_deref().map(|path| {
+                        BackgroundWavWriter::create(path, spec.freq as u32)
+                            .expect("Failed to create WAV file")
+                    }),
+                }
+            })
+            .unwrap();
+        device.resume();
+        Some(device)
+    } else {
+        None
+    };
+    let audio_queue = if audio_device.is_none() {
+        match open_audio_queue(&sdl_context) {
+            Ok(queue) => {
+                shared_nes
+                    .lock()
+                    .unwrap()
+                    .enable_audio(queue.spec().freq as u32);
+                Some(queue)
+            }
+            Err(err) => {
+                eprintln!("Audio disabled: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut external_input: Option<Box<dyn InputPort>> = match &options.input {
+        InputSource::Keyboard => None,
+        InputSource::Stdin => Some(Box::new(StreamInput::stdio())),
+        InputSource::Fifo { input, output } => Some(Box::new(
+            StreamInput::open_fifo(input, output).expect("Failed to open input pipes"),
+        )),
+    };
+
+    // Rebuilds the renderer when the display device is lost, the emulator state is untouched
+    loop {
+        let creator = canvas.texture_creator();
+        let mut texture = creator
+            .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
+            .unwrap();
+        let debug_creator = debug_canvas.texture_creator();
+        let mut debug_texture = debug_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                VIEW_WIDTH as u32,
+                VIEW_HEIGHT as u32,
+            )
+            .unwrap();
+        // Set when textures were lost, the renderer is rebuilt after this frame
+        let mut display_lost = false;
+
+        loop {
+            // 0. External input replaces the keyboard for the next frame
+            if let Some(external_input) = &mut external_input {
+                input_state.lock().unwrap().set(external_input.poll_input());
+            }
+
+            let frame_start = Instant::now();
+            let mut nes_guard = shared_nes.lock().unwrap();
+            let nes = &mut *nes_guard;
+            if input_latch == InputLatch::Immediate {
+                let state = input_state.lock().unwrap().poll_input();
+                nes.controller.set_controller_state(state);
+            }
+            let state = input_state_2.lock().unwrap().poll_input();
+            if let Some(controller) = nes.player_controller(1) {
+                controller.set_controller_state(state);
+            }
+
+            // 1. Execute until next frame, pausing on errors. Pausing only takes effect between
+            // frames (or audio buffers), which always end on an instruction boundary.
+            let is_paused =
+                error.is_some() || console.is_some() || remapping.is_some() || paused || focus_lost;
+            let is_rewinding = rewinding && !is_paused;
+            let is_paused = is_paused || is_rewinding;
+            let frame_result = if is_rewinding {
+                rewind.rewind(nes, REWIND_SPEED).err().map(Err)
+            } else if audio_device.is_some() {
+                is_running.store(!is_paused, Ordering::Relaxed);
+                audio_error.lock().unwrap().take().map(Err)
+            } else if !is_paused {
+                if let Some(movie) = &mut movie {
+                    movie.record(nes);
+                }
+                Some(next_frame_guarded(nes, &debugger))
+            } else {
+                None
+            };
+            if let Some(queue) = &audio_queue {
+                queue_frame_audio(queue, nes);
+            }
+            // The warning and state dump go to stderr, the title only points there
+            let stall = nes.stall_detector().and_then(|detector| detector.stall());
+            let stalled_at = stall.map(|stall| stall.program_counter);
+            if stalled_at != reported_stall {
+                if let Some(addr) = stalled_at {
+                    let title = format!("NES - Stuck polling $2002 at {:04X}, see stderr", addr);
+                    canvas.window_mut().set_title(&title);
+                }
+                reported_stall = stalled_at;
+            }
+            frame_number += 1;
+            if let Some(rumble) = &mut rumble {
+                rumble.update_outputs(nes.output_latch());
+            }
+            if error.is_none() && !is_rewinding {
+                rewind.record(nes);
+            }
+            if error.is_none() && frame_number % CRASH_SNAPSHOT_INTERVAL == 0 {
+                *crash_snapshot.lock().unwrap() = Snapshot::capture(nes, &baseline);
+            }
+            // Stops saving after a failed write rather than retrying every frame
+            if let (Some(save), Some(data), None) = (&mut battery_save, nes.battery_ram(), &error) {
+                if let Err(err) = save.update(&data, Instant::now()) {
+                    eprintln!("Battery saves disabled: {}", err);
+                    battery_save = None;
+                }
+            }
+            if let Some(result) = frame_result {
+                match result {
+                    Ok(StopReason::FrameDone) => {}
+                    Ok(StopReason::Breakpoint(addr)) => {
+                        let output = format!("Break at {:04X}", addr);
+                        println!("{}", output);
+                        canvas.window_mut().set_title(&console_title("", &output));
+                        console = Some(String::new());
+                    }
+                    Err(err) => {
+                        eprintln!("Emulation paused: {}", err);
+                        let title = format!("NES - {} - [C]ontinue [R]eset [D]ump state", err);
+                        canvas.window_mut().set_title(&title);
+                        error = Some(err);
+                    }
+                }
+            }
+
+            // 2. Update the display
+            let render_start = Instant::now();
+            nes.render_frame(&mut frame);
+            if show_priority_colors {
+                frame.colorize_priority();
+            }
+            options.display.apply(&mut frame);
+            if show_timing_hud {
+                draw_timing_hud(&mut frame, &nes.ppu_state.timing);
+                draw_chr_write_counter(&mut frame, &nes.ppu_state.chr_writes);
+                draw_sprite_counters(&mut frame, &nes.sprite_stats());
+            }
+            if let Some(profile) = nes.profile() {
+                draw_profile_hud(&mut frame, &profile.regions(nes.rom.prg_rom.len()));
+            }
+            if paused || focus_lost {
+                draw_pause_icon(&mut frame);
+            }
+            if show_debug_window {
+                debug_view.render(&nes.cpu_state, &nes.ppu_state, &nes.rom);
+            }
+            // Presenting waits for vsync, the audio callback can't be kept waiting that long
+            drop(nes_guard);
+            let present_start = Instant::now();
+            if texture.update(None, frame.as_bytes_ref(), 256 * 3).is_err() {
+                display_lost = true;
+            }
+            // Recomputed every frame so that resizing the window keeps the aspect ratio
+            let (width, height) = canvas.output_size().unwrap();
+            let target = options.display.target_rect(width, height);
+            let target = Rect::new(target.x, target.y, target.width, target.height);
+            canvas.set_draw_color(Color::BLACK);
+            canvas.clear();
+            let copied = canvas.copy_ex(
+                &texture,
+                None,
+                target,
+                options.display.rotation.degrees(),
+                None,
+                false,
+                false,
+            );
+            if copied.is_err() {
+                display_lost = true;
+            }
+            if show_debug_window {
+                let bytes = debug_view.to_bytes();
+                if debug_texture.update(None, &bytes, VIEW_WIDTH * 3).is_err()
+                    || debug_canvas.copy(&debug_texture, None, None).is_err()
+                {
+                    display_lost = true;
+                }
+                debug_canvas.present();
+            }
+            let sleep_start = Instant::now();
+            canvas.present();
+            if let Some(pacer) = &mut pacer {
+                pacer.wait();
+            }
+            let timings = FrameTimings {
+                emulation: render_start - frame_start,
+                render: present_start - render_start,
+                present: sleep_start - present_start,
+                // Presenting blocks until vsync, or the pacer sleeps
+                sleep: sleep_start.elapsed(),
+                paused: is_paused,
+            };
+            if let Err(err) = frame_stats.record(timings) {
+                eprintln!("Failed to write frame stats: {}", err);
+            }
+
+            // 3. Read user input
+            let mut nes_guard = shared_nes.lock().unwrap();
+            let nes = &mut *nes_guard;
+            for event in event_pump.poll_iter() {
+                match event {
+                    // Remapping takes every key, including Escape to cancel
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } if remapping.is_some() => {
+                        let (player, button, pending) = remapping.as_mut().unwrap();
+                        if keycode == Keycode::Escape {
+                            remapping = None;
+                            canvas.window_mut().set_title("NES - Remapping cancelled");
+                            continue;
+                        }
+                        pending.set_key(*button, &keycode.name());
+                        *button += 1;
+                        if *button < BUTTONS.len() {
+                            canvas
+                                .window_mut()
+                                .set_title(&remap_title(*player, *button));
+                            continue;
+                        }
+                        let (player, _, pending) = remapping.take().unwrap();
+                        key_maps[player] = create_key_map(&pending, player);
+                        bindings[player] = pending;
+                        input_state.lock().unwrap().set(ControllerState::empty());
+                        input_state_2.lock().unwrap().set(ControllerState::empty());
+                        let path = KEY_BINDINGS_PATHS[player];
+                        let title = match write(path, bindings[player].to_config()) {
+                            Ok(()) => format!("NES - Controls saved to {}", path),
+                            Err(err) => format!("NES - Failed to save controls: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F2),
+                        keymod,
+                        ..
+                    } if error.is_none() && console.is_none() => {
+                        let player = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) as usize;
+                        remapping = Some((player, 0, bindings[player].clone()));
+                        canvas.window_mut().set_title(&remap_title(player, 0));
+                    }
+                    // "remap" or "remap 2" in the debug console does the same
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Return),
+                        ..
+                    } if matches!(console.as_deref().map(str::trim), Some("remap" | "remap 2")) => {
+                        let player =
+                            (console.as_deref().map(str::trim) == Some("remap 2")) as usize;
+                        console = None;
+                        remapping = Some((player, 0, bindings[player].clone()));
+                        canvas.window_mut().set_title(&remap_title(player, 0));
+                    }
+                    // The debugger window only handles being closed, keys pressed there act on
+                    // the game like in the game window
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Close,
+                        ..
+                    }
+                    | Event::KeyDown {
+                        window_id,
+                        keycode: Some(Keycode::Escape | Keycode::F8),
+                        ..
+                    } if window_id == debug_window_id => {
+                        show_debug_window = false;
+                        debug_canvas.window_mut().hide();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F8),
+                        ..
+                    } => {
+                        show_debug_window = !show_debug_window;
+                        match show_debug_window {
+                            true => debug_canvas.window_mut().show(),
+                            false => debug_canvas.window_mut().hide(),
+                        }
+                    }
+                    // With two windows open, closing the game window doesn't send Quit
+                    Event::Quit { .. }
+                    | Event::Window {
+                        win_event: WindowEvent::Close,
+                        ..
+                    }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => {
+                        if let Some(audit) = nes.audit() {
+                            eprint!("{}", audit.report());
+                        }
+                        if let Some(profile) = nes.profile() {
+                            eprint!("{}", profile.report(nes.rom.prg_rom.len()));
+                        }
+                        // Paused on an error the state may be broken, keep the last good one
+                        let (snapshot, kind) = match error {
+                            None => (Snapshot::capture(nes, &baseline), AutosaveKind::Exit),
+                            Some(_) => {
+                                (crash_snapshot.lock().unwrap().clone(), AutosaveKind::Crash)
+                            }
+                        };
+                        if let Err(err) = autosave::write(&autosave_path, &nes.rom, &snapshot, kind)
+                        {
+                            eprintln!("Failed to write autosave: {}", err);
+                        }
+                        // Unless paused on an error, the game may have broken its save
+                        if let (Some(save), Some(data), None) =
+                            (&mut battery_save, nes.battery_ram(), &error)
+                        {
+                            if let Err(err) = save.flush(&data) {
+                                eprintln!("{}", err);
+                            }
+                        }
+                        if let (Some(movie), Some(path)) = (&movie, &options.record_movie) {
+                            match movie.save(path) {
+                                Ok(()) => {
+                                    eprintln!("Recorded {} frames to {}", movie.frames.len(), path)
+                                }
+                                Err(err) => eprintln!("Failed to save movie: {}", err),
+                            }
+                        }
+                        if options.frame_stats.is_some() {
+                            let average = frame_stats.average();
+                            eprintln!(
+                                "{} frames ({} paused), average emulation {}us render {}us present {}us sleep {}us, frame time deviation {}us",
+                                frame_stats.frame_count(),
+                                frame_stats.paused_count(),
+                                average.emulation.as_micros(),
+                                average.render.as_micros(),
+                                average.present.as_micros(),
+                                average.sleep.as_micros(),
+                                frame_stats.deviation().as_micros()
+                            );
+                            // Exiting skips destructors, so the buffered rows are written here
+                            if let Err(err) = frame_stats.flush() {
+                                eprintln!("Failed to write frame stats: {}", err);
+                            }
+                        }
+                        // Closing the device drops the audio recorder, finishing the WAV file. The
+                        // callback may be waiting for the NES, so that's unlocked first.
+                        drop(nes_guard);
+                        drop(audio_device);
+                        std::process::exit(0)
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backquote),
+                        ..
+                    } if error.is_none() => {
+                        console = match console {
+                            Some(_) => {
+                                canvas.window_mut().set_title("NES");
+                                None
+                            }
+                            None => {
+                                canvas.window_mut().set_title(&console_title("", ""));
+                                Some(String::new())
+                            }
+                        };
+                    }
+                    // Debug console, output also goes to stdout since the title only fits one line
+                    Event::TextInput { text, .. } if console.is_some() => {
+                        let input = console.as_mut().unwrap();
+                        input.extend(text.chars().filter(|c| *c != '`'));
+                        canvas.window_mut().set_title(&console_title(input, ""));
+                    }
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } if console.is_some() => {
+                        let input = console.as_mut().unwrap();
+                        let output = match keycode {
+                            Keycode::Backspace => {
+                                input.pop();
+                                String::new()
+                            }
+                            Keycode::Return => {
+                                let result = debugger.execute(nes, input);
+                                input.clear();
+                                let output = result.unwrap_or_else(|err| err);
+                                print!("{}", output);
+                                if !output.ends_with('\n') {
+                                    println!();
+                                }
+                                output.lines().last().unwrap_or_default().to_string()
+                            }
+                            _ => continue,
+                        };
+                        canvas
+                            .window_mut()
+                            .set_title(&console_title(input, &output));
+                    }
+                    // Error menu
+                    Event::KeyDown {
+                        keycode: Some(keycode @ (Keycode::C | Keycode::R | Keycode::D)),
+                        ..
+                    } if error.is_some() => match keycode {
+                        Keycode::C | Keycode::R => {
+                            if keycode == Keycode::R {
+                                nes.reset();
+                            }
+                            error = None;
+                            canvas.window_mut().set_title("NES");
+                        }
+                        _ => match write(DUMP_PATH, nes.dump_state()) {
+                            Ok(()) => eprintln!("Dumped state to {}", DUMP_PATH),
+                            Err(err) => eprintln!("Failed to dump state: {}", err),
+                        },
+                    },
+                    // Held to rewind, left to the game like P if it's bound to a button
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        repeat: false,
+                        ..
+                    } if error.is_none()
+                        && key_maps
+                            .iter()
+                            .all(|key_map| !key_map.contains_key(&Keycode::Backspace)) =>
+                    {
+                        rewinding = true;
+                        canvas.window_mut().set_title("NES - Rewinding");
+                    }
+                    Event::KeyUp {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } if rewinding => {
+                        rewinding = false;
+                        canvas.window_mut().set_title("NES");
+                    }
+                    // P is left to the game if it's bound to a button
+                    Event::KeyDown {
+                        keycode: Some(keycode @ (Keycode::P | Keycode::Pause)),
+                        ..
+                    } if error.is_none()
+                        && (keycode == Keycode::Pause
+                            || key_maps
+                                .iter()
+                                .all(|key_map| !key_map.contains_key(&keycode))) =>
+                    {
+                        paused = !paused;
+                        let title = match paused {
+                            true => paused_title(nes),
+                            false => "NES".to_string(),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::FocusLost,
+                        ..
+                    } if options.pause_on_focus_loss => {
+                        focus_lost = true;
+                        // Keys released while unfocused never send KeyUp
+                        input_state.lock().unwrap().set(ControllerState::empty());
+                        input_state_2.lock().unwrap().set(ControllerState::empty());
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::FocusGained,
+                        ..
+                    } => focus_lost = false,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        ..
+                    } => {
+                        show_timing_hud = !show_timing_hud;
+                        let chr_writes = &nes.ppu_state.chr_writes;
+                        if show_timing_hud && chr_writes.count > 0 {
+                            canvas.window_mut().set_title(&format!(
+                                "NES - {} CHR ROM writes ignored ({} addresses)",
+                                chr_writes.count,
+                                chr_writes.addresses()
+                            ));
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F4),
+                        ..
+                    } => show_priority_colors = !show_priority_colors,
+                    // Quick savestate next to the ROM, not while paused on an error since the
+                    // state may be broken
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        ..
+                    } if error.is_none() => {
+                        let state_path = savestate_path(path);
+                        let title = match write_atomic(&state_path, &nes.save_state()) {
+                            Ok(()) => format!("NES - Saved state to {}", state_path.display()),
+                            Err(err) => format!("NES - Failed to save state: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    // Loading one also gets out of an error
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F7),
+                        ..
+                    } => {
+                        let state_path = savestate_path(path);
+                        let loaded = read(&state_path)
+                            .map_err(|err| err.to_string())
+                            .and_then(|bytes| nes.load_state(&bytes));
+                        let title = match loaded {
+                            Ok(()) => {
+                                error = None;
+                                format!("NES - Loaded state from {}", state_path.display())
+                            }
+                            Err(err) => format!("NES - Failed to load state: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F6),
+                        ..
+                    } => match nes.profile() {
+                        Some(profile) => {
+                            eprint!("{}", profile.report(nes.rom.prg_rom.len()));
+                            nes.disable_profiler();
+                        }
+                        None => nes.enable_profiler(),
+                    },
+                    // Every toggle can change between instructions, so the switch is immediate
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F10),
+                        ..
+                    } => {
+                        accuracy = accuracy.next();
+                        accuracy.settings().apply(nes);
+                        let title = match write(ACCURACY_CONFIG_PATH, accuracy.to_string()) {
+                            Ok(()) => format!("NES - Accuracy: {}", accuracy),
+                            Err(err) => format!("NES - Failed to save accuracy: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    // Saved as shown, with the overlays and display options
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F12),
+                        ..
+                    } => {
+                        let info = ScreenshotInfo::for_nes(nes, path);
+                        let title = match save_screenshot(&frame, Path::new("."), &info) {
+                            Ok(saved) => format!("NES - Saved {}", saved.display()),
+                            Err(err) => format!("NES - Failed to save screenshot: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    // Pattern tables in the first background palette, next to the screenshots
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F9),
+                        ..
+                    } => {
+                        let info = ScreenshotInfo::for_nes(nes, path);
+                        let sheet_path = format!("{}_chr.png", info.rom_name);
+                        let colors = ppu_palette(&nes.ppu_state, 0);
+                        let title = match export_chr_sheets(&nes.rom, &colors, &sheet_path) {
+                            Ok(written) => format!("NES - Saved {}", written.join(", ")),
+                            Err(err) => format!("NES - Failed to export CHR: {}", err),
+                        };
+                        canvas.window_mut().set_title(&title);
+                    }
+                    // Volume, shown in the title until something else replaces it
+                    Event::KeyDown {
+                        keycode:
+                            Some(
+                                keycode @ (Keycode::Minus
+                                | Keycode::Equals
+                                | Keycode::Num1
+                                | Keycode::Num2
+                                | Keycode::Num3
+                                | Keycode::Num4
+                                | Keycode::Num5),
+                            ),
+                        ..
+                    } if error.is_none() => {
+                        if let Some(message) = adjust_mixer(&mixer, keycode) {
+                            canvas.window_mut().set_title(&format!("NES - {}", message));
+                        }
+                    }
+                    Event::ControllerDeviceAdded { which, .. } => {
+                        let connected = gamepads.as_mut().and_then(|pads| pads.connect(which));
+                        if let Some((player, name)) = connected {
+                            let title = format!("NES - {} is player {}", name, player + 1);
+                            canvas.window_mut().set_title(&title);
+                        }
+                    }
+                    Event::ControllerDeviceRemoved { which, .. } => {
+                        let player = gamepads.as_mut().and_then(|pads| pads.disconnect(which));
+                        if let Some(player) = player {
+                            // Buttons held on the pad are let go of with it
+                            latches[player]
+                                .lock()
+                                .unwrap()
+                                .set(ControllerState::empty());
+                            let title = format!("NES - Player {} controller unplugged", player + 1);
+                            canvas.window_mut().set_title(&title);
+                        }
+                    }
+                    Event::ControllerButtonDown { which, button, .. } => {
+                        let player = gamepads.as_ref().and_then(|pads| pads.player(which));
+                        if let (Some(player), Some(state)) = (player, button_state(button)) {
+                            latches[player].lock().unwrap().press(state);
+                        }
+                    }
+                    Event::ControllerButtonUp { which, button, .. } => {
+                        let player = gamepads.as_ref().and_then(|pads| pads.player(which));
+                        if let (Some(player), Some(state)) = (player, button_state(button)) {
+                            latches[player].lock().unwrap().release(state);
+                        }
+                    }
+                    Event::ControllerAxisMotion {
+                        which, axis, value, ..
+                    } => {
+                        let player = gamepads.as_ref().and_then(|pads| pads.player(which));
+                        if let (Some(player), Some((pressed, released))) =
+                            (player, stick_state(axis, value))
+                        {
+                            let mut latch = latches[player].lock().unwrap();
+                            latch.release(released);
+                            latch.press(pressed);
+                        }
+                    }
+                    Event::KeyDown { keycode, .. } => {
+                        let keycode = keycode.unwrap_or(Keycode::Ampersand);
+                        if let Some(key) = key_maps[0].get(&keycode) {
+                            input_state.lock().unwrap().press(*key);
+                        }
+                        if let Some(key) = key_maps[1].get(&keycode) {
+                            input_state_2.lock().unwrap().press(*key);
+                        }
+                    }
+                    Event::KeyUp { keycode, .. } => {
+                        let keycode = keycode.unwrap_or(Keycode::Ampersand);
+                        if let Some(key) = key_maps[0].get(&keycode) {
+                            input_state.lock().unwrap().release(*key);
+                        }
+                        if let Some(key) = key_maps[1].get(&keycode) {
+                            input_state_2.lock().unwrap().release(*key);
+                        }
+                    }
+                    // Mouse drives the device in port 2
+                    Event::MouseMotion { x, xrel, yrel, .. } => match &mut nes.port_2 {
+                        PortDevice::Paddle(paddle) => {
+                            let (width, height) = canvas.output_size().unwrap();
+                            let target = options.display.target_rect(width, height);
+                            paddle.set_position_from_screen(x - target.x, target.width)
+                        }
+                        PortDevice::Mouse(mouse) => mouse.add_motion(xrel, yrel),
+                        _ => {}
+                    },
+                    Event::MouseButtonDown { mouse_btn, .. }
+                    | Event::MouseButtonUp { mouse_btn, .. } => {
+                        let is_down = matches!(event, Event::MouseButtonDown { .. });
+                        match (&mut nes.port_2, mouse_btn) {
+                            (PortDevice::Paddle(paddle), MouseButton::Left) => {
+                                paddle.fire = is_down
+                            }
+                            (PortDevice::Mouse(mouse), MouseButton::Left) => mouse.left = is_down,
+                            (PortDevice::Mouse(mouse), MouseButton::Right) => mouse.right = is_down,
+                            _ => {}
+                        }
+                    }
+                    // Target textures lose their contents, but the frame is uploaded every frame
+                    Event::RenderTargetsReset { .. } => {}
+                    Event::RenderDeviceReset { .. } => display_lost = true,
+                    _ => {}
+                }
+            }
+            if display_lost {
+                break;
+            }
+        }
+
+        // Emulation is paused while rebuilding, the audio callback also stops running frames
+        is_running.store(false, Ordering::Relaxed);
+        eprintln!("Display device lost, recreating the renderer");
+        drop(texture);
+        drop(creator);
+        drop(debug_texture);
+        drop(debug_creator);
+        canvas = create_canvas(canvas.into_window(), options.display.sync);
+        debug_canvas = create_debug_canvas(debug_canvas.into_window());
+    }
+}
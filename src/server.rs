@@ -0,0 +1,394 @@
+// Headless server streaming frames to a browser over WebSocket (RFC 6455)
+//
+// One client is served at a time, and the emulator keeps running from where the last client
+// left off. Every frame is sent as a binary message, a PNG or 256x240 packed RGB bytes. Clients
+// send the controller bitmask as a text message in the --input-stdin format ("0x81", "129" or
+// "0b10000001"), or as a one byte binary message, and it stays held until the next one.
+//
+//     const ws = new WebSocket("ws://localhost:8080");
+//     ws.onmessage = (msg) => img.src = URL.createObjectURL(msg.data);
+//     ws.send("0x08"); // Start
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::async_nes::{block_on, AsyncNes};
+use crate::controller::ControllerState;
+use crate::frontend::{parse_buttons, InputPort, VideoSink};
+use crate::nes::NES;
+use crate::screen::frame::Frame;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+// Input messages are a few bytes, anything bigger isn't from a well behaved client
+const MAX_MESSAGE_SIZE: u64 = 1024;
+const MAX_REQUEST_SIZE: usize = 8192;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// Clients can press buttons, so only this machine can connect unless asked otherwise
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    #[default]
+    Png,
+    // 256x240 packed RGB, bigger but nothing to decode
+    Raw,
+}
+
+impl FrameFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "png" => Ok(FrameFormat::Png),
+            "raw" => Ok(FrameFormat::Raw),
+            _ => Err(format!("Unknown frame format {}", name)),
+        }
+    }
+}
+
+/// Listens on `host`:`port` and streams `nes` to one client at a time, forever
+pub fn serve(nes: AsyncNes, host: &str, port: u16, format: FrameFormat) -> Result<(), String> {
+    let listener = TcpListener::bind((host, port)).map_err(|e| e.to_string())?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    eprintln!("Serving on ws://{}", addr);
+    let mut nes = nes;
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(|e| e.to_string())
+            .and_then(|stream| serve_client(&mut nes, stream, format, None));
+        match result {
+            Ok(()) => eprintln!("Client disconnected"),
+            Err(err) => eprintln!("Client dropped: {}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Runs `nes` for one client at 60 frames per second until it disconnects, or for `frames`
+/// frames
+pub fn serve_client(
+    nes: &mut AsyncNes,
+    stream: TcpStream,
+    format: FrameFormat,
+    frames: Option<usize>,
+) -> Result<(), String> {
+    let mut client = RemoteClient::accept(stream, format)?;
+    let mut frame = Frame::new();
+    let mut deadline = Instant::now();
+    let mut count = 0;
+    while !client.is_closed() && frames.is_none_or(|frames| count < frames) {
+        let state = client.poll_input();
+        nes.nes_mut()
+            .update_controller(0, ControllerState::all(), false);
+        nes.nes_mut().update_controller(0, state, true);
+        block_on(nes.run_frame())?;
+        nes.nes().render_frame(&mut frame);
+        client.present_frame(&frame)?;
+        count += 1;
+
+        deadline += FRAME_DURATION;
+        let now = Instant::now();
+        match deadline.checked_duration_since(now) {
+            Some(wait) => thread::sleep(wait),
+            // Fell behind, e.g. a slow network, don't try to catch up
+            None => deadline = now,
+        }
+    }
+    Ok(())
+}
+
+/// A connected browser, frames go out on this thread and input is read on another
+pub struct RemoteClient {
+    stream: TcpStream,
+    format: FrameFormat,
+    buttons: Arc<Mutex<ControllerState>>,
+    is_closed: Arc<AtomicBool>,
+}
+
+impl RemoteClient {
+    /// Completes the WebSocket handshake and starts reading input messages
+    pub fn accept(mut stream: TcpStream, format: FrameFormat) -> Result<Self, String> {
+        handshake(&mut stream)?;
+        let buttons = Arc::new(Mutex::new(ControllerState::empty()));
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let mut reader = stream.try_clone().map_err(|e| e.to_string())?;
+        let (reader_buttons, reader_closed) = (Arc::clone(&buttons), Arc::clone(&is_closed));
+        thread::spawn(move || {
+            // Errors mean the connection is gone, same as a close message
+            while let Ok((opcode, payload)) = read_message(&mut reader, MAX_MESSAGE_SIZE) {
+                let state = match (opcode, &payload[..]) {
+                    (OPCODE_TEXT, text) => parse_buttons(&String::from_utf8_lossy(text)),
+                    (OPCODE_BINARY, [bits]) => Some(ControllerState::from_bits_retain(*bits)),
+                    (OPCODE_CLOSE, _) => break,
+                    _ => None,
+                };
+                if let Some(state) = state {
+                    *reader_buttons.lock().unwrap() = state;
+                }
+            }
+            reader_closed.store(true, Ordering::Relaxed);
+        });
+        Ok(RemoteClient {
+            stream,
+            format,
+            buttons,
+            is_closed,
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for RemoteClient {
+    fn drop(&mut self) {
+        // Also ends the input thread
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl VideoSink for RemoteClient {
+    fn present_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        let payload = match self.format {
+            FrameFormat::Png => frame.to_png()?,
+            FrameFormat::Raw => frame.as_bytes_ref().to_vec(),
+        };
+        write_message(&mut self.stream, OPCODE_BINARY, &payload).map_err(|e| e.to_string())
+    }
+}
+
+impl InputPort for RemoteClient {
+    fn poll_input(&mut self) -> ControllerState {
+        *self.buttons.lock().unwrap()
+    }
+}
+
+// Reads the HTTP upgrade request and accepts it
+fn handshake(stream: &mut (impl Read + Write)) -> Result<(), String> {
+    // Byte by byte, so nothing after the request is read into a buffer and lost
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err("Handshake request is too long".to_string());
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte).map_err(|e| e.to_string())?;
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let key = request
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, key)| key.trim())
+        .ok_or_else(|| "Not a WebSocket request".to_string())?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+// Unmasked and unfragmented, like servers send them
+fn write_message(output: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => header.push(len as u8),
+        len @ 126..=0xFFFF => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    output.write_all(&header)?;
+    output.write_all(payload)?;
+    output.flush()
+}
+
+// Returns the opcode and unmasked payload. Fragments come back as separate messages, input
+// messages are too small for browsers to split.
+fn read_message(input: &mut impl Read, max_len: u64) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    input.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            input.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            input.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message is too long",
+        ));
+    }
+    let mut mask = [0; 4];
+    if header[1] & 0x80 != 0 {
+        input.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    input.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+// Only used for the handshake, so speed doesn't matter
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hash: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = hash;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in hash.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_exact_mut(4).zip(hash) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ActionNES;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+        assert_eq!("YQ==", base64(b"a"));
+    }
+
+    #[test]
+    fn test_read_masked_message() {
+        let mask = [0x11, 0x22, 0x33, 0x44];
+        let mut bytes = vec![0x81, 0x84];
+        bytes.extend_from_slice(&mask);
+        bytes.extend(b"0x08".iter().zip(mask).map(|(byte, mask)| byte ^ mask));
+        let (opcode, payload) = read_message(&mut &bytes[..], MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!((OPCODE_TEXT, b"0x08".to_vec()), (opcode, payload));
+
+        let mut sent = Vec::new();
+        write_message(&mut sent, OPCODE_BINARY, &[0; 300]).unwrap();
+        assert_eq!([0x82, 126, 0x01, 0x2C], sent[..4]);
+        assert_eq!(304, sent.len());
+    }
+
+    #[test]
+    fn test_serve_client() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let request = "GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = Vec::new();
+            while !response.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+            let frames: Vec<_> = (0..2)
+                .map(|_| read_message(&mut stream, u64::MAX))
+                .collect();
+            (String::from_utf8(response).unwrap(), frames)
+        });
+
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        let mut nes = AsyncNes::new(nes);
+        let (stream, _) = listener.accept().unwrap();
+        serve_client(&mut nes, stream, FrameFormat::Raw, Some(2)).unwrap();
+
+        let (response, frames) = client.join().unwrap();
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        for frame in frames {
+            let (opcode, payload) = frame.unwrap();
+            assert_eq!(OPCODE_BINARY, opcode);
+            assert_eq!(3 * 256 * 240, payload.len());
+        }
+        assert_eq!(2, nes.nes().frame_count());
+    }
+}
@@ -0,0 +1,754 @@
+// Compact in-memory savestates, cheap enough to take every frame for rewind
+//
+// Memory regions are stored as XOR diffs against a baseline (power-on by default), keeping
+// only the runs of bytes that changed, and registers are copied as is. The ROM (apart from the
+// board's bank registers and CHR RAM), hooks, history, audit, scanline timing and the picture the
+// PPU drew aren't part of a snapshot, the accuracy settings and frame count are.
+//
+// Snapshots against the power-on baseline can also be written out with to_bytes, for
+// savestates on disk (see savestate.rs for the container). The board's registers go in the
+// container's mapper data (board_state), and of the controllers only controller 1's shift
+// register is written: the buttons held and the device in port 2 are whatever they are when the
+// bytes are loaded.
+use crate::accuracy::Accuracy;
+use crate::apu::{ApuState, ApuStatus};
+use crate::controller::Controller;
+use crate::cpu::{CpuStatus, PRG_RAM_SIZE};
+use crate::nes::ActionNES;
+use crate::peripheral::PortDevice;
+use crate::ppu::{
+    LineSprite, LoopyRegisters, OamAddr, PpuControl, PpuMask, PpuStatus, RenderPipeline,
+    MAX_LINE_SPRITES, VRAM_SIZE,
+};
+use crate::rom::mapper::Mapper;
+
+// Unchanged bytes shorter than this don't split a run, saves the 4 bytes of run header
+const MIN_GAP: usize = 4;
+// Bumped when the layout written by to_bytes changes
+const BYTES_VERSION: u8 = 8;
+// Pattern planes, attributes, x and the sprite 0 flag
+const LINE_SPRITE_BYTES: usize = 5;
+
+// Little endian encoding for to_bytes
+struct ByteWriter(Vec<u8>);
+
+impl ByteWriter {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.0.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| "Snapshot is truncated".to_string())?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+}
+
+/// Memory regions diffs are taken against
+#[derive(Debug, Clone)]
+pub struct SnapshotBaseline {
+    cpu_ram: [u8; 0x800],
+    prg_ram: Vec<u8>,
+    ppu_ram: [u8; VRAM_SIZE],
+    oam_data: [u8; 256],
+    palette_table: [u8; 32],
+    // Empty for ROMs without CHR RAM, diffs against zeros then
+    chr_ram: Vec<u8>,
+}
+
+impl Default for SnapshotBaseline {
+    fn default() -> Self {
+        Self::power_on()
+    }
+}
+
+impl SnapshotBaseline {
+    /// Memory as it is after ActionNES::new
+    pub fn power_on() -> Self {
+        Self::from_nes(&ActionNES::new())
+    }
+
+    /// Uses the current memory of `nes`, e.g. a keyframe a few seconds back
+    pub fn from_nes(nes: &ActionNES) -> Self {
+        SnapshotBaseline {
+            cpu_ram: nes.cpu_state.ram,
+            prg_ram: nes.cpu_state.prg_ram.to_vec(),
+            ppu_ram: nes.ppu_state.ram,
+            oam_data: nes.ppu_state.oam_data,
+            palette_table: nes.ppu_state.palette_table,
+            chr_ram: chr_ram(nes).to_vec(),
+        }
+    }
+
+    fn chr_ram(&self, length: usize) -> Vec<u8> {
+        match self.chr_ram.len() == length {
+            true => self.chr_ram.clone(),
+            false => vec![0; length],
+        }
+    }
+}
+
+// CHR the game can write, sized by the ROM
+fn chr_ram(nes: &ActionNES) -> &[u8] {
+    match nes.rom.chr_ram {
+        true => &nes.rom.chr_rom,
+        false => &[],
+    }
+}
+
+// Runs of XORed bytes, each (start, length) indexing into data
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RegionDiff {
+    runs: Vec<(u16, u16)>,
+    data: Vec<u8>,
+}
+
+impl RegionDiff {
+    fn new(memory: &[u8], baseline: &[u8]) -> Self {
+        let mut diff = RegionDiff::default();
+        let mut index = 0;
+        while index < memory.len() {
+            if memory[index] == baseline[index] {
+                index += 1;
+                continue;
+            }
+            // Extend the run until MIN_GAP unchanged bytes in a row
+            let start = index;
+            let mut end = index + 1;
+            let mut gap = 0;
+            while end < memory.len() && gap < MIN_GAP {
+                if memory[end] == baseline[end] {
+                    gap += 1;
+                } else {
+                    gap = 0;
+                }
+                end += 1;
+            }
+            let end = end - gap;
+            diff.runs.push((start as u16, (end - start) as u16));
+            diff.data
+                .extend((start..end).map(|i| memory[i] ^ baseline[i]));
+            index = end;
+        }
+        diff
+    }
+
+    fn apply(&self, memory: &mut [u8], baseline: &[u8]) {
+        memory.copy_from_slice(baseline);
+        let mut data = self.data.iter();
+        for (start, length) in &self.runs {
+            let start = *start as usize;
+            for byte in &mut memory[start..start + *length as usize] {
+                *byte ^= data.next().expect("Snapshot diff is truncated");
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.runs.len() * 4 + self.data.len()
+    }
+
+    // Most bytes write takes for a region of `region_len` bytes. Runs are at least MIN_GAP apart
+    // and a run header is no longer than that, so it's the whole region as one run.
+    fn max_written_len(region_len: usize) -> usize {
+        match region_len {
+            0 => 2,
+            _ => 2 + 4 + region_len,
+        }
+    }
+
+    fn write(&self, writer: &mut ByteWriter) {
+        writer.u16(self.runs.len() as u16);
+        for (start, length) in &self.runs {
+            writer.u16(*start);
+            writer.u16(*length);
+        }
+        writer.0.extend_from_slice(&self.data);
+    }
+
+    // Checks the runs fit in a region of `region_len` bytes, so apply can't go out of bounds
+    fn read(reader: &mut ByteReader, region_len: usize) -> Result<Self, String> {
+        let mut diff = RegionDiff::default();
+        let mut data_len = 0;
+        for _ in 0..reader.u16()? {
+            let (start, length) = (reader.u16()?, reader.u16()?);
+            if start as usize + length as usize > region_len {
+                return Err("Snapshot memory run is out of bounds".to_string());
+            }
+            diff.runs.push((start, length));
+            data_len += length as usize;
+        }
+        diff.data = reader.take(data_len)?.to_vec();
+        Ok(diff)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CpuRegisters {
+    reg_a: u8,
+    reg_x: u8,
+    reg_y: u8,
+    status: CpuStatus,
+    stack_pointer: u8,
+    program_counter: u16,
+    page_cross_flag: bool,
+    branch_flag: bool,
+    irq_interrupt_poll: Option<()>,
+    nmi_hijacked: bool,
+    cycle_counter: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PpuRegisters {
+    ppuctrl: PpuControl,
+    ppumask: PpuMask,
+    ppustatus: PpuStatus,
+    oamaddr: OamAddr,
+    loopy: LoopyRegisters,
+    ppudata: u8,
+    pipeline: RenderPipeline,
+    nmi_interrupt_poll: Option<()>,
+    cycle_counter: usize,
+    cur_scanline: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    accuracy: Accuracy,
+    frame_count: usize,
+    cpu: CpuRegisters,
+    ppu: PpuRegisters,
+    apu_state: ApuState,
+    controller: Controller,
+    port_2: PortDevice,
+    board: Box<dyn Mapper>,
+    cpu_ram: RegionDiff,
+    prg_ram: RegionDiff,
+    ppu_ram: RegionDiff,
+    oam_data: RegionDiff,
+    palette_table: RegionDiff,
+    chr_ram: RegionDiff,
+}
+
+impl Snapshot {
+    pub fn capture(nes: &ActionNES, baseline: &SnapshotBaseline) -> Self {
+        let cpu = &nes.cpu_state;
+        let ppu = &nes.ppu_state;
+        Snapshot {
+            accuracy: Accuracy::of(nes),
+            frame_count: nes.frame_count,
+            cpu: CpuRegisters {
+                reg_a: cpu.reg_a,
+                reg_x: cpu.reg_x,
+                reg_y: cpu.reg_y,
+                status: cpu.status,
+                stack_pointer: cpu.stack_pointer,
+                program_counter: cpu.program_counter,
+                page_cross_flag: cpu.page_cross_flag,
+                branch_flag: cpu.branch_flag,
+                irq_interrupt_poll: cpu.irq_interrupt_poll,
+                nmi_hijacked: cpu.nmi_hijacked,
+                cycle_counter: cpu.cycle_counter,
+            },
+            ppu: PpuRegisters {
+                ppuctrl: ppu.ppuctrl,
+                ppumask: ppu.ppumask,
+                ppustatus: ppu.ppustatus,
+                oamaddr: ppu.oamaddr,
+                loopy: ppu.loopy,
+                ppudata: ppu.ppudata,
+                pipeline: ppu.pipeline,
+                nmi_interrupt_poll: ppu.nmi_interrupt_poll,
+                cycle_counter: ppu.cycle_counter,
+                cur_scanline: ppu.cur_scanline,
+            },
+            apu_state: nes.apu_state,
+            controller: nes.controller,
+            port_2: nes.port_2,
+            board: nes.rom.board.clone(),
+            cpu_ram: RegionDiff::new(&cpu.ram, &baseline.cpu_ram),
+            prg_ram: RegionDiff::new(&cpu.prg_ram, &baseline.prg_ram),
+            ppu_ram: RegionDiff::new(&ppu.ram, &baseline.ppu_ram),
+            oam_data: RegionDiff::new(&ppu.oam_data, &baseline.oam_data),
+            palette_table: RegionDiff::new(&ppu.palette_table, &baseline.palette_table),
+            chr_ram: RegionDiff::new(chr_ram(nes), &baseline.chr_ram(chr_ram(nes).len())),
+        }
+    }
+
+    /// Restores the snapshot into `nes`, which should have the same ROM loaded
+    pub fn restore(&self, nes: &mut ActionNES, baseline: &SnapshotBaseline) {
+        let cpu = &mut nes.cpu_state;
+        cpu.reg_a = self.cpu.reg_a;
+        cpu.reg_x = self.cpu.reg_x;
+        cpu.reg_y = self.cpu.reg_y;
+        cpu.status = self.cpu.status;
+        cpu.stack_pointer = self.cpu.stack_pointer;
+        cpu.program_counter = self.cpu.program_counter;
+        cpu.page_cross_flag = self.cpu.page_cross_flag;
+        cpu.branch_flag = self.cpu.branch_flag;
+        cpu.irq_interrupt_poll = self.cpu.irq_interrupt_poll;
+        cpu.nmi_hijacked = self.cpu.nmi_hijacked;
+        cpu.cycle_counter = self.cpu.cycle_counter;
+        self.cpu_ram.apply(&mut cpu.ram, &baseline.cpu_ram);
+        self.prg_ram.apply(&mut cpu.prg_ram, &baseline.prg_ram);
+
+        let ppu = &mut nes.ppu_state;
+        ppu.ppuctrl = self.ppu.ppuctrl;
+        ppu.ppumask = self.ppu.ppumask;
+        ppu.ppustatus = self.ppu.ppustatus;
+        ppu.oamaddr = self.ppu.oamaddr;
+        ppu.loopy = self.ppu.loopy;
+        ppu.ppudata = self.ppu.ppudata;
+        ppu.pipeline = self.ppu.pipeline;
+        ppu.nmi_interrupt_poll = self.ppu.nmi_interrupt_poll;
+        ppu.cycle_counter = self.ppu.cycle_counter;
+        ppu.cur_scanline = self.ppu.cur_scanline;
+        self.ppu_ram.apply(&mut ppu.ram, &baseline.ppu_ram);
+        self.oam_data.apply(&mut ppu.oam_data, &baseline.oam_data);
+        self.palette_table
+            .apply(&mut ppu.palette_table, &baseline.palette_table);
+        if nes.rom.chr_ram {
+            let chr_baseline = baseline.chr_ram(nes.rom.chr_rom.len());
+            self.chr_ram.apply(&mut nes.rom.chr_rom, &chr_baseline);
+        }
+
+        nes.apu_state = self.apu_state;
+        nes.controller = self.controller;
+        nes.port_2 = self.port_2;
+        nes.rom.board = self.board.clone();
+        nes.frame_count = self.frame_count;
+        self.accuracy.apply(nes);
+        nes.sync_timing();
+    }
+
+    /// Serializes a snapshot taken against SnapshotBaseline::power_on
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = ByteWriter(Vec::new());
+        writer.u8(BYTES_VERSION);
+        writer.u8(self.accuracy.to_bits());
+        writer.usize(self.frame_count);
+        let cpu = &self.cpu;
+        writer.u8(cpu.reg_a);
+        writer.u8(cpu.reg_x);
+        writer.u8(cpu.reg_y);
+        writer.u8(cpu.status.bits());
+        writer.u8(cpu.stack_pointer);
+        writer.u16(cpu.program_counter);
+        writer.bool(cpu.page_cross_flag);
+        writer.bool(cpu.branch_flag);
+        writer.bool(cpu.irq_interrupt_poll.is_some());
+        writer.bool(cpu.nmi_hijacked);
+        writer.usize(cpu.cycle_counter);
+
+        let ppu = &self.ppu;
+        writer.u8(ppu.ppuctrl.bits());
+        writer.u8(ppu.ppumask.bits());
+        writer.u8(ppu.ppustatus.bits());
+        writer.u8(ppu.oamaddr.read());
+        writer.u16(ppu.loopy.v);
+        writer.u16(ppu.loopy.t);
+        writer.u8(ppu.loopy.x);
+        writer.bool(ppu.loopy.w);
+        writer.u8(ppu.ppudata);
+        let pipeline = &ppu.pipeline;
+        writer.usize(pipeline.dot);
+        writer.u8(pipeline.tile_id);
+        writer.u8(pipeline.attribute);
+        for plane in 0..2 {
+            writer.u8(pipeline.pattern[plane]);
+            writer.u16(pipeline.pattern_shift[plane]);
+            writer.u16(pipeline.attribute_shift[plane]);
+        }
+        writer.u8(pipeline.sprite_count as u8);
+        for sprite in pipeline.line_sprites() {
+            writer.u8(sprite.pattern[0]);
+            writer.u8(sprite.pattern[1]);
+            writer.u8(sprite.attributes);
+            writer.u8(sprite.x);
+            writer.bool(sprite.is_sprite_zero);
+        }
+        writer.bool(ppu.nmi_interrupt_poll.is_some());
+        writer.usize(ppu.cycle_counter);
+        writer.usize(ppu.cur_scanline);
+
+        let apu = &self.apu_state;
+        for channel in 0..4 {
+            writer.u8(apu.length_counters[channel]);
+            writer.bool(apu.length_halt[channel]);
+        }
+        writer.u8(apu.enabled.bits());
+        writer.bool(apu.dmc_irq_enabled);
+        writer.bool(apu.dmc_loop);
+        writer.u16(apu.dmc_rate);
+        writer.u16(apu.dmc_sample_length);
+        writer.u16(apu.dmc_bytes_remaining);
+        writer.usize(apu.dmc_timer);
+        writer.bool(apu.five_step_mode);
+        writer.bool(apu.irq_inhibit);
+        writer.usize(apu.frame_cycle);
+        writer.bool(apu.frame_irq);
+        writer.bool(apu.dmc_irq);
+
+        writer.bool(self.controller.strobe);
+        writer.u8(self.controller.cur_flag);
+
+        self.cpu_ram.write(&mut writer);
+        self.prg_ram.write(&mut writer);
+        self.ppu_ram.write(&mut writer);
+        self.oam_data.write(&mut writer);
+        self.palette_table.write(&mut writer);
+        self.chr_ram.write(&mut writer);
+        writer.0
+    }
+
+    /// Reads bytes written by to_bytes, the controllers are taken from `nes`
+    pub fn from_bytes(bytes: &[u8], nes: &ActionNES) -> Result<Self, String> {
+        let mut reader = ByteReader { bytes, position: 0 };
+        let version = reader.u8()?;
+        if version != BYTES_VERSION {
+            return Err(format!("Unsupported snapshot version {}", version));
+        }
+        let accuracy = Accuracy::from_bits(reader.u8()?);
+        let frame_count = reader.usize()?;
+        let cpu = CpuRegisters {
+            reg_a: reader.u8()?,
+            reg_x: reader.u8()?,
+            reg_y: reader.u8()?,
+            status: CpuStatus::from_bits_retain(reader.u8()?),
+            stack_pointer: reader.u8()?,
+            program_counter: reader.u16()?,
+            page_cross_flag: reader.bool()?,
+            branch_flag: reader.bool()?,
+            irq_interrupt_poll: reader.bool()?.then_some(()),
+            nmi_hijacked: reader.bool()?,
+            cycle_counter: reader.usize()?,
+        };
+
+        let ppuctrl = PpuControl::from_bits_retain(reader.u8()?);
+        let ppumask = PpuMask::from_bits_retain(reader.u8()?);
+        let ppustatus = PpuStatus::from_bits_retain(reader.u8()?);
+        let mut oamaddr = OamAddr::new();
+        oamaddr.write(reader.u8()?);
+        let loopy = LoopyRegisters {
+            v: reader.u16()?,
+            t: reader.u16()?,
+            x: reader.u8()?,
+            w: reader.bool()?,
+        };
+        let ppudata = reader.u8()?;
+        let mut pipeline = RenderPipeline::new();
+        pipeline.dot = reader.usize()?;
+        pipeline.tile_id = reader.u8()?;
+        pipeline.attribute = reader.u8()?;
+        for plane in 0..2 {
+            pipeline.pattern[plane] = reader.u8()?;
+            pipeline.pattern_shift[plane] = reader.u16()?;
+            pipeline.attribute_shift[plane] = reader.u16()?;
+        }
+        pipeline.sprite_count = reader.u8()? as usize;
+        if pipeline.sprite_count > MAX_LINE_SPRITES {
+            return Err(format!(
+                "Snapshot has {} sprites on a line",
+                pipeline.sprite_count
+            ));
+        }
+        for sprite in &mut pipeline.sprites[..pipeline.sprite_count] {
+            *sprite = LineSprite {
+                pattern: [reader.u8()?, reader.u8()?],
+                attributes: reader.u8()?,
+                x: reader.u8()?,
+                is_sprite_zero: reader.bool()?,
+            };
+        }
+        let ppu = PpuRegisters {
+            ppuctrl,
+            ppumask,
+            ppustatus,
+            oamaddr,
+            loopy,
+            ppudata,
+            pipeline,
+            nmi_interrupt_poll: reader.bool()?.then_some(()),
+            cycle_counter: reader.usize()?,
+            cur_scanline: reader.usize()?,
+        };
+
+        let mut apu_state = ApuState::new();
+        for channel in 0..4 {
+            apu_state.length_counters[channel] = reader.u8()?;
+            apu_state.length_halt[channel] = reader.bool()?;
+        }
+        apu_state.enabled = ApuStatus::from_bits_retain(reader.u8()?);
+        apu_state.dmc_irq_enabled = reader.bool()?;
+        apu_state.dmc_loop = reader.bool()?;
+        apu_state.dmc_rate = reader.u16()?;
+        apu_state.dmc_sample_length = reader.u16()?;
+        apu_state.dmc_bytes_remaining = reader.u16()?;
+        apu_state.dmc_timer = reader.usize()?;
+        apu_state.five_step_mode = reader.bool()?;
+        apu_state.irq_inhibit = reader.bool()?;
+        apu_state.frame_cycle = reader.usize()?;
+        apu_state.frame_irq = reader.bool()?;
+        apu_state.dmc_irq = reader.bool()?;
+
+        let mut controller = nes.controller;
+        controller.strobe = reader.bool()?;
+        controller.cur_flag = reader.u8()?;
+
+        let snapshot = Snapshot {
+            accuracy,
+            frame_count,
+            cpu,
+            ppu,
+            apu_state,
+            controller,
+            port_2: nes.port_2,
+            board: nes.rom.board.clone(),
+            cpu_ram: RegionDiff::read(&mut reader, 0x800)?,
+            prg_ram: RegionDiff::read(&mut reader, PRG_RAM_SIZE)?,
+            ppu_ram: RegionDiff::read(&mut reader, VRAM_SIZE)?,
+            oam_data: RegionDiff::read(&mut reader, 256)?,
+            palette_table: RegionDiff::read(&mut reader, 32)?,
+            chr_ram: RegionDiff::read(&mut reader, chr_ram(nes).len())?,
+        };
+        // Zeros are padding, e.g. from a frontend's fixed size buffer
+        if bytes[reader.position..].iter().any(|byte| *byte != 0) {
+            return Err("Snapshot has trailing bytes".to_string());
+        }
+        Ok(snapshot)
+    }
+
+    /// Most bytes to_bytes writes for a console with `nes`'s ROM, however much of its memory
+    /// has changed and however many sprites are on the line
+    pub fn max_bytes_len(nes: &ActionNES) -> usize {
+        // Without any memory changed from the baseline
+        let unchanged = Snapshot::capture(nes, &SnapshotBaseline::from_nes(nes));
+        let regions = [0x800, PRG_RAM_SIZE, VRAM_SIZE, 256, 32, chr_ram(nes).len()];
+        unchanged.to_bytes().len()
+            + (MAX_LINE_SPRITES - unchanged.ppu.pipeline.sprite_count) * LINE_SPRITE_BYTES
+            + regions
+                .iter()
+                .map(|&length| RegionDiff::max_written_len(length) - 2)
+                .sum::<usize>()
+    }
+
+    /// The board's registers, for the mapper data of a savestate
+    pub fn board_state(&self) -> Vec<u8> {
+        self.board.save_state()
+    }
+
+    /// Loads the board's registers from a savestate's mapper data, savestates from before
+    /// mappers saved anything have none and keep the registers from_bytes took from the console
+    pub fn load_board_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if !data.is_empty() {
+            self.board.load_state(data)?;
+        }
+        Ok(())
+    }
+
+    /// Frames the console had completed when the snapshot was taken
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Approximate heap and inline size in bytes
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.cpu_ram.size()
+            + self.prg_ram.size()
+            + self.ppu_ram.size()
+            + self.oam_data.size()
+            + self.palette_table.size()
+            + self.chr_ram.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accuracy::AccuracyPreset;
+    use crate::nes::NES;
+    use crate::rom::ROM;
+
+    fn run_nestest(frames: usize) -> ActionNES {
+        let mut nes = ActionNES::new();
+        nes.load_from_path("test_roms/nestest.nes").unwrap();
+        nes.reset().unwrap();
+        nes.step_frames(frames).unwrap();
+        nes
+    }
+
+    #[test]
+    fn test_region_diff() {
+        let baseline = [0u8; 16];
+        let mut memory = [0u8; 16];
+        memory[1] = 0x11;
+        memory[3] = 0x33;
+        memory[12] = 0xCC;
+        let diff = RegionDiff::new(&memory, &baseline);
+        // Short gaps are kept inside a run
+        assert_eq!(vec![(1, 3), (12, 1)], diff.runs);
+        assert_eq!(vec![0x11, 0x00, 0x33, 0xCC], diff.data);
+        let mut restored = [0xFFu8; 16];
+        diff.apply(&mut restored, &baseline);
+        assert_eq!(memory, restored);
+    }
+
+    #[test]
+    fn test_restore_round_trip() {
+        let baseline = SnapshotBaseline::power_on();
+        let mut nes = run_nestest(5);
+        let snapshot = Snapshot::capture(&nes, &baseline);
+        let expected_timing = nes.ppu_state.timing;
+        let expected = format!(
+            "{:?}",
+            (nes.frame_count, nes.cpu_state, nes.ppu_state, nes.apu_state)
+        );
+
+        nes.step_frames(3).unwrap();
+        snapshot.restore(&mut nes, &baseline);
+        nes.ppu_state.timing = expected_timing;
+        let restored = format!(
+            "{:?}",
+            (nes.frame_count, nes.cpu_state, nes.ppu_state, nes.apu_state)
+        );
+        assert_eq!(expected, restored);
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let baseline = SnapshotBaseline::power_on();
+        let mut nes = run_nestest(5);
+        AccuracyPreset::Accurate.settings().apply(&mut nes);
+        let bytes = Snapshot::capture(&nes, &baseline).to_bytes();
+        let expected = format!(
+            "{:?}",
+            (nes.frame_count, nes.cpu_state, nes.ppu_state, nes.apu_state)
+        );
+
+        let mut other = nes.clone();
+        other.step_frames(3).unwrap();
+        // The accuracy settings are restored too
+        AccuracyPreset::Fast.settings().apply(&mut other);
+        Snapshot::from_bytes(&bytes, &other)
+            .unwrap()
+            .restore(&mut other, &baseline);
+        other.ppu_state.timing = nes.ppu_state.timing;
+        let restored = format!(
+            "{:?}",
+            (
+                other.frame_count,
+                other.cpu_state,
+                other.ppu_state,
+                other.apu_state
+            )
+        );
+        assert_eq!(expected, restored);
+
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1], &nes).is_err());
+        assert!(Snapshot::from_bytes(&[bytes.as_slice(), &[1]].concat(), &nes).is_err());
+        // Zero padding is ignored
+        assert!(Snapshot::from_bytes(&[bytes.as_slice(), &[0; 16]].concat(), &nes).is_ok());
+    }
+
+    #[test]
+    fn test_max_bytes_len() {
+        let baseline = SnapshotBaseline::power_on();
+        let mut nes = run_nestest(5);
+        let max = Snapshot::max_bytes_len(&nes);
+        assert!(Snapshot::capture(&nes, &baseline).to_bytes().len() <= max);
+        // Every byte changed, which takes all of it, and single changed bytes as close as they
+        // get without joining
+        let mut lengths = Vec::new();
+        for changed in [
+            |_: usize| true,
+            |index: usize| index.is_multiple_of(MIN_GAP + 1),
+        ] {
+            let flip = |memory: &mut [u8], baseline: &[u8]| {
+                for (index, byte) in memory.iter_mut().enumerate() {
+                    *byte = baseline[index] ^ if changed(index) { 0xFF } else { 0 };
+                }
+            };
+            flip(&mut nes.cpu_state.ram, &baseline.cpu_ram);
+            flip(&mut nes.cpu_state.prg_ram, &baseline.prg_ram);
+            flip(&mut nes.ppu_state.ram, &baseline.ppu_ram);
+            flip(&mut nes.ppu_state.oam_data, &baseline.oam_data);
+            flip(&mut nes.ppu_state.palette_table, &baseline.palette_table);
+            nes.ppu_state.pipeline.sprite_count = MAX_LINE_SPRITES;
+            lengths.push(Snapshot::capture(&nes, &baseline).to_bytes().len());
+        }
+        assert_eq!(max, lengths[0]);
+        assert!(lengths[1] <= max, "{} > {}", lengths[1], max);
+        assert_eq!(max, Snapshot::max_bytes_len(&nes));
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut rom = ROM::new();
+        rom.prg_rom = vec![0xEA; 0x4000];
+        rom.chr_rom = vec![0; 0x4000];
+        rom.chr_ram = true;
+        let mut nes = ActionNES::new();
+        nes.set_rom(rom).unwrap();
+        nes.rom.chr_rom[0x3FFF] = 0x42;
+        let bytes = Snapshot::capture(&nes, &SnapshotBaseline::power_on()).to_bytes();
+        nes.rom.chr_rom[0x3FFF] = 0;
+        nes.rom.chr_rom[0] = 0x11;
+        Snapshot::from_bytes(&bytes, &nes)
+            .unwrap()
+            .restore(&mut nes, &SnapshotBaseline::power_on());
+        assert_eq!((0, 0x42), (nes.rom.chr_rom[0], nes.rom.chr_rom[0x3FFF]));
+    }
+
+    #[test]
+    fn test_keyframe_baseline_is_smaller() {
+        let nes = run_nestest(5);
+        let keyframe = SnapshotBaseline::from_nes(&nes);
+        let mut next = nes.clone();
+        next.step_frames(1).unwrap();
+        let from_power_on = Snapshot::capture(&next, &SnapshotBaseline::power_on());
+        let from_keyframe = Snapshot::capture(&next, &keyframe);
+        assert!(from_keyframe.size() < from_power_on.size());
+    }
+}
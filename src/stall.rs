@@ -0,0 +1,169 @@
+// Heuristic for games stuck polling $2002 with NMI disabled
+//
+// Waiting for vblank or sprite 0 in a loop like `BIT $2002; BPL loop` only takes a frame or two.
+// A game that spends whole frames in the same few bytes reading $2002 while NMI is disabled can
+// only get out through the flag it's waiting for, so the PPU never setting it is usually an
+// emulation bug (a missing flag or NMI) rather than a game bug.
+use std::fmt;
+
+use crate::cpu::{Instruction, Opcode, Param};
+
+// Frames in a loop before it's reported, one second
+pub const DEFAULT_STALL_FRAMES: usize = 60;
+// Loops spanning more bytes than this are doing more than polling
+const MAX_LOOP_SPAN: u16 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    // Lowest address of the loop
+    pub program_counter: u16,
+    pub frames: usize,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Game stuck polling $2002 at {:04X} for {} frames with NMI disabled, \
+             the PPU may be missing a flag or NMI",
+            self.program_counter, self.frames
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StallDetector {
+    threshold: usize,
+    // Lowest and highest address executed this frame
+    span: Option<(u16, u16)>,
+    read_status: bool,
+    // Loop the last frames were stuck in, with the number of frames in a row
+    stuck: Option<Stall>,
+}
+
+impl StallDetector {
+    /// Reports loops that last `threshold` frames
+    pub fn new(threshold: usize) -> Self {
+        StallDetector {
+            threshold: threshold.max(1),
+            span: None,
+            read_status: false,
+            stuck: None,
+        }
+    }
+
+    pub fn record(&mut self, program_counter: u16, instruction: &Instruction) {
+        self.span = Some(match self.span {
+            Some((low, high)) => (low.min(program_counter), high.max(program_counter)),
+            None => (program_counter, program_counter),
+        });
+        let is_write = matches!(
+            instruction.opcode,
+            Opcode::STA
+                | Opcode::STX
+                | Opcode::STY
+                | Opcode::SAX
+                | Opcode::AHX
+                | Opcode::SHX
+                | Opcode::SHY
+                | Opcode::TAS
+        );
+        if let Param::Address(addr @ 0x2000..=0x3FFF) = instruction.param {
+            // Mirrored every 8 bytes
+            self.read_status |= addr & 0b111 == 2 && !is_write;
+        }
+    }
+
+    /// Ends a frame, returning the stall when a loop has just lasted `threshold` frames. Loops
+    /// are only reported once, until the game gets out of them.
+    pub fn end_frame(&mut self, nmi_enabled: bool) -> Option<Stall> {
+        let loop_start = match self.span {
+            Some((low, high)) if high - low < MAX_LOOP_SPAN => Some(low),
+            _ => None,
+        };
+        let is_stuck = self.read_status && !nmi_enabled;
+        self.stuck = match (loop_start, self.stuck) {
+            (Some(start), Some(stall)) if is_stuck && stall.program_counter == start => {
+                Some(Stall {
+                    frames: stall.frames + 1,
+                    ..stall
+                })
+            }
+            (Some(start), _) if is_stuck => Some(Stall {
+                program_counter: start,
+                frames: 1,
+            }),
+            _ => None,
+        };
+        self.span = None;
+        self.read_status = false;
+        self.stuck.filter(|stall| stall.frames == self.threshold)
+    }
+
+    /// The loop the game is stuck in, once it's been reported
+    pub fn stall(&self) -> Option<Stall> {
+        self.stuck.filter(|stall| stall.frames >= self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{AddressingMode, InstructionMetaData};
+
+    fn instruction(opcode: Opcode, param: Param) -> Instruction {
+        Instruction {
+            opcode,
+            param,
+            meta: InstructionMetaData {
+                cycles: 4,
+                mode: AddressingMode::Absolute,
+                raw_opcode: 0,
+                length: 3,
+            },
+        }
+    }
+
+    // BIT $2002, BPL $8000
+    fn run_loop_frame(detector: &mut StallDetector, status: u16) {
+        for _ in 0..100 {
+            detector.record(0x8000, &instruction(Opcode::BIT, Param::Address(status)));
+            detector.record(0x8003, &instruction(Opcode::BPL, Param::Address(0x8000)));
+        }
+    }
+
+    #[test]
+    fn test_reports_once() {
+        let mut detector = StallDetector::new(3);
+        for _ in 0..2 {
+            // $3FFA mirrors $2002
+            run_loop_frame(&mut detector, 0x3FFA);
+            assert_eq!(None, detector.end_frame(false));
+        }
+        run_loop_frame(&mut detector, 0x2002);
+        let stall = Stall {
+            program_counter: 0x8000,
+            frames: 3,
+        };
+        assert_eq!(Some(stall), detector.end_frame(false));
+        run_loop_frame(&mut detector, 0x2002);
+        assert_eq!(None, detector.end_frame(false));
+        assert_eq!(4, detector.stall().unwrap().frames);
+        // Leaving the loop for a frame starts the count over
+        detector.record(0x9000, &instruction(Opcode::NOP, Param::None));
+        run_loop_frame(&mut detector, 0x2002);
+        assert_eq!(None, detector.end_frame(false));
+        assert_eq!(None, detector.stall());
+    }
+
+    #[test]
+    fn test_ignores_nmi_and_other_registers() {
+        let mut detector = StallDetector::new(1);
+        run_loop_frame(&mut detector, 0x2002);
+        assert_eq!(None, detector.end_frame(true));
+        run_loop_frame(&mut detector, 0x2004);
+        assert_eq!(None, detector.end_frame(false));
+        detector.record(0x8000, &instruction(Opcode::STA, Param::Address(0x2002)));
+        assert_eq!(None, detector.end_frame(false));
+    }
+}
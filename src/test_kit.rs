@@ -0,0 +1,15 @@
+// Fixtures for the unit tests that run a few instructions
+use crate::nes::{ActionNES, NES};
+use crate::rom::ROM;
+
+/// A 16KB NROM with `program` at $8000, padded with NOPs, reset to run it
+pub fn program_nes(program: &[u8]) -> ActionNES {
+    let mut rom = ROM::new();
+    rom.prg_rom = vec![0xEA; 0x4000];
+    rom.prg_rom[..program.len()].copy_from_slice(program);
+    rom.prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set rom");
+    nes.reset().expect("Failed to reset");
+    nes
+}
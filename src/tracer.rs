@@ -1,16 +1,26 @@
+use std::io::Write;
+use std::time::Duration;
+
 use crate::{
-    cpu::{AddressingMode, CpuBus, CpuState, Instruction, InstructionMetaData, Param},
+    controller::ControllerState,
+    cpu::{
+        AddressingMode, CpuBus, CpuState, Instruction, InstructionMetaData, Param, OPCODE_TABLE,
+    },
     nes::{ActionNES, NES},
-    ppu::PpuState,
+    ppu::{PpuState, SpriteStats},
+    rom::ROM,
+    screen::frame::Frame,
 };
 
 type ProgramTrace = Vec<String>;
 
-// TODO: Make this implement NES
+/// ActionNES logging every instruction in the nestest.log format
 #[derive(Default)]
 pub struct TraceNes {
     nes: ActionNES,
     pub program_trace: ProgramTrace,
+    // Trace lines are written here instead of kept in program_trace when set
+    output: Option<Box<dyn Write + Send>>,
 }
 
 impl TraceNes {
@@ -23,17 +33,22 @@ impl TraceNes {
         self.nes
             .load_from_path("test_roms/nestest.nes")
             .expect("Failed to load from path");
+        self.nes.reset().expect("Failed to reset");
         self.nes.cpu_state.program_counter = self.nes.as_cpu_bus().peek_two_bytes(0xFFFC) - 4;
         self.nes.cpu_state.cycle_counter = 7;
         self.nes.ppu_state.cycle_counter = 21;
+        self.nes.sync_timing();
         self
     }
 
-    pub fn next_cpu_instruction(&mut self) -> Result<Instruction, String> {
-        let prev_nes = self.nes.clone();
-        let instruction = self.nes.next_cpu_instruction()?;
-        Self::log_trace(&mut self.program_trace, &instruction, prev_nes)?;
-        Ok(instruction)
+    /// Streams the trace to `output`, for long runs that would fill memory
+    pub fn log_to(mut self, output: Box<dyn Write + Send>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn nes(&self) -> &ActionNES {
+        &self.nes
     }
 
     /* TODO: this is all spaghetti, need to change this. Maybe move program_trace out of ActionNES
@@ -47,8 +62,11 @@ impl TraceNes {
         let ActionNES {
             cpu_state: mut original_cpu_state,
             ppu_state: mut original_ppu_state,
+            apu_state: mut original_apu_state,
             controller: mut original_controller,
-            rom,
+            port_2: mut original_port_2,
+            mut rom,
+            ..
         } = nes;
         let Instruction {
             opcode,
@@ -85,37 +103,20 @@ impl TraceNes {
         } = original_ppu_state;
         let ppu_cycle = cycle_counter;
 
-        // get the parsed arg as a u16
-        let arg = match length {
-            1 => 0,
-            2 => {
-                let bus = CpuBus::new(
-                    &mut original_cpu_state,
-                    &mut original_ppu_state,
-                    &mut original_controller,
-                    &rom,
-                );
-                let address: u8 = bus.peek_byte(program_counter + 1);
-                hex_dump.push(address);
-                address as u16
-            }
-            3 => {
-                let bus = CpuBus::new(
-                    &mut original_cpu_state,
-                    &mut original_ppu_state,
-                    &mut original_controller,
-                    &rom,
-                );
-                let address_lo = bus.peek_byte(program_counter + 1);
-                let address_hi = bus.peek_byte(program_counter + 2);
-                hex_dump.push(address_lo);
-                hex_dump.push(address_hi);
-
-                bus.peek_two_bytes(program_counter + 1)
-            }
-            _ => {
-                panic!()
-            }
+        // get the parsed arg as a u16, the length comes from the opcode table
+        let bus = CpuBus::new(
+            &mut original_cpu_state,
+            &mut original_ppu_state,
+            &mut original_apu_state,
+            &mut original_controller,
+            &mut original_port_2,
+            &mut rom,
+        );
+        hex_dump.extend((1..length).map(|i| bus.peek_byte(program_counter + i)));
+        let arg = match hex_dump[1..] {
+            [value] => value as u16,
+            [lo, hi] => u16::from_le_bytes([lo, hi]),
+            _ => 0,
         };
 
         // create temp string for operand details
@@ -131,8 +132,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:02x} = {:02x}", address, stored_value)
@@ -141,8 +144,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:02x},X @ {:02x} = {:02x}", arg, address, stored_value)
@@ -151,8 +156,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:02x},Y @ {:02x} = {:02x}", arg, address, stored_value)
@@ -161,8 +168,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!(
@@ -177,8 +186,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!(
@@ -205,8 +216,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:04x} = {:02x}", address, stored_value)
@@ -215,8 +228,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:04x},X @ {:04x} = {:02x}", arg, address, stored_value)
@@ -225,8 +240,10 @@ impl TraceNes {
                 let bus = CpuBus::new(
                     &mut original_cpu_state,
                     &mut original_ppu_state,
+                    &mut original_apu_state,
                     &mut original_controller,
-                    &rom,
+                    &mut original_port_2,
+                    &mut rom,
                 );
                 let stored_value = bus.peek_byte(address);
                 format!("${:04x},Y @ {:04x} = {:02x}", arg, address, stored_value)
@@ -241,7 +258,11 @@ impl TraceNes {
         // Get clock cycle information
 
         // Add strings together
-        let opstring = format!("{:?}", opcode);
+        // nestest.log marks unofficial opcodes with a *
+        let opstring = match OPCODE_TABLE[raw_opcode as usize] {
+            Some(info) if !info.official => format!("*{:?}", opcode),
+            _ => format!("{:?}", opcode),
+        };
         let hex_str = hex_dump
             .iter()
             .map(|z| format!("{:02x}", z))
@@ -268,3 +289,277 @@ impl TraceNes {
         Ok(())
     }
 }
+
+impl NES for TraceNes {
+    fn next_cpu_instruction(&mut self) -> Result<Instruction, String> {
+        let prev_nes = self.nes.clone();
+        let instruction = self.nes.next_cpu_instruction()?;
+        Self::log_trace(&mut self.program_trace, &instruction, prev_nes)?;
+        if let Some(output) = &mut self.output {
+            let line = self.program_trace.pop().unwrap_or_default();
+            writeln!(output, "{}", line).map_err(|err| err.to_string())?;
+        }
+        Ok(instruction)
+    }
+
+    // Instruction by instruction so every one is logged
+    fn next_ppu_frame(&mut self) -> Result<(), String> {
+        let frame = self.nes.frame_count();
+        while self.nes.frame_count() == frame {
+            self.next_cpu_instruction()?;
+        }
+        Ok(())
+    }
+
+    fn next_ppu_scanline(&mut self) -> Result<(), String> {
+        let scanline = self.nes.ppu_state.cur_scanline;
+        while self.nes.ppu_state.cur_scanline == scanline {
+            self.next_cpu_instruction()?;
+        }
+        Ok(())
+    }
+
+    fn next_cpu_cycles(&mut self, cycles: usize) -> Result<(), String> {
+        let target = self.nes.cpu_state.cycle_counter + cycles;
+        while self.nes.cpu_state.cycle_counter < target {
+            self.next_cpu_instruction()?;
+        }
+        Ok(())
+    }
+
+    fn update_controller(&mut self, player: usize, key: ControllerState, bit: bool) {
+        self.nes.update_controller(player, key, bit);
+    }
+
+    fn set_rom(&mut self, rom: ROM) -> Result<(), String> {
+        self.nes.set_rom(rom)
+    }
+
+    fn load_from_path(&mut self, path: &str) -> Result<(), String> {
+        self.nes.load_from_path(path)
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        self.nes.reset()
+    }
+
+    fn peek_cpu_state(&self) -> CpuState {
+        self.nes.peek_cpu_state()
+    }
+
+    fn peek_ppu_state(&self) -> PpuState {
+        self.nes.peek_ppu_state()
+    }
+
+    fn render_frame(&self, frame: &mut Frame) {
+        self.nes.render_frame(frame);
+    }
+
+    fn frame_count(&self) -> usize {
+        self.nes.frame_count()
+    }
+
+    fn emulated_duration(&self) -> Duration {
+        self.nes.emulated_duration()
+    }
+
+    fn sprite_stats(&self) -> SpriteStats {
+        self.nes.sprite_stats()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.nes.save_state()
+    }
+
+    fn max_state_len(&self) -> usize {
+        self.nes.max_state_len()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.nes.load_state(state)
+    }
+}
+
+/// Part of a trace line compared by diff_traces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceField {
+    ProgramCounter,
+    RegA,
+    RegX,
+    RegY,
+    Status,
+    StackPointer,
+    // Scanline and dot
+    Ppu,
+    Cycles,
+    // One trace ended before the other
+    Length,
+}
+
+// Keys of the fields after the PC in nestest style logs, e.g. "A:00 X:00 ... PPU:  0, 21 CYC:7"
+const TRACE_KEYS: [(&str, TraceField); 7] = [
+    ("A:", TraceField::RegA),
+    ("X:", TraceField::RegX),
+    ("Y:", TraceField::RegY),
+    ("P:", TraceField::Status),
+    ("SP:", TraceField::StackPointer),
+    ("PPU:", TraceField::Ppu),
+    ("CYC:", TraceField::Cycles),
+];
+
+/// First difference between two traces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    // 1-based, like in a text editor
+    pub line: usize,
+    pub field: TraceField,
+    // Value of the field in each trace, None if the trace ended
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<String>| value.clone().unwrap_or("end of trace".to_string());
+        write!(
+            f,
+            "Line {}: {:?} differs, {} vs {}",
+            self.line,
+            self.field,
+            show(&self.a),
+            show(&self.b)
+        )
+    }
+}
+
+// Fields of a trace line in TRACE_KEYS order, None for fields the line doesn't have
+fn parse_trace_line(line: &str) -> Vec<(TraceField, Option<String>)> {
+    let line = line.to_ascii_uppercase();
+    let program_counter = line.split_whitespace().next().map(str::to_string);
+    let mut fields = vec![(TraceField::ProgramCounter, program_counter)];
+    for (key, field) in TRACE_KEYS {
+        // Keys start a word, so "SP:" isn't mistaken for "P:"
+        let start = line
+            .match_indices(key)
+            .find(|(index, _)| *index == 0 || line.as_bytes()[index - 1] == b' ')
+            .map(|(index, _)| index + key.len());
+        let value = start.map(|start| {
+            let rest = &line[start..];
+            // The PPU value is padded with spaces, so it runs up to the next key
+            let end = match field {
+                TraceField::Ppu => rest.find(" CYC:").unwrap_or(rest.len()),
+                _ => rest.find(' ').unwrap_or(rest.len()),
+            };
+            rest[..end].split_whitespace().collect::<String>()
+        });
+        fields.push((field, value));
+    }
+    fields
+}
+
+/// Compares two nestest style traces line by line, e.g. ours against a Mesen or Nintendulator
+/// log, and returns the first field that differs. Fields only one of the logs has are skipped,
+/// so logs without PPU or cycle columns can still be compared.
+pub fn diff_traces<A, B>(a: A, b: B) -> Option<Divergence>
+where
+    A: IntoIterator,
+    A::Item: AsRef<str>,
+    B: IntoIterator,
+    B::Item: AsRef<str>,
+{
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    for line in 1.. {
+        let (line_a, line_b) = match (a.next(), b.next()) {
+            (None, None) => return None,
+            (Some(line_a), Some(line_b)) => (line_a, line_b),
+            (line_a, line_b) => {
+                return Some(Divergence {
+                    line,
+                    field: TraceField::Length,
+                    a: line_a.map(|text| text.as_ref().to_string()),
+                    b: line_b.map(|text| text.as_ref().to_string()),
+                })
+            }
+        };
+        let fields_a = parse_trace_line(line_a.as_ref());
+        let fields_b = parse_trace_line(line_b.as_ref());
+        for ((field, value_a), (_, value_b)) in fields_a.into_iter().zip(fields_b) {
+            if let (Some(value_a), Some(value_b)) = (&value_a, &value_b) {
+                if value_a != value_b {
+                    return Some(Divergence {
+                        line,
+                        field,
+                        a: Some(value_a.clone()),
+                        b: Some(value_b.clone()),
+                    });
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str =
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+
+    #[test]
+    fn test_parse_trace_line() {
+        let fields = parse_trace_line(LINE);
+        assert_eq!(
+            (TraceField::ProgramCounter, Some("C000".to_string())),
+            fields[0]
+        );
+        assert_eq!((TraceField::Status, Some("24".to_string())), fields[4]);
+        assert_eq!(
+            (TraceField::StackPointer, Some("FD".to_string())),
+            fields[5]
+        );
+        assert_eq!((TraceField::Ppu, Some("0,21".to_string())), fields[6]);
+        assert_eq!((TraceField::Cycles, Some("7".to_string())), fields[7]);
+    }
+
+    #[test]
+    fn test_trace_core() {
+        let mut cores: Vec<Box<dyn NES>> = vec![
+            Box::new(ActionNES::new()),
+            Box::new(TraceNes::new().setup()),
+        ];
+        for core in cores.iter_mut() {
+            core.load_from_path("test_roms/nestest.nes").unwrap();
+            core.reset().unwrap();
+            core.next_ppu_frame().unwrap();
+            assert_eq!(1, core.frame_count());
+        }
+        // Both cores run the same console
+        let [action, trace] = &cores[..] else {
+            unreachable!()
+        };
+        assert_eq!(
+            action.peek_cpu_state().cycle_counter,
+            trace.peek_cpu_state().cycle_counter
+        );
+    }
+
+    #[test]
+    fn test_diff_traces() {
+        let other = LINE.replace("P:24", "P:26").to_lowercase();
+        assert_eq!(None, diff_traces([LINE, LINE], [LINE, LINE]));
+        let divergence = diff_traces([LINE, LINE], [LINE, other.as_str()]).unwrap();
+        assert_eq!(2, divergence.line);
+        assert_eq!(TraceField::Status, divergence.field);
+        assert_eq!("Line 2: Status differs, 24 vs 26", divergence.to_string());
+
+        // Missing columns are skipped
+        let short = &LINE[..LINE.find(" PPU:").unwrap()];
+        assert_eq!(None, diff_traces([LINE], [short]));
+        let divergence = diff_traces([LINE, LINE], [LINE]).unwrap();
+        assert_eq!(
+            (2, TraceField::Length, None),
+            (divergence.line, divergence.field, divergence.b)
+        );
+    }
+}
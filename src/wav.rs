@@ -0,0 +1,149 @@
+// 16-bit PCM WAV files, written on a background thread so recording doesn't slow down emulation
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Writes mono 16-bit samples, the sizes in the header are filled in by finish
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_count: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &str, sample_rate: u32) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        Self::new(BufWriter::new(file), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> Result<Self, String> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, 1 channel
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        // Byte rate, block align and bits per sample
+        header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(WavWriter {
+            writer,
+            sample_count: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), String> {
+        for sample in samples {
+            self.writer
+                .write_all(&sample.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        self.sample_count += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Fills in the chunk sizes, returns the underlying writer
+    pub fn finish(mut self) -> Result<W, String> {
+        let data_size = 2 * self.sample_count;
+        let mut patch = |offset: u64, value: u32| {
+            self.writer.seek(SeekFrom::Start(offset))?;
+            self.writer.write_all(&value.to_le_bytes())
+        };
+        patch(4, HEADER_SIZE - 8 + data_size).map_err(|e| e.to_string())?;
+        patch(40, data_size).map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())?;
+        Ok(self.writer)
+    }
+}
+
+/// Sends samples to a WavWriter on another thread, pushing never blocks on disk IO
+pub struct BackgroundWavWriter {
+    sender: Option<Sender<Vec<i16>>>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl BackgroundWavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<Self, String> {
+        let mut wav = WavWriter::create(path, sample_rate)?;
+        let (sender, receiver) = channel::<Vec<i16>>();
+        let thread = thread::spawn(move || {
+            // Ends when the sender is dropped
+            for samples in receiver {
+                wav.write_samples(&samples)?;
+            }
+            wav.finish().map(|_| ())
+        });
+        Ok(BackgroundWavWriter {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn push(&self, samples: &[i16]) {
+        if let Some(sender) = &self.sender {
+            // Only fails if the writer thread hit an error, reported by finish
+            let _ = sender.send(samples.to_vec());
+        }
+    }
+
+    /// Writes the remaining samples and closes the file
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.sender = None;
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| "WAV writer thread panicked".to_string())?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BackgroundWavWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            log::error!("Failed to write WAV file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_samples(&[0, 1, -1]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(44 + 6, bytes.len());
+        assert_eq!(b"RIFF", &bytes[..4]);
+        assert_eq!(42, u32::from_le_bytes(bytes[4..8].try_into().unwrap()));
+        assert_eq!(44100, u32::from_le_bytes(bytes[24..28].try_into().unwrap()));
+        assert_eq!(6, u32::from_le_bytes(bytes[40..44].try_into().unwrap()));
+        assert_eq!(&[0, 0, 1, 0, 0xFF, 0xFF], &bytes[44..]);
+    }
+
+    #[test]
+    fn test_background_writer() {
+        let path = std::env::temp_dir().join("nes_background_writer.wav");
+        let path = path.to_str().unwrap();
+        let mut writer = BackgroundWavWriter::create(path, 48000).unwrap();
+        writer.push(&[1; 100]);
+        writer.push(&[2; 50]);
+        writer.finish().unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(44 + 300, bytes.len());
+    }
+}
@@ -0,0 +1,20 @@
+// Fixtures shared by the test modules
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::rom::ROM;
+
+pub const NMI_HANDLER: u16 = 0x9000;
+pub const BRK_HANDLER: u16 = 0xA000;
+
+/// A 16KB NROM with `program` at $8000, padded with NOPs, reset to run it. NMIs go to
+/// NMI_HANDLER and IRQs and BRK to BRK_HANDLER, both NOPs.
+pub fn program_nes(program: &[u8]) -> ActionNES {
+    let mut rom = ROM::new();
+    rom.prg_rom = vec![0xEA; 0x4000];
+    rom.prg_rom[..program.len()].copy_from_slice(program);
+    // Vectors, $C000-$FFFF mirrors $8000-$BFFF
+    rom.prg_rom[0x3FFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0xA0]);
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set rom");
+    nes.reset().expect("Failed to reset");
+    nes
+}
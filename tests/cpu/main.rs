@@ -1 +1,5 @@
+mod common;
 mod test_cpu;
+mod test_interrupts;
+mod test_opcode_table;
+mod test_programs;
@@ -0,0 +1,127 @@
+use rust_nes_emulator::cpu::CpuStatus;
+use rust_nes_emulator::nes::{ActionNES, NES};
+
+use crate::common::{program_nes, BRK_HANDLER, NMI_HANDLER};
+
+fn pushed_status(nes: &mut ActionNES) -> u8 {
+    let stack_pointer = nes.cpu_state.stack_pointer as u16;
+    nes.as_cpu_bus().peek_byte(0x100 + stack_pointer + 1)
+}
+
+fn pushed_program_counter(nes: &mut ActionNES) -> u16 {
+    let stack_pointer = nes.cpu_state.stack_pointer as u16;
+    nes.as_cpu_bus().peek_two_bytes(0x100 + stack_pointer + 2)
+}
+
+#[test]
+fn test_brk() {
+    let mut nes = program_nes(&[0x00, 0x00]);
+    let stack_pointer = nes.cpu_state.stack_pointer;
+    nes.next_cpu_instruction()
+        .expect("Failed to run instruction");
+
+    assert_eq!(BRK_HANDLER, nes.cpu_state.program_counter);
+    assert_eq!(stack_pointer.wrapping_sub(3), nes.cpu_state.stack_pointer);
+    assert_eq!(0x8002, pushed_program_counter(&mut nes));
+    assert_ne!(0, pushed_status(&mut nes) & CpuStatus::BRK.bits());
+    assert!(nes.cpu_state.status.contains(CpuStatus::INT_DISABLE));
+}
+
+#[test]
+fn test_nmi_hijacks_brk() {
+    let mut nes = program_nes(&[0x00, 0x00]);
+    nes.ppu_state.ppuctrl.write(0b1000_0000);
+    nes.ppu_state.cur_scanline = 240;
+    // Vblank starts 3 CPU cycles into the BRK, before the vector is fetched
+    nes.ppu_state.cycle_counter = 332;
+    nes.sync_timing();
+    nes.next_cpu_instruction()
+        .expect("Failed to run instruction");
+
+    assert_eq!(NMI_HANDLER, nes.cpu_state.program_counter);
+    assert_eq!(0x8002, pushed_program_counter(&mut nes));
+    // B flag still pushed, software can tell it was a BRK
+    assert_ne!(0, pushed_status(&mut nes) & CpuStatus::BRK.bits());
+
+    // The hijacked NMI is not serviced a second time
+    let stack_pointer = nes.cpu_state.stack_pointer;
+    nes.next_cpu_instruction()
+        .expect("Failed to run instruction");
+    assert_eq!(NMI_HANDLER + 1, nes.cpu_state.program_counter);
+    assert_eq!(stack_pointer, nes.cpu_state.stack_pointer);
+}
+
+#[test]
+fn test_late_nmi_does_not_hijack_brk() {
+    let mut nes = program_nes(&[0x00, 0x00]);
+    nes.ppu_state.ppuctrl.write(0b1000_0000);
+    nes.ppu_state.cur_scanline = 240;
+    // Vblank starts 6 CPU cycles into the BRK, after the vector is fetched
+    nes.ppu_state.cycle_counter = 323;
+    nes.sync_timing();
+    nes.next_cpu_instruction()
+        .expect("Failed to run instruction");
+    assert_eq!(BRK_HANDLER, nes.cpu_state.program_counter);
+
+    // NMI is serviced before the next instruction instead
+    nes.next_cpu_instruction()
+        .expect("Failed to run instruction");
+    assert_eq!(NMI_HANDLER + 1, nes.cpu_state.program_counter);
+    assert_eq!(BRK_HANDLER, pushed_program_counter(&mut nes));
+    assert_eq!(0, pushed_status(&mut nes) & CpuStatus::BRK.bits());
+}
+
+#[test]
+fn test_irq_taken_an_instruction_after_cli() {
+    // CLI, NOP
+    let mut nes = program_nes(&[0x58, 0xEA]);
+    // The APU frame counter holds the line low, masked while I is set
+    nes.apu_state.frame_irq = true;
+    nes.next_cpu_instruction().unwrap();
+    assert!(!nes.cpu_state.status.contains(CpuStatus::INT_DISABLE));
+    // CLI polled the line before clearing I, the NOP after it still runs
+    nes.next_cpu_instruction().unwrap();
+    assert_eq!(0x8002, nes.cpu_state.program_counter);
+
+    nes.next_cpu_instruction().unwrap();
+    assert_eq!(BRK_HANDLER + 1, nes.cpu_state.program_counter);
+    assert_eq!(0x8002, pushed_program_counter(&mut nes));
+    // Pushed without B, unlike BRK
+    assert_eq!(0, pushed_status(&mut nes) & CpuStatus::BRK.bits());
+    assert!(nes.cpu_state.status.contains(CpuStatus::INT_DISABLE));
+}
+
+#[test]
+fn test_irq_acknowledged_before_it_is_taken() {
+    // CLI, NOP, NOP
+    let mut nes = program_nes(&[0x58, 0xEA, 0xEA]);
+    nes.apu_state.frame_irq = true;
+    nes.next_cpu_instruction().unwrap();
+    nes.next_cpu_instruction().unwrap();
+    // Reading $4015 releases the line, there's nothing left to take
+    nes.as_cpu_bus().read_byte(0x4015);
+    nes.next_cpu_instruction().unwrap();
+    assert_eq!(0x8003, nes.cpu_state.program_counter);
+}
+
+#[test]
+fn test_reset_sequence() {
+    // LDA #$42, CLI, SEC
+    let mut nes = program_nes(&[0xA9, 0x42, 0x58, 0x38]);
+    assert_eq!(0x8000, nes.cpu_state.program_counter);
+    assert_eq!(0xFD, nes.cpu_state.stack_pointer);
+    assert_eq!(0x24, nes.cpu_state.status.bits());
+    for _ in 0..3 {
+        nes.next_cpu_instruction().unwrap();
+    }
+
+    // Pressing reset only moves SP and sets I, nothing is pushed
+    let stack = nes.peek_memory(0x1FA, 3);
+    nes.reset().unwrap();
+    assert_eq!(0x8000, nes.cpu_state.program_counter);
+    assert_eq!(0xFA, nes.cpu_state.stack_pointer);
+    assert_eq!(0x42, nes.cpu_state.reg_a);
+    assert!(nes.cpu_state.status.contains(CpuStatus::INT_DISABLE));
+    assert!(nes.cpu_state.status.contains(CpuStatus::CARRY));
+    assert_eq!(stack, nes.peek_memory(0x1FA, 3));
+}
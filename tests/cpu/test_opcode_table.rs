@@ -0,0 +1,112 @@
+use rust_nes_emulator::cpu::{AddressingMode, FlatCpu, Opcode, OPCODE_TABLE};
+
+// Reference tables, indexed by [high nibble][low nibble]. JAM opcodes halt the CPU and have no
+// cycle count, they're 0 here.
+// Ref: https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+// Ref: http://www.6502.org/tutorials/6502opcodes.html
+#[rustfmt::skip]
+const SIZES: [[u8; 16]; 16] = [
+    [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // 0
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // 1
+    [3, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // 2
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // 3
+    [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // 4
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // 5
+    [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // 6
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // 7
+    [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // 8
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // 9
+    [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // A
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // B
+    [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // C
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // D
+    [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // E
+    [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // F
+];
+
+// Base cycles, without page cross or branch penalties
+#[rustfmt::skip]
+const CYCLES: [[u8; 16]; 16] = [
+    [7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6], // 0
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // 1
+    [6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6], // 2
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // 3
+    [6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6], // 4
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // 5
+    [6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6], // 6
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // 7
+    [2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4], // 8
+    [2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5], // 9
+    [2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4], // A
+    [2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4], // B
+    [2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6], // C
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // D
+    [2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6], // E
+    [2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7], // F
+];
+
+// Reads that take a cycle more when indexing crosses a page, the "+" of the reference tables
+#[rustfmt::skip]
+const PAGE_CROSS: [u8; 32] = [
+    // ORA, AND, EOR, ADC
+    0x11, 0x19, 0x1D, 0x31, 0x39, 0x3D, 0x51, 0x59, 0x5D, 0x71, 0x79, 0x7D,
+    // LDA, LDX, LDY, LAX, LAS
+    0xB1, 0xB9, 0xBD, 0xBE, 0xBC, 0xB3, 0xBF, 0xBB,
+    // CMP, SBC
+    0xD1, 0xD9, 0xDD, 0xF1, 0xF9, 0xFD,
+    // NOP abs,X
+    0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC,
+];
+
+#[test]
+fn test_opcode_table_matches_reference() {
+    for (raw_opcode, info) in OPCODE_TABLE.iter().enumerate() {
+        let Some(info) = info else {
+            continue;
+        };
+        let (row, column) = (raw_opcode >> 4, raw_opcode & 0xF);
+        let name = format!("{:02X} {:?} {:?}", raw_opcode, info.opcode, info.mode);
+        assert_ne!(0, CYCLES[row][column], "{} jams the CPU", name);
+        assert_eq!(SIZES[row][column], info.size, "Size of {}", name);
+        assert_eq!(CYCLES[row][column], info.cycles, "Cycles of {}", name);
+        assert_eq!(
+            PAGE_CROSS.contains(&(raw_opcode as u8)),
+            info.page_cross_penalty,
+            "Page cross penalty of {}",
+            name
+        );
+        // Only jumps take their operand as the target, everything else reads or writes it
+        let is_jump = matches!(info.opcode, Opcode::JMP | Opcode::JSR);
+        let is_jump_mode = matches!(
+            info.mode,
+            AddressingMode::AbsoluteJump | AddressingMode::IndirectJump
+        );
+        assert_eq!(is_jump, is_jump_mode, "Addressing mode of {}", name);
+    }
+    let official = OPCODE_TABLE.iter().flatten().filter(|info| info.official);
+    assert_eq!(151, official.count());
+}
+
+#[test]
+fn test_parse_consumes_size() {
+    // Catches read_arg and AddressingMode::size disagreeing on a mode's operand bytes
+    for (raw_opcode, info) in OPCODE_TABLE.iter().enumerate() {
+        let Some(info) = info else {
+            continue;
+        };
+        let mut cpu = FlatCpu::new();
+        // Operands point at zero page, so indirect modes read plain memory
+        cpu.load(&[raw_opcode as u8, 0x10, 0x00], 0x0600).unwrap();
+        let (parsed_opcode, parsed, _) = cpu.parse().unwrap();
+        assert_eq!(raw_opcode as u8, parsed_opcode);
+        assert_eq!(info, parsed);
+        assert_eq!(
+            0x0600 + info.size as u16,
+            cpu.cpu_state.program_counter,
+            "{:02X} {:?} {:?} read the wrong number of bytes",
+            raw_opcode,
+            info.opcode,
+            info.mode
+        );
+    }
+}
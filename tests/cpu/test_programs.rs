@@ -0,0 +1,52 @@
+use rust_nes_emulator::cpu::{run_program, CpuStatus, FlatCpu};
+
+#[test]
+fn test_program_multiply() {
+    // 7 * 6 by repeated addition
+    // LDA #$00, LDX #$06, loop: CLC, ADC #$07, DEX, BNE loop, BRK
+    let state = run_program(
+        &[
+            0xA9, 0x00, 0xA2, 0x06, 0x18, 0x69, 0x07, 0xCA, 0xD0, 0xFA, 0x00,
+        ],
+        0x0600,
+    );
+    assert_eq!(42, state.reg_a);
+    assert_eq!(0, state.reg_x);
+    assert!(state.status.contains(CpuStatus::ZERO));
+}
+
+#[test]
+fn test_program_subroutine() {
+    // JSR $0610, BRK, ... $0610: LDY #$33, RTS
+    let mut program = vec![0xEA; 0x13];
+    program[..4].copy_from_slice(&[0x20, 0x10, 0x06, 0x00]);
+    program[0x10..].copy_from_slice(&[0xA0, 0x33, 0x60]);
+    let state = run_program(&program, 0x0600);
+    assert_eq!(0x33, state.reg_y);
+    assert_eq!(0x0603, state.program_counter);
+    assert_eq!(0xFD, state.stack_pointer);
+}
+
+#[test]
+fn test_program_overflow_flag() {
+    // LDA #$50, CLC, ADC #$50, BRK
+    let state = run_program(&[0xA9, 0x50, 0x18, 0x69, 0x50, 0x00], 0x0600);
+    assert_eq!(0xA0, state.reg_a);
+    assert!(state.status.contains(CpuStatus::OVERFLOW));
+    assert!(state.status.contains(CpuStatus::NEGATIVE));
+    assert!(!state.status.contains(CpuStatus::CARRY));
+}
+
+#[test]
+fn test_program_memory_copy() {
+    // Copies 4 bytes from $0700 to $0200
+    // LDX #$03, loop: LDA $0700,X, STA $0200,X, DEX, BPL loop, BRK
+    let program = [
+        0xA2, 0x03, 0xBD, 0x00, 0x07, 0x9D, 0x00, 0x02, 0xCA, 0x10, 0xF7, 0x00,
+    ];
+    let mut cpu = FlatCpu::new();
+    cpu.load(&program, 0x0600).unwrap();
+    cpu.memory.data[0x0700..0x0704].copy_from_slice(&[1, 2, 3, 4]);
+    cpu.run_until_brk(100).unwrap();
+    assert_eq!(&[1, 2, 3, 4], &cpu.memory.data[0x0200..0x0204]);
+}
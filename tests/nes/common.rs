@@ -0,0 +1,25 @@
+// Fixtures shared by the test modules
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::rom::ROM;
+
+pub const NESTEST: &str = "test_roms/nestest.nes";
+
+/// Loads the .nes file at `path` and resets
+pub fn load_nes(path: &str) -> ActionNES {
+    let mut nes = ActionNES::new();
+    nes.load_from_path(path).expect("Failed to load from path");
+    nes.reset().expect("Failed to reset");
+    nes
+}
+
+/// A 16KB NROM with `program` at $8000, padded with NOPs, reset to run it
+pub fn program_nes(program: &[u8]) -> ActionNES {
+    let mut rom = ROM::new();
+    rom.prg_rom = vec![0xEA; 0x4000];
+    rom.prg_rom[..program.len()].copy_from_slice(program);
+    rom.prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set rom");
+    nes.reset().expect("Failed to reset");
+    nes
+}
@@ -0,0 +1,13 @@
+mod common;
+mod test_determinism;
+mod test_headless;
+mod test_history;
+// The vblank hook is left out of minimal builds
+#[cfg(not(feature = "minimal"))]
+mod test_hooks;
+mod test_memory;
+mod test_movie;
+mod test_ppu_registers;
+mod test_rendering;
+mod test_savestate;
+mod test_timing;
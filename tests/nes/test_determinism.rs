@@ -0,0 +1,180 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use rust_nes_emulator::audit::Nondeterminism;
+use rust_nes_emulator::common::crc32;
+use rust_nes_emulator::controller::ControllerState;
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::screen::frame::Frame;
+
+use crate::common::{load_nes, program_nes, NESTEST};
+
+const TEST_ROMS: [&str; 2] = [NESTEST, "test_roms/color_test.nes"];
+const FRAMES: usize = 10;
+
+fn state_hash(nes: &ActionNES) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let cpu = &nes.cpu_state;
+    cpu.ram.hash(&mut hasher);
+    (cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status.bits()).hash(&mut hasher);
+    (cpu.stack_pointer, cpu.program_counter, cpu.cycle_counter).hash(&mut hasher);
+    let ppu = &nes.ppu_state;
+    ppu.ram.hash(&mut hasher);
+    ppu.oam_data.hash(&mut hasher);
+    ppu.palette_table.hash(&mut hasher);
+    (ppu.ppuctrl.bits(), ppu.ppumask.bits(), ppu.ppustatus.bits()).hash(&mut hasher);
+    (ppu.cycle_counter, ppu.cur_scanline).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn frame_hash(nes: &ActionNES) -> u64 {
+    let mut frame = Frame::new();
+    nes.render_frame(&mut frame);
+    let mut hasher = DefaultHasher::new();
+    frame.as_bytes_ref().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Runs FRAMES frames with next_ppu_frame, returning (final cpu cycle, state hash, frame hash)
+fn run_by_frame(path: &str) -> (usize, u64, u64) {
+    let mut nes = load_nes(path);
+    for _ in 0..FRAMES {
+        nes.next_ppu_frame().expect("Failed to run frame");
+    }
+    (
+        nes.cpu_state.cycle_counter,
+        state_hash(&nes),
+        frame_hash(&nes),
+    )
+}
+
+#[test]
+fn test_step_by_instruction_matches_frame() {
+    for path in TEST_ROMS {
+        let (target, expected_state, expected_frame) = run_by_frame(path);
+        let mut nes = load_nes(path);
+        while nes.cpu_state.cycle_counter < target {
+            nes.next_cpu_instruction()
+                .expect("Failed to run instruction");
+        }
+        assert_eq!(target, nes.cpu_state.cycle_counter, "{}", path);
+        assert_eq!(expected_state, state_hash(&nes), "{}", path);
+        assert_eq!(expected_frame, frame_hash(&nes), "{}", path);
+    }
+}
+
+#[test]
+fn test_step_by_scanline_matches_frame() {
+    for path in TEST_ROMS {
+        let (target, expected_state, expected_frame) = run_by_frame(path);
+        let mut nes = load_nes(path);
+        while nes.cpu_state.cycle_counter < target {
+            nes.next_ppu_scanline().expect("Failed to run scanline");
+        }
+        assert_eq!(target, nes.cpu_state.cycle_counter, "{}", path);
+        assert_eq!(expected_state, state_hash(&nes), "{}", path);
+        assert_eq!(expected_frame, frame_hash(&nes), "{}", path);
+    }
+}
+
+#[test]
+fn test_step_by_cycles_matches_frame() {
+    for path in TEST_ROMS {
+        let (target, expected_state, expected_frame) = run_by_frame(path);
+        // Odd step sizes so that steps rarely land on instruction boundaries
+        for step in [1, 113, 1000] {
+            let mut nes = load_nes(path);
+            while nes.cpu_state.cycle_counter < target {
+                let remaining = target - nes.cpu_state.cycle_counter;
+                nes.next_cpu_cycles(remaining.min(step))
+                    .expect("Failed to run cycles");
+            }
+            assert_eq!(target, nes.cpu_state.cycle_counter, "{} {}", path, step);
+            assert_eq!(expected_state, state_hash(&nes), "{} {}", path, step);
+            assert_eq!(expected_frame, frame_hash(&nes), "{} {}", path, step);
+        }
+    }
+}
+
+// Buttons held on `frame`, walking through nestest's menu and starting its tests
+fn scripted_input(frame: usize) -> ControllerState {
+    match frame % 120 {
+        30..=31 => ControllerState::DOWN,
+        60..=61 => ControllerState::SELECT,
+        90..=91 => ControllerState::START,
+        _ => ControllerState::empty(),
+    }
+}
+
+/// Runs `frames` frames of nestest with scripted input, returning (state hash, frame CRC)
+fn run_scripted(frames: usize) -> (u32, u32) {
+    let mut nes = load_nes(NESTEST);
+    for frame in 0..frames {
+        nes.controller.controller_state = scripted_input(frame);
+        nes.next_ppu_frame().expect("Failed to run frame");
+    }
+    let mut frame = Frame::new();
+    nes.render_frame(&mut frame);
+    (nes.state_hash(), crc32(frame.as_bytes_ref()))
+}
+
+// The stored hashes catch emulation that differs between platforms or builds, e.g. from float
+// timing or iterating a HashMap. Changes that are meant to alter emulation update them.
+#[test]
+fn test_scripted_run_matches_stored_hash() {
+    assert_eq!((0x6F03BDB3, 0x11EA5DCA), run_scripted(300));
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with cargo test --release -- --ignored"]
+fn test_long_scripted_run_matches_stored_hash() {
+    assert_eq!((0xCEFC0CD3, 0x73020BCB), run_scripted(10_000));
+}
+
+// The audit starts after the reset's vector reads
+fn audited_nes(program: &[u8]) -> ActionNES {
+    let mut nes = program_nes(program);
+    nes.enable_audit();
+    nes
+}
+
+#[test]
+fn test_audit_clean_program() {
+    // LDA #$01, STA $10, LDA $10, LDA $0810 (mirror of $10)
+    let mut nes = audited_nes(&[0xA9, 0x01, 0x85, 0x10, 0xA5, 0x10, 0xAD, 0x10, 0x08]);
+    for _ in 0..4 {
+        nes.next_cpu_instruction().unwrap();
+    }
+    assert!(nes.audit().unwrap().is_clean());
+}
+
+#[test]
+fn test_audit_flags_nondeterministic_reads() {
+    // LDA $20, LDA $20, LDA $4000 (write-only)
+    let mut nes = audited_nes(&[0xA5, 0x20, 0xA5, 0x20, 0xAD, 0x00, 0x40]);
+    for _ in 0..3 {
+        nes.next_cpu_instruction().unwrap();
+    }
+    let audit = nes.audit().unwrap();
+    let records: Vec<_> = audit
+        .records()
+        .map(|(source, record)| (*source, record.count))
+        .collect();
+    assert_eq!(
+        vec![
+            (Nondeterminism::UninitializedRam(0x20), 2),
+            (Nondeterminism::OpenBus(0x4000), 1)
+        ],
+        records
+    );
+}
+
+#[cfg(not(feature = "minimal"))]
+#[test]
+fn test_audit_vblank_hook() {
+    let mut nes = audited_nes(&[]);
+    nes.set_on_vblank(|_| {});
+    nes.next_ppu_frame().unwrap();
+    let records: Vec<_> = nes.audit().unwrap().records().map(|(s, _)| *s).collect();
+    assert_eq!(vec![Nondeterminism::VblankHook], records);
+}
@@ -0,0 +1,49 @@
+use rust_nes_emulator::nes::NES;
+use rust_nes_emulator::tracer::TraceNes;
+
+use crate::common::{load_nes, NESTEST};
+
+#[test]
+fn test_run_for_frames() {
+    let mut nes = load_nes(NESTEST);
+    let frame = nes.run_for_frames(3).unwrap();
+    assert_eq!(3, nes.frame_count());
+    let expected = load_nes(NESTEST).frames().nth(2).unwrap().unwrap();
+    assert!(expected.data == frame.data);
+
+    // Any core, through the trait object too
+    let mut cores: Vec<Box<dyn NES>> = vec![Box::new(load_nes(NESTEST)), Box::new(TraceNes::new())];
+    cores[1].load_from_path(NESTEST).unwrap();
+    cores[1].reset().unwrap();
+    for core in &mut cores {
+        assert!(core.run_for_frames(3).unwrap().data == frame.data);
+    }
+}
+
+#[test]
+fn test_run_for_cpu_cycles() {
+    let mut nes = load_nes(NESTEST);
+    let start = nes.cpu_state.cycle_counter;
+    nes.run_for_cpu_cycles(1000).unwrap();
+    // Whole instructions, the last one may run over
+    let elapsed = nes.cpu_state.cycle_counter - start;
+    assert!((1000..1007).contains(&elapsed), "{} cycles", elapsed);
+}
+
+#[test]
+fn test_run_until() {
+    let mut nes = load_nes(NESTEST);
+    nes.run_until(100_000, |nes| nes.ppu_state.cur_scanline == 241)
+        .unwrap();
+    assert_eq!(241, nes.ppu_state.cur_scanline);
+    // Already true, nothing runs
+    let cycles = nes.cpu_state.cycle_counter;
+    nes.run_until(0, |nes| nes.ppu_state.cur_scanline == 241)
+        .unwrap();
+    assert_eq!(cycles, nes.cpu_state.cycle_counter);
+
+    let Err(err) = nes.run_until(1000, |_| false) else {
+        panic!("Ran until a condition that never holds");
+    };
+    assert!(err.starts_with("Condition not met after 100"), "{}", err);
+}
@@ -0,0 +1,46 @@
+use rust_nes_emulator::nes::{ActionNES, NES};
+
+use crate::common::program_nes;
+
+// LDA #$42, LDX #$07, then an unimplemented opcode
+fn create_nes() -> ActionNES {
+    program_nes(&[0xA9, 0x42, 0xA2, 0x07, 0x02])
+}
+
+#[test]
+fn test_history_disabled_by_default() {
+    let mut nes = create_nes();
+    nes.next_cpu_instruction().unwrap();
+    assert!(nes.history().is_none());
+}
+
+#[test]
+fn test_history_records_failed_instruction() {
+    let mut nes = create_nes();
+    nes.enable_history(2);
+    nes.next_cpu_instruction().unwrap();
+    nes.next_cpu_instruction().unwrap();
+    assert!(nes.next_cpu_instruction().is_err());
+
+    let history = nes.history().expect("History not enabled");
+    assert_eq!(2, history.len());
+    let entries: Vec<_> = history.iter().collect();
+    assert_eq!(0x8002, entries[0].program_counter);
+    assert_eq!(Some(0xA2), entries[0].raw_opcode);
+    assert_eq!(0x42, entries[0].reg_a);
+    // Failing instruction is last, with no decoded opcode
+    assert_eq!(0x8004, entries[1].program_counter);
+    assert_eq!(None, entries[1].raw_opcode);
+    assert_eq!(0x07, entries[1].reg_x);
+}
+
+#[test]
+fn test_dump_state() {
+    let mut nes = create_nes();
+    nes.enable_history(4);
+    nes.next_cpu_instruction().unwrap();
+    let dump = nes.dump_state();
+    assert!(dump.starts_with("CPU: PC:8002 A:42"));
+    assert!(dump.contains("Last 1 instructions:\n8000  A9"));
+    assert!(dump.contains("07F0: 00 00"));
+}
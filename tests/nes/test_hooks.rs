@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rust_nes_emulator::controller::ControllerState;
+use rust_nes_emulator::nes::{ActionNES, NES};
+
+#[test]
+fn test_on_vblank_called_once_per_frame() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes")
+        .expect("Failed to load from path");
+    nes.reset().expect("Failed to reset");
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let hook_count = Arc::clone(&count);
+    nes.set_on_vblank(move |controller| {
+        hook_count.fetch_add(1, Ordering::SeqCst);
+        controller.set_controller_state(ControllerState::START);
+    });
+
+    for _ in 0..5 {
+        nes.next_ppu_frame().expect("Failed to run frame");
+    }
+    assert_eq!(5, count.load(Ordering::SeqCst));
+    assert!(nes
+        .controller
+        .controller_state
+        .contains(ControllerState::START));
+
+    nes.clear_on_vblank();
+    nes.next_ppu_frame().expect("Failed to run frame");
+    assert_eq!(5, count.load(Ordering::SeqCst));
+}
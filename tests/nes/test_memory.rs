@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use rust_nes_emulator::controller::{Controller, ControllerState};
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::peripheral::PortDevice;
+use rust_nes_emulator::profiler::MemoryRegion;
+use rust_nes_emulator::rom::mapper::{BandaiFcg, VirtualClock};
+use rust_nes_emulator::rom::{CHR_RAM_SIZE, ROM, TRAINER_SIZE};
+use rust_nes_emulator::screen::frame::Frame;
+
+#[test]
+fn test_peek_memory_ram_mirroring() {
+    let mut nes = ActionNES::new();
+    nes.as_cpu_bus().write_byte(0x1803, 0x77);
+    for base in [0x0000, 0x0800, 0x1000, 0x1800] {
+        assert_eq!(vec![0x77], nes.peek_memory(base + 3, 1));
+    }
+    assert_eq!(0x77, nes.cpu_state.ram[3]);
+    assert_eq!(
+        nes.peek_memory(0x0000, 0x800),
+        nes.peek_memory(0x1000, 0x800)
+    );
+}
+
+#[test]
+fn test_observe_set_inputs_step_frames() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes")
+        .expect("Failed to load from path");
+    nes.reset().expect("Failed to reset");
+    nes.as_cpu_bus().write_byte(0x0010, 0x12);
+    nes.as_cpu_bus().write_byte(0x0020, 0x34);
+    assert_eq!(vec![0x34, 0x12], nes.observe(&[0x0020, 0x0010]));
+
+    nes.set_inputs(ControllerState::A | ControllerState::UP);
+    assert_eq!(
+        (ControllerState::A | ControllerState::UP).bits(),
+        nes.controller.controller_state.bits()
+    );
+
+    let cycles = nes.cpu_state.cycle_counter;
+    nes.step_frames(2).expect("Failed to step frames");
+    // Roughly 29780 CPU cycles per frame
+    assert!(nes.cpu_state.cycle_counter - cycles > 2 * 29000);
+}
+
+#[test]
+fn test_frames_iterator() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes")
+        .expect("Failed to load from path");
+    nes.reset().expect("Failed to reset");
+    let frames: Vec<Box<Frame>> = nes
+        .frames()
+        .take(3)
+        .collect::<Result<_, _>>()
+        .expect("Failed to run frames");
+    assert_eq!(3, frames.len());
+    assert_eq!(3, nes.frame_count());
+    // 89342 dots a frame at 3 dots per cycle of the 1.789773MHz clock
+    assert_eq!(Duration::from_nanos(49_918_062), nes.emulated_duration());
+    let mut frame = Frame::new();
+    nes.render_frame(&mut frame);
+    assert!(frames[2].data == frame.data);
+    // Frames only run when they're asked for
+    let _ = nes.frames();
+    assert_eq!(3, nes.frame_count());
+}
+
+#[test]
+fn test_player_2_reads_from_4017() {
+    let mut nes = ActionNES::new();
+    nes.port_2 = PortDevice::Joypad(Controller::new());
+    nes.update_controller(0, ControllerState::A, true);
+    nes.update_controller(1, ControllerState::B | ControllerState::START, true);
+    // One strobe latches both controllers, each shifts out on its own address
+    let mut bus = nes.as_cpu_bus();
+    bus.write_byte(0x4016, 1);
+    bus.write_byte(0x4016, 0);
+    let mut read = |addr| (0..8).map(|_| bus.read_byte(addr) & 1).collect::<Vec<_>>();
+    let player_1 = read(0x4016);
+    let player_2 = read(0x4017);
+    // A, B, Select, Start, Up, Down, Left, Right
+    assert_eq!(vec![1, 0, 0, 0, 0, 0, 0, 0], player_1);
+    assert_eq!(vec![0, 1, 0, 1, 0, 0, 0, 0], player_2);
+
+    // Nothing to press without a controller in port 2
+    nes.port_2 = PortDevice::Disconnected;
+    nes.update_controller(1, ControllerState::A, true);
+    assert!(nes.player_controller(1).is_none());
+}
+
+#[test]
+fn test_trainer_loaded_into_prg_ram() {
+    let raw = std::fs::read("test_roms/nestest.nes").expect("Failed to read ROM");
+    let mut rom = ROM::from(raw).expect("Failed to load ROM");
+    let mut trainer = vec![0; TRAINER_SIZE];
+    trainer[0] = 0x12;
+    trainer[TRAINER_SIZE - 1] = 0x34;
+    rom.trainer = Some(trainer);
+    let mut nes = ActionNES::new();
+    nes.as_cpu_bus().write_byte(0x6000, 0xFF);
+    nes.set_rom(rom).expect("Failed to set ROM");
+    assert_eq!(vec![0x00, 0x12], nes.peek_memory(0x6FFF, 2));
+    assert_eq!(vec![0x34, 0x00], nes.peek_memory(0x71FF, 2));
+    // The rest of PRG RAM is cleared for the new cartridge
+    assert_eq!(vec![0x00], nes.peek_memory(0x6000, 1));
+
+    nes.as_cpu_bus().write_byte(0x7000, 0x56);
+    assert_eq!(0x56, nes.as_cpu_bus().read_byte(0x7000));
+}
+
+#[test]
+fn test_battery_ram() {
+    let create_nes = |mapper: u8, battery: bool| {
+        let mut rom = ROM::new();
+        rom.mapper = mapper;
+        rom.prg_rom = vec![0xEA; 0x8000];
+        rom.battery = battery;
+        let mut nes = ActionNES::new();
+        nes.set_rom(rom).expect("Failed to set ROM");
+        nes
+    };
+    assert_eq!(None, create_nes(0, false).battery_ram());
+
+    // PRG RAM with a battery, loaded back into the next session
+    let mut nes = create_nes(0, true);
+    nes.as_cpu_bus().write_byte(0x6001, 0x42);
+    let save = nes.battery_ram().unwrap().to_vec();
+    assert_eq!(0x2000, save.len());
+    // Only as much as the cartridge has
+    nes.rom.prg_ram_size = 0x800;
+    assert_eq!(0x800, nes.battery_ram().unwrap().len());
+    let mut next = create_nes(0, true);
+    next.load_battery_ram(&save);
+    assert_eq!(0x42, next.as_cpu_bus().read_byte(0x6001));
+
+    // Boards with their own memory save that instead, the Bandai EEPROM here
+    let mut bandai = create_nes(16, false);
+    bandai.load_battery_ram(&[0x12; 256]);
+    assert_eq!(&[0x12; 256][..], &*bandai.battery_ram().unwrap());
+}
+
+#[test]
+fn test_rtc_clock_source() {
+    let mut rom = ROM::new();
+    rom.mapper = 16;
+    rom.submapper = BandaiFcg::RTC_SUBMAPPER;
+    rom.prg_rom = vec![0xEA; 0x8000];
+    rom.prg_ram_size = 0;
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set ROM");
+    // Tens of years
+    let read_year = |nes: &mut ActionNES| {
+        nes.as_cpu_bus().write_byte(0x800E, 0x0B);
+        nes.as_cpu_bus().read_byte(0x6000) & 0x0F
+    };
+    assert_eq!(0, read_year(&mut nes));
+    nes.set_clock_source(Box::new(VirtualClock::new(1_700_000_000)));
+    assert_eq!(2, read_year(&mut nes));
+    // The EEPROM then the time the game set, none here
+    assert_eq!(264, nes.battery_ram().unwrap().len());
+}
+
+#[test]
+fn test_prg_ram_sizes() {
+    let create_nes = |prg_ram_size: usize| {
+        let mut rom = ROM::new();
+        rom.mapper = 16;
+        rom.prg_rom = (0..4u8).flat_map(|bank| [bank; 0x4000]).collect();
+        rom.prg_ram_size = prg_ram_size;
+        let mut nes = ActionNES::new();
+        nes.set_rom(rom).expect("Failed to set ROM");
+        nes
+    };
+    // 2KB mirrored across $6000-$7FFF
+    let mut nes = create_nes(0x800);
+    nes.as_cpu_bus().write_byte(0x7808, 0x42);
+    assert_eq!(0x42, nes.as_cpu_bus().read_byte(0x6008));
+    assert_eq!(Ok(0x42), nes.with_memory_edit(|mem| mem.read(0x6808)));
+    // Without PRG RAM the board gets the accesses, $6008 is the Bandai PRG bank register
+    let mut nes = create_nes(0);
+    nes.as_cpu_bus().write_byte(0x6008, 2);
+    assert_eq!(2, nes.as_cpu_bus().read_byte(0x8000));
+    assert!(nes.with_memory_edit(|mem| mem.read(0x6000)).is_err());
+}
+
+#[test]
+fn test_mmc1_banks_and_chr_ram() {
+    // Eight 16KB banks, each filled with its number
+    let mut rom = ROM::new();
+    rom.mapper = 1;
+    rom.prg_rom = (0..8u8).flat_map(|bank| [bank; 0x4000]).collect();
+    rom.chr_rom = vec![0; CHR_RAM_SIZE];
+    rom.chr_ram = true;
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set ROM");
+    let mut bus = nes.as_cpu_bus();
+    assert_eq!((0, 7), (bus.read_byte(0x8000), bus.read_byte(0xFFFF)));
+    // Bank 3 into $8000, one bit per write
+    for bit in 0..5 {
+        bus.write_byte(0xE000, (3 >> bit) & 1);
+    }
+    assert_eq!((3, 7), (bus.read_byte(0xBFFF), bus.read_byte(0xC000)));
+
+    // The PPU writes CHR RAM through PPUDATA
+    bus.write_byte(0x2006, 0x00);
+    bus.write_byte(0x2006, 0x10);
+    bus.write_byte(0x2007, 0x42);
+    assert_eq!(0x42, nes.rom.chr_rom[0x10]);
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x40,       // LDA #$40, no APU frame IRQ
+        0x8D, 0x17, 0x40, // STA $4017
+        0x58,             // CLI
+        0xA9, 0x1E,       // LDA #$1E, rendering on
+        0x8D, 0x01, 0x20, // STA $2001
+        0xA9, 0x0A,       // LDA #10
+        0x8D, 0x00, 0xC0, // STA $C000, IRQ latch
+        0x8D, 0x01, 0xC0, // STA $C001, reload
+        0x8D, 0x01, 0xE0, // STA $E001, enable
+        0x4C, 0x16, 0xE0, // JMP $E016
+    ];
+    #[rustfmt::skip]
+    let handler = [
+        0xE6, 0x10,       // INC $10
+        0x8D, 0x00, 0xE0, // STA $E000, acknowledge
+        0x8D, 0x01, 0xE0, // STA $E001
+        0x40,             // RTI
+    ];
+    // $E000 is fixed to the last 8KB bank
+    let mut rom = ROM::new();
+    rom.mapper = 4;
+    rom.prg_rom = vec![0; 0x8000];
+    rom.prg_rom[0x6000..0x6000 + program.len()].copy_from_slice(&program);
+    rom.prg_rom[0x6100..0x6100 + handler.len()].copy_from_slice(&handler);
+    // NMI, reset and IRQ vectors
+    rom.prg_rom[0x7FFA..].copy_from_slice(&[0x08, 0xE1, 0x00, 0xE0, 0x00, 0xE1]);
+    rom.chr_rom = vec![0; CHR_RAM_SIZE];
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set ROM");
+    nes.reset().expect("Failed to reset");
+    nes.step_frames(2).expect("Failed to step frames");
+    let count = nes.peek_memory(0x0010, 1)[0];
+    nes.step_frames(1).expect("Failed to step frames");
+    // Every 11 of the 241 scanlines with pattern fetches
+    let irqs = nes.peek_memory(0x0010, 1)[0].wrapping_sub(count);
+    assert!((21..=22).contains(&irqs), "{} IRQs in a frame", irqs);
+}
+
+#[test]
+fn test_memory_profiler() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes")
+        .expect("Failed to load from path");
+    nes.reset().expect("Failed to reset");
+    assert!(nes.profile().is_none());
+    nes.enable_profiler();
+    nes.step_frames(2).expect("Failed to step frames");
+
+    let profile = nes.profile().unwrap();
+    let regions = profile.regions(nes.rom.prg_rom.len());
+    let region = |wanted: MemoryRegion| {
+        regions
+            .iter()
+            .find(|(region, _)| *region == wanted)
+            .map(|(_, counts)| *counts)
+            .unwrap_or_default()
+    };
+    // Waiting for vblank polls PPUSTATUS, and all code runs from the one PRG bank
+    assert!(region(MemoryRegion::PpuRegisters).reads > 0);
+    assert!(region(MemoryRegion::PrgBank(0)).reads > region(MemoryRegion::Stack).total());
+    assert_eq!(0, region(MemoryRegion::PrgBank(0)).writes);
+    // The vblank wait loop reads PPUSTATUS as often as its own opcodes, ties go to the lower address
+    let (hottest, _) = profile.hot_addresses(1)[0];
+    assert_eq!(0x2002, hottest);
+
+    nes.disable_profiler();
+    assert!(nes.profile().is_none());
+}
@@ -0,0 +1,66 @@
+use rust_nes_emulator::controller::ControllerState;
+use rust_nes_emulator::movie::Movie;
+use rust_nes_emulator::nes::{ActionNES, NES};
+
+use crate::common::{load_nes, NESTEST};
+
+// Moves through nestest's menu and starts a test, so the input changes what runs
+fn scripted_input(frame: usize) -> ControllerState {
+    match frame {
+        20..=23 => ControllerState::DOWN,
+        40..=43 => ControllerState::SELECT,
+        60..=63 => ControllerState::START,
+        _ => ControllerState::empty(),
+    }
+}
+
+fn record(nes: &mut ActionNES, movie: &mut Movie, frames: usize, offset: usize) {
+    for _ in 0..frames {
+        nes.set_inputs(scripted_input(nes.frame_count() + offset));
+        movie.record(nes);
+        nes.next_ppu_frame().unwrap();
+    }
+}
+
+#[test]
+fn test_replay_matches_recording() {
+    let mut nes = load_nes(NESTEST);
+    let mut movie = Movie::new(&nes.rom);
+    record(&mut nes, &mut movie, 120, 0);
+    assert_eq!(120, movie.frames.len());
+
+    let mut idle = load_nes(NESTEST);
+    idle.step_frames(120).unwrap();
+    assert_ne!(idle.state_hash(), nes.state_hash());
+
+    let loaded = Movie::parse(&movie.to_text()).unwrap();
+    let mut replay = load_nes(NESTEST);
+    loaded.play(&mut replay).unwrap();
+    assert_eq!(120, replay.frame_count());
+    assert_eq!(nes.state_hash(), replay.state_hash());
+}
+
+#[test]
+fn test_rerecording_after_loading_a_state() {
+    let mut nes = load_nes(NESTEST);
+    let mut movie = Movie::new(&nes.rom);
+    record(&mut nes, &mut movie, 30, 0);
+    let state = nes.save_state();
+    record(&mut nes, &mut movie, 60, 0);
+    // Going back to frame 30 and playing it differently replaces the rest of the movie
+    nes.load_state(&state).unwrap();
+    record(&mut nes, &mut movie, 40, 20);
+    assert_eq!(70, movie.frames.len());
+
+    let mut replay = load_nes(NESTEST);
+    movie.play(&mut replay).unwrap();
+    assert_eq!(nes.state_hash(), replay.state_hash());
+}
+
+#[test]
+fn test_replay_needs_the_same_rom() {
+    let nes = load_nes(NESTEST);
+    let movie = Movie::new(&nes.rom);
+    let mut other = load_nes("test_roms/color_test.nes");
+    assert!(movie.play(&mut other).is_err());
+}
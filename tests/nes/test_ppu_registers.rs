@@ -0,0 +1,92 @@
+// $2005 and $2006 share one write toggle (w), cleared by reading $2002. These are the write
+// sequences games use to set the scroll and VRAM address, including mid-frame.
+use rust_nes_emulator::nes::ActionNES;
+
+fn write(nes: &mut ActionNES, writes: &[(u16, u8)]) {
+    for (addr, data) in writes {
+        nes.as_cpu_bus().write_byte(*addr, *data);
+    }
+}
+
+fn read_status(nes: &mut ActionNES) {
+    nes.as_cpu_bus().read_byte(0x2002);
+}
+
+#[test]
+fn test_status_read_resets_scroll_toggle() {
+    let mut nes = ActionNES::new();
+    // A stray write leaves the toggle on the second (Y) write
+    write(&mut nes, &[(0x2005, 0x7D)]);
+    read_status(&mut nes);
+    write(&mut nes, &[(0x2005, 0x10), (0x2005, 0x20)]);
+    assert_eq!((0x10, 0x20), nes.ppu_state.loopy.get_scroll());
+    assert!(!nes.ppu_state.loopy.w);
+}
+
+#[test]
+fn test_scroll_then_address() {
+    let mut nes = ActionNES::new();
+    read_status(&mut nes);
+    write(&mut nes, &[(0x2005, 0x7D), (0x2005, 0x5E)]);
+    assert_eq!((0x7D, 0x5E), nes.ppu_state.loopy.get_scroll());
+    read_status(&mut nes);
+    write(&mut nes, &[(0x2006, 0x21), (0x2006, 0x08)]);
+    // $2006 replaces t, and the second write copies it to v
+    assert_eq!(0x2108, nes.ppu_state.loopy.v);
+    assert_eq!(nes.ppu_state.loopy.t, nes.ppu_state.loopy.v);
+    // Fine X is only written by $2005
+    assert_eq!(0x5, nes.ppu_state.loopy.x);
+    assert!(!nes.ppu_state.loopy.w);
+}
+
+#[test]
+fn test_address_write_continues_scroll_toggle() {
+    let mut nes = ActionNES::new();
+    read_status(&mut nes);
+    write(&mut nes, &[(0x2005, 0x00)]);
+    // Without a $2002 read this is taken as the low byte and copied to v right away
+    write(&mut nes, &[(0x2006, 0x21)]);
+    assert_eq!(0x0021, nes.ppu_state.loopy.v);
+    assert!(!nes.ppu_state.loopy.w);
+    write(&mut nes, &[(0x2006, 0x3F)]);
+    assert_eq!(0x0021, nes.ppu_state.loopy.v);
+    assert!(nes.ppu_state.loopy.w);
+}
+
+#[test]
+fn test_mid_frame_scroll_split() {
+    let mut nes = ActionNES::new();
+    nes.ppu_state.cur_scanline = 120;
+    read_status(&mut nes);
+    // Nametable 0, Y 0x48 and X 0x28 through $2006, $2005, $2005, $2006
+    let (y, x) = (0x48u8, 0x28u8);
+    write(
+        &mut nes,
+        &[
+            (0x2006, 0x00),
+            (0x2005, y),
+            (0x2005, x),
+            (0x2006, ((y & 0xF8) << 2) | (x >> 3)),
+        ],
+    );
+    // Coarse Y 9 and coarse X 5 take effect immediately
+    assert_eq!((9 << 5) | 5, nes.ppu_state.loopy.v);
+    assert_eq!(0, nes.ppu_state.loopy.x);
+    assert!(!nes.ppu_state.loopy.w);
+}
+
+#[test]
+fn test_oam_dma_with_nonzero_oamaddr() {
+    let mut nes = ActionNES::new();
+    for i in 0..0x200u16 {
+        nes.cpu_state.ram[0x200 + i as usize] = i as u8 ^ (i >> 8) as u8;
+    }
+    write(&mut nes, &[(0x2003, 0x08), (0x4014, 0x02)]);
+    // $0200 goes to OAMADDR and the copy wraps, only the source page is read
+    assert_eq!(0x00, nes.ppu_state.oam_data[0x08]);
+    assert_eq!(0xF7, nes.ppu_state.oam_data[0xFF]);
+    assert_eq!(0xFF, nes.ppu_state.oam_data[0x07]);
+    assert_eq!(0x08, nes.ppu_state.oamaddr.read());
+    // $2004 reads from OAMADDR, which is back at the first byte copied
+    assert_eq!(0x00, nes.as_cpu_bus().read_byte(0x2004));
+}
@@ -0,0 +1,188 @@
+// The PPU draws a dot at a time, so scroll, pattern tables and sprites are whatever they are on
+// the scanline being drawn, like a status bar split made with a mid-frame $2005 or $2000 write
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::ppu::{PpuMask, DOTS_PER_SCANLINE, PRE_RENDER_SCANLINE};
+use rust_nes_emulator::rom::ROM;
+use rust_nes_emulator::screen::frame::{Frame, PixelSource};
+use rust_nes_emulator::screen::palette::{self, MaskedPalette};
+
+fn write(nes: &mut ActionNES, writes: &[(u16, u8)]) {
+    for (addr, data) in writes {
+        nes.as_cpu_bus().write_byte(*addr, *data);
+    }
+}
+
+// Tile 1 is solid color 1 and fills the left column of the nametable, rendering is on from
+// the pre-render line
+fn create_nes() -> ActionNES {
+    let mut rom = ROM::new();
+    rom.chr_rom = vec![0; 0x2000];
+    rom.chr_rom[16..24].copy_from_slice(&[0xFF; 8]);
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).unwrap();
+    fill_left_column(nes)
+}
+
+fn fill_left_column(mut nes: ActionNES) -> ActionNES {
+    // Going down a row with each $2007 write
+    write(&mut nes, &[(0x2000, 0b100), (0x2006, 0x20), (0x2006, 0x00)]);
+    write(&mut nes, &[(0x2007, 1); 30]);
+    write(
+        &mut nes,
+        &[
+            (0x2006, 0x3F),
+            (0x2006, 0x11),
+            (0x2007, 0x16),
+            (0x2000, 0),
+            (0x2005, 0),
+            (0x2005, 0),
+            (0x2001, 0b0001_1110),
+        ],
+    );
+    nes.ppu_state.cur_scanline = PRE_RENDER_SCANLINE;
+    nes
+}
+
+fn run_scanlines(nes: &mut ActionNES, scanlines: usize) {
+    for _ in 0..scanlines {
+        nes.ppu_state.cycle_counter = DOTS_PER_SCANLINE;
+        nes.as_ppu_action().end_scanline();
+    }
+}
+
+fn render(nes: &ActionNES) -> Frame {
+    let mut frame = Frame::new();
+    nes.render_frame(&mut frame);
+    frame
+}
+
+#[test]
+fn test_mid_frame_scroll_split() {
+    let mut nes = create_nes();
+    // The pre-render line and the first 120 visible ones
+    run_scanlines(&mut nes, 121);
+    write(&mut nes, &[(0x2005, 8), (0x2005, 0)]);
+    run_scanlines(&mut nes, 120);
+    let frame = render(&nes);
+    // Tiles repeat every 8 lines down the left edge above the split
+    assert!(frame.is_background_opaque(0, 0));
+    assert!(frame.is_background_opaque(7, 112));
+    assert!(!frame.is_background_opaque(8, 112));
+    // Below it the tile is scrolled off the left edge and wraps around to the right, the
+    // coarse X written on line 120 is picked up at the end of it
+    assert!(frame.is_background_opaque(0, 120));
+    assert!(!frame.is_background_opaque(0, 121));
+    assert!(frame.is_background_opaque(248, 128));
+    assert!(!frame.is_background_opaque(0, 128));
+}
+
+#[test]
+fn test_mid_frame_pattern_table_switch() {
+    let mut nes = create_nes();
+    run_scanlines(&mut nes, 1 + 60);
+    // The background now comes from $1000, where tile 1 is blank
+    write(&mut nes, &[(0x2000, 0b1_0000)]);
+    run_scanlines(&mut nes, 2);
+    let frame = render(&nes);
+    assert!(frame.is_background_opaque(0, 59));
+    // The first two tiles of a line are fetched at the end of the one above it
+    assert!(frame.is_background_opaque(0, 60));
+    assert!(!frame.is_background_opaque(0, 61));
+}
+
+#[test]
+fn test_fine_x_scroll() {
+    let mut nes = create_nes();
+    write(&mut nes, &[(0x2005, 3), (0x2005, 0)]);
+    run_scanlines(&mut nes, 9);
+    let frame = render(&nes);
+    assert!(frame.is_background_opaque(4, 0));
+    assert!(!frame.is_background_opaque(5, 0));
+    assert!(frame.is_background_opaque(253, 7));
+    assert!(!frame.is_background_opaque(252, 7));
+}
+
+#[test]
+fn test_sprites_drawn_a_line_below_oam_y() {
+    let mut nes = create_nes();
+    nes.ppu_state.oam_data[0..4].copy_from_slice(&[50, 1, 0, 100]);
+    run_scanlines(&mut nes, 1 + 60);
+    let frame = render(&nes);
+    assert_eq!(PixelSource::Backdrop, frame.source(100, 50));
+    assert_eq!(PixelSource::Sprite(0), frame.source(100, 51));
+    assert_eq!(PixelSource::Sprite(0), frame.source(107, 58));
+    assert_eq!(PixelSource::Backdrop, frame.source(100, 59));
+}
+
+#[test]
+fn test_chr_ram_tiles_drawn() {
+    // No CHR banks in the header, the game writes its tiles through $2007
+    let mut rom = ROM::new();
+    rom.prg_rom = vec![0; 0x4000];
+    let rom = ROM::from(rom.to_ines().unwrap()).unwrap();
+    assert!(rom.chr_ram);
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).unwrap();
+    write(&mut nes, &[(0x2006, 0x00), (0x2006, 0x10)]);
+    write(&mut nes, &[(0x2007, 0xFF); 8]);
+    let mut nes = fill_left_column(nes);
+    run_scanlines(&mut nes, 1 + 8);
+    let frame = render(&nes);
+    assert!(frame.is_background_opaque(0, 0));
+    assert!(frame.is_background_opaque(7, 7));
+    assert!(!frame.is_background_opaque(8, 0));
+}
+
+#[test]
+fn test_rendering_off_draws_backdrop() {
+    let mut nes = create_nes();
+    run_scanlines(&mut nes, 1 + 4);
+    write(&mut nes, &[(0x2001, 0)]);
+    run_scanlines(&mut nes, 4);
+    let frame = render(&nes);
+    assert!(frame.is_background_opaque(0, 3));
+    assert!(!frame.is_background_opaque(0, 4));
+    assert!(!frame.is_background_opaque(0, 7));
+}
+
+#[test]
+fn test_mid_frame_emphasis_and_greyscale() {
+    let mut nes = create_nes();
+    nes.ppu_state.palette_table[1] = 0x16;
+    run_scanlines(&mut nes, 1 + 4);
+    write(&mut nes, &[(0x2001, 0b0011_1110)]);
+    run_scanlines(&mut nes, 4);
+    write(&mut nes, &[(0x2001, 0b0001_1111)]);
+    run_scanlines(&mut nes, 4);
+    let frame = render(&nes);
+    let red = MaskedPalette::new(PpuMask::EMPHASIZE_RED);
+    // Each line keeps the bits it started with
+    assert_eq!(palette::SYSTEM_PALLETE[0x16], frame.data[3 * 256]);
+    assert_eq!(red.get_color(0x16), frame.data[4 * 256]);
+    assert_eq!(red.get_color(0x16), frame.data[7 * 256]);
+    // Greyscale takes the grey from the same row of the palette
+    assert_eq!(palette::SYSTEM_PALLETE[0x10], frame.data[8 * 256]);
+}
+
+#[test]
+fn test_8x16_sprites() {
+    let mut nes = create_nes();
+    // Tile 3 in the $1000 table is solid color 2, the bottom half of the pair starting at tile 2
+    let mut chr = nes.rom.chr_rom.clone();
+    chr[0x1038..0x1040].copy_from_slice(&[0xFF; 8]);
+    nes.rom.chr_rom = chr;
+    write(&mut nes, &[(0x2000, 0b10_0000)]);
+    nes.ppu_state.oam_data[0..4].copy_from_slice(&[20, 3, 0, 100]);
+    nes.ppu_state.oam_data[4..8].copy_from_slice(&[20, 3, 0b1000_0000, 140]);
+    run_scanlines(&mut nes, 1 + 40);
+    let frame = render(&nes);
+    // 16 lines tall, the top tile is blank and the bottom one solid
+    assert_eq!(PixelSource::Backdrop, frame.source(100, 28));
+    assert_eq!(PixelSource::Sprite(0), frame.source(100, 29));
+    assert_eq!(PixelSource::Sprite(0), frame.source(100, 36));
+    assert_eq!(PixelSource::Backdrop, frame.source(100, 37));
+    // Flipped vertically the solid tile is on top
+    assert_eq!(PixelSource::Sprite(0), frame.source(140, 21));
+    assert_eq!(PixelSource::Sprite(0), frame.source(140, 28));
+    assert_eq!(PixelSource::Backdrop, frame.source(140, 29));
+}
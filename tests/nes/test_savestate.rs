@@ -0,0 +1,58 @@
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::rom::ROM;
+
+use crate::common::{load_nes, program_nes, NESTEST};
+
+#[test]
+fn test_load_state_resumes_the_same_run() {
+    let mut nes = load_nes(NESTEST);
+    nes.step_frames(5).unwrap();
+    let state = nes.save_state();
+    nes.step_frames(3).unwrap();
+    let expected = nes.state_hash();
+
+    let mut loaded = load_nes(NESTEST);
+    loaded.load_state(&state).unwrap();
+    loaded.step_frames(3).unwrap();
+    assert_eq!(expected, loaded.state_hash());
+    // The frame count carries on from the savestate's
+    assert_eq!(8, loaded.frame_count());
+    assert_eq!(nes.emulated_duration(), loaded.emulated_duration());
+}
+
+#[test]
+fn test_load_state_restores_bank_registers() {
+    // MMC1 with eight 16KB banks, each filled with its number
+    let mut rom = ROM::new();
+    rom.mapper = 1;
+    rom.prg_rom = (0..8u8).flat_map(|bank| [bank; 0x4000]).collect();
+    let mut nes = ActionNES::new();
+    nes.set_rom(rom).expect("Failed to set ROM");
+    let select_bank = |nes: &mut ActionNES, bank: u8| {
+        let mut bus = nes.as_cpu_bus();
+        for bit in 0..5 {
+            bus.write_byte(0xE000, (bank >> bit) & 1);
+        }
+    };
+    select_bank(&mut nes, 3);
+    let state = nes.save_state();
+    select_bank(&mut nes, 5);
+    assert_eq!(5, nes.as_cpu_bus().read_byte(0x8000));
+    nes.load_state(&state).unwrap();
+    assert_eq!(3, nes.as_cpu_bus().read_byte(0x8000));
+}
+
+#[test]
+fn test_load_state_refuses_other_game() {
+    let mut nes = load_nes(NESTEST);
+    nes.step_frames(2).unwrap();
+    let state = nes.save_state();
+
+    let mut other = program_nes(&[]);
+    let before = other.state_hash();
+    let err = other.load_state(&state).unwrap_err();
+    assert!(err.contains("different game"), "{}", err);
+    assert_eq!(before, other.state_hash());
+    // Truncated states are refused too
+    assert!(nes.load_state(&state[..state.len() - 1]).is_err());
+}
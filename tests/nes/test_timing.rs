@@ -0,0 +1,44 @@
+use rust_nes_emulator::nes::{ActionNES, NES};
+use rust_nes_emulator::snapshot::{Snapshot, SnapshotBaseline};
+
+// CPU cycles from a $4017 write to the frame IRQ in 4-step mode
+const FRAME_IRQ_CYCLES: usize = 29829;
+
+#[test]
+fn test_apu_frame_irq_timing() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes").unwrap();
+    nes.reset().unwrap();
+    // 4-step mode with the IRQ enabled, nestest never writes $4017
+    nes.as_cpu_bus().write_byte(0x4017, 0);
+    let start = nes.cpu_state.cycle_counter;
+    // The IRQ lands mid-instruction, so it must be raised by the end of the instruction that
+    // crosses it and not before
+    while nes.cpu_state.cycle_counter - start < FRAME_IRQ_CYCLES + 10 {
+        nes.next_cpu_instruction().unwrap();
+        let elapsed = nes.cpu_state.cycle_counter - start;
+        assert_eq!(
+            elapsed >= FRAME_IRQ_CYCLES,
+            nes.apu_state.frame_irq,
+            "{} cycles in",
+            elapsed
+        );
+    }
+}
+
+#[test]
+fn test_restored_apu_stays_in_step() {
+    let mut nes = ActionNES::new();
+    nes.load_from_path("test_roms/nestest.nes").unwrap();
+    nes.reset().unwrap();
+    nes.step_frames(3).unwrap();
+    // Savestates restore the clocks, the APU picks up where it left off
+    let mut restored = ActionNES::new();
+    restored.load_from_path("test_roms/nestest.nes").unwrap();
+    let baseline = SnapshotBaseline::power_on();
+    Snapshot::capture(&nes, &baseline).restore(&mut restored, &baseline);
+    nes.step_frames(2).unwrap();
+    restored.step_frames(2).unwrap();
+    assert_eq!(nes.state_hash(), restored.state_hash());
+    assert_eq!(nes.apu_state.frame_cycle, restored.apu_state.frame_cycle);
+}